@@ -1,35 +1,63 @@
 // Copyright (c) 2023 LogCraft, SAS.
 // SPDX-License-Identifier: MPL-2.0
 
+use anyhow::anyhow;
 use anyhow::bail;
 use anyhow::Result;
 use dialoguer::Confirm;
 use dialoguer::Input;
 use dialoguer::Password;
 use dialoguer::Select;
+use glob::Pattern;
 use kclvm_api::{gpyrpc::ValidateCodeArgs, API};
 use kclvm_query::get_schema_type;
 use kclvm_query::GetSchemaOption;
 use kclvm_sema::ty::TypeKind;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::json;
 use serde_json::Map;
 use serde_json::Value;
 use std::collections::{BTreeMap, BTreeSet};
 use std::{
-    fs::File,
+    env,
+    fs::{self, File},
     hash::{Hash, Hasher},
     io::BufWriter,
-    path::PathBuf,
+    path::{Path, PathBuf},
     str::FromStr,
+    time::Duration,
 };
 
 pub const LGC_CONFIG_PATH: &str = "lgc.yaml";
 pub const LGC_RULES_DIR: &str = "rules";
-
-use crate::plugins::Plugin;
-use crate::state::backends::StateBackend;
+pub const LGC_POLICIES_DIR: &str = "policies";
+pub const LGC_ARCHIVE_DIR: &str = "archive";
+pub const LGC_TESTS_DIR: &str = "tests";
+
+/// Overlays of the configuration by environment, `{env}` being the environment ID.
+pub const LGC_OVERLAY_PATH: &str = "lgc.{env}.yaml";
+
+/// Default time allowed for a single remote operation.
+pub const DEFAULT_OPERATION_TIMEOUT: Duration = Duration::from_secs(60);
+/// Default number of consecutive failures before skipping a service.
+pub const DEFAULT_MAX_FAILURES: usize = 3;
+/// Environment variable disabling `--auto-approve`, `always` or a comma separated list of environments.
+pub const LGC_REQUIRE_APPROVAL_ENV: &str = "LGC_REQUIRE_APPROVAL";
+
+use crate::detections::{DetectionFormat, DEFAULT_DETECTION_EXTENSIONS};
+use crate::hooks::Hooks;
+use crate::limits::{Limits, RetryPolicy};
+use crate::naming::NamingTemplate;
+use crate::notifications::Notifier;
+use crate::plugins::{trust::PluginTrust, Plugin, LGC_PLUGINS_PATH};
+use crate::policies::PolicyPack;
+use crate::secrets::{credential_helper_settings, resolve_secrets};
+use crate::sigma::SigmaMapping;
+use crate::state::{backends::StateBackend, current_workspace, LGC_DEFAULT_WORKSPACE};
+use crate::telemetry::TelemetryConfig;
+use crate::transforms::Transform;
 use crate::utils::ensure_kebab_case;
+use crate::variables::render_configuration;
 
 /// ProjectConfiguration definition
 /// BTreeSet has been chosen rather than BTreeMap in order to improve readability over name field in config file.
@@ -42,6 +70,121 @@ pub struct ProjectConfiguration {
     pub plugins: BTreeMap<String, Plugin>,
     pub environments: BTreeSet<Environment>,
     pub services: BTreeSet<Service>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub policies: BTreeMap<String, PolicyPack>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transforms: Vec<Transform>,
+    /// Conversion of Sigma rules, by plugin name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sigma: BTreeMap<String, SigmaMapping>,
+    /// Default values of detection variables
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub variables: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "CoreConfiguration::is_empty")]
+    pub core: CoreConfiguration,
+    /// Named workspaces, each with its own state
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub workspaces: BTreeSet<String>,
+    /// Commands run around deployments
+    #[serde(default, skip_serializing_if = "Hooks::is_empty")]
+    pub hooks: Hooks,
+}
+
+/// Changes allowed by commands of the project.
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProjectMode {
+    #[default]
+    ReadWrite,
+    /// Commands changing services or the state are refused
+    ReadOnly,
+}
+
+impl ProjectMode {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Project wide settings.
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct CoreConfiguration {
+    /// `read-only` refuses deployments, destructions and state changes
+    #[serde(default, skip_serializing_if = "ProjectMode::is_default")]
+    pub mode: ProjectMode,
+    /// Operational limits used as commands defaults
+    #[serde(default, skip_serializing_if = "Limits::is_empty")]
+    pub limits: Limits,
+    /// Extensions of the detection files to load (e.g. `[yaml, json]`), all supported ones if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detection_extensions: Option<Vec<String>>,
+    /// Usage telemetry, disabled unless a webhook is set
+    #[serde(default, skip_serializing_if = "TelemetryConfig::is_empty")]
+    pub telemetry: TelemetryConfig,
+    /// Destinations of deploy and destroy summaries
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notifications: Vec<Notifier>,
+    /// Publishers whose plugins can be installed, plugins are not verified if none
+    #[serde(default, skip_serializing_if = "PluginTrust::is_empty")]
+    pub plugin_trust: PluginTrust,
+    /// Glob patterns of rules never deleted by `lgc deploy` or `lgc destroy` (e.g. `lookup-*`)
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        deserialize_with = "deserialize_patterns"
+    )]
+    pub protected_rules: Vec<String>,
+}
+
+impl CoreConfiguration {
+    pub fn is_empty(&self) -> bool {
+        self.mode.is_default()
+            && self.limits.is_empty()
+            && self.detection_extensions.is_none()
+            && self.telemetry.is_empty()
+            && self.notifications.is_empty()
+            && self.plugin_trust.is_empty()
+            && self.protected_rules.is_empty()
+    }
+
+    /// Whether a rule matches one of the `protected_rules` patterns.
+    pub fn is_protected(&self, name: &str) -> bool {
+        self.protected_rules
+            .iter()
+            .any(|pattern| Pattern::new(pattern).is_ok_and(|pattern| pattern.matches(name)))
+    }
+
+    /// Extensions of the detection files to load.
+    pub fn detection_extensions(&self) -> Result<Vec<String>> {
+        let Some(extensions) = &self.detection_extensions else {
+            return Ok(DEFAULT_DETECTION_EXTENSIONS.map(String::from).to_vec());
+        };
+
+        for ext in extensions {
+            if DetectionFormat::from_extension(ext).is_none() {
+                bail!(
+                    "unsupported detection file extension `{}`, expected one of: {}",
+                    ext,
+                    DEFAULT_DETECTION_EXTENSIONS.join(", ")
+                )
+            }
+        }
+
+        Ok(extensions.clone())
+    }
+}
+
+/// Glob patterns, rejected when loading the configuration rather than never matching.
+fn deserialize_patterns<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Vec<String>, D::Error> {
+    let patterns = Vec::<String>::deserialize(deserializer)?;
+    for pattern in &patterns {
+        Pattern::new(pattern).map_err(|e| {
+            serde::de::Error::custom(format!("invalid pattern `{}`: {}", pattern, e))
+        })?;
+    }
+    Ok(patterns)
 }
 
 impl ProjectConfiguration {
@@ -59,6 +202,67 @@ impl ProjectConfiguration {
             .collect()
     }
 
+    /// Reason why changes to these services must be approved interactively, if any.
+    ///
+    /// Environments opt in with `require_approval`, `LGC_REQUIRE_APPROVAL` enforces it on
+    /// every environment (`always`) or on listed ones, regardless of the configuration.
+    pub fn approval_required(&self, service_ids: &[&str]) -> Option<String> {
+        let enforced = env::var(LGC_REQUIRE_APPROVAL_ENV).unwrap_or_default();
+        let enforced: Vec<&str> = enforced
+            .split(',')
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .collect();
+
+        if enforced.contains(&"always") {
+            return Some(format!("`{}=always`", LGC_REQUIRE_APPROVAL_ENV));
+        }
+
+        self.environments
+            .iter()
+            .filter(|env| env.require_approval || enforced.contains(&env.id.as_str()))
+            .find(|env| {
+                env.services
+                    .iter()
+                    .any(|id| service_ids.contains(&id.as_str()))
+            })
+            .map(|env| format!("environment `{}`", env.id))
+    }
+
+    /// Naming convention of the rules deployed on a service, rules keep their name if none is set.
+    ///
+    /// The first environment listing the service provides `{env}` and overrides the plugin template.
+    pub fn naming(&self, svc: &Service) -> Result<NamingTemplate> {
+        let env = self
+            .environments
+            .iter()
+            .find(|env| env.services.contains(&svc.id));
+        let template = env.and_then(|env| env.naming.as_ref()).or_else(|| {
+            self.plugins
+                .get(&svc.plugin)
+                .and_then(|plugin| plugin.naming.as_ref())
+        });
+
+        match template {
+            Some(template) => NamingTemplate::new(template, env.map(|env| env.id.as_str()), svc),
+            None => Ok(NamingTemplate::default()),
+        }
+    }
+
+    /// Selected workspace, which must be declared unless it is the default one.
+    pub fn workspace(&self) -> Result<String> {
+        let workspace = current_workspace();
+        if workspace != LGC_DEFAULT_WORKSPACE && !self.workspaces.contains(&workspace) {
+            bail!(
+                "workspace `{}` does not exist, create it with `lgc workspace new {}` or select another one",
+                workspace,
+                workspace
+            )
+        }
+
+        Ok(workspace)
+    }
+
     pub fn service_ids(&self) -> Result<Vec<&str>> {
         self.services
             .iter()
@@ -66,6 +270,47 @@ impl ProjectConfiguration {
             .collect()
     }
 
+    /// Service IDs in deployment order, each service after the ones it depends on.
+    pub fn deployment_order(&self) -> Result<Vec<&str>> {
+        for svc in &self.services {
+            for dependency in &svc.depends_on {
+                if !self.services.iter().any(|other| &other.id == dependency) {
+                    bail!(
+                        "service `{}` depends on service `{}` which does not exist",
+                        svc.id,
+                        dependency
+                    )
+                }
+            }
+        }
+
+        // Services are ordered by ID among the ones whose dependencies are deployed
+        let mut order: Vec<&str> = Vec::with_capacity(self.services.len());
+        while order.len() < self.services.len() {
+            let next = self.services.iter().find(|svc| {
+                !order.contains(&svc.id.as_str())
+                    && svc
+                        .depends_on
+                        .iter()
+                        .all(|dependency| order.contains(&dependency.as_str()))
+            });
+            match next {
+                Some(svc) => order.push(&svc.id),
+                None => {
+                    let cycle: Vec<&str> = self
+                        .services
+                        .iter()
+                        .map(|svc| svc.id.as_str())
+                        .filter(|id| !order.contains(id))
+                        .collect();
+                    bail!("services `{}` depend on each other", cycle.join("`, `"))
+                }
+            }
+        }
+
+        Ok(order)
+    }
+
     pub fn remove_service(&mut self, id: &String) {
         self.services.remove(&Service {
             id: id.to_owned(),
@@ -73,6 +318,157 @@ impl ProjectConfiguration {
         });
     }
 
+    /// Look for configuration references which cannot be resolved or are unused.
+    pub fn lint(&self) -> Vec<LintFinding> {
+        let mut findings = self.state.lint();
+        findings.extend(self.lint_plugins_dir());
+
+        for name in self.plugins.keys() {
+            if !Path::new(LGC_PLUGINS_PATH).join(name).is_file() {
+                findings.push(LintFinding::new(
+                    format!("plugin `{}` is declared but not installed", name),
+                    format!("reinstall it with `lgc plugins install <source>` or remove it with `lgc plugins uninstall {}`", name),
+                ));
+            }
+            if !self.services.iter().any(|svc| &svc.plugin == name) {
+                findings.push(LintFinding::new(
+                    format!("plugin `{}` is not used by any service", name),
+                    format!("add a service with `lgc services add -p {}`", name),
+                ));
+            }
+        }
+
+        for svc in &self.services {
+            if !self.plugins.contains_key(&svc.plugin) {
+                findings.push(LintFinding::new(
+                    format!(
+                        "service `{}` references plugin `{}` which is not installed",
+                        svc.id, svc.plugin
+                    ),
+                    format!(
+                        "install the plugin with `lgc plugins install <source>` or remove the service with `lgc services remove {}`",
+                        svc.id
+                    ),
+                ));
+            }
+            if let Err(e) = self.naming(svc) {
+                findings.push(LintFinding::new(
+                    format!("invalid naming of service `{}`: {}", svc.id, e),
+                    String::from("fix the `naming` template of its environment or plugin"),
+                ));
+            }
+            if !self
+                .environments
+                .iter()
+                .any(|env| env.services.contains(&svc.id))
+            {
+                findings.push(LintFinding::new(
+                    format!("service `{}` is not linked to any environment", svc.id),
+                    format!("link it with `lgc envs link <environment> -s {}`", svc.id),
+                ));
+            }
+        }
+
+        if let Err(e) = self.deployment_order() {
+            findings.push(LintFinding::new(
+                e.to_string(),
+                String::from("fix the `depends_on` lists of the services"),
+            ));
+        }
+
+        for transform in &self.transforms {
+            if !transform.script.is_file() {
+                findings.push(LintFinding::new(
+                    format!(
+                        "transform script `{}` does not exist",
+                        transform.script.display()
+                    ),
+                    String::from("fix the script path or remove the transform"),
+                ));
+            }
+            for plugin in &transform.plugins {
+                if !self.plugins.contains_key(plugin) {
+                    findings.push(LintFinding::new(
+                        format!(
+                            "transform `{}` references plugin `{}` which is not installed",
+                            transform.script.display(),
+                            plugin
+                        ),
+                        format!("remove `{}` from the transform plugins", plugin),
+                    ));
+                }
+            }
+        }
+
+        for plugin in self.sigma.keys() {
+            if !self.plugins.contains_key(plugin) {
+                findings.push(LintFinding::new(
+                    format!(
+                        "sigma mapping references plugin `{}` which is not installed",
+                        plugin
+                    ),
+                    format!("remove `sigma.{}` from the configuration", plugin),
+                ));
+            }
+        }
+
+        for env in &self.environments {
+            if env.services.is_empty() {
+                findings.push(LintFinding::new(
+                    format!("environment `{}` has no service", env.id),
+                    format!(
+                        "link a service with `lgc envs link {} -s <service>` or remove it with `lgc envs remove {}`",
+                        env.id, env.id
+                    ),
+                ));
+            }
+            for svc_id in &env.services {
+                if !self.services.iter().any(|svc| &svc.id == svc_id) {
+                    findings.push(LintFinding::new(
+                        format!(
+                            "environment `{}` references service `{}` which does not exist",
+                            env.id, svc_id
+                        ),
+                        format!("unlink it with `lgc envs unlink {} -s {}`", env.id, svc_id),
+                    ));
+                }
+            }
+        }
+
+        findings
+    }
+
+    /// Look for plugin binaries which are not declared in the configuration.
+    pub fn lint_plugins_dir(&self) -> Vec<LintFinding> {
+        let Ok(entries) = fs::read_dir(LGC_PLUGINS_PATH) else {
+            return Vec::new();
+        };
+
+        let mut names: Vec<String> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_file())
+            .filter_map(|entry| entry.file_name().to_str().map(str::to_string))
+            .filter(|name| !self.plugins.contains_key(name))
+            .collect();
+        names.sort();
+
+        names
+            .into_iter()
+            .map(|name| {
+                LintFinding::new(
+                    format!(
+                        "plugin `{}` is present in `{}` but not declared",
+                        name, LGC_PLUGINS_PATH
+                    ),
+                    format!(
+                        "reinstall it with `lgc plugins install {}/{}` or delete the file",
+                        LGC_PLUGINS_PATH, name
+                    ),
+                )
+            })
+            .collect()
+    }
+
     pub fn unlink_environments(&mut self, name: &String) {
         // Cannot mutate BTreeSet in place, replacing configuration envs with a new one.
         let mut modified_envs: BTreeSet<Environment> = BTreeSet::new();
@@ -85,10 +481,102 @@ impl ProjectConfiguration {
     }
 }
 
+/// Create the project directories read by lgc, returning the ones which were missing.
+/// Configuration document seen by commands targeting `environment`: the environment overlay
+/// (`lgc.<env>.yaml`) merged over `lgc.yaml`, then placeholders rendered.
+pub fn resolve_configuration(environment: Option<&str>) -> Result<String> {
+    let mut config: serde_yaml_ng::Value =
+        serde_yaml_ng::from_str(&fs::read_to_string(LGC_CONFIG_PATH)?)
+            .map_err(|e| anyhow!("invalid `{}`: {}", LGC_CONFIG_PATH, e))?;
+
+    if let Some(environment) = environment {
+        let path = LGC_OVERLAY_PATH.replace("{env}", environment);
+        if Path::new(&path).is_file() {
+            let overlay = serde_yaml_ng::from_str(&fs::read_to_string(&path)?)
+                .map_err(|e| anyhow!("invalid overlay `{}`: {}", path, e))?;
+            merge_overlay(&mut config, overlay);
+        }
+    }
+
+    render_configuration(&mut config, environment)?;
+    Ok(serde_yaml_ng::to_string(&config)?)
+}
+
+/// Merge an overlay over a configuration document. Tables are merged recursively, lists of
+/// items with an `id` (services, environments) are merged by ID and other values replaced.
+pub(crate) fn merge_overlay(base: &mut serde_yaml_ng::Value, overlay: serde_yaml_ng::Value) {
+    use serde_yaml_ng::Value as Yaml;
+
+    let id = |item: &Yaml| item.get("id").cloned();
+    match (base, overlay) {
+        (Yaml::Mapping(base), Yaml::Mapping(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(field) => merge_overlay(field, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (Yaml::Sequence(base), Yaml::Sequence(overlay))
+            if !overlay.is_empty() && overlay.iter().all(|item| id(item).is_some()) =>
+        {
+            for item in overlay {
+                match base.iter_mut().find(|known| id(known) == id(&item)) {
+                    Some(known) => merge_overlay(known, item),
+                    None => base.push(item),
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+pub fn ensure_workspace_dirs() -> Result<Vec<&'static str>> {
+    let mut created = Vec::new();
+    for dir in [LGC_RULES_DIR, LGC_POLICIES_DIR] {
+        if !Path::new(dir).is_dir() {
+            fs::create_dir_all(dir)
+                .map_err(|e| anyhow!("unable to create `{}` directory: {}", dir, e))?;
+            created.push(dir);
+        }
+    }
+
+    Ok(created)
+}
+
+/// Configuration issue reported by linting, with a suggested fix.
+pub struct LintFinding {
+    pub message: String,
+    pub suggestion: String,
+}
+
+impl LintFinding {
+    pub fn new(message: String, suggestion: String) -> Self {
+        Self {
+            message,
+            suggestion,
+        }
+    }
+}
+
 #[derive(Eq, Serialize, Deserialize, Default, Clone)]
 pub struct Environment {
     pub id: String,
     pub services: BTreeSet<String>,
+    /// Always prompt before changes, ignoring `--auto-approve`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub require_approval: bool,
+    /// Destroying rules requires `--yes-i-am-sure` or typing the environment ID
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub production: bool,
+    /// Name of deployed rules (e.g. `[{env}] {name} - LGC`), overrides plugins naming
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub naming: Option<String>,
+    /// Project variables overridden when this environment is targeted, as `${var.<name>}`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub variables: BTreeMap<String, String>,
 }
 
 impl PartialEq for Environment {
@@ -120,6 +608,46 @@ pub struct Service {
     pub id: String,
     pub plugin: String,
     pub settings: BTreeMap<String, Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quota: Option<Quota>,
+    /// Time allowed for a single remote operation (e.g. `30s`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<String>,
+    /// Consecutive failures after which remaining operations are skipped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_failures: Option<usize>,
+    /// Store the full remote object of each rule in state
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub remote_snapshots: bool,
+    /// Retries of failed operations, overriding the project limits
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry: Option<RetryPolicy>,
+    /// Services deployed before this one, which is skipped if any of them fails
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub depends_on: Vec<String>,
+    /// Command printing short-lived settings (e.g. tokens) merged over the service settings
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credential_helper: Option<String>,
+}
+
+/// Limits of the remote platform behind a service.
+#[derive(Eq, PartialEq, Serialize, Deserialize, Clone)]
+pub struct Quota {
+    /// Maximum number of rules that can be deployed on the service
+    pub max_rules: usize,
+    /// Behaviour when the quota is exceeded
+    #[serde(default)]
+    pub enforcement: QuotaEnforcement,
+}
+
+#[derive(Eq, PartialEq, Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum QuotaEnforcement {
+    /// Display a warning and continue
+    #[default]
+    Warn,
+    /// Refuse to continue
+    Error,
 }
 
 impl PartialEq for Service {
@@ -147,6 +675,33 @@ impl Ord for Service {
 }
 
 impl Service {
+    /// Settings passed to the plugin, with secret references resolved.
+    pub async fn config(&self) -> Result<String> {
+        let mut settings = resolve_secrets(&self.settings)
+            .await
+            .map_err(|e| anyhow!("service `{}`: {}", self.id, e))?;
+        settings.extend(self.helper_settings().await?);
+        Ok(serde_json::to_string(&settings)?)
+    }
+
+    /// Settings to check against the plugin schema: secret references are left
+    /// unresolved, but settings printed by the credential helper are merged in.
+    pub async fn checked_settings(&self) -> Result<BTreeMap<String, Value>> {
+        let mut settings = self.settings.clone();
+        settings.extend(self.helper_settings().await?);
+        Ok(settings)
+    }
+
+    /// Settings printed by the credential helper, if any.
+    pub async fn helper_settings(&self) -> Result<BTreeMap<String, Value>> {
+        match &self.credential_helper {
+            Some(helper) => credential_helper_settings(helper, &self.id, &self.plugin)
+                .await
+                .map_err(|e| anyhow!("service `{}`: {}", self.id, e)),
+            None => Ok(BTreeMap::new()),
+        }
+    }
+
     pub fn configure(&mut self, code: String, default: bool) -> Result<()> {
         let schema = get_schema_type(
             "",
@@ -207,6 +762,51 @@ impl Service {
         Ok(())
     }
 
+    /// Time allowed to each operation on the service, `default` if not set.
+    pub fn timeout(&self, default: Duration) -> Result<Duration> {
+        match &self.timeout {
+            Some(timeout) => humantime::parse_duration(timeout)
+                .map_err(|e| anyhow!("invalid timeout for service `{}`: {}", self.id, e)),
+            None => Ok(default),
+        }
+    }
+
+    /// Limits of operations on the service: CLI `overrides` > service `retry` > `project` limits.
+    pub fn limits(&self, project: &Limits, overrides: &Limits) -> Limits {
+        match &self.retry {
+            Some(retry) => project.merge(&retry.limits()).merge(overrides),
+            None => project.merge(overrides),
+        }
+    }
+
+    pub fn max_failures(&self) -> usize {
+        self.max_failures.unwrap_or(DEFAULT_MAX_FAILURES).max(1)
+    }
+
+    /// Check that `count` rules fit in the service quota, if any.
+    pub fn check_quota(&self, count: usize) -> Result<()> {
+        if let Some(quota) = &self.quota {
+            if count > quota.max_rules {
+                match quota.enforcement {
+                    QuotaEnforcement::Warn => tracing::warn!(
+                        "service `{}` would hold {} rules, exceeding its quota of {}",
+                        self.id,
+                        count,
+                        quota.max_rules
+                    ),
+                    QuotaEnforcement::Error => bail!(
+                        "service `{}` would hold {} rules, exceeding its quota of {}",
+                        self.id,
+                        count,
+                        quota.max_rules
+                    ),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn validate(&self, code: String, data: String) -> Result<()> {
         let kcl_api = API::default();
 
@@ -226,6 +826,38 @@ impl Service {
     }
 }
 
+/// Attribute of a KCL schema provided by a plugin.
+pub struct SchemaField {
+    pub name: String,
+    pub ty: String,
+    pub required: bool,
+    pub sensitive: bool,
+    pub doc: Option<String>,
+}
+
+/// List the attributes of `schema` defined in `code`, or nothing if the schema does not exist.
+pub fn schema_fields(code: &str, schema: &str) -> Result<Vec<SchemaField>> {
+    let schemas = get_schema_type("", Some(code), Some(schema), GetSchemaOption::Definitions)?;
+    let Some(schema) = schemas.get(schema) else {
+        return Ok(Vec::new());
+    };
+
+    Ok(schema
+        .attrs
+        .iter()
+        .map(|(name, attr)| SchemaField {
+            name: name.to_string(),
+            ty: attr.ty.ty_str(),
+            required: !attr.is_optional && attr.default.is_none(),
+            sensitive: attr
+                .decorators
+                .iter()
+                .any(|decorator| decorator.keywords.contains_key("sensitive")),
+            doc: attr.doc.as_deref().map(trim_quotes),
+        })
+        .collect())
+}
+
 fn trim_quotes(s: &str) -> String {
     s.trim_matches(|c| c == '"' || c == '\'').to_string()
 }
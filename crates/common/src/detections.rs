@@ -1,26 +1,35 @@
 // Copyright (c) 2023 LogCraft, SAS.
 // SPDX-License-Identifier: MPL-2.0
 
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Result};
 use console::{style, Style};
 use dashmap::DashMap;
 use kclvm_api::gpyrpc::ValidateCodeArgs;
 use kclvm_api::service::KclvmServiceImpl;
-use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use serde_with::skip_serializing_none;
 use similar::{ChangeTag, TextDiff};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::{
     collections::HashSet,
+    fmt,
     hash::{Hash, Hasher},
+    sync::Mutex,
 };
 
 use crate::{
-    configuration::{Service, LGC_RULES_DIR},
-    plugins::LGC_PLUGINS_PATH,
+    configuration::{LintFinding, ProjectConfiguration, Service, LGC_RULES_DIR},
+    expansion::{expand_detection, extend_detection},
+    plugins::{
+        manager::{PluginActions, PluginManager},
+        Capability, Plugin, LGC_PLUGINS_PATH,
+    },
+    utils::workspace_path,
+    variables::Variables,
 };
 
 pub const GENERIC_DETECTION: &str = r#"
@@ -30,6 +39,12 @@ schema Detection:
     ----------
     name : str, required,
         Name of the detection
+    common: {str:any}, optional,
+        Plugin agnostic implementation, mapped by each plugin
+    metadata: {str:any}, optional,
+        Owner, tags, MITRE ATT&CK techniques and references of the detection
+    manage_fields: [str], optional,
+        Fields managed by lgc, other remote fields are left untouched
     rules: [any], required,
         <plugin>:
             Plugin specific implementation
@@ -37,9 +52,60 @@ schema Detection:
             Plugin specific implementation
     """
     name: str
+    common?: {str:any}
+    metadata?: {str:any}
+    manage_fields?: [str]
     rules: {str:any}
 "#;
 
+/// Extensions of detection files loaded by default.
+pub const DEFAULT_DETECTION_EXTENSIONS: [&str; 4] = ["yaml", "yml", "json", "toml"];
+
+/// Format of a detection file, found from its extension.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DetectionFormat {
+    Yaml,
+    Json,
+    Toml,
+}
+
+impl DetectionFormat {
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "yaml" | "yml" => Some(Self::Yaml),
+            "json" => Some(Self::Json),
+            "toml" => Some(Self::Toml),
+            _ => None,
+        }
+    }
+
+    /// Format of a detection file, `None` if its extension is not in `extensions`.
+    pub fn from_path(path: &Path, extensions: &[String]) -> Option<Self> {
+        path.extension()
+            .and_then(|ext| ext.to_str())
+            .filter(|ext| extensions.iter().any(|accepted| accepted == ext))
+            .and_then(Self::from_extension)
+    }
+
+    /// Parse a detection file, syntax errors being reported with their position.
+    pub fn parse(self, content: &str) -> Result<serde_yaml_ng::Value> {
+        match self {
+            Self::Yaml => serde_yaml_ng::from_str(content).map_err(|e| match e.location() {
+                Some(location) => anyhow!(
+                    "line {}, column {}: {}",
+                    location.line(),
+                    location.column(),
+                    e
+                ),
+                None => anyhow!("{}", e),
+            }),
+            Self::Json => serde_json::from_str(content)
+                .map_err(|e| anyhow!("line {}, column {}: {}", e.line(), e.column(), e)),
+            Self::Toml => toml::from_str(content).map_err(|e| anyhow!("{}", e)),
+        }
+    }
+}
+
 // Helper types to store detections per plugin or per service
 pub type PluginDetections = HashMap<String, HashSet<DetectionState>>;
 pub type ServiceDetections = HashMap<String, HashSet<DetectionState>>;
@@ -47,17 +113,152 @@ pub type ServiceDetections = HashMap<String, HashSet<DetectionState>>;
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Detection {
     pub name: String,
+    #[serde(default)]
+    pub common: Option<CommonRule>,
+    #[serde(default)]
+    pub metadata: Option<RuleMetadata>,
+    /// Fields managed by lgc, all fields are managed if empty
+    #[serde(default)]
+    pub manage_fields: Vec<String>,
     pub rules: HashMap<String, Value>,
 }
 
+/// Ownership and classification of a detection, shared by all its rules.
+/// Only passed to plugins advertising the `metadata` capability.
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct RuleMetadata {
+    pub owner: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// MITRE ATT&CK technique identifiers (e.g. `T1110`, `T1110.003`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mitre_attack: Vec<String>,
+    /// Links documenting the detection
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub references: Vec<String>,
+}
+
+impl RuleMetadata {
+    pub fn validate(&self) -> Result<()> {
+        if self
+            .owner
+            .as_ref()
+            .is_some_and(|owner| owner.trim().is_empty())
+        {
+            bail!("metadata `owner` cannot be empty")
+        }
+
+        for tag in &self.tags {
+            if tag.is_empty() || tag.contains(char::is_whitespace) {
+                bail!(
+                    "invalid metadata tag `{}`, tags cannot be empty or contain spaces",
+                    tag
+                )
+            }
+        }
+
+        for technique in &self.mitre_attack {
+            if !is_mitre_technique(technique) {
+                bail!(
+                    "invalid MITRE ATT&CK technique `{}`, expected `T<4 digits>` or `T<4 digits>.<3 digits>`",
+                    technique
+                )
+            }
+        }
+
+        for reference in &self.references {
+            if !reference.starts_with("https://") && !reference.starts_with("http://") {
+                bail!("invalid metadata reference `{}`, expected a URL", reference)
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether the detection has one of these tags.
+    pub fn has_any_tag(&self, tags: &[String]) -> bool {
+        self.tags.iter().any(|tag| tags.contains(tag))
+    }
+}
+
+/// Technique or sub-technique identifier, such as `T1110` or `T1110.003`.
+fn is_mitre_technique(technique: &str) -> bool {
+    let Some(id) = technique.strip_prefix('T') else {
+        return false;
+    };
+    let (technique, sub_technique) = match id.split_once('.') {
+        Some((technique, sub_technique)) => (technique, Some(sub_technique)),
+        None => (id, None),
+    };
+
+    let digits =
+        |value: &str, len: usize| value.len() == len && value.chars().all(|c| c.is_ascii_digit());
+    digits(technique, 4) && sub_technique.map_or(true, |sub| digits(sub, 3))
+}
+
+/// Plugin agnostic rule definition.
+/// Each plugin maps it to its own format, plugin specific content being used as overrides.
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct CommonRule {
+    pub title: String,
+    pub description: Option<String>,
+    pub query: String,
+    pub schedule: Option<String>,
+    pub severity: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub actions: Vec<Value>,
+}
+
+/// Detection file which could not be loaded.
+#[derive(Debug, Clone)]
+pub struct InvalidDetection {
+    pub path: PathBuf,
+    pub message: String,
+}
+
+impl fmt::Display for InvalidDetection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", workspace_path(&self.path), self.message)
+    }
+}
+
 impl Detection {
-    pub fn pre_validate(path: String) -> Result<Self> {
+    /// Detections of a file, templates expanding into several of them.
+    pub fn pre_validate(
+        path: String,
+        format: DetectionFormat,
+        variables: &Variables,
+    ) -> Result<Vec<Self>> {
+        let content = variables.substitute(fs::read_to_string(&path)?)?;
+
+        expand_detection(content)?
+            .into_iter()
+            .map(|content| Self::validate_content(content, format, variables))
+            .collect()
+    }
+
+    fn validate_content(
+        content: String,
+        format: DetectionFormat,
+        variables: &Variables,
+    ) -> Result<Self> {
+        // Syntax errors are reported with their position before schema validation
+        let mut value = format.parse(&content)?;
+        let extended = extend_detection(&mut value, variables)?;
+        let content = match format {
+            DetectionFormat::Yaml if !extended => content,
+            _ => serde_yaml_ng::to_string(&value)?,
+        };
+
         // KCL validation
         // ! Validation does not provide specific check for now
         // ! It is used for better configuration messages
         let serv = KclvmServiceImpl::default();
         let args = ValidateCodeArgs {
-            datafile: path.clone(),
+            data: content.clone(),
             code: GENERIC_DETECTION.to_string(),
             schema: String::from("Detection"),
             format: String::from("yaml"),
@@ -66,36 +267,44 @@ impl Detection {
 
         let check = serv.validate_code(&args)?;
         if !check.success {
-            tracing::error!(
-                "failed to verify detection file `{}`: {}",
-                path,
-                check.err_message
-            );
-            std::process::exit(1);
+            bail!("{}", check.err_message)
         };
 
-        serde_yaml_ng::from_str(&fs::read_to_string(&path)?)
-            .map_err(|e| anyhow::Error::msg(format!("{e}")))
+        let detection: Self = serde_yaml_ng::from_value(value).map_err(|e| anyhow!("{}", e))?;
+        if let Some(metadata) = &detection.metadata {
+            metadata.validate()?;
+        }
+
+        Ok(detection)
     }
 }
 
+/// Invalid files make loading fail once all of them are reported, unless `skip_invalid`
+/// is set, in which case they are returned along with the detections of valid files.
 pub fn map_plugin_detections(
     detection_id: Option<String>,
-) -> Result<HashMap<String, HashSet<DetectionState>>> {
+    variables: &Variables,
+    extensions: &[String],
+    skip_invalid: bool,
+) -> Result<(PluginDetections, Vec<InvalidDetection>)> {
     let entries: Vec<PathBuf> = if let Some(detection_id) = detection_id {
-        let detection_path = PathBuf::from(format!("{}/{}.yaml", LGC_RULES_DIR, detection_id));
-        if detection_path.is_file() {
-            vec![detection_path]
-        } else {
-            bail!("detection `{}` does not exist", detection_id)
+        match extensions
+            .iter()
+            .map(|ext| PathBuf::from(LGC_RULES_DIR).join(format!("{}.{}", detection_id, ext)))
+            .find(|path| path.is_file())
+        {
+            Some(detection_path) => vec![detection_path],
+            None => bail!("detection `{}` does not exist", detection_id),
         }
     } else {
         fs::read_dir(LGC_RULES_DIR)?
             .filter_map(|file| file.ok().map(|f| f.path()))
+            .filter(|path| path.is_file())
             .collect()
     };
 
     let plugins: DashMap<String, HashSet<DetectionState>> = DashMap::new();
+    let invalid: Mutex<Vec<InvalidDetection>> = Mutex::new(Vec::new());
 
     // Check plugin existence
     if !PathBuf::from(LGC_PLUGINS_PATH).exists() {
@@ -116,29 +325,62 @@ pub fn map_plugin_detections(
     // Map detections for each plugin
     entries
         .into_par_iter()
-        .filter_map(|path| match path.extension().and_then(|ext| ext.to_str()) {
-            Some("yml") | Some("yaml") => {
-                match Detection::pre_validate(path.display().to_string()) {
-                    Ok(detection) => Some((path, detection)),
+        .filter_map(|path| match DetectionFormat::from_path(&path, extensions) {
+            Some(format) => {
+                match Detection::pre_validate(path.display().to_string(), format, variables) {
+                    Ok(detections) => Some((path, detections)),
                     Err(e) => {
-                        tracing::error!("{e}");
+                        // Safe unwrap as the lock is never held across a panic
+                        invalid.lock().unwrap().push(InvalidDetection {
+                            path,
+                            message: e.to_string(),
+                        });
                         None
                     }
                 }
             }
-            _ => None,
+            None => {
+                // Hidden files (e.g. `.gitkeep`) are not detections
+                if !path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with('.'))
+                {
+                    tracing::warn!(
+                        "skipping `{}`: not a detection file extension ({})",
+                        workspace_path(&path),
+                        extensions.join(", ")
+                    );
+                }
+                None
+            }
+        })
+        .flat_map_iter(|(path, detections)| {
+            detections
+                .into_iter()
+                .map(move |detection| (path.clone(), detection))
         })
         .for_each(|(path, detection)| {
             detection.rules.into_iter().for_each(|(plugin, content)| {
                 if plugins_name.contains(&plugin) {
-                    if !plugins.entry(plugin).or_default().insert(DetectionState {
+                    let rule = DetectionState {
                         name: detection.name.clone(),
                         content,
-                    }) {
+                        common: detection.common.clone(),
+                        metadata: detection.metadata.clone(),
+                        remote: None,
+                        manage_fields: detection.manage_fields.clone(),
+                    };
+                    // Common rules are checked once mapped to the plugin format
+                    if rule.common.is_none() {
+                        rule.check_managed_fields();
+                    }
+
+                    if !plugins.entry(plugin).or_default().insert(rule) {
                         tracing::error!(
                             "detection duplication - {} appears again in: {}",
                             &detection.name,
-                            path.display()
+                            workspace_path(&path)
                         );
                         std::process::exit(1);
                     };
@@ -146,19 +388,204 @@ pub fn map_plugin_detections(
                     tracing::error!(
                         "referenced plugin `{}` in `{}` does not exist",
                         &plugin,
-                        path.display()
+                        workspace_path(&path)
                     )
                 }
             });
         });
 
-    Ok(plugins.into_iter().collect())
+    // Safe unwrap as every parallel task is done
+    let mut invalid = invalid.into_inner().unwrap();
+    invalid.sort_by(|a, b| a.path.cmp(&b.path));
+    if !invalid.is_empty() {
+        for detection in &invalid {
+            tracing::error!("{}", detection);
+        }
+
+        if !skip_invalid {
+            bail!(
+                "{} invalid detection file(s), fix them or use `--skip-invalid` to continue without them",
+                invalid.len()
+            )
+        }
+
+        tracing::warn!(
+            "skipping {} invalid detection file(s):\n{}",
+            invalid.len(),
+            invalid
+                .iter()
+                .map(|detection| format!("  - {}", workspace_path(&detection.path)))
+                .collect::<Vec<_>>()
+                .join("\n")
+        );
+    }
+
+    Ok((plugins.into_iter().collect(), invalid))
+}
+
+/// Look for detection files referencing plugins which are not installed.
+///
+/// Files are resolved as the loader does, with project variables, so that templates are
+/// linted on the detections they expand to.
+pub fn lint_detections(config: &ProjectConfiguration) -> Result<Vec<LintFinding>> {
+    let mut findings = Vec::new();
+
+    if !PathBuf::from(LGC_RULES_DIR).is_dir() {
+        findings.push(LintFinding::new(
+            format!("rules directory `{}` does not exist", LGC_RULES_DIR),
+            format!("create it with `mkdir {}`", LGC_RULES_DIR),
+        ));
+        return Ok(findings);
+    }
+
+    let extensions = config.core.detection_extensions()?;
+    let variables = Variables::resolve(&config.variables, &[], &[])?;
+    let mut entries: Vec<(PathBuf, DetectionFormat)> = fs::read_dir(LGC_RULES_DIR)?
+        .filter_map(|file| file.ok().map(|f| f.path()))
+        .filter_map(|path| {
+            let format = DetectionFormat::from_path(&path, &extensions)?;
+            Some((path, format))
+        })
+        .collect();
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut referenced = HashSet::new();
+    for (path, format) in entries {
+        findings.extend(lint_detection_file(
+            config,
+            &path,
+            format,
+            &variables,
+            &mut referenced,
+        ));
+    }
+
+    for name in config.plugins.keys() {
+        if !referenced.contains(name) {
+            findings.push(LintFinding::new(
+                format!("plugin `{}` has no detection in `{}`", name, LGC_RULES_DIR),
+                format!(
+                    "add a `{}` section to detections in `{}` or import rules with `lgc import -s <service>`",
+                    name, LGC_RULES_DIR
+                ),
+            ));
+        }
+    }
+
+    Ok(findings)
+}
+
+/// Findings of the detections a file resolves to, adding the plugins they reference.
+fn lint_detection_file(
+    config: &ProjectConfiguration,
+    path: &Path,
+    format: DetectionFormat,
+    variables: &Variables,
+    referenced: &mut HashSet<String>,
+) -> Vec<LintFinding> {
+    let detections = match Detection::pre_validate(path.display().to_string(), format, variables) {
+        Ok(detections) => detections,
+        Err(e) => {
+            return vec![LintFinding::new(
+                format!(
+                    "invalid detection file `{}`: {}",
+                    workspace_path(path),
+                    e
+                ),
+                String::from("run `lgc validate` for details"),
+            )]
+        }
+    };
+
+    let mut findings = Vec::new();
+    for detection in detections {
+        for plugin in detection.rules.keys() {
+            referenced.insert(plugin.clone());
+            if !config.plugins.contains_key(plugin) {
+                findings.push(LintFinding::new(
+                    format!(
+                        "detection `{}` in `{}` references plugin `{}` which is not installed",
+                        detection.name,
+                        workspace_path(path),
+                        plugin
+                    ),
+                    format!(
+                        "install the plugin with `lgc plugins install <source>` or remove the `{}` section",
+                        plugin
+                    ),
+                ));
+            }
+        }
+    }
+
+    findings
 }
 
 #[derive(Eq, Debug, Clone, Serialize, Deserialize)]
 pub struct DetectionState {
     pub name: String,
     pub content: Value,
+    /// Common rule definition, only used until mapped to the plugin format
+    #[serde(skip)]
+    pub common: Option<CommonRule>,
+    /// Detection metadata, only used until passed to plugins supporting it
+    #[serde(skip)]
+    pub metadata: Option<RuleMetadata>,
+    /// Full remote object, stored when the service keeps remote snapshots
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote: Option<Value>,
+    /// Fields managed by lgc, all fields are managed if empty
+    #[serde(skip)]
+    pub manage_fields: Vec<String>,
+}
+
+impl DetectionState {
+    /// Warn about managed fields the rule content does not define.
+    pub fn check_managed_fields(&self) {
+        if let Value::Object(content) = &self.content {
+            for field in &self.manage_fields {
+                if !content.contains_key(field) {
+                    tracing::warn!(
+                        "managed field `{}` is not defined in detection `{}`",
+                        field,
+                        self.name
+                    );
+                }
+            }
+        }
+    }
+
+    /// Restrict a content of this rule to its managed fields, so unmanaged remote
+    /// fields are neither read for diffing nor overwritten on update.
+    pub fn managed(&self, content: &Value) -> Value {
+        match content {
+            Value::Object(fields) if !self.manage_fields.is_empty() => Value::Object(
+                fields
+                    .iter()
+                    .filter(|(key, _)| self.manage_fields.contains(key))
+                    .map(|(key, value)| (key.clone(), value.clone()))
+                    .collect(),
+            ),
+            content => content.clone(),
+        }
+    }
+
+    /// Content sent to update the existing remote rule, new rules are created in full.
+    pub fn managed_content(&self) -> Value {
+        self.managed(&self.content)
+    }
+
+    /// Content of this rule from a bulk listing, restricted to its fields as `read` does.
+    pub fn from_listing(&self, listed: &str) -> Result<Value> {
+        let requested = self.managed_content();
+        Ok(match (serde_json::from_str(listed)?, &requested) {
+            (Value::Object(mut content), Value::Object(requested)) => {
+                content.retain(|key, _| requested.contains_key(key));
+                Value::Object(content)
+            }
+            (content, _) => content,
+        })
+    }
 }
 
 impl PartialEq for DetectionState {
@@ -173,48 +600,149 @@ impl Hash for DetectionState {
     }
 }
 
+/// Keep rules of detections with one of these tags.
+pub fn retain_tagged(detections: &mut PluginDetections, tags: &[String]) {
+    for rules in detections.values_mut() {
+        rules.retain(|rule| {
+            rule.metadata
+                .as_ref()
+                .is_some_and(|metadata| metadata.has_any_tag(tags))
+        });
+    }
+    detections.retain(|_, rules| !rules.is_empty());
+}
+
+/// Map detections written in the common rule format to their plugin format.
+/// Plugin specific content of these detections overrides the mapped rule.
+///
+/// Detection metadata is added to the `metadata` field of rules for plugins supporting it.
+pub async fn resolve_common_rules(
+    plugin_manager: &PluginManager,
+    detections: &mut PluginDetections,
+) -> Result<()> {
+    for (plugin_name, rules) in detections.iter_mut() {
+        if !rules
+            .iter()
+            .any(|rule| rule.common.is_some() || rule.metadata.is_some())
+        {
+            continue;
+        }
+
+        let (instance, mut store) = plugin_manager.load_plugin(plugin_name).await?;
+        let with_metadata = instance.supports(Capability::Metadata);
+        let mut resolved = HashSet::with_capacity(rules.len());
+        for mut rule in rules.drain() {
+            if let Some(common) = rule.common.take() {
+                let mapped = instance
+                    .from_common(&mut store, &serde_json::to_string(&common)?)
+                    .await?;
+                let mut content: Value = serde_json::from_str(&mapped)?;
+                merge_values(&mut content, std::mem::take(&mut rule.content));
+                rule.content = content;
+                rule.check_managed_fields();
+            }
+            if let (Some(metadata), Value::Object(content)) = (
+                rule.metadata.take().filter(|_| with_metadata),
+                &mut rule.content,
+            ) {
+                content.insert(String::from("metadata"), serde_json::to_value(metadata)?);
+            }
+            resolved.insert(rule);
+        }
+        *rules = resolved;
+    }
+
+    Ok(())
+}
+
+/// Recursively merge `overrides` into `base`, `null` overrides being ignored.
+pub fn merge_values(base: &mut Value, overrides: Value) {
+    match (base, overrides) {
+        (Value::Object(base), Value::Object(overrides)) => {
+            for (key, value) in overrides {
+                merge_values(base.entry(key).or_insert(Value::Null), value);
+            }
+        }
+        (_, Value::Null) => (),
+        (base, overrides) => *base = overrides,
+    }
+}
+
 // Return true if there is a change in detections
 pub fn compare_detections(
     detections: &PluginDetections,
     retrieved_detections: &ServiceDetections,
     services: &HashMap<String, Vec<&Service>>,
+    plugins: &BTreeMap<String, Plugin>,
     debug: bool,
 ) -> ServiceDetections {
-    let changed: DashMap<String, HashSet<DetectionState>> = DashMap::new();
-
-    detections.par_iter().for_each(|(plugin_name, rules)| {
-        if let Some(services) = services.get(plugin_name) {
-            for service in services {
-                if let Some(retrieved) = retrieved_detections.get(&service.id) {
-                    for rule in rules {
-                        if let Some(retrieved_rule) = retrieved.get(rule) {
+    let mut changed: ServiceDetections = HashMap::new();
+
+    // Plugins, services and rules are walked in order to print a stable plan
+    let mut plugin_names: Vec<&String> = detections.keys().collect();
+    plugin_names.sort();
+    for plugin_name in plugin_names {
+        let Some(services) = services.get(plugin_name) else {
+            continue;
+        };
+        let diff = plugins
+            .get(plugin_name)
+            .map(|plugin| plugin.diff.clone())
+            .unwrap_or_default();
+
+        for service in sorted_services(services) {
+            let Some(retrieved) = retrieved_detections.get(&service.id) else {
+                continue;
+            };
+
+            for rule in sorted_rules(&detections[plugin_name]) {
+                if let Some(retrieved_rule) = retrieved.get(rule) {
+                    // Unmanaged fields are left out, as recorded rules hold them too
+                    let retrieved_content = rule.managed(&retrieved_rule.content);
+                    let requested_content = rule.managed_content();
+                    if !diff.equivalent(&retrieved_content, &requested_content) {
+                        changed
+                            .entry(service.id.clone())
+                            .or_default()
+                            .insert(rule.clone());
+                        if debug {
+                            println!(
+                                "[~] rule: `{}` will be updated on `{}`:",
+                                style(&rule.name).yellow(),
+                                &service.id
+                            );
+                            // Ignored fields are left out of the displayed diff
                             let retrieved =
-                                serde_json::to_string_pretty(&retrieved_rule.content).unwrap();
-                            let requested = serde_json::to_string_pretty(&rule.content).unwrap();
-                            if retrieved != requested {
-                                changed
-                                    .entry(service.id.clone())
-                                    .and_modify(|s| {
-                                        s.insert(rule.clone());
-                                    })
-                                    .or_insert(HashSet::from([rule.clone()]));
-                                if debug {
-                                    println!(
-                                        "[~] rule: `{}` will be updated on `{}`:",
-                                        style(&rule.name).yellow(),
-                                        &service.id
-                                    );
-                                    show_diff(&retrieved, &requested);
-                                }
-                            }
+                                serde_json::to_string_pretty(&diff.normalize(&retrieved_content))
+                                    .unwrap();
+                            let requested =
+                                serde_json::to_string_pretty(&diff.normalize(&requested_content))
+                                    .unwrap();
+                            show_diff(&retrieved, &requested);
                         }
                     }
                 }
             }
         }
-    });
+    }
 
-    changed.into_iter().collect()
+    changed
+}
+
+/// Rules sorted by name, so outputs do not depend on hash ordering.
+pub fn sorted_rules<'a>(
+    rules: impl IntoIterator<Item = &'a DetectionState>,
+) -> Vec<&'a DetectionState> {
+    let mut rules: Vec<_> = rules.into_iter().collect();
+    rules.sort_by(|a, b| a.name.cmp(&b.name));
+    rules
+}
+
+/// Services sorted by ID.
+pub fn sorted_services<'a>(services: &[&'a Service]) -> Vec<&'a Service> {
+    let mut services = services.to_vec();
+    services.sort_by(|a, b| a.id.cmp(&b.id));
+    services
 }
 
 pub fn show_diff(old: &str, new: &str) {
@@ -230,3 +758,69 @@ pub fn show_diff(old: &str, new: &str) {
         }
     }
 }
+
+/// Unified diff from the remote to the local content of a rule, `None` being a missing rule.
+pub fn rule_diff(remote: Option<&Value>, local: Option<&Value>) -> Result<String> {
+    labeled_diff("remote", remote, "local", local)
+}
+
+/// Unified diff between two contents of a rule, headed by their labels.
+pub fn labeled_diff(
+    old_label: &str,
+    old: Option<&Value>,
+    new_label: &str,
+    new: Option<&Value>,
+) -> Result<String> {
+    let pretty = |content: Option<&Value>| -> Result<String> {
+        Ok(match content {
+            Some(content) => serde_json::to_string_pretty(content)? + "\n",
+            None => String::new(),
+        })
+    };
+    let (old, new) = (pretty(old)?, pretty(new)?);
+
+    Ok(TextDiff::from_lines(&old, &new)
+        .unified_diff()
+        .header(old_label, new_label)
+        .to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn lint_resolves_templated_detections() {
+        let mut file = tempfile::Builder::new().suffix(".yaml").tempfile().unwrap();
+        file.write_all(b"name: ${lint_name}\nrules:\n  ${lint_plugin}:\n    search: index=auth\n")
+            .unwrap();
+        let variables = Variables::resolve(
+            &BTreeMap::from([
+                (String::from("lint_name"), String::from("brute-force")),
+                (String::from("lint_plugin"), String::from("splunk")),
+            ]),
+            &[],
+            &[],
+        )
+        .unwrap();
+
+        let mut referenced = HashSet::new();
+        let findings = lint_detection_file(
+            &ProjectConfiguration::default(),
+            file.path(),
+            DetectionFormat::Yaml,
+            &variables,
+            &mut referenced,
+        );
+
+        assert_eq!(referenced, HashSet::from([String::from("splunk")]));
+        assert_eq!(findings.len(), 1);
+        assert!(findings[0]
+            .message
+            .starts_with("detection `brute-force` in"));
+        assert!(findings[0]
+            .message
+            .ends_with("references plugin `splunk` which is not installed"));
+    }
+}
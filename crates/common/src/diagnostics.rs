@@ -0,0 +1,323 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde_yaml_ng::Value;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fmt, fs,
+    path::Path,
+};
+
+use crate::{
+    configuration::{
+        merge_overlay, CoreConfiguration, Environment, ProjectConfiguration, Service,
+        LGC_CONFIG_PATH, LGC_OVERLAY_PATH,
+    },
+    hooks::Hooks,
+    plugins::{manager::PluginManager, Plugin, LGC_PLUGINS_PATH},
+    policies::PolicyPack,
+    sigma::SigmaMapping,
+    state::backends::StateBackend,
+    transforms::Transform,
+    variables::render_configuration,
+};
+
+/// Top-level sections of `lgc.yaml`, required ones first.
+const SECTIONS: [&str; 11] = [
+    "plugins",
+    "environments",
+    "services",
+    "state",
+    "policies",
+    "transforms",
+    "sigma",
+    "variables",
+    "core",
+    "workspaces",
+    "hooks",
+];
+const REQUIRED_SECTIONS: usize = 3;
+
+/// Problem found in a configuration file, with the line it relates to when known.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub file: String,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.file, line, self.message),
+            None => write!(f, "{}: {}", self.file, self.message),
+        }
+    }
+}
+
+/// Validation of the configuration files, collecting every problem instead of stopping at
+/// the first one.
+pub struct ConfigValidation {
+    /// Files making the configuration, `lgc.yaml` then the environment overlay
+    sources: Vec<(String, String)>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl ConfigValidation {
+    /// Read `lgc.yaml`, and the overlay of `environment` if it exists.
+    pub fn new(environment: Option<&str>) -> Result<Self> {
+        let mut sources = vec![(
+            LGC_CONFIG_PATH.to_string(),
+            fs::read_to_string(LGC_CONFIG_PATH)?,
+        )];
+        if let Some(environment) = environment {
+            let path = LGC_OVERLAY_PATH.replace("{env}", environment);
+            if Path::new(&path).is_file() {
+                let content = fs::read_to_string(&path)?;
+                sources.push((path, content));
+            }
+        }
+
+        Ok(Self {
+            sources,
+            diagnostics: Vec::new(),
+        })
+    }
+
+    /// Check the syntax and structure of the configuration, which is returned when valid.
+    pub fn check_structure(&mut self, environment: Option<&str>) -> Option<ProjectConfiguration> {
+        let mut documents = Vec::new();
+        for (file, content) in &self.sources {
+            match serde_yaml_ng::from_str::<Value>(content) {
+                Ok(document) => documents.push(document),
+                Err(e) => self.diagnostics.push(Diagnostic {
+                    file: file.clone(),
+                    line: e.location().map(|location| location.line()),
+                    message: e.to_string(),
+                }),
+            }
+        }
+        if !self.diagnostics.is_empty() {
+            return None;
+        }
+
+        let mut documents = documents.into_iter();
+        let mut config = documents.next()?;
+        for overlay in documents {
+            merge_overlay(&mut config, overlay);
+        }
+        if let Err(e) = render_configuration(&mut config, environment) {
+            self.report(None, None, format!("unable to render placeholders: {}", e));
+            return None;
+        }
+
+        let Some(sections) = config.as_mapping() else {
+            self.report(None, None, String::from("configuration must be a table"));
+            return None;
+        };
+        for name in sections.keys() {
+            let name = name.as_str().unwrap_or_default();
+            if !SECTIONS.contains(&name) {
+                self.report(
+                    Some(name),
+                    None,
+                    format!("unknown section `{}`, it is ignored", name),
+                );
+            }
+        }
+        for name in &SECTIONS[..REQUIRED_SECTIONS] {
+            if !sections.contains_key(*name) {
+                self.report(None, None, format!("missing section `{}`", name));
+            }
+        }
+
+        for (name, section) in sections {
+            match name.as_str().unwrap_or_default() {
+                "plugins" => self.check_named::<Plugin>("plugins", "plugin", section),
+                "environments" => {
+                    self.check_items::<Environment>("environments", "environment", section)
+                }
+                "services" => self.check_items::<Service>("services", "service", section),
+                "state" => self.check_section::<StateBackend>("state", section),
+                "policies" => {
+                    self.check_section::<BTreeMap<String, PolicyPack>>("policies", section)
+                }
+                "transforms" => self.check_section::<Vec<Transform>>("transforms", section),
+                "sigma" => self.check_section::<BTreeMap<String, SigmaMapping>>("sigma", section),
+                "variables" => self.check_section::<BTreeMap<String, String>>("variables", section),
+                "core" => self.check_section::<CoreConfiguration>("core", section),
+                "workspaces" => self.check_section::<BTreeSet<String>>("workspaces", section),
+                "hooks" => self.check_section::<Hooks>("hooks", section),
+                _ => (),
+            }
+        }
+
+        // Unknown sections are only warnings
+        if self
+            .diagnostics
+            .iter()
+            .any(|diagnostic| !diagnostic.message.starts_with("unknown section"))
+        {
+            return None;
+        }
+        match serde_yaml_ng::from_value(config) {
+            Ok(config) => Some(config),
+            Err(e) => {
+                self.report(None, None, e.to_string());
+                None
+            }
+        }
+    }
+
+    /// Check settings of each service against the settings schema of its plugin.
+    pub async fn check_settings(
+        &mut self,
+        config: &ProjectConfiguration,
+        plugin_manager: &PluginManager,
+    ) -> Result<()> {
+        for svc in &config.services {
+            if !config.plugins.contains_key(&svc.plugin)
+                || !Path::new(LGC_PLUGINS_PATH).join(&svc.plugin).is_file()
+            {
+                self.report(
+                    Some("services"),
+                    Some(&svc.id),
+                    format!(
+                        "service `{}`: plugin `{}` is not installed, settings cannot be checked",
+                        svc.id, svc.plugin
+                    ),
+                );
+                continue;
+            }
+
+            let schemas = match plugin_manager.plugin_schemas(&svc.plugin).await {
+                Ok(schemas) => schemas,
+                Err(e) => {
+                    self.report(
+                        Some("plugins"),
+                        Some(&svc.plugin),
+                        format!("unable to load schemas of plugin `{}`: {}", svc.plugin, e),
+                    );
+                    continue;
+                }
+            };
+            let settings = match svc.checked_settings().await {
+                Ok(settings) => settings,
+                Err(e) => {
+                    self.report(Some("services"), Some(&svc.id), e.to_string());
+                    continue;
+                }
+            };
+            if let Err(e) = svc.validate(schemas.settings, serde_json::to_string(&settings)?) {
+                self.report(
+                    Some("services"),
+                    Some(&svc.id),
+                    format!("service `{}`: invalid settings: {}", svc.id, e),
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_section<T: DeserializeOwned>(&mut self, name: &str, section: &Value) {
+        if let Err(e) = serde_yaml_ng::from_value::<T>(section.clone()) {
+            self.report(Some(name), None, format!("section `{}`: {}", name, e));
+        }
+    }
+
+    /// Check a table of items, keyed by name.
+    fn check_named<T: DeserializeOwned>(&mut self, name: &str, kind: &str, section: &Value) {
+        let Some(items) = section.as_mapping() else {
+            self.report(Some(name), None, format!("`{}` must be a table", name));
+            return;
+        };
+
+        for (key, item) in items {
+            let key = key.as_str().unwrap_or_default();
+            if let Err(e) = serde_yaml_ng::from_value::<T>(item.clone()) {
+                self.report(Some(name), Some(key), format!("{} `{}`: {}", kind, key, e));
+            }
+        }
+    }
+
+    /// Check a list of items identified by their `id`.
+    fn check_items<T: DeserializeOwned>(&mut self, name: &str, kind: &str, section: &Value) {
+        let Some(items) = section.as_sequence() else {
+            self.report(Some(name), None, format!("`{}` must be a list", name));
+            return;
+        };
+
+        let mut ids = HashSet::new();
+        for (index, item) in items.iter().enumerate() {
+            let id = item.get("id").and_then(|id| id.as_str());
+            let label = match id {
+                Some(id) => format!("{} `{}`", kind, id),
+                None => format!("{} #{}", kind, index + 1),
+            };
+            if let Some(id) = id {
+                if !ids.insert(id) {
+                    self.report(
+                        Some(name),
+                        Some(id),
+                        format!("{} is defined more than once, only one is kept", label),
+                    );
+                }
+            }
+            if let Err(e) = serde_yaml_ng::from_value::<T>(item.clone()) {
+                self.report(Some(name), id, format!("{}: {}", label, e));
+            }
+        }
+    }
+
+    fn report(&mut self, section: Option<&str>, key: Option<&str>, message: String) {
+        let (file, line) = self.locate(section, key);
+        self.diagnostics.push(Diagnostic {
+            file,
+            line,
+            message,
+        });
+    }
+
+    /// File and line defining `key` in a top-level `section`, overlays first as they win.
+    fn locate(&self, section: Option<&str>, key: Option<&str>) -> (String, Option<usize>) {
+        let Some(section) = section else {
+            return (LGC_CONFIG_PATH.to_string(), None);
+        };
+
+        for (file, content) in self.sources.iter().rev() {
+            let lines: Vec<&str> = content.lines().collect();
+            let Some(start) = lines.iter().position(|line| {
+                line.strip_prefix(section) == Some(":")
+                    || line.starts_with(&format!("{}: ", section))
+            }) else {
+                continue;
+            };
+            let Some(key) = key else {
+                return (file.clone(), Some(start + 1));
+            };
+
+            // Keys are looked for until the next top-level section
+            let found = lines[start + 1..]
+                .iter()
+                .take_while(|line| line.is_empty() || line.starts_with([' ', '-', '#']))
+                .position(|line| {
+                    let line = line.trim_start().trim_start_matches("- ").trim_start();
+                    let id = line
+                        .strip_prefix("id:")
+                        .map(|id| id.trim().trim_matches(['"', '\'']));
+                    id == Some(key)
+                        || line
+                            .strip_prefix(key)
+                            .is_some_and(|rest| rest.starts_with(':'))
+                });
+            if let Some(offset) = found {
+                return (file.clone(), Some(start + offset + 2));
+            }
+        }
+
+        (LGC_CONFIG_PATH.to_string(), None)
+    }
+}
@@ -0,0 +1,207 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Number, Value};
+
+/// Comparison of remote and local rules of a plugin, to silence differences which are not
+/// changes (e.g. fields generated by the service, booleans stored as strings).
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct DiffConfig {
+    /// Fields left out of comparisons, as JSON pointers where `*` matches any key or index
+    /// (e.g. `/etag`, `/actions/*/lastModified`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ignore: Vec<String>,
+    /// Compare strings regardless of their case
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub case_insensitive: bool,
+    /// Compare strings with the booleans and numbers they represent (e.g. `"1"`, `"true"`
+    /// and `true`, or `"60"` and `60`)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub coerce: bool,
+}
+
+impl DiffConfig {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Content without its ignored fields, as compared and displayed.
+    pub fn normalize(&self, content: &Value) -> Value {
+        let mut content = content.clone();
+        for pointer in &self.ignore {
+            let segments: Vec<String> = pointer
+                .split('/')
+                .skip(1)
+                .map(|segment| segment.replace("~1", "/").replace("~0", "~"))
+                .collect();
+            remove_path(&mut content, &segments);
+        }
+        content
+    }
+
+    /// Whether remote and local contents only differ by ignored fields or representations.
+    pub fn equivalent(&self, remote: &Value, local: &Value) -> bool {
+        self.values_equal(&self.normalize(remote), &self.normalize(local))
+    }
+
+    /// Values are compared as they are first, representations are only coerced between
+    /// values of different types.
+    fn values_equal(&self, remote: &Value, local: &Value) -> bool {
+        if remote == local {
+            return true;
+        }
+
+        match (remote, local) {
+            (Value::Object(remote), Value::Object(local)) => {
+                remote.len() == local.len()
+                    && remote.iter().all(|(key, value)| {
+                        local
+                            .get(key)
+                            .is_some_and(|local| self.values_equal(value, local))
+                    })
+            }
+            (Value::Array(remote), Value::Array(local)) => {
+                remote.len() == local.len()
+                    && remote
+                        .iter()
+                        .zip(local)
+                        .all(|(remote, local)| self.values_equal(remote, local))
+            }
+            (Value::String(remote), Value::String(local)) if self.case_insensitive => {
+                remote.to_lowercase() == local.to_lowercase()
+            }
+            (Value::String(text), other) | (other, Value::String(text))
+                if self.coerce && !other.is_string() =>
+            {
+                coerced_equal(text, other)
+            }
+            (Value::Number(remote), Value::Number(local)) => numbers_equal(remote, local),
+            _ => false,
+        }
+    }
+}
+
+/// Whether a string represents a boolean or a number.
+fn coerced_equal(text: &str, value: &Value) -> bool {
+    let text = text.trim();
+    match value {
+        Value::Bool(value) => match text.to_lowercase().as_str() {
+            "true" | "1" | "yes" => *value,
+            "false" | "0" | "no" => !*value,
+            _ => false,
+        },
+        Value::Number(value) => text
+            .parse::<Number>()
+            .is_ok_and(|text| numbers_equal(&text, value)),
+        _ => false,
+    }
+}
+
+/// Whether numbers are equal, integers and floats being compared as floats (e.g. `60` and
+/// `60.0`) so that integers of the same type keep their precision.
+fn numbers_equal(a: &Number, b: &Number) -> bool {
+    a == b || (a.is_f64() != b.is_f64() && a.as_f64() == b.as_f64())
+}
+
+/// Remove the fields matching a pointer, `*` segments matching any key or index.
+fn remove_path(value: &mut Value, segments: &[String]) {
+    let Some((segment, rest)) = segments.split_first() else {
+        return;
+    };
+
+    match value {
+        Value::Object(fields) => {
+            if rest.is_empty() {
+                if segment == "*" {
+                    fields.clear();
+                } else {
+                    fields.remove(segment);
+                }
+            } else if segment == "*" {
+                fields
+                    .values_mut()
+                    .for_each(|field| remove_path(field, rest));
+            } else if let Some(field) = fields.get_mut(segment) {
+                remove_path(field, rest);
+            }
+        }
+        Value::Array(items) => {
+            if segment == "*" {
+                if rest.is_empty() {
+                    items.clear();
+                } else {
+                    items.iter_mut().for_each(|item| remove_path(item, rest));
+                }
+            } else if let Ok(index) = segment.parse::<usize>() {
+                if rest.is_empty() {
+                    if index < items.len() {
+                        items.remove(index);
+                    }
+                } else if let Some(item) = items.get_mut(index) {
+                    remove_path(item, rest);
+                }
+            }
+        }
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn config(case_insensitive: bool, coerce: bool) -> DiffConfig {
+        DiffConfig {
+            case_insensitive,
+            coerce,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn raw_values_compare_first() {
+        let coerce = config(false, true);
+        assert!(coerce.equivalent(&json!(" 60"), &json!(" 60")));
+        assert!(!coerce.equivalent(&json!(" 60"), &json!("60")));
+        assert!(!coerce.equivalent(&json!("yes"), &json!("true")));
+    }
+
+    #[test]
+    fn large_integers_keep_their_precision() {
+        let diff = config(false, false);
+        assert!(!diff.equivalent(&json!(9007199254740993u64), &json!(9007199254740992u64)));
+        assert!(diff.equivalent(&json!(60), &json!(60.0)));
+    }
+
+    #[test]
+    fn coercion_applies_across_types_only() {
+        let coerce = config(false, true);
+        assert!(coerce.equivalent(&json!({"enabled": "1"}), &json!({"enabled": true})));
+        assert!(coerce.equivalent(&json!(["no"]), &json!([false])));
+        assert!(coerce.equivalent(&json!("60"), &json!(60)));
+        assert!(!coerce.equivalent(&json!("60s"), &json!(60)));
+        assert!(!coerce.equivalent(&json!("9007199254740993"), &json!(9007199254740992u64)));
+        assert!(!config(false, false).equivalent(&json!("60"), &json!(60)));
+    }
+
+    #[test]
+    fn case_insensitive_strings() {
+        assert!(config(true, false).equivalent(&json!("Splunk"), &json!("splunk")));
+        assert!(!config(false, false).equivalent(&json!("Splunk"), &json!("splunk")));
+    }
+
+    #[test]
+    fn ignored_fields() {
+        let diff = DiffConfig {
+            ignore: vec!["/etag".to_string(), "/actions/*/updated".to_string()],
+            ..Default::default()
+        };
+        assert!(diff.equivalent(
+            &json!({"etag": "a", "actions": [{"name": "x", "updated": 1}]}),
+            &json!({"actions": [{"name": "x"}]})
+        ));
+        assert!(!diff.equivalent(&json!({"name": "x"}), &json!({"name": "y"})));
+    }
+}
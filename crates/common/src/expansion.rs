@@ -0,0 +1,142 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{anyhow, bail, Result};
+use minijinja::{context, Environment, UndefinedBehavior, Value};
+use std::{fs, path::Path};
+
+use crate::{detections::DetectionFormat, variables::Variables};
+
+/// Top-level key of detection templates, naming the values file they are expanded with.
+const FOR_EACH_KEY: &str = "for_each:";
+/// Top-level key of detections, listing the fragments they extend.
+const EXTENDS_KEY: &str = "extends";
+
+/// Expand a detection template into one detection per item of its `for_each` values file.
+///
+/// Templates are rendered with minijinja before being parsed, each item being available as
+/// `item` and its position as `index`. Contents without `for_each` are returned as is.
+pub fn expand_detection(content: String) -> Result<Vec<String>> {
+    let Some((line, values_path)) = content.lines().enumerate().find_map(|(line, text)| {
+        text.strip_prefix(FOR_EACH_KEY)
+            .map(|path| (line, path.trim().trim_matches(['"', '\'']).to_string()))
+    }) else {
+        return Ok(vec![content]);
+    };
+
+    // The key is not part of the detection schema
+    let template = content
+        .lines()
+        .enumerate()
+        .filter(|(index, _)| *index != line)
+        .map(|(_, text)| text)
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let values = fs::read_to_string(&values_path)
+        .map_err(|e| anyhow!("unable to read values file `{}`: {}", values_path, e))?;
+    let items: Vec<serde_yaml_ng::Value> = serde_yaml_ng::from_str(&values).map_err(|e| {
+        anyhow!(
+            "invalid values file `{}`, expected a list: {}",
+            values_path,
+            e
+        )
+    })?;
+
+    // Missing values fail instead of rendering empty strings
+    let mut env = Environment::new();
+    env.set_undefined_behavior(UndefinedBehavior::Strict);
+
+    items
+        .iter()
+        .enumerate()
+        .map(|(index, item)| {
+            env.render_str(
+                &template,
+                context! { item => Value::from_serialize(item), index => index },
+            )
+            .map_err(|e| {
+                anyhow!(
+                    "unable to expand item #{} of `{}`: {}",
+                    index + 1,
+                    values_path,
+                    e
+                )
+            })
+        })
+        .collect()
+}
+
+/// Merge the fragments listed in the `extends` key of a detection under it, the detection
+/// overriding them. Returns whether the detection extends fragments.
+pub fn extend_detection(
+    detection: &mut serde_yaml_ng::Value,
+    variables: &Variables,
+) -> Result<bool> {
+    let Some(extends) = detection
+        .as_mapping_mut()
+        .and_then(|detection| detection.remove(EXTENDS_KEY))
+    else {
+        return Ok(false);
+    };
+    let paths: Vec<String> = match extends {
+        serde_yaml_ng::Value::String(path) => vec![path],
+        extends => serde_yaml_ng::from_value(extends)
+            .map_err(|_| anyhow!("`extends` must be a fragment path or a list of them"))?,
+    };
+
+    // Fragments are merged in order, later ones overriding earlier ones
+    let mut extended = serde_yaml_ng::Value::Mapping(Default::default());
+    for path in &paths {
+        merge_fragment(&mut extended, load_fragment(path, variables)?);
+    }
+    merge_fragment(&mut extended, std::mem::take(detection));
+    *detection = extended;
+
+    Ok(true)
+}
+
+/// Merge a fragment over a detection. Tables are merged recursively, other values, lists
+/// included, are replaced: unlike configuration overlays, items with an `id` are not merged.
+fn merge_fragment(base: &mut serde_yaml_ng::Value, overlay: serde_yaml_ng::Value) {
+    match (base, overlay) {
+        (serde_yaml_ng::Value::Mapping(base), serde_yaml_ng::Value::Mapping(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(field) => merge_fragment(field, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+fn load_fragment(path: &str, variables: &Variables) -> Result<serde_yaml_ng::Value> {
+    let format = Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(DetectionFormat::from_extension)
+        .ok_or_else(|| {
+            anyhow!(
+                "unsupported fragment `{}`, expected a YAML, JSON or TOML file",
+                path
+            )
+        })?;
+    let content = fs::read_to_string(path)
+        .map_err(|e| anyhow!("unable to read fragment `{}`: {}", path, e))?;
+    let fragment = format
+        .parse(&variables.substitute(content)?)
+        .map_err(|e| anyhow!("invalid fragment `{}`: {}", path, e))?;
+
+    if !fragment.is_mapping() {
+        bail!("fragment `{}` must be a table", path)
+    }
+    if fragment.get(EXTENDS_KEY).is_some() {
+        bail!("fragment `{}` cannot extend other fragments", path)
+    }
+
+    Ok(fragment)
+}
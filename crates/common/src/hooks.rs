@@ -0,0 +1,99 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{fmt, process::Stdio};
+use tokio::io::AsyncWriteExt;
+
+use crate::utils::shell_command;
+
+/// Environment variable holding the event of a running hook.
+pub const LGC_HOOK_ENV: &str = "LGC_HOOK";
+
+/// Commands run around deployments, e.g. to open a change ticket or notify a channel.
+///
+/// Commands run through the shell from the project root, with a JSON context on stdin.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Hooks {
+    /// Run once changes are approved, deployments are aborted when it fails
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pre_apply: Option<String>,
+    /// Run once every change is deployed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_apply: Option<String>,
+    /// Run when deploying changes failed, partially or entirely
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_failure: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HookEvent {
+    PreApply,
+    PostApply,
+    OnFailure,
+}
+
+impl HookEvent {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::PreApply => "pre_apply",
+            Self::PostApply => "post_apply",
+            Self::OnFailure => "on_failure",
+        }
+    }
+}
+
+impl fmt::Display for HookEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl Hooks {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    fn command(&self, event: HookEvent) -> Option<&str> {
+        match event {
+            HookEvent::PreApply => self.pre_apply.as_deref(),
+            HookEvent::PostApply => self.post_apply.as_deref(),
+            HookEvent::OnFailure => self.on_failure.as_deref(),
+        }
+    }
+
+    /// Run the hook of `event` if configured, failing if it exits with an error.
+    ///
+    /// `context` is written on the hook stdin as a JSON object, with the `event` field added.
+    pub async fn run(&self, event: HookEvent, mut context: Value) -> Result<()> {
+        let Some(command) = self.command(event) else {
+            return Ok(());
+        };
+        if let Value::Object(context) = &mut context {
+            context.insert("event".into(), event.name().into());
+        }
+
+        // Hooks output goes to stderr, stdout is kept for results
+        let mut child = shell_command(command)
+            .env(LGC_HOOK_ENV, event.name())
+            .stdin(Stdio::piped())
+            .stdout(std::io::stderr())
+            .spawn()
+            .map_err(|e| anyhow!("unable to run {} hook `{}`: {}", event, command, e))?;
+
+        if let Some(mut stdin) = child.stdin.take() {
+            // Hooks are free to ignore their context
+            let _ = stdin.write_all(&serde_json::to_vec(&context)?).await;
+        }
+
+        let status = child.wait().await?;
+        if !status.success() {
+            bail!("{} hook `{}` failed: {}", event, command, status)
+        }
+
+        Ok(())
+    }
+}
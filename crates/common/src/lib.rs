@@ -4,6 +4,21 @@
 //LogCraft common library
 pub mod configuration;
 pub mod detections;
+pub mod diagnostics;
+pub mod diff;
+pub mod expansion;
+pub mod hooks;
+pub mod limits;
+pub mod naming;
+pub mod notifications;
+pub mod plan;
 pub mod plugins;
+pub mod policies;
+pub mod report;
+pub mod secrets;
+pub mod sigma;
 pub mod state;
+pub mod telemetry;
+pub mod transforms;
 pub mod utils;
+pub mod variables;
@@ -0,0 +1,166 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::time::{Duration, Instant};
+
+use crate::{configuration::DEFAULT_OPERATION_TIMEOUT, plugins::PluginError};
+
+/// Number of plugins, and reads per plugin, processed concurrently by default.
+pub const DEFAULT_PARALLELISM: usize = 4;
+/// Delay before the first retry of a failed operation by default.
+pub const DEFAULT_RETRY_BACKOFF: Duration = Duration::from_secs(2);
+/// Number of rules requested per page by bulk listings by default.
+pub const DEFAULT_LIST_PAGE_SIZE: u32 = 100;
+
+/// Operational limits of the project, read by every command as defaults.
+///
+/// CLI flags override these values, services `timeout` overrides the default timeout.
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// Number of plugins loaded and deployed, and remote reads run per plugin, concurrently
+    pub parallelism: Option<usize>,
+    /// Time allowed to each remote operation (e.g. `30s`)
+    pub timeout: Option<String>,
+    /// Number of retries of operations failing with errors plugins report as retryable
+    pub retries: Option<usize>,
+    /// Delay before the first retry, doubled on each retry (e.g. `2s`)
+    pub retry_backoff: Option<String>,
+    /// HTTP status codes retried even when plugins do not report errors as retryable,
+    /// `429` and `5xx` by default
+    pub retry_on: Option<Vec<u16>>,
+    /// Maximum number of remote operations per second on a service
+    pub rate_limit: Option<u32>,
+    /// Number of rules requested per page by bulk listings, bounding the memory of each page
+    pub list_page_size: Option<u32>,
+}
+
+impl Limits {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Override configured values with the ones which are set in `other`.
+    pub fn merge(&self, other: &Limits) -> Limits {
+        Limits {
+            parallelism: other.parallelism.or(self.parallelism),
+            timeout: other.timeout.clone().or_else(|| self.timeout.clone()),
+            retries: other.retries.or(self.retries),
+            retry_backoff: other
+                .retry_backoff
+                .clone()
+                .or_else(|| self.retry_backoff.clone()),
+            retry_on: other.retry_on.clone().or_else(|| self.retry_on.clone()),
+            rate_limit: other.rate_limit.or(self.rate_limit),
+            list_page_size: other.list_page_size.or(self.list_page_size),
+        }
+    }
+
+    pub fn parallelism(&self) -> Result<usize> {
+        match self.parallelism.unwrap_or(DEFAULT_PARALLELISM) {
+            0 => bail!("parallelism must be greater than 0"),
+            parallelism => Ok(parallelism),
+        }
+    }
+
+    pub fn timeout(&self) -> Result<Duration> {
+        match &self.timeout {
+            Some(timeout) => humantime::parse_duration(timeout)
+                .map_err(|e| anyhow!("invalid timeout `{}`: {}", timeout, e)),
+            None => Ok(DEFAULT_OPERATION_TIMEOUT),
+        }
+    }
+
+    pub fn list_page_size(&self) -> Result<u32> {
+        match self.list_page_size.unwrap_or(DEFAULT_LIST_PAGE_SIZE) {
+            0 => bail!("list page size must be greater than 0"),
+            size => Ok(size),
+        }
+    }
+
+    pub fn retries(&self) -> usize {
+        self.retries.unwrap_or_default()
+    }
+
+    /// Whether a failed operation may succeed if retried: plugins reported it as retryable,
+    /// or the remote service answered with one of the `retry_on` status codes.
+    pub fn is_retryable(&self, error: &anyhow::Error) -> bool {
+        let Some(error) = error.downcast_ref::<PluginError>() else {
+            return false;
+        };
+
+        error.retryable
+            || error
+                .status_code
+                .is_some_and(|status| match &self.retry_on {
+                    Some(codes) => codes.contains(&status),
+                    None => status == 429 || (500..600).contains(&status),
+                })
+    }
+
+    /// Delay before the `attempt`-th retry.
+    pub fn retry_backoff(&self, attempt: usize) -> Result<Duration> {
+        let backoff = match &self.retry_backoff {
+            Some(backoff) => humantime::parse_duration(backoff)
+                .map_err(|e| anyhow!("invalid retry backoff `{}`: {}", backoff, e))?,
+            None => DEFAULT_RETRY_BACKOFF,
+        };
+
+        Ok(backoff.saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1) as u32)))
+    }
+
+    pub fn rate_limiter(&self) -> RateLimiter {
+        RateLimiter {
+            interval: self
+                .rate_limit
+                .filter(|rate| *rate > 0)
+                .map(|rate| Duration::from_secs(1) / rate),
+            last: None,
+        }
+    }
+}
+
+/// Retries of the operations of a service, overriding the project limits.
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Number of retries of operations failing with retryable errors
+    pub retries: Option<usize>,
+    /// Delay before the first retry, doubled on each retry (e.g. `2s`)
+    pub backoff: Option<String>,
+    /// HTTP status codes retried even when plugins do not report errors as retryable
+    pub retry_on: Option<Vec<u16>>,
+}
+
+impl RetryPolicy {
+    pub fn limits(&self) -> Limits {
+        Limits {
+            retries: self.retries,
+            retry_backoff: self.backoff.clone(),
+            retry_on: self.retry_on.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Spaces out remote operations to stay under a rate limit.
+pub struct RateLimiter {
+    interval: Option<Duration>,
+    last: Option<Instant>,
+}
+
+impl RateLimiter {
+    /// Wait until the next operation is allowed.
+    pub async fn wait(&mut self) {
+        if let (Some(interval), Some(last)) = (self.interval, self.last) {
+            let elapsed = last.elapsed();
+            if elapsed < interval {
+                tokio::time::sleep(interval - elapsed).await;
+            }
+        }
+        self.last = Some(Instant::now());
+    }
+}
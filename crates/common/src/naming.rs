@@ -0,0 +1,72 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{bail, Result};
+
+use crate::configuration::Service;
+
+/// Placeholders replaced by the rule name in naming templates.
+const NAME_PLACEHOLDERS: [&str; 2] = ["{name}", "{title}"];
+
+/// Naming convention of the rules deployed on a service (e.g. `[{env}] {name} - LGC`).
+///
+/// Rules keep their local name in detections and state, only remote calls use the remote name.
+/// Besides `{name}` (or `{title}`), templates may use `{env}`, `{service}` and `{plugin}`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NamingTemplate {
+    prefix: String,
+    suffix: String,
+}
+
+impl NamingTemplate {
+    pub fn new(template: &str, env: Option<&str>, service: &Service) -> Result<Self> {
+        let (placeholder, position) = match NAME_PLACEHOLDERS
+            .iter()
+            .flat_map(|placeholder| {
+                template
+                    .match_indices(placeholder)
+                    .map(move |(position, _)| (*placeholder, position))
+            })
+            .collect::<Vec<_>>()[..]
+        {
+            [found] => found,
+            _ => bail!(
+                "naming template `{}` must contain `{{name}}` exactly once",
+                template
+            ),
+        };
+
+        let render = |part: &str| -> Result<String> {
+            if part.contains("{env}") && env.is_none() {
+                bail!(
+                    "naming template `{}` uses `{{env}}` but service `{}` is not part of any environment",
+                    template,
+                    service.id
+                )
+            }
+            Ok(part
+                .replace("{env}", env.unwrap_or_default())
+                .replace("{service}", &service.id)
+                .replace("{plugin}", &service.plugin))
+        };
+
+        Ok(Self {
+            prefix: render(&template[..position])?,
+            suffix: render(&template[position + placeholder.len()..])?,
+        })
+    }
+
+    /// Name of a rule on the remote service.
+    pub fn apply(&self, name: &str) -> String {
+        format!("{}{}{}", self.prefix, name, self.suffix)
+    }
+
+    /// Local name of a remote rule, `None` if the rule does not follow the convention.
+    pub fn reverse(&self, remote: &str) -> Option<String> {
+        remote
+            .strip_prefix(&self.prefix)
+            .and_then(|name| name.strip_suffix(&self.suffix))
+            .filter(|name| !name.is_empty())
+            .map(String::from)
+    }
+}
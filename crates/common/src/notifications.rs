@@ -0,0 +1,313 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{anyhow, bail, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{collections::BTreeMap, env, time::Duration};
+
+use crate::{
+    configuration::{ProjectConfiguration, Service},
+    report::{Action, Outcome, RunReport},
+    secrets::{is_sensitive, resolve_reference, resolve_secrets, REDACTED},
+};
+
+/// Environment variable holding a link to the run (e.g. the CI job), added to summaries.
+pub const LGC_RUN_URL_ENV: &str = "LGC_RUN_URL";
+
+/// Time allowed to each notification attempt.
+const NOTIFICATION_TIMEOUT: Duration = Duration::from_secs(10);
+/// Attempts made to deliver a notification.
+const NOTIFICATION_ATTEMPTS: u32 = 3;
+/// Failures listed in text notifications, the others are counted.
+const LISTED_FAILURES: usize = 20;
+
+/// Destination of run summaries.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Notifier {
+    /// Slack incoming webhook
+    Slack {
+        webhook: String,
+        #[serde(default)]
+        when: NotifyWhen,
+    },
+    /// Microsoft Teams incoming webhook
+    Teams {
+        webhook: String,
+        #[serde(default)]
+        when: NotifyWhen,
+    },
+    /// Address receiving the summary as JSON
+    Http {
+        url: String,
+        /// Headers sent with summaries (e.g. authentication)
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        headers: BTreeMap<String, String>,
+        #[serde(default)]
+        when: NotifyWhen,
+    },
+}
+
+/// Runs reported by a notifier.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifyWhen {
+    #[default]
+    Always,
+    /// Only runs with failed operations
+    Failure,
+}
+
+/// Summary of the operations of a deploy or destroy run.
+#[derive(Serialize, Clone, Debug)]
+pub struct RunSummary {
+    /// Command which ran, e.g. `deploy`
+    pub command: String,
+    pub environment: Option<String>,
+    pub workspace: String,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    /// Operations which ran, by action (e.g. `update`)
+    pub actions: BTreeMap<String, usize>,
+    pub failures: Vec<Failure>,
+    /// Link to the run, from `LGC_RUN_URL`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub run_url: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Failure {
+    pub service: String,
+    pub rule: String,
+    pub action: Action,
+    pub error: String,
+}
+
+impl RunSummary {
+    pub fn new(
+        command: &str,
+        environment: Option<&str>,
+        workspace: &str,
+        report: &RunReport,
+    ) -> Self {
+        let mut summary = Self {
+            command: command.to_string(),
+            environment: environment.map(str::to_string),
+            workspace: workspace.to_string(),
+            succeeded: 0,
+            failed: 0,
+            skipped: 0,
+            actions: BTreeMap::new(),
+            failures: Vec::new(),
+            run_url: env::var(LGC_RUN_URL_ENV).ok().filter(|url| !url.is_empty()),
+        };
+
+        for operation in &report.operations {
+            *summary
+                .actions
+                .entry(operation.action.to_string())
+                .or_default() += 1;
+            match &operation.outcome {
+                Outcome::Succeeded => summary.succeeded += 1,
+                Outcome::Skipped(_) => summary.skipped += 1,
+                Outcome::Failed(error) => {
+                    summary.failed += 1;
+                    summary.failures.push(Failure {
+                        service: operation.service.clone(),
+                        rule: operation.rule.clone(),
+                        action: operation.action,
+                        error: error.clone(),
+                    });
+                }
+            }
+        }
+
+        summary
+    }
+
+    /// Replace secrets of `services` settings in failure messages, which may quote requests.
+    pub async fn redact(&mut self, services: &[&Service]) -> Result<()> {
+        let mut secrets = Vec::new();
+        for svc in services {
+            let resolved = resolve_secrets(&svc.settings).await?;
+            for (name, value) in &resolved {
+                let referenced = svc.settings.get(name) != Some(value);
+                if referenced || is_sensitive(name) {
+                    collect_strings(value, &mut secrets);
+                }
+            }
+            // Credential helpers print short-lived credentials only
+            for value in svc.helper_settings().await?.values() {
+                collect_strings(value, &mut secrets);
+            }
+        }
+        // Longest first, so that secrets containing others are fully redacted
+        secrets.retain(|secret| secret.len() >= 4);
+        secrets.sort_by_key(|secret| std::cmp::Reverse(secret.len()));
+
+        // Credentials of URLs and bearer tokens, whatever their origin
+        let patterns = [
+            (Regex::new(r"(://[^/\s:@]+:)[^/\s@]+@")?, "${1}***@"),
+            (
+                Regex::new(r"(?i)(bearer\s+)[A-Za-z0-9\-._~+/]+=*")?,
+                "${1}***",
+            ),
+        ];
+        for failure in &mut self.failures {
+            for secret in &secrets {
+                failure.error = failure.error.replace(secret.as_str(), REDACTED);
+            }
+            for (pattern, replacement) in &patterns {
+                failure.error = pattern
+                    .replace_all(&failure.error, *replacement)
+                    .to_string();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Summary formatted for chat messages.
+    pub fn text(&self) -> String {
+        let target = match &self.environment {
+            Some(environment) => format!(" on `{}`", environment),
+            None => String::new(),
+        };
+        let mut text = format!(
+            "lgc {}{} (workspace `{}`): {} succeeded, {} failed, {} skipped",
+            self.command, target, self.workspace, self.succeeded, self.failed, self.skipped
+        );
+
+        for failure in self.failures.iter().take(LISTED_FAILURES) {
+            text.push_str(&format!(
+                "\n- {} of `{}` on `{}` failed: {}",
+                failure.action, failure.rule, failure.service, failure.error
+            ));
+        }
+        if self.failures.len() > LISTED_FAILURES {
+            text.push_str(&format!(
+                "\n- and {} more failure(s)",
+                self.failures.len() - LISTED_FAILURES
+            ));
+        }
+        if let Some(url) = &self.run_url {
+            text.push_str(&format!("\n{}", url));
+        }
+
+        text
+    }
+}
+
+impl Notifier {
+    fn when(&self) -> NotifyWhen {
+        match self {
+            Self::Slack { when, .. } | Self::Teams { when, .. } | Self::Http { when, .. } => *when,
+        }
+    }
+
+    /// Send a summary, retrying failed deliveries.
+    pub async fn notify(&self, summary: &RunSummary) -> Result<()> {
+        if self.when() == NotifyWhen::Failure && summary.failed == 0 {
+            return Ok(());
+        }
+
+        let (url, body, headers) = match self {
+            Self::Slack { webhook, .. } => (webhook, json!({ "text": summary.text() }), None),
+            Self::Teams { webhook, .. } => (
+                webhook,
+                json!({
+                    "@type": "MessageCard",
+                    "@context": "https://schema.org/extensions",
+                    "summary": format!("lgc {}", summary.command),
+                    "text": summary.text().replace('\n', "\n\n"),
+                }),
+                None,
+            ),
+            Self::Http { url, headers, .. } => (url, serde_json::to_value(summary)?, Some(headers)),
+        };
+
+        // Webhooks hold credentials, they can be secret references
+        let url = resolve_reference(url).await?.unwrap_or_else(|| url.clone());
+        let client = reqwest::Client::builder()
+            .timeout(NOTIFICATION_TIMEOUT)
+            .build()?;
+        let mut attempt = 1;
+        loop {
+            match post(&client, &url, &body, headers).await {
+                Err(e) if attempt < NOTIFICATION_ATTEMPTS => {
+                    tracing::debug!("retrying notification: {}", e);
+                    tokio::time::sleep(Duration::from_secs(u64::from(attempt))).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+async fn post(
+    client: &reqwest::Client,
+    url: &str,
+    body: &Value,
+    headers: Option<&BTreeMap<String, String>>,
+) -> Result<()> {
+    let mut request = client.post(url).json(body);
+    for (name, value) in headers.into_iter().flatten() {
+        request = request.header(name, value);
+    }
+
+    let response = request
+        .send()
+        .await
+        .map_err(|e| anyhow!("notification webhook unreachable: {}", e.without_url()))?;
+    if !response.status().is_success() {
+        bail!("notification webhook returned {}", response.status())
+    }
+    Ok(())
+}
+
+/// Send the summary of a run to every notifier of the project, failures are only reported
+/// as changes are done by then.
+pub async fn notify_run(
+    config: &ProjectConfiguration,
+    command: &str,
+    environment: Option<&str>,
+    report: &RunReport,
+) {
+    let notifiers = &config.core.notifications;
+    if notifiers.is_empty() || report.operations.is_empty() {
+        return;
+    }
+
+    let workspace = config.workspace().unwrap_or_default();
+    let mut summary = RunSummary::new(command, environment, &workspace, report);
+    let services: Vec<&Service> = config
+        .services
+        .iter()
+        .filter(|svc| report.operations.iter().any(|op| op.service == svc.id))
+        .collect();
+    if let Err(e) = summary.redact(&services).await {
+        tracing::warn!("unable to redact notifications, none is sent: {}", e);
+        return;
+    }
+
+    for notifier in notifiers {
+        if let Err(e) = notifier.notify(&summary).await {
+            tracing::warn!("unable to send notification: {}", e);
+        }
+    }
+}
+
+fn collect_strings(value: &Value, strings: &mut Vec<String>) {
+    match value {
+        Value::String(string) => strings.push(string.clone()),
+        Value::Array(items) => items.iter().for_each(|item| collect_strings(item, strings)),
+        Value::Object(fields) => fields
+            .values()
+            .for_each(|field| collect_strings(field, strings)),
+        _ => (),
+    }
+}
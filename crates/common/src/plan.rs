@@ -0,0 +1,106 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, path::Path};
+use uuid::Uuid;
+
+use crate::{detections::DetectionState, report::Action, state::State, utils::workspace_path};
+
+/// Version of the plan file format.
+const LGC_PLAN_VERSION: usize = 1;
+
+/// Changes computed by `lgc diff --out`, applied as is by `lgc deploy --plan`.
+#[derive(Serialize, Deserialize)]
+pub struct Plan {
+    version: usize,
+    /// Version of LogCraft CLI which computed the plan
+    lgc_version: String,
+    /// Lineage of the state the plan was computed against
+    lineage: Uuid,
+    /// Serial of the state the plan was computed against
+    serial: usize,
+    /// Operations by service ID, in execution order
+    pub services: BTreeMap<String, Vec<PlannedOperation>>,
+}
+
+/// Operation on a remote service, with the rule content to send.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PlannedOperation {
+    pub action: Action,
+    pub rule: DetectionState,
+}
+
+impl Plan {
+    pub fn new(state: &State) -> Self {
+        Self {
+            version: LGC_PLAN_VERSION,
+            lgc_version: env!("CARGO_PKG_VERSION").to_string(),
+            lineage: state.lineage(),
+            serial: state.serial(),
+            services: BTreeMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, service_id: &str, action: Action, rule: &DetectionState) {
+        // Managed fields are not saved, updates are planned with the content they send
+        let mut rule = rule.clone();
+        if action == Action::Update {
+            rule.content = rule.managed_content();
+        }
+        self.services
+            .entry(service_id.to_string())
+            .or_default()
+            .push(PlannedOperation { action, rule });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.services.values().all(Vec::is_empty)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)
+            .map_err(|e| anyhow!("unable to write plan `{}`: {}", workspace_path(path), e))
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| anyhow!("unable to read plan `{}`: {}", workspace_path(path), e))?;
+        let plan: Self = serde_json::from_str(&content)
+            .map_err(|e| anyhow!("invalid plan `{}`: {}", workspace_path(path), e))?;
+
+        if plan.version != LGC_PLAN_VERSION {
+            bail!(
+                "unsupported plan version `{}`, compute it again with `lgc diff --out`",
+                plan.version
+            )
+        }
+        if plan
+            .services
+            .values()
+            .flatten()
+            .any(|operation| operation.action == Action::Read)
+        {
+            bail!(
+                "invalid plan `{}`: only create, update and delete operations can be planned",
+                workspace_path(path)
+            )
+        }
+
+        Ok(plan)
+    }
+
+    /// Refuse to apply a plan once the state it was computed against changed.
+    pub fn check_state(&self, state: &State) -> Result<()> {
+        if self.lineage != state.lineage() || self.serial != state.serial() {
+            bail!(
+                "state changed since the plan was computed (serial `{}`, now `{}`), compute it again with `lgc diff --out`",
+                self.serial,
+                state.serial()
+            )
+        }
+
+        Ok(())
+    }
+}
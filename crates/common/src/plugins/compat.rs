@@ -0,0 +1,253 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{bail, Result};
+use lgc_runtime::{
+    compat::{v0_1, v0_2},
+    plugin_component::plugin::{Bundle, Metadata, RulePage, ServiceStatus, TailPage},
+    state::State,
+};
+use wasmtime::{
+    component::{Component, Linker},
+    Store,
+};
+
+use super::{interface_major_minor, PluginError, PLUGIN_INTERFACE_VERSION};
+
+/// Plugin interface versions, as `(major, minor)`, still driven by lgc.
+pub const COMPAT_INTERFACE_VERSIONS: [(u64, u64); 2] = [(0, 1), (0, 2)];
+
+/// Component built against a previous plugin interface version.
+///
+/// Calls are mapped to the current interface, functions added since fail as if the plugin did
+/// not advertise them.
+pub enum CompatPlugin {
+    V0_1(v0_1::Plugins),
+    V0_2(v0_2::Plugins),
+}
+
+impl CompatPlugin {
+    /// Instantiate a component exporting the `version` plugin interface.
+    pub async fn instantiate(
+        version: &str,
+        store: &mut Store<State>,
+        component: &Component,
+        linker: &Linker<State>,
+    ) -> Result<Self> {
+        Ok(match interface_major_minor(version) {
+            Some((0, 1)) => {
+                Self::V0_1(v0_1::Plugins::instantiate_async(store, component, linker).await?)
+            }
+            Some((0, 2)) => {
+                Self::V0_2(v0_2::Plugins::instantiate_async(store, component, linker).await?)
+            }
+            _ => bail!("plugin interface `{}` is not supported", version),
+        })
+    }
+
+    /// Metadata of the plugin, plugins of `0.1` advertise no optional function.
+    pub async fn load(&self, store: &mut Store<State>) -> Result<Metadata> {
+        Ok(match self {
+            Self::V0_1(interface) => {
+                let metadata = interface.logcraft_lgc_plugin().call_load(store).await?;
+                Metadata {
+                    name: metadata.name,
+                    version: metadata.version,
+                    author: metadata.author,
+                    description: metadata.description,
+                    interface_version: String::from("0.1.0"),
+                    capabilities: Vec::new(),
+                }
+            }
+            Self::V0_2(interface) => {
+                let metadata = interface.logcraft_lgc_plugin().call_load(store).await?;
+                Metadata {
+                    name: metadata.name,
+                    version: metadata.version,
+                    author: metadata.author,
+                    description: metadata.description,
+                    interface_version: metadata.interface_version,
+                    capabilities: metadata.capabilities,
+                }
+            }
+        })
+    }
+
+    pub async fn settings(&self, store: &mut Store<State>) -> Result<String> {
+        match self {
+            Self::V0_1(interface) => interface.logcraft_lgc_plugin().call_settings(store).await,
+            Self::V0_2(interface) => interface.logcraft_lgc_plugin().call_settings(store).await,
+        }
+    }
+
+    pub async fn schema(&self, store: &mut Store<State>) -> Result<String> {
+        match self {
+            Self::V0_1(interface) => interface.logcraft_lgc_plugin().call_schema(store).await,
+            Self::V0_2(interface) => interface.logcraft_lgc_plugin().call_schema(store).await,
+        }
+    }
+
+    pub async fn from_common(&self, store: &mut Store<State>, rule: &str) -> Result<String> {
+        match self {
+            Self::V0_2(interface) => interface
+                .logcraft_lgc_plugin()
+                .call_from_common(store, rule)
+                .await?
+                .map_err(|e| PluginError::from(e).into()),
+            _ => self.missing("from-common"),
+        }
+    }
+
+    /// Create, read, update or delete a rule, `method` being the function name.
+    pub async fn rule(
+        &self,
+        method: &str,
+        store: &mut Store<State>,
+        config: &str,
+        name: &str,
+        params: &str,
+    ) -> Result<Option<String>> {
+        match self {
+            Self::V0_1(interface) => {
+                let plugin = interface.logcraft_lgc_plugin();
+                match method {
+                    "create" => plugin.call_create(store, config, name, params).await?,
+                    "read" => plugin.call_read(store, config, name, params).await?,
+                    "update" => plugin.call_update(store, config, name, params).await?,
+                    "delete" => plugin.call_delete(store, config, name, params).await?,
+                    _ => return self.missing(method),
+                }
+                .map_err(|message| legacy_error(message).into())
+            }
+            Self::V0_2(interface) => {
+                let plugin = interface.logcraft_lgc_plugin();
+                match method {
+                    "create" => plugin.call_create(store, config, name, params).await?,
+                    "read" => plugin.call_read(store, config, name, params).await?,
+                    "read-remote" => plugin.call_read_remote(store, config, name, params).await?,
+                    "update" => plugin.call_update(store, config, name, params).await?,
+                    "delete" => plugin.call_delete(store, config, name, params).await?,
+                    _ => return self.missing(method),
+                }
+                .map_err(|e| PluginError::from(e).into())
+            }
+        }
+    }
+
+    pub async fn list_rules(
+        &self,
+        store: &mut Store<State>,
+        config: &str,
+        cursor: Option<&str>,
+        count: u32,
+    ) -> Result<RulePage> {
+        match self {
+            Self::V0_2(interface) => interface
+                .logcraft_lgc_plugin()
+                .call_list_rules(store, config, cursor, count)
+                .await?
+                .map(|page| RulePage {
+                    rules: page.rules,
+                    next: page.next,
+                })
+                .map_err(|e| PluginError::from(e).into()),
+            _ => self.missing("list-rules"),
+        }
+    }
+
+    pub async fn tail(
+        &self,
+        store: &mut Store<State>,
+        config: &str,
+        name: &str,
+        params: &str,
+        cursor: Option<&str>,
+    ) -> Result<TailPage> {
+        match self {
+            Self::V0_2(interface) => interface
+                .logcraft_lgc_plugin()
+                .call_tail(store, config, name, params, cursor)
+                .await?
+                .map(|page| TailPage {
+                    events: page.events,
+                    next: page.next,
+                })
+                .map_err(|e| PluginError::from(e).into()),
+            _ => self.missing("tail"),
+        }
+    }
+
+    pub async fn template(
+        &self,
+        store: &mut Store<State>,
+        config: &str,
+        rules: &[(String, String)],
+    ) -> Result<Bundle> {
+        match self {
+            Self::V0_2(interface) => interface
+                .logcraft_lgc_plugin()
+                .call_template(store, config, rules)
+                .await?
+                .map(|bundle| Bundle {
+                    file_name: bundle.file_name,
+                    content: bundle.content,
+                })
+                .map_err(|e| PluginError::from(e).into()),
+            _ => self.missing("template"),
+        }
+    }
+
+    pub async fn ping(&self, store: &mut Store<State>, config: &str) -> Result<ServiceStatus> {
+        match self {
+            // Only reachability was reported before `0.2`
+            Self::V0_1(interface) => interface
+                .logcraft_lgc_plugin()
+                .call_ping(store, config)
+                .await?
+                .map(|alive| ServiceStatus {
+                    alive,
+                    writable: None,
+                    api_version: None,
+                    license: None,
+                    remaining_quota: None,
+                })
+                .map_err(|message| legacy_error(message).into()),
+            Self::V0_2(interface) => interface
+                .logcraft_lgc_plugin()
+                .call_ping(store, config)
+                .await?
+                .map(|status| ServiceStatus {
+                    alive: status.alive,
+                    writable: status.writable,
+                    api_version: status.api_version,
+                    license: status.license,
+                    remaining_quota: status.remaining_quota,
+                })
+                .map_err(|e| PluginError::from(e).into()),
+        }
+    }
+
+    /// Error of a function the plugin interface version does not define.
+    pub fn missing<T>(&self, method: &str) -> Result<T> {
+        let version = match self {
+            Self::V0_1(_) => "0.1",
+            Self::V0_2(_) => "0.2",
+        };
+        bail!(
+            "`{}` is not part of plugin interface `{}`, rebuild the plugin against `{}`",
+            method,
+            version,
+            PLUGIN_INTERFACE_VERSION
+        )
+    }
+}
+
+/// Plugins before `0.2` reported errors as plain messages.
+fn legacy_error(message: String) -> PluginError {
+    PluginError {
+        status_code: None,
+        message,
+        endpoint: None,
+        retryable: false,
+    }
+}
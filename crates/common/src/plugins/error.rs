@@ -0,0 +1,79 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use lgc_runtime::{compat::v0_2, plugin_component::plugin};
+use std::fmt;
+
+use super::grpc;
+
+/// Error reported by a plugin call, whatever the plugin transport.
+///
+/// Returned inside `anyhow::Error`, use `PluginError::is_retryable` or downcast it to inspect it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PluginError {
+    /// HTTP status code returned by the remote service
+    pub status_code: Option<u16>,
+    pub message: String,
+    /// Remote endpoint which failed
+    pub endpoint: Option<String>,
+    /// Whether the operation may succeed if retried
+    pub retryable: bool,
+}
+
+impl PluginError {
+    /// Whether an error comes from a plugin which reported it as retryable.
+    pub fn is_retryable(error: &anyhow::Error) -> bool {
+        error
+            .downcast_ref::<PluginError>()
+            .is_some_and(|error| error.retryable)
+    }
+}
+
+impl fmt::Display for PluginError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        match (self.status_code, &self.endpoint) {
+            (Some(status), Some(endpoint)) => write!(f, " (status {} on `{}`)", status, endpoint),
+            (Some(status), None) => write!(f, " (status {})", status),
+            (None, Some(endpoint)) => write!(f, " (on `{}`)", endpoint),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+impl std::error::Error for PluginError {}
+
+impl From<plugin::PluginError> for PluginError {
+    fn from(error: plugin::PluginError) -> Self {
+        Self {
+            status_code: error.status_code,
+            message: error.message,
+            endpoint: error.endpoint,
+            retryable: error.retryable,
+        }
+    }
+}
+
+impl From<v0_2::exports::logcraft::lgc::plugin::PluginError> for PluginError {
+    fn from(error: v0_2::exports::logcraft::lgc::plugin::PluginError) -> Self {
+        Self {
+            status_code: error.status_code,
+            message: error.message,
+            endpoint: error.endpoint,
+            retryable: error.retryable,
+        }
+    }
+}
+
+impl From<grpc::PluginErrorDetails> for PluginError {
+    fn from(error: grpc::PluginErrorDetails) -> Self {
+        Self {
+            status_code: error
+                .status_code
+                .and_then(|status| u16::try_from(status).ok()),
+            message: error.message,
+            endpoint: error.endpoint,
+            retryable: error.retryable,
+        }
+    }
+}
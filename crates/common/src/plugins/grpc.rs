@@ -0,0 +1,398 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+//! Out of process plugins, see `proto/plugin.proto` for the protocol.
+
+use anyhow::{anyhow, bail, Result};
+use lgc_runtime::plugin_component::plugin::Metadata;
+use prost::Message;
+use std::{path::Path, process::Stdio, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    process::{Child, Command},
+};
+use tonic::{
+    codec::ProstCodec,
+    codegen::http::uri::PathAndQuery,
+    transport::{Channel, Endpoint},
+    Code,
+};
+
+use super::PluginError;
+
+/// Time allowed to a plugin process to print its handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Handshake protocol versions supported.
+const CORE_PROTOCOL_VERSION: &str = "1";
+const APP_PROTOCOL_VERSION: &str = "1";
+
+/// First bytes of every wasm binary.
+const WASM_MAGIC: &[u8] = b"\0asm";
+
+/// Wasm binaries are loaded in process, anything else is started as a gRPC plugin.
+pub fn is_wasm(bytes: &[u8]) -> bool {
+    bytes.starts_with(WASM_MAGIC)
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Empty {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Text {
+    #[prost(string, tag = "1")]
+    pub value: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PluginMetadata {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub version: String,
+    #[prost(string, tag = "3")]
+    pub author: String,
+    #[prost(string, tag = "4")]
+    pub description: String,
+    #[prost(string, tag = "5")]
+    pub interface_version: String,
+    #[prost(string, repeated, tag = "6")]
+    pub capabilities: Vec<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RuleRequest {
+    #[prost(string, tag = "1")]
+    pub config: String,
+    #[prost(string, tag = "2")]
+    pub name: String,
+    #[prost(string, tag = "3")]
+    pub params: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RuleResponse {
+    #[prost(string, optional, tag = "1")]
+    pub content: Option<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListRulesRequest {
+    #[prost(string, tag = "1")]
+    pub config: String,
+    #[prost(string, optional, tag = "2")]
+    pub cursor: Option<String>,
+    #[prost(uint32, tag = "3")]
+    pub count: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Rule {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub content: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RulePage {
+    #[prost(message, repeated, tag = "1")]
+    pub rules: Vec<Rule>,
+    #[prost(string, optional, tag = "2")]
+    pub next: Option<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TailRequest {
+    #[prost(message, optional, tag = "1")]
+    pub rule: Option<RuleRequest>,
+    #[prost(string, optional, tag = "2")]
+    pub cursor: Option<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TailPage {
+    #[prost(string, repeated, tag = "1")]
+    pub events: Vec<String>,
+    #[prost(string, optional, tag = "2")]
+    pub next: Option<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TemplateRequest {
+    #[prost(string, tag = "1")]
+    pub config: String,
+    #[prost(message, repeated, tag = "2")]
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Bundle {
+    #[prost(string, tag = "1")]
+    pub file_name: String,
+    #[prost(string, tag = "2")]
+    pub content: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct CheckRequest {
+    #[prost(string, optional, tag = "1")]
+    pub config: Option<String>,
+    #[prost(string, tag = "2")]
+    pub name: String,
+    #[prost(string, tag = "3")]
+    pub params: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct CheckResponse {
+    #[prost(string, repeated, tag = "1")]
+    pub problems: Vec<String>,
+}
+
+/// Encoded in gRPC status details, see `PluginError`.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PluginErrorDetails {
+    #[prost(uint32, optional, tag = "1")]
+    pub status_code: Option<u32>,
+    #[prost(string, tag = "2")]
+    pub message: String,
+    #[prost(string, optional, tag = "3")]
+    pub endpoint: Option<String>,
+    #[prost(bool, tag = "4")]
+    pub retryable: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PingRequest {
+    #[prost(string, tag = "1")]
+    pub config: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PingResponse {
+    #[prost(bool, tag = "1")]
+    pub alive: bool,
+    #[prost(bool, optional, tag = "2")]
+    pub writable: Option<bool>,
+    #[prost(string, optional, tag = "3")]
+    pub api_version: Option<String>,
+    #[prost(string, optional, tag = "4")]
+    pub license: Option<String>,
+    #[prost(uint64, optional, tag = "5")]
+    pub remaining_quota: Option<u64>,
+}
+
+/// Running plugin process and its gRPC channel.
+pub struct GrpcPlugin {
+    channel: Channel,
+    // Killed when the plugin is dropped
+    _process: Child,
+}
+
+impl GrpcPlugin {
+    /// Start the plugin executable and connect to the address it advertises.
+    pub async fn start(path: &Path) -> Result<Self> {
+        let mut process = Command::new(path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| anyhow!("unable to start plugin `{}`: {}", path.display(), e))?;
+
+        // Safe unwrap as stdout is piped
+        let mut stdout = BufReader::new(process.stdout.take().unwrap());
+        let mut handshake = String::new();
+        tokio::time::timeout(HANDSHAKE_TIMEOUT, stdout.read_line(&mut handshake))
+            .await
+            .map_err(|_| anyhow!("plugin `{}` handshake timed out", path.display()))??;
+
+        let address = parse_handshake(handshake.trim())
+            .map_err(|e| anyhow!("invalid handshake from plugin `{}`: {}", path.display(), e))?;
+
+        let channel = Endpoint::from_shared(format!("http://{}", address))?
+            .connect()
+            .await
+            .map_err(|e| anyhow!("unable to connect to plugin `{}`: {}", path.display(), e))?;
+
+        Ok(Self {
+            channel,
+            _process: process,
+        })
+    }
+
+    pub async fn load(&self) -> Result<Metadata> {
+        let metadata: PluginMetadata = self.call("Load", Empty {}).await?;
+        Ok(Metadata {
+            name: metadata.name,
+            version: metadata.version,
+            author: metadata.author,
+            description: metadata.description,
+            interface_version: metadata.interface_version,
+            capabilities: metadata.capabilities,
+        })
+    }
+
+    pub async fn settings(&self) -> Result<String> {
+        Ok(self.call::<_, Text>("Settings", Empty {}).await?.value)
+    }
+
+    pub async fn schema(&self) -> Result<String> {
+        Ok(self.call::<_, Text>("Schema", Empty {}).await?.value)
+    }
+
+    pub async fn from_common(&self, rule: &str) -> Result<String> {
+        let request = Text {
+            value: rule.to_string(),
+        };
+        Ok(self.call::<_, Text>("FromCommon", request).await?.value)
+    }
+
+    /// Call one of the rule CRUD methods.
+    pub async fn rule(
+        &self,
+        method: &'static str,
+        config: &str,
+        name: &str,
+        params: &str,
+    ) -> Result<Option<String>> {
+        let request = RuleRequest {
+            config: config.to_string(),
+            name: name.to_string(),
+            params: params.to_string(),
+        };
+        Ok(self.call::<_, RuleResponse>(method, request).await?.content)
+    }
+
+    pub async fn list_rules(
+        &self,
+        config: &str,
+        cursor: Option<&str>,
+        count: u32,
+    ) -> Result<RulePage> {
+        let request = ListRulesRequest {
+            config: config.to_string(),
+            cursor: cursor.map(str::to_string),
+            count,
+        };
+        self.call("ListRules", request).await
+    }
+
+    pub async fn tail(
+        &self,
+        config: &str,
+        name: &str,
+        params: &str,
+        cursor: Option<&str>,
+    ) -> Result<TailPage> {
+        let request = TailRequest {
+            rule: Some(RuleRequest {
+                config: config.to_string(),
+                name: name.to_string(),
+                params: params.to_string(),
+            }),
+            cursor: cursor.map(str::to_string),
+        };
+        self.call("Tail", request).await
+    }
+
+    pub async fn template(&self, config: &str, rules: &[(String, String)]) -> Result<Bundle> {
+        let request = TemplateRequest {
+            config: config.to_string(),
+            rules: rules
+                .iter()
+                .map(|(name, content)| Rule {
+                    name: name.clone(),
+                    content: content.clone(),
+                })
+                .collect(),
+        };
+        self.call("Template", request).await
+    }
+
+    pub async fn check(
+        &self,
+        config: Option<&str>,
+        name: &str,
+        params: &str,
+    ) -> Result<CheckResponse> {
+        let request = CheckRequest {
+            config: config.map(str::to_string),
+            name: name.to_string(),
+            params: params.to_string(),
+        };
+        self.call("Check", request).await
+    }
+
+    pub async fn ping(&self, config: &str) -> Result<PingResponse> {
+        let request = PingRequest {
+            config: config.to_string(),
+        };
+        self.call("Ping", request).await
+    }
+
+    async fn call<Req, Resp>(&self, method: &'static str, request: Req) -> Result<Resp>
+    where
+        Req: prost::Message + Send + Sync + 'static,
+        Resp: prost::Message + Default + Send + Sync + 'static,
+    {
+        let mut client = tonic::client::Grpc::new(self.channel.clone());
+        client
+            .ready()
+            .await
+            .map_err(|e| anyhow!("plugin is not ready: {}", e))?;
+
+        let path = PathAndQuery::try_from(format!("/logcraft.lgc.plugin.Plugin/{}", method))?;
+        let response = client
+            .unary(
+                tonic::Request::new(request),
+                path,
+                ProstCodec::<Req, Resp>::default(),
+            )
+            .await
+            .map_err(status_error)?;
+
+        Ok(response.into_inner())
+    }
+}
+
+/// Plugin error from a gRPC status, using its details when the plugin provided them.
+fn status_error(status: tonic::Status) -> PluginError {
+    match PluginErrorDetails::decode(status.details()) {
+        Ok(details) if !details.message.is_empty() => details.into(),
+        _ => PluginError {
+            status_code: None,
+            message: status.message().to_string(),
+            endpoint: None,
+            retryable: matches!(
+                status.code(),
+                Code::Unavailable | Code::ResourceExhausted | Code::Aborted
+            ),
+        },
+    }
+}
+
+/// Extract the plugin address from a `core|app|network|address|protocol` handshake line.
+fn parse_handshake(line: &str) -> Result<String> {
+    let parts: Vec<&str> = line.split('|').collect();
+    let [core, app, network, address, protocol] = parts.as_slice() else {
+        bail!(
+            "expected `core|app|network|address|protocol`, got `{}`",
+            line
+        )
+    };
+
+    if *core != CORE_PROTOCOL_VERSION || *app != APP_PROTOCOL_VERSION {
+        bail!("unsupported protocol version `{}|{}`", core, app)
+    }
+
+    if *protocol != "grpc" {
+        bail!("unsupported protocol `{}`", protocol)
+    }
+
+    match *network {
+        "tcp" => Ok(address.to_string()),
+        _ => bail!("unsupported network `{}`", network),
+    }
+}
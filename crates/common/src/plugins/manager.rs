@@ -4,102 +4,528 @@
 use anyhow::{anyhow, bail, Result};
 use async_trait::async_trait;
 use lgc_runtime::{
-    plugin_component::plugin::Metadata, state::State, Config, Engine, Plugins,
-    DEFAULT_EPOCH_TICK_INTERVAL,
+    cache::digest,
+    exported_interface_version,
+    network::Network,
+    plugin_component::plugin::{Bundle, Metadata, RulePage, ServiceStatus, TailPage},
+    state::{is_filesystem_denial, State},
+    Cache, Config, Engine, Plugins, DEFAULT_EPOCH_TICK_INTERVAL,
 };
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     fmt, fs,
     io::Write,
     path::{Path, PathBuf},
     time::Duration,
 };
 use tempfile::NamedTempFile;
-use wasmtime::{component::Component, Store};
+use tokio::sync::Semaphore;
+use wasmtime::{Store, Trap};
 
-use crate::plugins::cleanup_plugin;
+use crate::configuration::schema_fields;
+use crate::detections::DetectionState;
+use crate::limits::Limits;
+use crate::naming::NamingTemplate;
+use crate::plugins::{
+    check_interface_version, cleanup_plugin,
+    compat::CompatPlugin,
+    grpc::{is_wasm, GrpcPlugin},
+    trust::{PluginTrust, SIGNATURE_EXTENSION},
+    Capability, FilesystemGrant, InterfaceCompatibility, Plugin, PluginError, PluginSandbox,
+    PluginTransport, PLUGIN_INTERFACE_VERSION,
+};
+use crate::utils::ensure_kebab_case;
 
 use super::LGC_PLUGINS_PATH;
 
+/// Time allowed to wasm execution for each plugin call.
+const CALL_EPOCH_DEADLINE: Duration = Duration::from_secs(60);
+
+/// Reset the wasm execution deadline before a plugin call, from the plugin sandbox.
+fn set_call_deadline(store: &mut Store<State>) {
+    let deadline = store
+        .data()
+        .sandbox
+        .call_timeout
+        .unwrap_or(CALL_EPOCH_DEADLINE);
+    store.set_epoch_deadline(
+        (deadline.as_micros() / DEFAULT_EPOCH_TICK_INTERVAL.as_micros()) as u64,
+    );
+}
+
 pub struct InstanceData {
-    interface: Plugins,
+    transport: Transport,
     pub metadata: Metadata,
+    /// Whether the plugin was granted filesystem access
+    filesystem: bool,
+}
+
+/// How the plugin is reached.
+enum Transport {
+    /// Component running in the embedded wasm runtime
+    Wasm(Plugins),
+    /// Component built against a previous plugin interface
+    Compat(CompatPlugin),
+    /// Native executable reached through gRPC
+    Grpc(GrpcPlugin),
+}
+
+/// KCL schemas of a plugin, cached by digest to spare instantiating it for prompts and validation.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PluginSchemas {
+    /// Service settings, defined by the `Configuration` schema
+    pub settings: String,
+    /// Rules, defined by the `Rule` schema
+    pub rule: String,
 }
 
 #[derive(Clone)]
 pub struct PluginManager {
     engine: Engine,
+    cache: Cache,
+    /// Filesystem capabilities granted per plugin name
+    filesystem: BTreeMap<String, Vec<FilesystemGrant>>,
+    /// Resource limits per plugin name
+    sandboxes: BTreeMap<String, PluginSandbox>,
+    /// Plugins configured with the `grpc` transport, the only ones run as native executables
+    native: BTreeSet<String>,
+    /// Proxy and certificates of the host, shared by every plugin
+    network: Network,
 }
 
 impl PluginManager {
     pub fn new() -> Result<Self> {
         // Setup wasmtime
-        let mut config = Config::default();
-        if let Err(e) = config.enable_cache(&None) {
-            tracing::warn!(err = ?e, "failed to load wasm cache");
-            bail!("{e}")
+        let config = Config::default();
+        let engine = Engine::builder(&config)?.build();
+
+        // Compiled components are shared between projects
+        let cache = match Cache::new(None) {
+            Ok(cache) => cache,
+            Err(e) => {
+                tracing::warn!(err = ?e, "failed to load wasm cache");
+                bail!("{e}")
+            }
         };
 
-        let engine = Engine::builder(&config)?.build();
+        Ok(Self {
+            engine,
+            cache,
+            filesystem: BTreeMap::new(),
+            sandboxes: BTreeMap::new(),
+            native: BTreeSet::new(),
+            network: Network::from_env()?,
+        })
+    }
 
-        Ok(Self { engine })
+    /// Apply capabilities granted to plugins in the project configuration.
+    pub fn with_capabilities(mut self, plugins: &BTreeMap<String, Plugin>) -> Self {
+        self.filesystem = plugins
+            .iter()
+            .filter(|(_, plugin)| !plugin.filesystem.is_empty())
+            .map(|(name, plugin)| (name.clone(), plugin.filesystem.clone()))
+            .collect();
+        self.sandboxes = plugins
+            .iter()
+            .filter(|(_, plugin)| !plugin.sandbox.is_empty())
+            .map(|(name, plugin)| (name.clone(), plugin.sandbox.clone()))
+            .collect();
+        self.native = plugins
+            .iter()
+            .filter(|(_, plugin)| plugin.transport == PluginTransport::Grpc)
+            .map(|(name, _)| name.clone())
+            .collect();
+        self
     }
 
-    pub async fn install_plugin(&self, location: &PluginLocation) -> Result<Metadata> {
+    /// Install the plugin at `location`, once verified against `trust` with its `signature`
+    /// (`<plugin>.minisig` next to the plugin by default). Native executables are only
+    /// installed with the `grpc` transport.
+    pub async fn install_plugin(
+        &mut self,
+        location: &PluginLocation,
+        trust: &PluginTrust,
+        signature: Option<&Path>,
+        transport: PluginTransport,
+    ) -> Result<Metadata> {
         // Create and load plugin in temporary file
         let mut file = NamedTempFile::new()?;
-        file.write_all(&location.load().await?)?;
+        let bytes = location.load().await?;
+        // Verify before anything runs, native plugins are executables
+        if !trust.is_empty() {
+            let signature = match signature {
+                Some(signature) => signature.to_path_buf(),
+                None => location.signature(),
+            };
+            trust
+                .verify(&bytes, &signature)
+                .map_err(|e| anyhow!("unable to verify plugin signature: {}", e))?;
+        }
+        file.write_all(&bytes)?;
+        // Close the file, native plugins cannot be executed while opened for writing
+        let file = file.into_temp_path();
+
+        // Native plugins are started as executables
+        let path: &Path = &file;
+        if transport == PluginTransport::Grpc {
+            #[cfg(unix)]
+            if !is_wasm(&bytes) {
+                use std::os::unix::fs::PermissionsExt;
+                fs::set_permissions(&file, fs::Permissions::from_mode(0o755))?;
+            }
+            self.native.insert(path.to_string_lossy().to_string());
+        }
+
         // Instanciate plugin
-        let path = file.path();
-        let (instance, _) = self.load_plugin(&path).await?;
+        let (instance, _) = self.load_plugin(path).await?;
+        // The name locates the plugin, it must not escape the plugins directory
+        ensure_kebab_case(&instance.metadata.name)
+            .map_err(|e| anyhow!("invalid plugin name in metadata: {}", e))?;
+        if transport == PluginTransport::Grpc {
+            self.native.insert(instance.metadata.name.clone());
+        }
         // Check if plugin directory exists
         let plugin_path = PathBuf::from(LGC_PLUGINS_PATH);
         if !plugin_path.exists() {
             fs::create_dir_all(&plugin_path)?;
         }
 
+        // Keep the installed version until the new one passes the smoke test
+        let name = &instance.metadata.name;
+        let installed = plugin_path.join(name);
+        let backup = plugin_path.join(format!("{}.bak", name));
+        if installed.exists() {
+            fs::rename(&installed, &backup)?;
+        }
+
         // Copying file to avoid cross-device link error
-        if let Err(e) = fs::copy(path, plugin_path.join(&instance.metadata.name)) {
-            cleanup_plugin(&instance.metadata.name)?;
+        if let Err(e) = fs::copy(path, &installed) {
+            rollback_plugin(name, &backup)?;
             bail!("failed to move loaded plugin to plugins directory: {}", e);
         };
         fs::remove_file(path)?;
 
+        if let Err(e) = self.smoke_test(name).await {
+            rollback_plugin(name, &backup)?;
+            bail!(
+                "plugin `{}` failed its smoke test, installation rolled back: {}",
+                name,
+                e
+            );
+        }
+        if backup.exists() {
+            fs::remove_file(&backup)?;
+        }
+
         Ok(instance.metadata)
     }
 
+    /// Check that an installed plugin loads and exposes valid metadata and schemas,
+    /// caching the schemas for later commands.
+    async fn smoke_test(&self, name: &str) -> Result<()> {
+        let (instance, mut store) = self.load_plugin(name).await?;
+        let metadata = &instance.metadata;
+        ensure_kebab_case(&metadata.name)?;
+        if metadata.version.is_empty() {
+            bail!("missing version in metadata")
+        }
+
+        let schemas = PluginSchemas {
+            settings: instance.settings(&mut store).await?,
+            rule: instance.schema(&mut store).await?,
+        };
+        for (code, schema) in [
+            (&schemas.settings, "Configuration"),
+            (&schemas.rule, "Rule"),
+        ] {
+            if schema_fields(code, schema)
+                .map_err(|e| anyhow!("invalid `{}` schema: {}", schema, e))?
+                .is_empty()
+            {
+                tracing::warn!("plugin `{}` defines no `{}` field", name, schema);
+            }
+        }
+
+        self.cache_schemas(name, &schemas);
+        Ok(())
+    }
+
+    /// Schemas of an installed plugin, only instantiating it when they are not cached yet.
+    pub async fn plugin_schemas(&self, name: &str) -> Result<PluginSchemas> {
+        let path = PathBuf::from(LGC_PLUGINS_PATH).join(name);
+        let bytes =
+            fs::read(&path).map_err(|e| anyhow!("unable to read plugin `{}`: {}", name, e))?;
+        if let Some(schemas) = self
+            .cache
+            .load_schemas(&digest(&bytes))
+            .and_then(|schemas| serde_json::from_str(&schemas).ok())
+        {
+            return Ok(schemas);
+        }
+
+        let (instance, mut store) = self.load_plugin(name).await?;
+        let schemas = PluginSchemas {
+            settings: instance.settings(&mut store).await?,
+            rule: instance.schema(&mut store).await?,
+        };
+        self.cache_schemas(name, &schemas);
+        Ok(schemas)
+    }
+
+    /// Cache the schemas of an installed plugin, failures only cost a later instantiation.
+    fn cache_schemas(&self, name: &str, schemas: &PluginSchemas) {
+        let cached = fs::read(PathBuf::from(LGC_PLUGINS_PATH).join(name))
+            .map_err(anyhow::Error::from)
+            .and_then(|bytes| {
+                self.cache
+                    .store_schemas(&digest(&bytes), &serde_json::to_string(schemas)?)
+            });
+        if let Err(e) = cached {
+            tracing::warn!("unable to cache schemas of plugin `{}`: {}", name, e);
+        }
+    }
+
+    /// Load a plugin, failing if it targets an incompatible plugin interface.
     pub async fn load_plugin(
         &self,
         path: impl AsRef<Path>,
     ) -> Result<(InstanceData, Store<State>)> {
-        // Load the component
+        let (mut instance, store) = self.load_unchecked(path).await?;
+        check_interface_version(&mut instance.metadata)?;
+        Ok((instance, store))
+    }
+
+    /// Load a plugin whatever the interface it targets, only its metadata can be trusted
+    /// before its interface version is checked.
+    #[tracing::instrument(name = "plugin.load", skip_all, fields(plugin = %path.as_ref().display()))]
+    pub async fn load_unchecked(
+        &self,
+        path: impl AsRef<Path>,
+    ) -> Result<(InstanceData, Store<State>)> {
+        let name = path.as_ref().to_string_lossy().to_string();
+        let grants = self
+            .filesystem
+            .get(&name)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let sandbox = self.sandboxes.get(&name).cloned().unwrap_or_default();
         let path = PathBuf::from(LGC_PLUGINS_PATH).join(path);
-        let component = Component::from_file(&self.engine.inner, path)?;
+        let bytes = fs::read(&path)?;
 
-        let mut store = wasmtime::Store::new(&self.engine.inner, State::default());
+        // Native plugins run in their own process, the store stays unused
+        let native = self.native.contains(&name);
+        match (is_wasm(&bytes), native) {
+            (false, false) => bail!(
+                "plugin `{}` is a native executable, only run unsandboxed with `transport: grpc` (`lgc plugins install --grpc`)",
+                name
+            ),
+            (true, true) => bail!(
+                "plugin `{}` is a wasm component, remove `transport: grpc` from its configuration",
+                name
+            ),
+            _ => (),
+        }
+        if native {
+            if !grants.is_empty() {
+                tracing::warn!(
+                    "plugin `{}` runs natively, filesystem capabilities are not enforced",
+                    name
+                );
+            }
+            if !sandbox.is_empty() {
+                tracing::warn!(
+                    "plugin `{}` runs natively, sandbox limits are not enforced",
+                    name
+                );
+            }
+
+            let store = wasmtime::Store::new(&self.engine.inner, State::default());
+            let plugin = GrpcPlugin::start(&path).await?;
+            let metadata = plugin.load().await?;
+            return Ok((
+                InstanceData {
+                    transport: Transport::Grpc(plugin),
+                    metadata,
+                    filesystem: true,
+                },
+                store,
+            ));
+        }
 
-        // TODO: Check for better value
-        let deadline = Duration::from_secs(60);
-        store.set_epoch_deadline(
-            (deadline.as_micros() / DEFAULT_EPOCH_TICK_INTERVAL.as_micros()) as u64,
+        // Wasm plugins only see the directories granted to them
+        let preopens = grants
+            .iter()
+            .map(FilesystemGrant::preopen)
+            .collect::<Result<Vec<_>>>()
+            .map_err(|e| anyhow!("plugin `{}`: {}", name, e))?;
+        let sandbox = sandbox
+            .sandbox()
+            .map_err(|e| anyhow!("plugin `{}`: {}", name, e))?;
+        let mut store = wasmtime::Store::new(
+            &self.engine.inner,
+            State::with_preopens(&preopens)?
+                .with_sandbox(sandbox)
+                .with_network(self.network.clone()),
         );
+        store.limiter(|state| &mut state.limits);
 
-        let interface =
-            Plugins::instantiate_async(&mut store, &component, &self.engine.linker).await?;
+        // Load the component
+        let component = self.cache.load_component(&self.engine.inner, &bytes)?;
+
+        set_call_deadline(&mut store);
 
-        let metadata = interface
-            .logcraft_lgc_plugin()
-            .call_load(&mut store)
-            .await?;
+        // Components of previous plugin interfaces are driven through their own bindings
+        let version = exported_interface_version(&bytes)?.unwrap_or_default();
+        let (transport, metadata) = match InterfaceCompatibility::of(&version) {
+            InterfaceCompatibility::Older => {
+                let plugin = CompatPlugin::instantiate(
+                    &version,
+                    &mut store,
+                    &component,
+                    &self.engine.linker,
+                )
+                .await?;
+                let metadata = plugin.load(&mut store).await?;
+                (Transport::Compat(plugin), metadata)
+            }
+            InterfaceCompatibility::Incompatible => bail!(
+                "plugin `{}` targets plugin interface `{}`, lgc implements `{}`",
+                name,
+                version,
+                PLUGIN_INTERFACE_VERSION
+            ),
+            _ => {
+                let interface =
+                    Plugins::instantiate_async(&mut store, &component, &self.engine.linker).await?;
+                let metadata = interface
+                    .logcraft_lgc_plugin()
+                    .call_load(&mut store)
+                    .await?;
+                (Transport::Wasm(interface), metadata)
+            }
+        };
 
         Ok((
             InstanceData {
-                interface,
-                metadata: metadata.clone(),
+                transport,
+                metadata,
+                filesystem: !preopens.is_empty(),
             },
             store,
         ))
     }
+
+    /// Load `size` instances of a plugin to run calls concurrently, each with its own store.
+    /// Native plugins get a single instance, their process handles concurrency on its own.
+    pub async fn load_plugin_pool(
+        &self,
+        path: impl AsRef<Path>,
+        size: usize,
+    ) -> Result<Vec<(InstanceData, Store<State>)>> {
+        let mut pool = vec![self.load_plugin(path.as_ref()).await?];
+        if matches!(
+            pool[0].0.transport,
+            Transport::Wasm(_) | Transport::Compat(_)
+        ) {
+            for _ in 1..size {
+                pool.push(self.load_plugin(path.as_ref()).await?);
+            }
+        }
+        Ok(pool)
+    }
+}
+
+/// Read rules concurrently, spreading them over `instance` and additional `readers` of the
+/// same plugin, each read holding one of the `permits`. Results are in the order of `rules`,
+/// read under their `naming` remote name, a failed or timed out read only failing its own
+/// result once retried within `limits`.
+#[allow(clippy::too_many_arguments)]
+pub async fn read_rules(
+    instance: &InstanceData,
+    store: &mut Store<State>,
+    readers: &mut [(InstanceData, Store<State>)],
+    config: &str,
+    naming: &NamingTemplate,
+    rules: &[&DetectionState],
+    limits: &Limits,
+    timeout: Duration,
+    permits: &Semaphore,
+) -> Result<Vec<Result<Option<String>>>> {
+    let pool: Vec<(&InstanceData, &mut Store<State>)> = std::iter::once((instance, store))
+        .chain(
+            readers
+                .iter_mut()
+                .map(|(instance, store)| (&*instance, store)),
+        )
+        .collect();
+    let size = pool.len();
+
+    let shares = pool
+        .into_iter()
+        .enumerate()
+        .map(|(offset, (instance, store))| async move {
+            let mut results = Vec::new();
+            for (index, rule) in rules.iter().enumerate().skip(offset).step_by(size) {
+                let params = serde_json::to_string(&rule.managed_content())?;
+                let mut attempt = 0;
+                let result = loop {
+                    let permit = permits.acquire().await?;
+                    let read = tokio::time::timeout(
+                        timeout,
+                        instance.read(store, config, &naming.apply(&rule.name), &params),
+                    )
+                    .await
+                    .unwrap_or_else(|_| {
+                        Err(anyhow!(
+                            "timed out after {}",
+                            humantime::format_duration(timeout)
+                        ))
+                    });
+                    // Backoffs do not hold a permit
+                    drop(permit);
+                    match read {
+                        Err(e) if attempt < limits.retries() && limits.is_retryable(&e) => {
+                            attempt += 1;
+                            let backoff = limits.retry_backoff(attempt)?;
+                            tracing::warn!(
+                                "retrying read of `{}` in {} ({}/{})",
+                                rule.name,
+                                humantime::format_duration(backoff),
+                                attempt,
+                                limits.retries()
+                            );
+                            tokio::time::sleep(backoff).await;
+                        }
+                        result => break result,
+                    }
+                };
+                results.push((index, result));
+            }
+            Ok::<_, anyhow::Error>(results)
+        });
+
+    let mut results: Vec<_> = futures::future::try_join_all(shares)
+        .await?
+        .into_iter()
+        .flatten()
+        .collect();
+    results.sort_by_key(|(index, _)| *index);
+    Ok(results.into_iter().map(|(_, content)| content).collect())
+}
+
+/// Restore the previously installed version of a plugin, or remove it if there is none.
+fn rollback_plugin(name: &str, backup: &Path) -> Result<()> {
+    if backup.exists() {
+        let installed = PathBuf::from(LGC_PLUGINS_PATH).join(name);
+        if installed.exists() {
+            fs::remove_file(&installed)?;
+        }
+        fs::rename(backup, installed)?;
+        Ok(())
+    } else {
+        cleanup_plugin(name)
+    }
 }
 
 /// Designed to be able to execute requests in parallel.
@@ -109,6 +535,7 @@ pub trait PluginActions: Send + 'static {
     async fn load(&self, store: &mut Store<State>) -> Result<Metadata>;
     async fn settings(&self, store: &mut Store<State>) -> Result<String>;
     async fn schema(&self, store: &mut Store<State>) -> Result<String>;
+    async fn from_common(&self, store: &mut Store<State>, rule: &str) -> Result<String>;
     async fn create(
         &self,
         store: &mut Store<State>,
@@ -123,6 +550,13 @@ pub trait PluginActions: Send + 'static {
         name: &str,
         params: &str,
     ) -> Result<Option<String>>;
+    async fn read_remote(
+        &self,
+        store: &mut Store<State>,
+        config: &str,
+        name: &str,
+        params: &str,
+    ) -> Result<Option<String>>;
     async fn update(
         &self,
         store: &mut Store<State>,
@@ -137,29 +571,225 @@ pub trait PluginActions: Send + 'static {
         name: &str,
         params: &str,
     ) -> Result<Option<String>>;
-    async fn ping(&self, store: &mut Store<State>, config: &str) -> Result<bool>;
+    async fn list_rules(
+        &self,
+        store: &mut Store<State>,
+        config: &str,
+        cursor: Option<&str>,
+        count: u32,
+    ) -> Result<RulePage>;
+    async fn tail(
+        &self,
+        store: &mut Store<State>,
+        config: &str,
+        name: &str,
+        params: &str,
+        cursor: Option<&str>,
+    ) -> Result<TailPage>;
+    async fn template(
+        &self,
+        store: &mut Store<State>,
+        config: &str,
+        rules: &[(String, String)],
+    ) -> Result<Bundle>;
+    async fn check(
+        &self,
+        store: &mut Store<State>,
+        config: Option<&str>,
+        name: &str,
+        params: &str,
+    ) -> Result<Vec<String>>;
+    async fn ping(&self, store: &mut Store<State>, config: &str) -> Result<ServiceStatus>;
+}
+
+/// Rules deployed on a service, as listed by [`InstanceData::list_all_rules`].
+#[derive(Default)]
+pub struct RuleListing {
+    /// Names of every rule deployed on the service
+    pub names: BTreeSet<String>,
+    /// Contents of the wanted rules, by remote name
+    pub contents: HashMap<String, String>,
+}
+
+impl InstanceData {
+    /// Fail fast when credentials of a service are known to only permit reads.
+    ///
+    /// Unreachable services are let through, the first operation reports a precise error.
+    pub async fn check_writable(
+        &self,
+        store: &mut Store<State>,
+        service_id: &str,
+        config: &str,
+    ) -> Result<()> {
+        match self.ping(store, config).await {
+            Ok(ServiceStatus {
+                writable: Some(false),
+                ..
+            }) => bail!(
+                "credentials are read-only for service `{}`, use `lgc diff` to review changes",
+                service_id
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether the plugin advertises an optional function.
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.metadata
+            .capabilities
+            .iter()
+            .any(|name| name == capability.name())
+    }
+
+    /// Fail with a clear error when calling an optional function the plugin does not advertise.
+    fn require(&self, capability: Capability) -> Result<()> {
+        if !self.supports(capability) {
+            bail!(
+                "plugin `{}` does not support `{}`, check for a newer version of the plugin",
+                self.metadata.name,
+                capability
+            )
+        }
+        Ok(())
+    }
+
+    /// Every rule deployed on a service, following list pages.
+    ///
+    /// Only contents of `wanted` rules are kept, other contents are dropped with their page
+    /// so that memory follows the number of managed rules rather than the size of the service.
+    pub async fn list_all_rules(
+        &self,
+        store: &mut Store<State>,
+        config: &str,
+        wanted: &HashSet<String>,
+        page_size: u32,
+    ) -> Result<RuleListing> {
+        let mut listing = RuleListing::default();
+        let mut cursor = None;
+        // Plugins returning a previous cursor would list forever
+        let mut cursors = HashSet::new();
+        loop {
+            let page = self
+                .list_rules(store, config, cursor.as_deref(), page_size)
+                .await?;
+            for (name, content) in page.rules {
+                if wanted.contains(&name) {
+                    listing.contents.insert(name.clone(), content);
+                }
+                listing.names.insert(name);
+            }
+            match page.next {
+                Some(next) if !cursors.insert(next.clone()) => bail!(
+                    "plugin `{}` returned cursor `{}` again when listing rules",
+                    self.metadata.name,
+                    next
+                ),
+                Some(next) => cursor = Some(next),
+                None => return Ok(listing),
+            }
+        }
+    }
+
+    fn call_error(&self, method: &str, e: anyhow::Error) -> anyhow::Error {
+        // The original error stays the source, plugin errors are still typed for retries
+        if !self.filesystem && is_filesystem_denial(&e) {
+            let context = format!(
+                "capability error: plugin `{}` attempted to access the filesystem during {}, grant it in `plugins.{}.filesystem` if expected: {}",
+                self.metadata.name, method, self.metadata.name, e
+            );
+            return e.context(context);
+        }
+        if e.downcast_ref::<Trap>() == Some(&Trap::Interrupt) {
+            let context = format!(
+                "plugin `{}` exceeded its call timeout during {}, raise `plugins.{}.sandbox.call_timeout` if expected",
+                self.metadata.name, method, self.metadata.name
+            );
+            return e.context(context);
+        }
+
+        let message = e.to_string();
+
+        // Keep plugin errors typed for retry and reporting logic
+        let context = format!(
+            "when calling {} for plugin `{}`",
+            method, self.metadata.name
+        );
+        match e.downcast::<PluginError>() {
+            Ok(error) => PluginError {
+                message: format!("{}: {}", context, error.message),
+                ..error
+            }
+            .into(),
+            Err(_) => anyhow!("{}: {}", context, message),
+        }
+    }
 }
 
 #[async_trait]
 impl PluginActions for InstanceData {
     async fn load(&self, store: &mut Store<State>) -> Result<Metadata> {
-        self.interface.logcraft_lgc_plugin().call_load(store).await
+        match &self.transport {
+            Transport::Wasm(interface) => {
+                set_call_deadline(store);
+                interface.logcraft_lgc_plugin().call_load(store).await
+            }
+            Transport::Compat(plugin) => {
+                set_call_deadline(store);
+                plugin.load(store).await
+            }
+            Transport::Grpc(plugin) => plugin.load().await,
+        }
     }
 
     async fn settings(&self, store: &mut Store<State>) -> Result<String> {
-        self.interface
-            .logcraft_lgc_plugin()
-            .call_settings(store)
-            .await
+        match &self.transport {
+            Transport::Wasm(interface) => {
+                set_call_deadline(store);
+                interface.logcraft_lgc_plugin().call_settings(store).await
+            }
+            Transport::Compat(plugin) => {
+                set_call_deadline(store);
+                plugin.settings(store).await
+            }
+            Transport::Grpc(plugin) => plugin.settings().await,
+        }
     }
 
     async fn schema(&self, store: &mut Store<State>) -> Result<String> {
-        self.interface
-            .logcraft_lgc_plugin()
-            .call_schema(store)
-            .await
+        match &self.transport {
+            Transport::Wasm(interface) => {
+                set_call_deadline(store);
+                interface.logcraft_lgc_plugin().call_schema(store).await
+            }
+            Transport::Compat(plugin) => {
+                set_call_deadline(store);
+                plugin.schema(store).await
+            }
+            Transport::Grpc(plugin) => plugin.schema().await,
+        }
+    }
+
+    async fn from_common(&self, store: &mut Store<State>, rule: &str) -> Result<String> {
+        self.require(Capability::FromCommon)?;
+        match &self.transport {
+            Transport::Wasm(interface) => {
+                set_call_deadline(store);
+                interface
+                    .logcraft_lgc_plugin()
+                    .call_from_common(store, rule)
+                    .await?
+                    .map_err(|e| PluginError::from(e).into())
+            }
+            Transport::Compat(plugin) => {
+                set_call_deadline(store);
+                plugin.from_common(store, rule).await
+            }
+            Transport::Grpc(plugin) => plugin.from_common(rule).await,
+        }
+        .map_err(|e| self.call_error("from-common", e))
     }
 
+    #[tracing::instrument(name = "plugin.create", skip_all, fields(plugin = %self.metadata.name, rule = name))]
     async fn create(
         &self,
         store: &mut Store<State>,
@@ -167,26 +797,25 @@ impl PluginActions for InstanceData {
         name: &str,
         params: &str,
     ) -> Result<Option<String>> {
-        self.interface
-            .logcraft_lgc_plugin()
-            .call_create(store, config, name, params)
-            .await
-            .map_err(|e| {
-                anyhow!(
-                    "when calling read for plugin `{}`: {}",
-                    self.metadata.name,
-                    e
-                )
-            })?
-            .map_err(|e| {
-                anyhow!(
-                    "when calling create for plugin `{}`: {}",
-                    self.metadata.name,
-                    e
-                )
-            })
+        match &self.transport {
+            Transport::Wasm(interface) => {
+                set_call_deadline(store);
+                interface
+                    .logcraft_lgc_plugin()
+                    .call_create(store, config, name, params)
+                    .await?
+                    .map_err(|e| PluginError::from(e).into())
+            }
+            Transport::Compat(plugin) => {
+                set_call_deadline(store);
+                plugin.rule("create", store, config, name, params).await
+            }
+            Transport::Grpc(plugin) => plugin.rule("Create", config, name, params).await,
+        }
+        .map_err(|e| self.call_error("create", e))
     }
 
+    #[tracing::instrument(name = "plugin.read", skip_all, fields(plugin = %self.metadata.name, rule = name))]
     async fn read(
         &self,
         store: &mut Store<State>,
@@ -194,19 +823,54 @@ impl PluginActions for InstanceData {
         name: &str,
         params: &str,
     ) -> Result<Option<String>> {
-        self.interface
-            .logcraft_lgc_plugin()
-            .call_read(store, config, name, params)
-            .await?
-            .map_err(|e| {
-                anyhow!(
-                    "when calling read for plugin `{}`: {}",
-                    self.metadata.name,
-                    e
-                )
-            })
+        match &self.transport {
+            Transport::Wasm(interface) => {
+                set_call_deadline(store);
+                interface
+                    .logcraft_lgc_plugin()
+                    .call_read(store, config, name, params)
+                    .await?
+                    .map_err(|e| PluginError::from(e).into())
+            }
+            Transport::Compat(plugin) => {
+                set_call_deadline(store);
+                plugin.rule("read", store, config, name, params).await
+            }
+            Transport::Grpc(plugin) => plugin.rule("Read", config, name, params).await,
+        }
+        .map_err(|e| self.call_error("read", e))
+    }
+
+    #[tracing::instrument(name = "plugin.read-remote", skip_all, fields(plugin = %self.metadata.name, rule = name))]
+    async fn read_remote(
+        &self,
+        store: &mut Store<State>,
+        config: &str,
+        name: &str,
+        params: &str,
+    ) -> Result<Option<String>> {
+        self.require(Capability::ReadRemote)?;
+        match &self.transport {
+            Transport::Wasm(interface) => {
+                set_call_deadline(store);
+                interface
+                    .logcraft_lgc_plugin()
+                    .call_read_remote(store, config, name, params)
+                    .await?
+                    .map_err(|e| PluginError::from(e).into())
+            }
+            Transport::Compat(plugin) => {
+                set_call_deadline(store);
+                plugin
+                    .rule("read-remote", store, config, name, params)
+                    .await
+            }
+            Transport::Grpc(plugin) => plugin.rule("ReadRemote", config, name, params).await,
+        }
+        .map_err(|e| self.call_error("read-remote", e))
     }
 
+    #[tracing::instrument(name = "plugin.update", skip_all, fields(plugin = %self.metadata.name, rule = name))]
     async fn update(
         &self,
         store: &mut Store<State>,
@@ -214,19 +878,25 @@ impl PluginActions for InstanceData {
         name: &str,
         params: &str,
     ) -> Result<Option<String>> {
-        self.interface
-            .logcraft_lgc_plugin()
-            .call_update(store, config, name, params)
-            .await?
-            .map_err(|e| {
-                anyhow!(
-                    "when calling update for plugin `{}`: {}",
-                    self.metadata.name,
-                    e
-                )
-            })
+        match &self.transport {
+            Transport::Wasm(interface) => {
+                set_call_deadline(store);
+                interface
+                    .logcraft_lgc_plugin()
+                    .call_update(store, config, name, params)
+                    .await?
+                    .map_err(|e| PluginError::from(e).into())
+            }
+            Transport::Compat(plugin) => {
+                set_call_deadline(store);
+                plugin.rule("update", store, config, name, params).await
+            }
+            Transport::Grpc(plugin) => plugin.rule("Update", config, name, params).await,
+        }
+        .map_err(|e| self.call_error("update", e))
     }
 
+    #[tracing::instrument(name = "plugin.delete", skip_all, fields(plugin = %self.metadata.name, rule = name))]
     async fn delete(
         &self,
         store: &mut Store<State>,
@@ -234,31 +904,179 @@ impl PluginActions for InstanceData {
         name: &str,
         params: &str,
     ) -> Result<Option<String>> {
-        self.interface
-            .logcraft_lgc_plugin()
-            .call_delete(store, config, name, params)
-            .await?
-            .map_err(|e| {
-                anyhow!(
-                    "when calling delete for plugin `{}`: {}",
-                    self.metadata.name,
-                    e
-                )
-            })
+        match &self.transport {
+            Transport::Wasm(interface) => {
+                set_call_deadline(store);
+                interface
+                    .logcraft_lgc_plugin()
+                    .call_delete(store, config, name, params)
+                    .await?
+                    .map_err(|e| PluginError::from(e).into())
+            }
+            Transport::Compat(plugin) => {
+                set_call_deadline(store);
+                plugin.rule("delete", store, config, name, params).await
+            }
+            Transport::Grpc(plugin) => plugin.rule("Delete", config, name, params).await,
+        }
+        .map_err(|e| self.call_error("delete", e))
     }
 
-    async fn ping(&self, store: &mut Store<State>, config: &str) -> Result<bool> {
-        self.interface
-            .logcraft_lgc_plugin()
-            .call_ping(store, config)
-            .await?
-            .map_err(|e| {
-                anyhow!(
-                    "when calling ping for plugin `{}`: {}",
-                    self.metadata.name,
-                    e
-                )
-            })
+    #[tracing::instrument(name = "plugin.list-rules", skip_all, fields(plugin = %self.metadata.name))]
+    async fn list_rules(
+        &self,
+        store: &mut Store<State>,
+        config: &str,
+        cursor: Option<&str>,
+        count: u32,
+    ) -> Result<RulePage> {
+        self.require(Capability::ListRules)?;
+        match &self.transport {
+            Transport::Wasm(interface) => {
+                set_call_deadline(store);
+                interface
+                    .logcraft_lgc_plugin()
+                    .call_list_rules(store, config, cursor, count)
+                    .await?
+                    .map_err(|e| PluginError::from(e).into())
+            }
+            Transport::Compat(plugin) => {
+                set_call_deadline(store);
+                plugin.list_rules(store, config, cursor, count).await
+            }
+            Transport::Grpc(plugin) => {
+                plugin
+                    .list_rules(config, cursor, count)
+                    .await
+                    .map(|page| RulePage {
+                        rules: page
+                            .rules
+                            .into_iter()
+                            .map(|rule| (rule.name, rule.content))
+                            .collect(),
+                        next: page.next,
+                    })
+            }
+        }
+        .map_err(|e| self.call_error("list-rules", e))
+    }
+
+    #[tracing::instrument(name = "plugin.tail", skip_all, fields(plugin = %self.metadata.name, rule = name))]
+    async fn tail(
+        &self,
+        store: &mut Store<State>,
+        config: &str,
+        name: &str,
+        params: &str,
+        cursor: Option<&str>,
+    ) -> Result<TailPage> {
+        self.require(Capability::Tail)?;
+        match &self.transport {
+            Transport::Wasm(interface) => {
+                set_call_deadline(store);
+                interface
+                    .logcraft_lgc_plugin()
+                    .call_tail(store, config, name, params, cursor)
+                    .await?
+                    .map_err(|e| PluginError::from(e).into())
+            }
+            Transport::Compat(plugin) => {
+                set_call_deadline(store);
+                plugin.tail(store, config, name, params, cursor).await
+            }
+            Transport::Grpc(plugin) => {
+                plugin
+                    .tail(config, name, params, cursor)
+                    .await
+                    .map(|page| TailPage {
+                        events: page.events,
+                        next: page.next,
+                    })
+            }
+        }
+        .map_err(|e| self.call_error("tail", e))
+    }
+
+    #[tracing::instrument(name = "plugin.template", skip_all, fields(plugin = %self.metadata.name))]
+    async fn template(
+        &self,
+        store: &mut Store<State>,
+        config: &str,
+        rules: &[(String, String)],
+    ) -> Result<Bundle> {
+        self.require(Capability::Template)?;
+        match &self.transport {
+            Transport::Wasm(interface) => {
+                set_call_deadline(store);
+                interface
+                    .logcraft_lgc_plugin()
+                    .call_template(store, config, rules)
+                    .await?
+                    .map_err(|e| PluginError::from(e).into())
+            }
+            Transport::Compat(plugin) => {
+                set_call_deadline(store);
+                plugin.template(store, config, rules).await
+            }
+            Transport::Grpc(plugin) => plugin.template(config, rules).await.map(|bundle| Bundle {
+                file_name: bundle.file_name,
+                content: bundle.content,
+            }),
+        }
+        .map_err(|e| self.call_error("template", e))
+    }
+
+    #[tracing::instrument(name = "plugin.check", skip_all, fields(plugin = %self.metadata.name, rule = name))]
+    async fn check(
+        &self,
+        store: &mut Store<State>,
+        config: Option<&str>,
+        name: &str,
+        params: &str,
+    ) -> Result<Vec<String>> {
+        self.require(Capability::Check)?;
+        match &self.transport {
+            Transport::Wasm(interface) => {
+                set_call_deadline(store);
+                interface
+                    .logcraft_lgc_plugin()
+                    .call_check(store, config, name, params)
+                    .await?
+                    .map_err(|e| PluginError::from(e).into())
+            }
+            Transport::Compat(plugin) => plugin.missing("check"),
+            Transport::Grpc(plugin) => plugin
+                .check(config, name, params)
+                .await
+                .map(|resp| resp.problems),
+        }
+        .map_err(|e| self.call_error("check", e))
+    }
+
+    #[tracing::instrument(name = "plugin.ping", skip_all, fields(plugin = %self.metadata.name))]
+    async fn ping(&self, store: &mut Store<State>, config: &str) -> Result<ServiceStatus> {
+        match &self.transport {
+            Transport::Wasm(interface) => {
+                set_call_deadline(store);
+                interface
+                    .logcraft_lgc_plugin()
+                    .call_ping(store, config)
+                    .await?
+                    .map_err(|e| PluginError::from(e).into())
+            }
+            Transport::Compat(plugin) => {
+                set_call_deadline(store);
+                plugin.ping(store, config).await
+            }
+            Transport::Grpc(plugin) => plugin.ping(config).await.map(|resp| ServiceStatus {
+                alive: resp.alive,
+                writable: resp.writable,
+                api_version: resp.api_version,
+                license: resp.license,
+                remaining_quota: resp.remaining_quota,
+            }),
+        }
+        .map_err(|e| self.call_error("ping", e))
     }
 }
 
@@ -291,6 +1109,17 @@ impl Default for PluginLocation {
 }
 
 impl PluginLocation {
+    /// Default location of the plugin signature.
+    pub fn signature(&self) -> PathBuf {
+        match &self {
+            Self::Local(path) => {
+                let mut signature = path.clone().into_os_string();
+                signature.push(format!(".{}", SIGNATURE_EXTENSION));
+                PathBuf::from(signature)
+            }
+        }
+    }
+
     pub async fn load(&self) -> Result<Vec<u8>> {
         match &self {
             Self::Local(path) => {
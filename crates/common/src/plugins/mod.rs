@@ -1,22 +1,250 @@
 // Copyright (c) 2023 LogCraft, SAS.
 // SPDX-License-Identifier: MPL-2.0
 
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Result};
+use lgc_runtime::{
+    plugin_component::plugin::Metadata,
+    state::{Preopen, Sandbox},
+};
 use serde::{Deserialize, Serialize};
-use std::{fs, path::PathBuf, str::FromStr};
+use serde_with::skip_serializing_none;
+use std::{fmt, fs, path::PathBuf, str::FromStr};
 
+use crate::diff::DiffConfig;
+use compat::COMPAT_INTERFACE_VERSIONS;
+
+pub mod compat;
+pub mod error;
+pub mod grpc;
 pub mod manager;
+pub mod trust;
+pub use error::PluginError;
 pub use manager::PluginLocation;
 use url::Url;
 
 pub const LGC_PLUGINS_PATH: &str = ".logcraft/plugins";
 
+/// Version of the plugin interface (`wit/world.wit` package) implemented by lgc.
+pub const PLUGIN_INTERFACE_VERSION: &str = "0.3.0";
+
 #[derive(Serialize, Deserialize, Clone, Default)]
 pub struct Plugin {
     pub source: PluginLocation,
     pub author: String,
     pub description: String,
     pub version: String,
+    /// Native executables are only run with the `grpc` transport
+    #[serde(default)]
+    pub transport: PluginTransport,
+    /// Host directories the plugin may access, none by default
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub filesystem: Vec<FilesystemGrant>,
+    /// Name of deployed rules (e.g. `{name} - LGC`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub naming: Option<String>,
+    /// Differences between remote and local rules which are not changes
+    #[serde(default, skip_serializing_if = "DiffConfig::is_empty")]
+    pub diff: DiffConfig,
+    /// Resources the plugin may use, unrestricted by default
+    #[serde(default, skip_serializing_if = "PluginSandbox::is_empty")]
+    pub sandbox: PluginSandbox,
+}
+
+/// Host directory exposed to a wasm plugin.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FilesystemGrant {
+    /// Directory on the host, relative to the project root
+    pub path: PathBuf,
+    /// Path under which the plugin sees the directory, same as `path` if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub guest: Option<String>,
+    /// Allow the plugin to create and modify files
+    #[serde(default)]
+    pub writable: bool,
+}
+
+impl FilesystemGrant {
+    pub fn preopen(&self) -> Result<Preopen> {
+        if !self.path.is_dir() {
+            bail!(
+                "capability error: granted path `{}` is not a directory",
+                self.path.display()
+            )
+        }
+
+        Ok(Preopen {
+            host: self.path.clone(),
+            guest: self
+                .guest
+                .clone()
+                .unwrap_or_else(|| self.path.to_string_lossy().to_string()),
+            writable: self.writable,
+        })
+    }
+}
+
+/// Resource limits of a wasm plugin, enforced by the runtime.
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct PluginSandbox {
+    /// Maximum size of each plugin memory, in MiB
+    pub max_memory_mb: Option<usize>,
+    /// Time allowed to wasm execution for each plugin call (e.g. `2m`), `60s` by default
+    pub call_timeout: Option<String>,
+    /// Hosts the plugin may send requests to (e.g. `*.splunkcloud.com`), any if not set
+    pub allowed_hosts: Option<Vec<String>>,
+}
+
+impl PluginSandbox {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    pub fn sandbox(&self) -> Result<Sandbox> {
+        let call_timeout = match &self.call_timeout {
+            Some(timeout) => Some(
+                humantime::parse_duration(timeout)
+                    .map_err(|e| anyhow!("invalid call timeout `{}`: {}", timeout, e))?,
+            ),
+            None => None,
+        };
+
+        Ok(Sandbox {
+            max_memory: self.max_memory_mb.map(|size| size << 20),
+            call_timeout,
+            allowed_hosts: self.allowed_hosts.clone(),
+        })
+    }
+}
+
+/// How lgc communicates with a plugin.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PluginTransport {
+    /// Wasm component running in process
+    #[default]
+    Wasm,
+    /// Native executable serving the gRPC plugin protocol
+    Grpc,
+}
+
+/// Optional plugin function, advertised in the `capabilities` of plugin metadata.
+///
+/// Plugins only export stubs for functions they do not implement, lgc skips or replaces
+/// calls to functions a plugin does not advertise.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Capability {
+    /// Map rules written in the common format
+    FromCommon,
+    /// Read full remote objects, for remote snapshots
+    ReadRemote,
+    /// List deployed rules, for bulk reads
+    ListRules,
+    /// Fetch recent executions of a rule
+    Tail,
+    /// Render rules as a native bundle
+    Template,
+    /// Receive detection metadata in the `metadata` field of rules, and return it when read
+    Metadata,
+    /// Check rules beyond their schema, e.g. the syntax of queries
+    Check,
+}
+
+impl Capability {
+    /// Name of the function, as advertised by plugins.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::FromCommon => "from-common",
+            Self::ReadRemote => "read-remote",
+            Self::ListRules => "list-rules",
+            Self::Tail => "tail",
+            Self::Template => "template",
+            Self::Metadata => "metadata",
+            Self::Check => "check",
+        }
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Compatibility of the plugin interface a plugin was built against with lgc.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InterfaceCompatibility {
+    Compatible,
+    /// Previous version still driven by lgc, functions added since are disabled
+    Older,
+    /// Version which lgc cannot drive
+    Incompatible,
+    /// No valid version, optional functions are disabled
+    Unknown,
+}
+
+impl InterfaceCompatibility {
+    /// Before `1.0`, minor versions change exported functions as major versions do, so
+    /// versions are compared on their major and minor numbers.
+    pub fn of(version: &str) -> Self {
+        let host = interface_major_minor(PLUGIN_INTERFACE_VERSION).unwrap_or_default();
+        match interface_major_minor(version) {
+            None => Self::Unknown,
+            Some(target) if target == host => Self::Compatible,
+            Some(target) if COMPAT_INTERFACE_VERSIONS.contains(&target) => Self::Older,
+            Some(_) => Self::Incompatible,
+        }
+    }
+}
+
+impl fmt::Display for InterfaceCompatibility {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Compatible => "compatible",
+            Self::Older => "older",
+            Self::Incompatible => "incompatible",
+            Self::Unknown => "unknown",
+        })
+    }
+}
+
+/// Major and minor numbers of a plugin interface version, e.g. `(0, 3)` for `0.3.0`.
+pub fn interface_major_minor(version: &str) -> Option<(u64, u64)> {
+    let mut parts = version.trim_start_matches('v').split('.');
+    Some((parts.next()?.parse().ok()?, parts.next()?.parse().ok()?))
+}
+
+/// Check the plugin interface version a plugin was built against.
+///
+/// Plugins of previous versions still driven by lgc lack the functions added since, plugins
+/// reporting no valid version get their optional functions disabled, other versions cannot be
+/// driven by lgc.
+pub fn check_interface_version(metadata: &mut Metadata) -> Result<()> {
+    match InterfaceCompatibility::of(&metadata.interface_version) {
+        InterfaceCompatibility::Compatible => (),
+        InterfaceCompatibility::Older => tracing::warn!(
+            "plugin `{}` targets the older plugin interface `{}`, rebuild it against `{}` to use all lgc functions",
+            metadata.name,
+            metadata.interface_version,
+            PLUGIN_INTERFACE_VERSION
+        ),
+        InterfaceCompatibility::Unknown => {
+            tracing::warn!(
+                "plugin `{}` reports no valid interface version, optional functions are disabled",
+                metadata.name
+            );
+            metadata.capabilities.clear();
+        }
+        InterfaceCompatibility::Incompatible => bail!(
+            "plugin `{}` targets plugin interface `{}`, lgc implements `{}`",
+            metadata.name,
+            metadata.interface_version,
+            PLUGIN_INTERFACE_VERSION
+        ),
+    }
+
+    Ok(())
 }
 
 pub fn cleanup_plugin(name: &str) -> Result<()> {
@@ -0,0 +1,52 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{anyhow, bail, Result};
+use minisign_verify::{PublicKey, Signature};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+/// Extension of signature files, next to the signed plugin (e.g. `splunk.wasm.minisig`).
+pub const SIGNATURE_EXTENSION: &str = "minisig";
+
+/// Publishers whose plugins can be installed, plugins are not verified unless one is set.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct PluginTrust {
+    /// Minisign public keys of trusted publishers, as printed by `minisign -G`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keys: Vec<String>,
+}
+
+impl PluginTrust {
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Check that `bytes` are signed by a trusted publisher, with the minisign `signature` file.
+    pub fn verify(&self, bytes: &[u8], signature: &Path) -> Result<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+
+        let content = fs::read_to_string(signature).map_err(|e| {
+            anyhow!(
+                "unable to read plugin signature `{}`: {}",
+                signature.display(),
+                e
+            )
+        })?;
+        let signature = Signature::decode(&content)
+            .map_err(|e| anyhow!("invalid plugin signature `{}`: {}", signature.display(), e))?;
+
+        for key in &self.keys {
+            let key = PublicKey::from_base64(key)
+                .map_err(|e| anyhow!("invalid trusted key `{}`: {}", key, e))?;
+            if key.verify(bytes, &signature, false).is_ok() {
+                return Ok(());
+            }
+        }
+
+        bail!("plugin is not signed by a trusted key")
+    }
+}
@@ -0,0 +1,697 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{anyhow, bail, Result};
+use regex::Regex;
+use serde_json::Value;
+use std::{cmp::Ordering, fmt};
+
+/// Longest value rendered in violation messages.
+const MAX_RENDERED_VALUE: usize = 60;
+
+/// Policy condition, written in a subset of CEL with an optional `when` guard.
+///
+/// Rules are bound to `rule` (e.g. `rule.parameters["cron_schedule"] != "" when
+/// rule.parameters["enableSched"] == 1`). Supported: `||`, `&&`, `!`, comparisons, `in`,
+/// lists, `size()`, `has()` and the `matches`, `contains`, `startsWith` and `endsWith` methods.
+/// Missing fields evaluate to `null`.
+#[derive(Clone, Debug)]
+pub struct Expression {
+    source: String,
+    root: Node,
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Literal(Value),
+    List(Vec<Node>),
+    Ident(String),
+    Member(Box<Node>, String),
+    Index(Box<Node>, Box<Node>),
+    Call(Option<Box<Node>>, String, Vec<Node>),
+    Not(Box<Node>),
+    Neg(Box<Node>),
+    Binary(Op, Box<Node>, Box<Node>),
+    When(Box<Node>, Box<Node>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Op {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    In,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    Str(String),
+    Ident(String),
+    Symbol(&'static str),
+    End,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Number(number) => write!(f, "`{}`", number),
+            Self::Str(text) => write!(f, "`{:?}`", text),
+            Self::Ident(ident) => write!(f, "`{}`", ident),
+            Self::Symbol(symbol) => write!(f, "`{}`", symbol),
+            Self::End => write!(f, "end of expression"),
+        }
+    }
+}
+
+/// Symbols, longest first so that `==` is not read as `=`.
+const SYMBOLS: [&str; 17] = [
+    "==", "!=", "<=", ">=", "&&", "||", "<", ">", "!", "-", "(", ")", "[", "]", ".", ",", "=",
+];
+
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>> {
+    let mut tokens = Vec::new();
+    let chars: Vec<(usize, char)> = source.char_indices().collect();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let (position, c) = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() {
+            while i < chars.len() && (chars[i].1.is_ascii_digit() || chars[i].1 == '.') {
+                i += 1;
+            }
+            let end = chars.get(i).map_or(source.len(), |(end, _)| *end);
+            let number = source[position..end]
+                .parse()
+                .map_err(|_| anyhow!("invalid number at position {}", position + 1))?;
+            tokens.push((position, Token::Number(number)));
+        } else if c == '"' || c == '\'' {
+            let mut text = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => bail!("unterminated string at position {}", position + 1),
+                    Some((_, '\\')) => {
+                        match chars.get(i + 1).map(|(_, c)| *c) {
+                            Some('n') => text.push('\n'),
+                            Some('t') => text.push('\t'),
+                            Some(escaped) => text.push(escaped),
+                            None => bail!("unterminated string at position {}", position + 1),
+                        }
+                        i += 2;
+                    }
+                    Some((_, end)) if *end == c => {
+                        i += 1;
+                        break;
+                    }
+                    Some((_, other)) => {
+                        text.push(*other);
+                        i += 1;
+                    }
+                }
+            }
+            tokens.push((position, Token::Str(text)));
+        } else if c.is_alphabetic() || c == '_' {
+            let mut ident = String::new();
+            while i < chars.len() && (chars[i].1.is_alphanumeric() || chars[i].1 == '_') {
+                ident.push(chars[i].1);
+                i += 1;
+            }
+            tokens.push((position, Token::Ident(ident)));
+        } else {
+            let Some(symbol) = SYMBOLS
+                .iter()
+                .find(|symbol| source[position..].starts_with(**symbol))
+            else {
+                bail!("unexpected character `{}` at position {}", c, position + 1)
+            };
+            if *symbol == "=" {
+                bail!("unexpected `=` at position {}, use `==`", position + 1)
+            }
+            tokens.push((position, Token::Symbol(symbol)));
+            i += symbol.len();
+        }
+    }
+
+    tokens.push((source.len(), Token::End));
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.position].1
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.position].1.clone();
+        if token != Token::End {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn error(&self, expected: &str) -> anyhow::Error {
+        let (position, token) = &self.tokens[self.position];
+        anyhow!(
+            "expected {} at position {}, found {}",
+            expected,
+            position + 1,
+            token
+        )
+    }
+
+    fn eat(&mut self, symbol: &str) -> bool {
+        if matches!(self.peek(), Token::Symbol(s) if *s == symbol) {
+            self.position += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<()> {
+        if !self.eat(symbol) {
+            return Err(self.error(&format!("`{}`", symbol)));
+        }
+        Ok(())
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Token::Ident(ident) if ident == keyword)
+    }
+
+    fn expression(&mut self) -> Result<Node> {
+        let condition = self.or()?;
+        if self.is_keyword("when") {
+            self.next();
+            let guard = self.or()?;
+            return Ok(Node::When(Box::new(condition), Box::new(guard)));
+        }
+        Ok(condition)
+    }
+
+    fn or(&mut self) -> Result<Node> {
+        let mut node = self.and()?;
+        while self.eat("||") {
+            node = Node::Binary(Op::Or, Box::new(node), Box::new(self.and()?));
+        }
+        Ok(node)
+    }
+
+    fn and(&mut self) -> Result<Node> {
+        let mut node = self.comparison()?;
+        while self.eat("&&") {
+            node = Node::Binary(Op::And, Box::new(node), Box::new(self.comparison()?));
+        }
+        Ok(node)
+    }
+
+    fn comparison(&mut self) -> Result<Node> {
+        let left = self.unary()?;
+        let op = match self.peek() {
+            Token::Symbol("==") => Op::Eq,
+            Token::Symbol("!=") => Op::Ne,
+            Token::Symbol("<") => Op::Lt,
+            Token::Symbol("<=") => Op::Le,
+            Token::Symbol(">") => Op::Gt,
+            Token::Symbol(">=") => Op::Ge,
+            Token::Ident(ident) if ident == "in" => Op::In,
+            _ => return Ok(left),
+        };
+        self.next();
+        Ok(Node::Binary(op, Box::new(left), Box::new(self.unary()?)))
+    }
+
+    fn unary(&mut self) -> Result<Node> {
+        if self.eat("!") {
+            return Ok(Node::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("-") {
+            return Ok(Node::Neg(Box::new(self.unary()?)));
+        }
+        self.postfix()
+    }
+
+    fn postfix(&mut self) -> Result<Node> {
+        let mut node = self.primary()?;
+        loop {
+            if self.eat(".") {
+                let Token::Ident(name) = self.peek().clone() else {
+                    return Err(self.error("a field or method name"));
+                };
+                self.next();
+                node = if self.eat("(") {
+                    Node::Call(Some(Box::new(node)), name, self.arguments()?)
+                } else {
+                    Node::Member(Box::new(node), name)
+                };
+            } else if self.eat("[") {
+                let index = self.expression()?;
+                self.expect("]")?;
+                node = Node::Index(Box::new(node), Box::new(index));
+            } else {
+                return Ok(node);
+            }
+        }
+    }
+
+    fn arguments(&mut self) -> Result<Vec<Node>> {
+        let mut arguments = Vec::new();
+        if self.eat(")") {
+            return Ok(arguments);
+        }
+        loop {
+            arguments.push(self.expression()?);
+            if self.eat(")") {
+                return Ok(arguments);
+            }
+            self.expect(",")?;
+        }
+    }
+
+    fn primary(&mut self) -> Result<Node> {
+        let start = self.position;
+        match self.next() {
+            Token::Number(number) => Ok(Node::Literal(Value::from(number))),
+            Token::Str(text) => Ok(Node::Literal(Value::String(text))),
+            Token::Ident(ident) => match ident.as_str() {
+                "true" => Ok(Node::Literal(Value::Bool(true))),
+                "false" => Ok(Node::Literal(Value::Bool(false))),
+                "null" => Ok(Node::Literal(Value::Null)),
+                _ if self.eat("(") => Ok(Node::Call(None, ident, self.arguments()?)),
+                _ => Ok(Node::Ident(ident)),
+            },
+            Token::Symbol("(") => {
+                let node = self.expression()?;
+                self.expect(")")?;
+                Ok(node)
+            }
+            Token::Symbol("[") => {
+                let mut items = Vec::new();
+                if !self.eat("]") {
+                    loop {
+                        items.push(self.expression()?);
+                        if self.eat("]") {
+                            break;
+                        }
+                        self.expect(",")?;
+                    }
+                }
+                Ok(Node::List(items))
+            }
+            _ => {
+                self.position = start;
+                Err(self.error("a value"))
+            }
+        }
+    }
+}
+
+impl Expression {
+    pub fn parse(source: &str) -> Result<Self> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            position: 0,
+        };
+        let root = parser.expression()?;
+        if *parser.peek() != Token::End {
+            return Err(parser.error("an operator"));
+        }
+
+        Ok(Self {
+            source: source.to_string(),
+            root,
+        })
+    }
+
+    /// Whether a rule satisfies the condition, conditions guarded by a false `when` always do.
+    pub fn evaluate(&self, rule: &Value) -> Result<bool> {
+        truthy(&evaluate(&self.root, rule)?)
+    }
+
+    /// Fields read by the condition with their value in a rule, to explain violations.
+    pub fn explain(&self, rule: &Value) -> String {
+        let mut chains = Vec::new();
+        collect_chains(&self.root, &mut chains);
+
+        let mut fields: Vec<String> = Vec::new();
+        for chain in chains {
+            let Some(path) = render_chain(chain) else {
+                continue;
+            };
+            let mut value = evaluate(chain, rule)
+                .map(|value| value.to_string())
+                .unwrap_or_else(|e| e.to_string());
+            if value.chars().count() > MAX_RENDERED_VALUE {
+                value = format!(
+                    "{}...",
+                    value.chars().take(MAX_RENDERED_VALUE).collect::<String>()
+                );
+            }
+            let field = format!("`{}` is {}", path, value);
+            if !fields.contains(&field) {
+                fields.push(field);
+            }
+        }
+        fields.join(", ")
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+fn evaluate(node: &Node, rule: &Value) -> Result<Value> {
+    Ok(match node {
+        Node::Literal(value) => value.clone(),
+        Node::List(items) => Value::Array(
+            items
+                .iter()
+                .map(|item| evaluate(item, rule))
+                .collect::<Result<_>>()?,
+        ),
+        Node::Ident(ident) => match ident.as_str() {
+            "rule" => rule.clone(),
+            _ => bail!("unknown variable `{}`, rules are bound to `rule`", ident),
+        },
+        Node::Member(base, field) => evaluate(base, rule)?
+            .get(field)
+            .cloned()
+            .unwrap_or(Value::Null),
+        Node::Index(base, index) => {
+            let base = evaluate(base, rule)?;
+            match evaluate(index, rule)? {
+                Value::String(key) => base.get(&key).cloned().unwrap_or(Value::Null),
+                Value::Number(index) => index
+                    .as_u64()
+                    .and_then(|index| base.get(index as usize))
+                    .cloned()
+                    .unwrap_or(Value::Null),
+                index => bail!("invalid index `{}`", index),
+            }
+        }
+        Node::Call(receiver, name, arguments) => {
+            let mut values = Vec::new();
+            if let Some(receiver) = receiver {
+                values.push(evaluate(receiver, rule)?);
+            }
+            for argument in arguments {
+                values.push(evaluate(argument, rule)?);
+            }
+            call(name, receiver.is_some(), &values)?
+        }
+        Node::Not(node) => Value::Bool(!truthy(&evaluate(node, rule)?)?),
+        Node::Neg(node) => match evaluate(node, rule)?.as_f64() {
+            Some(number) => Value::from(-number),
+            None => bail!("`-` expects a number"),
+        },
+        Node::When(condition, guard) => {
+            if !truthy(&evaluate(guard, rule)?)? {
+                return Ok(Value::Bool(true));
+            }
+            evaluate(condition, rule)?
+        }
+        Node::Binary(Op::Or, left, right) => {
+            Value::Bool(truthy(&evaluate(left, rule)?)? || truthy(&evaluate(right, rule)?)?)
+        }
+        Node::Binary(Op::And, left, right) => {
+            Value::Bool(truthy(&evaluate(left, rule)?)? && truthy(&evaluate(right, rule)?)?)
+        }
+        Node::Binary(op, left, right) => {
+            let (left, right) = (evaluate(left, rule)?, evaluate(right, rule)?);
+            Value::Bool(match op {
+                Op::Eq => equals(&left, &right),
+                Op::Ne => !equals(&left, &right),
+                Op::In => match &right {
+                    Value::Array(items) => items.iter().any(|item| equals(&left, item)),
+                    Value::Object(fields) => {
+                        left.as_str().is_some_and(|key| fields.contains_key(key))
+                    }
+                    Value::String(text) => left.as_str().is_some_and(|part| text.contains(part)),
+                    Value::Null => false,
+                    _ => bail!("`in` expects a list, a map or a string"),
+                },
+                _ => {
+                    let ordering = compare(&left, &right)?;
+                    match op {
+                        Op::Lt => ordering == Ordering::Less,
+                        Op::Le => ordering != Ordering::Greater,
+                        Op::Gt => ordering == Ordering::Greater,
+                        _ => ordering != Ordering::Less,
+                    }
+                }
+            })
+        }
+    })
+}
+
+fn call(name: &str, method: bool, values: &[Value]) -> Result<Value> {
+    let text = |value: &Value| -> Result<String> {
+        match value {
+            Value::String(text) => Ok(text.clone()),
+            Value::Null => Ok(String::new()),
+            value => bail!("`{}` expects a string, found `{}`", name, value),
+        }
+    };
+
+    Ok(match (name, values) {
+        ("size", [value]) => Value::from(match value {
+            Value::String(text) => text.chars().count(),
+            Value::Array(items) => items.len(),
+            Value::Object(fields) => fields.len(),
+            Value::Null => 0,
+            value => bail!(
+                "`size` expects a string, a list or a map, found `{}`",
+                value
+            ),
+        }),
+        ("has", [value]) if !method => Value::Bool(!value.is_null()),
+        ("matches", [value, pattern]) if method => {
+            let pattern = text(pattern)?;
+            let regex = Regex::new(&pattern)
+                .map_err(|e| anyhow!("invalid pattern `{}`: {}", pattern, e))?;
+            Value::Bool(regex.is_match(&text(value)?))
+        }
+        ("contains", [value, part]) if method => Value::Bool(text(value)?.contains(&text(part)?)),
+        ("startsWith", [value, prefix]) if method => {
+            Value::Bool(text(value)?.starts_with(&text(prefix)?))
+        }
+        ("endsWith", [value, suffix]) if method => {
+            Value::Bool(text(value)?.ends_with(&text(suffix)?))
+        }
+        _ => bail!(
+            "unknown function `{}` with {} argument(s)",
+            name,
+            values.len() - usize::from(method)
+        ),
+    })
+}
+
+fn truthy(value: &Value) -> Result<bool> {
+    match value {
+        Value::Bool(value) => Ok(*value),
+        value => bail!("expected a boolean, found `{}`", value),
+    }
+}
+
+/// Equality, numbers being compared by value (`1 == 1.0`).
+fn equals(left: &Value, right: &Value) -> bool {
+    match (left.as_f64(), right.as_f64()) {
+        (Some(left), Some(right)) => left == right,
+        _ => left == right,
+    }
+}
+
+fn compare(left: &Value, right: &Value) -> Result<Ordering> {
+    match (left, right) {
+        (Value::Number(_), Value::Number(_)) => left
+            .as_f64()
+            .zip(right.as_f64())
+            .and_then(|(left, right)| left.partial_cmp(&right))
+            .ok_or_else(|| anyhow!("unable to compare `{}` and `{}`", left, right)),
+        (Value::String(left), Value::String(right)) => Ok(left.cmp(right)),
+        _ => bail!("unable to compare `{}` and `{}`", left, right),
+    }
+}
+
+/// Outermost field accesses of an expression (e.g. `rule.parameters["cron"]`).
+fn collect_chains<'a>(node: &'a Node, chains: &mut Vec<&'a Node>) {
+    match node {
+        Node::Member(..) | Node::Index(..) if render_chain(node).is_some() => chains.push(node),
+        Node::Member(base, _) => collect_chains(base, chains),
+        Node::Index(base, index) => {
+            collect_chains(base, chains);
+            collect_chains(index, chains);
+        }
+        Node::List(items) => items.iter().for_each(|item| collect_chains(item, chains)),
+        Node::Call(receiver, _, arguments) => {
+            if let Some(receiver) = receiver {
+                collect_chains(receiver, chains);
+            }
+            arguments
+                .iter()
+                .for_each(|argument| collect_chains(argument, chains));
+        }
+        Node::Not(node) | Node::Neg(node) => collect_chains(node, chains),
+        Node::Binary(_, left, right) | Node::When(left, right) => {
+            collect_chains(left, chains);
+            collect_chains(right, chains);
+        }
+        Node::Literal(_) | Node::Ident(_) => (),
+    }
+}
+
+fn render_chain(node: &Node) -> Option<String> {
+    match node {
+        Node::Ident(ident) => Some(ident.clone()),
+        Node::Member(base, field) => Some(format!("{}.{}", render_chain(base)?, field)),
+        Node::Index(base, index) => match index.as_ref() {
+            Node::Literal(index) => Some(format!("{}[{}]", render_chain(base)?, index)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn rule() -> Value {
+        json!({
+            "name": "brute-force",
+            "parameters": {"enableSched": 1, "cron_schedule": "*/5 * * * *", "tags": ["t1110"]},
+        })
+    }
+
+    fn evaluate(source: &str) -> Result<bool> {
+        Expression::parse(source)?.evaluate(&rule())
+    }
+
+    #[test]
+    fn precedence() {
+        let cases = [
+            ("true || false && false", true),
+            ("(true || false) && false", false),
+            ("!false && false", false),
+            ("!(false && false)", true),
+            ("-1 < 0 && 2 >= 2.0", true),
+            ("rule.name == 'x' || rule.parameters.enableSched == 1", true),
+            ("false when true || false", false),
+            ("false when rule.parameters.enableSched == 0", true),
+            ("rule.parameters.enableSched != 1 || true when false", true),
+            (
+                "'t1110' in rule.parameters.tags && 'cron' in 'cron_schedule'",
+                true,
+            ),
+            (
+                "rule.name.startsWith('brute') && !rule.name.endsWith('x')",
+                true,
+            ),
+            (
+                "rule.parameters['cron_schedule'].matches('^\\\\*/5 ')",
+                true,
+            ),
+            ("size(rule.parameters.tags) > 0 && has(rule.name)", true),
+        ];
+
+        for (source, expected) in cases {
+            assert_eq!(evaluate(source).unwrap(), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn invalid_syntax() {
+        let cases = [
+            ("rule.name = 'x'", "unexpected `=` at position 11, use `==`"),
+            (
+                "rule.name ==",
+                "expected a value at position 13, found end of expression",
+            ),
+            ("(true", "expected `)` at position 6"),
+            ("rule.name == 'x", "unterminated string at position 14"),
+            (
+                "true true",
+                "expected an operator at position 6, found `true`",
+            ),
+            // Comparisons do not chain
+            (
+                "1 == 1 == true",
+                "expected an operator at position 8, found `==`",
+            ),
+            ("rule.name # 1", "unexpected character `#` at position 11"),
+            ("rule.", "expected a field or method name at position 6"),
+            ("[1, 2", "expected `,` at position 6"),
+            ("1.2.3 == 1", "invalid number at position 1"),
+        ];
+
+        for (source, expected) in cases {
+            let error = Expression::parse(source).unwrap_err().to_string();
+            assert!(error.contains(expected), "{}: {}", source, error);
+        }
+    }
+
+    #[test]
+    fn missing_fields() {
+        let cases = [
+            ("rule.missing == null", true),
+            ("rule.parameters.missing.nested == null", true),
+            ("has(rule.missing)", false),
+            ("size(rule.missing) == 0", true),
+            ("rule.parameters.tags[5] == null", true),
+            ("rule.missing.contains('x')", false),
+            ("'x' in rule.missing", false),
+            ("rule.missing == 1 when has(rule.missing)", true),
+        ];
+
+        for (source, expected) in cases {
+            assert_eq!(evaluate(source).unwrap(), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn evaluation_errors() {
+        let cases = [
+            ("rule.missing", "expected a boolean, found `null`"),
+            ("rule.missing < 1", "unable to compare `null` and `1"),
+            ("policy.name == 'x'", "unknown variable `policy`"),
+            (
+                "rule.name.size2() == 1",
+                "unknown function `size2` with 0 argument(s)",
+            ),
+            ("rule.name.matches('(')", "invalid pattern `(`"),
+        ];
+
+        for (source, expected) in cases {
+            let error = evaluate(source).unwrap_err().to_string();
+            assert!(error.contains(expected), "{}: {}", source, error);
+        }
+    }
+
+    #[test]
+    fn explain_fields() {
+        let expression = Expression::parse(
+            "rule.parameters['cron_schedule'] == '' when rule.parameters.enableSched == 1",
+        )
+        .unwrap();
+        assert_eq!(
+            expression.explain(&rule()),
+            "`rule.parameters[\"cron_schedule\"]` is \"*/5 * * * *\", \
+             `rule.parameters.enableSched` is 1"
+        );
+    }
+}
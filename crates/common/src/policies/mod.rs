@@ -0,0 +1,355 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{anyhow, bail, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serde_with::skip_serializing_none;
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+use tempfile::TempDir;
+
+mod expression;
+
+pub use expression::Expression;
+
+use crate::{
+    configuration::LGC_POLICIES_DIR,
+    utils::{git, oras, workspace_path},
+};
+
+/// Policy pack installed from a remote repository.
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct PolicyPack {
+    /// Git repository of the pack, or OCI repository prefixed with `oci://`
+    pub source: String,
+    /// Branch or tag fetched, default branch if unset. Tag or digest of OCI packs, `latest`
+    /// if unset
+    pub reference: Option<String>,
+    /// Commit, or manifest digest of OCI packs, of the installed pack
+    pub revision: String,
+}
+
+impl PolicyPack {
+    /// Fetch the pack and install it in the policies directory under `name`.
+    pub fn install(&mut self, name: &str) -> Result<()> {
+        let checkout = TempDir::new()?;
+        self.revision = match self.source.strip_prefix("oci://") {
+            Some(repository) => self.pull(repository, checkout.path())?,
+            None => self.clone_repository(checkout.path())?,
+        };
+
+        // Copy pack next to the previous version, then swap them
+        let policies_dir = PathBuf::from(LGC_POLICIES_DIR);
+        let target = policies_dir.join(name);
+        let staging = policies_dir.join(format!(".{}.tmp", name));
+        if staging.exists() {
+            fs::remove_dir_all(&staging)?;
+        }
+        copy_dir(checkout.path(), &staging)?;
+
+        if target.exists() {
+            fs::remove_dir_all(&target)?;
+        }
+        fs::rename(&staging, &target)
+            .map_err(|e| anyhow!("unable to install policy pack `{}`: {}", name, e))
+    }
+
+    /// Short form of the installed revision.
+    pub fn short_revision(&self) -> &str {
+        let revision = self
+            .revision
+            .split_once(':')
+            .map_or(self.revision.as_str(), |(_, digest)| digest);
+        &revision[..revision.len().min(8)]
+    }
+
+    /// Shallow clone the requested reference, returning its commit.
+    fn clone_repository(&self, destination: &Path) -> Result<String> {
+        let mut args = vec!["clone", "--quiet", "--depth", "1"];
+        if let Some(reference) = &self.reference {
+            args.extend(["--branch", reference.as_str()]);
+        }
+        let destination_arg = destination.to_string_lossy();
+        args.extend([self.source.as_str(), destination_arg.as_ref()]);
+        git(&args, None)?;
+
+        git(&["rev-parse", "HEAD"], Some(destination))
+    }
+
+    /// Pull the files of the OCI artifact, returning its manifest digest. The digest is
+    /// resolved first so that the pulled files are those of the recorded revision.
+    fn pull(&self, repository: &str, destination: &Path) -> Result<String> {
+        let reference = match self.reference.as_deref() {
+            Some(digest) if digest.contains(':') => format!("{}@{}", repository, digest),
+            Some(tag) => format!("{}:{}", repository, tag),
+            None => format!("{}:latest", repository),
+        };
+        let digest = oras(&["resolve", &reference], None)?;
+
+        let artifact = format!("{}@{}", repository, digest);
+        let destination = destination.to_string_lossy();
+        oras(
+            &["pull", "--output", destination.as_ref(), artifact.as_str()],
+            None,
+        )?;
+        Ok(digest)
+    }
+}
+
+/// Severity of a policy violation, `error` ones fail `lgc validate`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    #[default]
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Info => write!(f, "info"),
+            Self::Warning => write!(f, "warning"),
+            Self::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Check of rules, loaded from `policies/<plugin>/*.yaml` or `policies/<pack>/<plugin>/*.yaml`.
+///
+/// Policies check a field, an expression, or both:
+///
+/// ```yaml
+/// description: searches must not use index wildcards
+/// severity: error
+/// field: /parameters/search
+/// forbidden_pattern: index\s*=\s*\*
+/// ```
+///
+/// ```yaml
+/// description: scheduled searches must have a schedule
+/// expression: rule.parameters["cron_schedule"] != "" when rule.parameters["enableSched"] == 1
+/// ```
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    /// Name reported with violations, the file name if not set
+    #[serde(default)]
+    pub name: String,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub severity: Severity,
+    /// JSON pointer of the checked field in rules (e.g. `/parameters/search`)
+    pub field: Option<String>,
+    /// The field must be set
+    #[serde(default)]
+    pub required: bool,
+    /// The field must not be set
+    #[serde(default)]
+    pub forbidden: bool,
+    /// The field, when set, must be one of these values
+    pub values: Option<Vec<Value>>,
+    /// The field, when set, must match this regex
+    pub pattern: Option<String>,
+    /// The field, when set, must not match this regex
+    pub forbidden_pattern: Option<String>,
+    /// Condition rules must satisfy, see [`Expression`]
+    pub expression: Option<String>,
+    #[serde(skip)]
+    compiled: Option<Expression>,
+}
+
+impl Policy {
+    /// Check the policy definition, compiling its expression.
+    fn compile(&mut self) -> Result<()> {
+        if self.field.is_none() && self.expression.is_none() {
+            bail!("policy `{}` must set `field` or `expression`", self.name)
+        }
+
+        if let Some(expression) = &self.expression {
+            self.compiled = Some(
+                Expression::parse(expression)
+                    .map_err(|e| anyhow!("invalid expression in policy `{}`: {}", self.name, e))?,
+            );
+        }
+        Ok(())
+    }
+
+    /// Violations of the policy by a rule content, empty if it complies.
+    pub fn check(&self, content: &Value) -> Result<Vec<String>> {
+        let mut violations = match &self.field {
+            Some(field) => self.check_field(field, content)?,
+            None => Vec::new(),
+        };
+
+        let expression = match (&self.compiled, &self.expression) {
+            (Some(compiled), _) => Some(compiled.clone()),
+            // Policies built in code are compiled on use
+            (None, Some(expression)) => Some(Expression::parse(expression)?),
+            (None, None) => None,
+        };
+        if let Some(expression) = expression {
+            match expression.evaluate(content) {
+                Ok(true) => (),
+                Ok(false) => violations.push(format!(
+                    "`{}` is not satisfied: {}",
+                    expression,
+                    expression.explain(content)
+                )),
+                Err(e) => violations.push(format!(
+                    "unable to evaluate `{}`: {} ({})",
+                    expression,
+                    e,
+                    expression.explain(content)
+                )),
+            }
+        }
+
+        Ok(violations)
+    }
+
+    fn check_field(&self, field: &str, content: &Value) -> Result<Vec<String>> {
+        let mut violations = Vec::new();
+        let value = content.pointer(field).filter(|value| !value.is_null());
+
+        let Some(value) = value else {
+            if self.required {
+                violations.push(format!("field `{}` must be set", field));
+            }
+            return Ok(violations);
+        };
+
+        if self.forbidden {
+            violations.push(format!("field `{}` must not be set", field));
+        }
+
+        if let Some(values) = &self.values {
+            if !values.contains(value) {
+                violations.push(format!(
+                    "field `{}` must be one of: {}",
+                    field,
+                    values
+                        .iter()
+                        .map(Value::to_string)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ));
+            }
+        }
+
+        // Non-string values are matched on their JSON representation
+        let text = match value {
+            Value::String(text) => text.clone(),
+            value => value.to_string(),
+        };
+        if let Some(pattern) = &self.pattern {
+            if !self.regex(pattern)?.is_match(&text) {
+                violations.push(format!("field `{}` must match `{}`", field, pattern));
+            }
+        }
+        if let Some(pattern) = &self.forbidden_pattern {
+            if self.regex(pattern)?.is_match(&text) {
+                violations.push(format!("field `{}` must not match `{}`", field, pattern));
+            }
+        }
+
+        Ok(violations)
+    }
+
+    fn regex(&self, pattern: &str) -> Result<Regex> {
+        Regex::new(pattern).map_err(|e| anyhow!("invalid pattern in policy `{}`: {}", self.name, e))
+    }
+}
+
+/// Policies applying to the rules of a plugin, from the project and installed packs.
+pub fn load_policies<'a>(
+    plugin: &str,
+    packs: impl IntoIterator<Item = &'a String>,
+) -> Result<Vec<Policy>> {
+    let policies_dir = PathBuf::from(LGC_POLICIES_DIR);
+    let dirs = std::iter::once(policies_dir.join(plugin)).chain(
+        packs
+            .into_iter()
+            .map(|pack| policies_dir.join(pack).join(plugin)),
+    );
+
+    let mut policies = Vec::new();
+    for dir in dirs.filter(|dir| dir.is_dir()) {
+        let mut paths = fs::read_dir(&dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        paths.sort();
+
+        for path in paths {
+            if !path
+                .extension()
+                .is_some_and(|ext| ext == "yaml" || ext == "yml")
+            {
+                continue;
+            }
+
+            let mut policy: Policy = serde_yaml_ng::from_str(&fs::read_to_string(&path)?)
+                .map_err(|e| anyhow!("invalid policy `{}`: {}", workspace_path(&path), e))?;
+            if policy.name.is_empty() {
+                policy.name = path
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().to_string())
+                    .unwrap_or_default();
+            }
+            policy
+                .compile()
+                .map_err(|e| anyhow!("{} (`{}`)", e, workspace_path(&path)))?;
+            policies.push(policy);
+        }
+    }
+
+    Ok(policies)
+}
+
+/// Remove an installed policy pack.
+pub fn cleanup_policy_pack(name: &str) -> Result<()> {
+    let path = PathBuf::from(LGC_POLICIES_DIR).join(name);
+    if path.exists() {
+        fs::remove_dir_all(path)?;
+    }
+
+    Ok(())
+}
+
+/// Default name of a pack, derived from its source.
+pub fn policy_pack_name(source: &str) -> String {
+    source
+        .trim_end_matches('/')
+        .rsplit(['/', ':'])
+        .next()
+        .unwrap_or(source)
+        .trim_end_matches(".git")
+        .to_lowercase()
+}
+
+fn copy_dir(source: &Path, destination: &Path) -> Result<()> {
+    fs::create_dir_all(destination)?;
+
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        if entry.file_name() == ".git" {
+            continue;
+        }
+
+        let path = entry.path();
+        if path.is_dir() {
+            copy_dir(&path, &destination.join(entry.file_name()))?;
+        } else {
+            fs::copy(&path, destination.join(entry.file_name()))?;
+        }
+    }
+
+    Ok(())
+}
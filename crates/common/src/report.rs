@@ -0,0 +1,383 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{anyhow, Result};
+use console::style;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serde_with::skip_serializing_none;
+use std::{collections::BTreeMap, fmt, fs, path::Path};
+
+use crate::detections::{rule_diff, DetectionState};
+
+/// Exit code returned when a run completed with failed operations.
+pub const PARTIAL_FAILURE_EXIT_CODE: i32 = 2;
+
+/// Error returned when some operations of a run failed while others succeeded.
+#[derive(Debug)]
+pub struct PartialFailure {
+    pub failed: usize,
+}
+
+impl fmt::Display for PartialFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} operation(s) failed, successful changes have been saved to state",
+            self.failed
+        )
+    }
+}
+
+impl std::error::Error for PartialFailure {}
+
+/// Operation performed on a remote service.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Create,
+    Read,
+    Update,
+    Delete,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Create => write!(f, "create"),
+            Self::Read => write!(f, "read"),
+            Self::Update => write!(f, "update"),
+            Self::Delete => write!(f, "delete"),
+        }
+    }
+}
+
+/// Result of an operation.
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "status", content = "reason", rename_all = "lowercase")]
+pub enum Outcome {
+    Succeeded,
+    Failed(String),
+    Skipped(String),
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct OperationReport {
+    pub service: String,
+    pub rule: String,
+    pub action: Action,
+    #[serde(flatten)]
+    pub outcome: Outcome,
+}
+
+/// Change to a remote rule, as printed by `--output json`.
+#[skip_serializing_none]
+#[derive(Serialize, Clone, Debug)]
+pub struct Change {
+    pub service: String,
+    pub rule: String,
+    pub action: Action,
+    /// Unified diff from the remote to the local rule
+    pub diff: Option<String>,
+    /// Result of the operation, once performed
+    #[serde(flatten)]
+    pub outcome: Option<Outcome>,
+}
+
+/// Changes of a run, in the order they are planned.
+#[derive(Serialize, Default, Debug)]
+pub struct ChangeReport {
+    pub changes: Vec<Change>,
+}
+
+impl ChangeReport {
+    pub fn add(&mut self, service: &str, rule: &str, action: Action, diff: Option<String>) {
+        self.changes.push(Change {
+            service: service.to_string(),
+            rule: rule.to_string(),
+            action,
+            diff,
+            outcome: None,
+        });
+    }
+
+    /// Add a planned operation with the diff of its rule, `remote` being the current remote content.
+    pub fn plan(
+        &mut self,
+        service: &str,
+        action: Action,
+        rule: &DetectionState,
+        remote: Option<&Value>,
+    ) -> Result<()> {
+        let diff = match action {
+            Action::Delete => rule_diff(Some(&rule.content), None)?,
+            Action::Update => rule_diff(
+                remote.map(|remote| rule.managed(remote)).as_ref(),
+                Some(&rule.managed_content()),
+            )?,
+            _ => rule_diff(remote, Some(&rule.content))?,
+        };
+        self.add(service, &rule.name, action, Some(diff));
+        Ok(())
+    }
+
+    /// Attach outcomes of the operations performed to the planned changes.
+    pub fn with_outcomes(mut self, report: &RunReport) -> Self {
+        for change in &mut self.changes {
+            change.outcome = report
+                .operations
+                .iter()
+                .find(|op| {
+                    op.service == change.service
+                        && op.rule == change.rule
+                        && op.action == change.action
+                })
+                .map(|op| op.outcome.clone());
+        }
+        self
+    }
+}
+
+/// Operations performed during a run.
+#[derive(Serialize, Default, Debug)]
+pub struct RunReport {
+    pub operations: Vec<OperationReport>,
+}
+
+impl RunReport {
+    pub fn record(&mut self, service: &str, rule: &str, action: Action, outcome: Outcome) {
+        self.operations.push(OperationReport {
+            service: service.to_string(),
+            rule: rule.to_string(),
+            action,
+            outcome,
+        });
+    }
+
+    /// Operations which failed or were skipped because of previous failures.
+    pub fn failures(&self) -> impl Iterator<Item = &OperationReport> {
+        self.operations
+            .iter()
+            .filter(|op| !matches!(op.outcome, Outcome::Succeeded))
+    }
+
+    pub fn has_failures(&self) -> bool {
+        self.failures().next().is_some()
+    }
+
+    /// Write the report as JSON to `path`.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)
+            .map_err(|e| anyhow!("unable to write report `{}`: {}", path.display(), e))
+    }
+
+    /// Fail with [`PartialFailure`] if any operation failed.
+    pub fn check(&self) -> Result<()> {
+        match self.failures().count() {
+            0 => Ok(()),
+            failed => Err(PartialFailure { failed }.into()),
+        }
+    }
+
+    /// Print a table of operations by service and outcome.
+    pub fn print_table(&self) {
+        let mut counts: BTreeMap<&str, [usize; 3]> = BTreeMap::new();
+        for op in &self.operations {
+            let column = match op.outcome {
+                Outcome::Succeeded => 0,
+                Outcome::Failed(_) => 1,
+                Outcome::Skipped(_) => 2,
+            };
+            counts.entry(&op.service).or_default()[column] += 1;
+        }
+
+        let rows: Vec<[String; 4]> = counts
+            .into_iter()
+            .map(|(service, [succeeded, failed, skipped])| {
+                [
+                    service.to_string(),
+                    succeeded.to_string(),
+                    failed.to_string(),
+                    skipped.to_string(),
+                ]
+            })
+            .collect();
+
+        println!("\n{}", style("Operations:").bold());
+        print_table(["SERVICE", "SUCCEEDED", "FAILED", "SKIPPED"], &rows);
+    }
+
+    /// Print failed operations grouped by service and error class.
+    ///
+    /// `command` is the command to suggest for retrying a service (e.g. `lgc deploy`).
+    pub fn print_summary(&self, command: &str) {
+        let mut groups: BTreeMap<&str, BTreeMap<ErrorClass, Vec<&OperationReport>>> =
+            BTreeMap::new();
+        for op in self.failures() {
+            if let Some(class) = ErrorClass::classify(&op.outcome) {
+                groups
+                    .entry(&op.service)
+                    .or_default()
+                    .entry(class)
+                    .or_default()
+                    .push(op);
+            }
+        }
+
+        if groups.is_empty() {
+            return;
+        }
+
+        println!("\n{}", style("Failed operations:").red().bold());
+        for (service, classes) in groups {
+            println!("- `{}`", style(service).bold());
+            for (class, operations) in classes {
+                println!(
+                    "  {} ({}): {}",
+                    style(class).yellow(),
+                    operations.len(),
+                    class.hint()
+                );
+                for op in operations {
+                    match &op.outcome {
+                        Outcome::Failed(reason) | Outcome::Skipped(reason) => {
+                            println!("    {} `{}`: {}", op.action, op.rule, style(reason).dim())
+                        }
+                        Outcome::Succeeded => (),
+                    }
+                }
+            }
+            println!("  retry with: {} -s {}", command, service);
+        }
+    }
+}
+
+/// Print rows under a bold header, in columns as wide as their widest cell.
+pub fn print_table<const N: usize>(header: [&str; N], rows: &[[String; N]]) {
+    let mut widths = header.map(str::len);
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let line = |cells: [&str; N]| {
+        cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+    println!("{}", style(line(header)).bold());
+    for row in rows {
+        println!("{}", line(row.each_ref().map(String::as_str)));
+    }
+}
+
+/// Broad category of a failure, used to group errors in summaries.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorClass {
+    Authentication,
+    Connectivity,
+    Timeout,
+    RateLimited,
+    Conflict,
+    Invalid,
+    Skipped,
+    Other,
+}
+
+impl ErrorClass {
+    /// Classify an operation outcome from its error message.
+    pub fn classify(outcome: &Outcome) -> Option<Self> {
+        let reason = match outcome {
+            Outcome::Succeeded => return None,
+            Outcome::Skipped(_) => return Some(Self::Skipped),
+            Outcome::Failed(reason) => reason.to_lowercase(),
+        };
+
+        let matches = |patterns: &[&str]| patterns.iter().any(|p| reason.contains(p));
+        Some(if matches(&["timed out", "timeout", "deadline"]) {
+            Self::Timeout
+        } else if matches(&[
+            "401",
+            "403",
+            "unauthorized",
+            "forbidden",
+            "token",
+            "credential",
+        ]) {
+            Self::Authentication
+        } else if matches(&["429", "rate limit", "too many requests"]) {
+            Self::RateLimited
+        } else if matches(&["409", "conflict", "already exists"]) {
+            Self::Conflict
+        } else if matches(&["400", "422", "invalid", "bad request", "parse"]) {
+            Self::Invalid
+        } else if matches(&["connect", "dns", "resolve", "unreachable", "refused", "tls"]) {
+            Self::Connectivity
+        } else {
+            Self::Other
+        })
+    }
+
+    /// Suggested next step for this class of failure.
+    pub fn hint(&self) -> &'static str {
+        match self {
+            Self::Authentication => "check the service credentials and permissions",
+            Self::Connectivity => "check the service url and network access to it",
+            Self::Timeout => "increase the service `timeout` or retry later",
+            Self::RateLimited => "wait before retrying or deploy fewer rules at once",
+            Self::Conflict => "the rule was modified remotely, run `lgc diff` to review it",
+            Self::Invalid => "fix the rule content, run `lgc validate` to check it",
+            Self::Skipped => "fix the previous failures on this service first",
+            Self::Other => "check the error messages above",
+        }
+    }
+}
+
+impl fmt::Display for ErrorClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Authentication => write!(f, "authentication"),
+            Self::Connectivity => write!(f, "connectivity"),
+            Self::Timeout => write!(f, "timeout"),
+            Self::RateLimited => write!(f, "rate limited"),
+            Self::Conflict => write!(f, "conflict"),
+            Self::Invalid => write!(f, "invalid rule"),
+            Self::Skipped => write!(f, "skipped"),
+            Self::Other => write!(f, "other"),
+        }
+    }
+}
+
+/// Stops sending operations to a service after too many consecutive failures.
+pub struct CircuitBreaker {
+    threshold: usize,
+    failures: usize,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: usize) -> Self {
+        Self {
+            threshold,
+            failures: 0,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.failures >= self.threshold
+    }
+
+    pub fn record(&mut self, success: bool) {
+        if success {
+            self.failures = 0;
+        } else {
+            self.failures += 1;
+        }
+    }
+}
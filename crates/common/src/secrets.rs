@@ -0,0 +1,273 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{anyhow, bail, Result};
+use futures::future::BoxFuture;
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{
+    collections::BTreeMap, env, fs, path::PathBuf, process::Stdio, sync::Mutex, time::SystemTime,
+};
+use tokio::io::AsyncWriteExt;
+
+use crate::utils::shell_command;
+
+/// Environment variables locating Vault, as used by the Vault CLI.
+const VAULT_ADDR_ENV: &str = "VAULT_ADDR";
+const VAULT_TOKEN_ENV: &str = "VAULT_TOKEN";
+const VAULT_NAMESPACE_ENV: &str = "VAULT_NAMESPACE";
+
+/// Setting names whose values are secrets even when not a secret reference.
+pub const SENSITIVE_SETTINGS: [&str; 6] = [
+    "token",
+    "password",
+    "secret",
+    "key",
+    "credential",
+    "authorization",
+];
+/// Replacement of secret values in outputs.
+pub const REDACTED: &str = "***";
+
+/// Resolve secret references of service settings, right before they are passed to plugins.
+///
+/// String values can reference an environment variable (`env:SPLUNK_TOKEN`), a file
+/// (`file:/run/secrets/token`) or a Vault secret field (`vault:secret/data/splunk#token`),
+/// so that credentials never need to be written in the project configuration.
+pub async fn resolve_secrets(
+    settings: &BTreeMap<String, Value>,
+) -> Result<BTreeMap<String, Value>> {
+    let mut resolved = settings.clone();
+    for (name, value) in resolved.iter_mut() {
+        resolve_value(value)
+            .await
+            .map_err(|e| anyhow!("unable to resolve setting `{}`: {}", name, e))?;
+    }
+
+    Ok(resolved)
+}
+
+fn resolve_value(value: &mut Value) -> BoxFuture<'_, Result<()>> {
+    Box::pin(async move {
+        match value {
+            Value::String(reference) => {
+                if let Some(secret) = resolve_reference(reference).await? {
+                    *reference = secret;
+                }
+            }
+            Value::Array(items) => {
+                for item in items {
+                    resolve_value(item).await?;
+                }
+            }
+            Value::Object(fields) => {
+                for field in fields.values_mut() {
+                    resolve_value(field).await?;
+                }
+            }
+            _ => (),
+        }
+
+        Ok(())
+    })
+}
+
+/// Whether a setting name suggests a secret value.
+pub fn is_sensitive(name: &str) -> bool {
+    let name = name.to_lowercase();
+    // Commands printing credentials, not credentials
+    name != "credential_helper"
+        && SENSITIVE_SETTINGS
+            .iter()
+            .any(|pattern| name.contains(pattern))
+}
+
+/// Mask values of sensitive fields at any depth, as well as credentials of URLs.
+/// Secret references are kept, as they only tell where secrets are read from.
+pub fn mask_sensitive(value: &mut serde_yaml_ng::Value) -> Result<()> {
+    let url_credentials = Regex::new(r"(://[^/\s:@]+:)[^/\s@]+@")?;
+    mask_value(value, false, &url_credentials);
+    Ok(())
+}
+
+fn mask_value(value: &mut serde_yaml_ng::Value, sensitive: bool, url_credentials: &Regex) {
+    use serde_yaml_ng::Value as Yaml;
+
+    match value {
+        Yaml::String(string) if is_reference(string) => (),
+        Yaml::String(_) | Yaml::Number(_) if sensitive => {
+            *value = Yaml::String(REDACTED.to_string())
+        }
+        Yaml::String(string) => {
+            *string = url_credentials
+                .replace_all(string, format!("${{1}}{}@", REDACTED))
+                .to_string()
+        }
+        Yaml::Sequence(items) => items
+            .iter_mut()
+            .for_each(|item| mask_value(item, sensitive, url_credentials)),
+        Yaml::Mapping(fields) => {
+            for (name, field) in fields.iter_mut() {
+                let sensitive = sensitive || name.as_str().is_some_and(is_sensitive);
+                mask_value(field, sensitive, url_credentials);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Whether a setting value references a secret rather than holding it.
+fn is_reference(value: &str) -> bool {
+    matches!(value.split_once(':'), Some(("env" | "file" | "vault", _)))
+}
+
+/// Secret referenced by a setting value, `None` if the value is not a reference.
+pub(crate) async fn resolve_reference(reference: &str) -> Result<Option<String>> {
+    let Some((provider, location)) = reference.split_once(':') else {
+        return Ok(None);
+    };
+
+    match provider {
+        "env" => env::var(location)
+            .map(Some)
+            .map_err(|_| anyhow!("environment variable `{}` is not set", location)),
+        "file" => fs::read_to_string(location)
+            .map(|secret| Some(secret.trim_end_matches(['\r', '\n']).to_string()))
+            .map_err(|e| anyhow!("unable to read secret file `{}`: {}", location, e)),
+        "vault" => vault_secret(location).await.map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// Read a field of a Vault KV secret (`<path>#<field>`), KV v2 paths including `data/`.
+async fn vault_secret(location: &str) -> Result<String> {
+    let (path, field) = location.split_once('#').ok_or_else(|| {
+        anyhow!(
+            "invalid vault reference `{}`, expected `vault:<path>#<field>`",
+            location
+        )
+    })?;
+    let address = env::var(VAULT_ADDR_ENV)
+        .map_err(|_| anyhow!("`{}` must be set to read vault secrets", VAULT_ADDR_ENV))?;
+    let token = match env::var(VAULT_TOKEN_ENV) {
+        Ok(token) => token,
+        Err(_) => saved_vault_token().ok_or_else(|| {
+            anyhow!(
+                "`{}` must be set, or a token saved by `vault login`, to read vault secrets",
+                VAULT_TOKEN_ENV
+            )
+        })?,
+    };
+
+    let mut request = reqwest::Client::new()
+        .get(format!(
+            "{}/v1/{}",
+            address.trim_end_matches('/'),
+            path.trim_start_matches('/')
+        ))
+        .header("X-Vault-Token", token);
+    if let Ok(namespace) = env::var(VAULT_NAMESPACE_ENV) {
+        request = request.header("X-Vault-Namespace", namespace);
+    }
+
+    let response = request.send().await?;
+    if !response.status().is_success() {
+        bail!(
+            "unable to read vault secret `{}`: {}",
+            path,
+            response.status()
+        )
+    }
+
+    let secret: Value = response.json().await?;
+    match secret
+        .pointer(&format!("/data/data/{}", field))
+        .or_else(|| secret.pointer(&format!("/data/{}", field)))
+    {
+        Some(Value::String(value)) => Ok(value.clone()),
+        Some(value) => Ok(value.to_string()),
+        None => bail!("vault secret `{}` has no field `{}`", path, field),
+    }
+}
+
+/// Token saved by `vault login`.
+fn saved_vault_token() -> Option<String> {
+    let home = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE"))?;
+    fs::read_to_string(PathBuf::from(home).join(".vault-token"))
+        .ok()
+        .map(|token| token.trim().to_string())
+}
+
+/// Settings obtained from credential helpers, reused until they expire.
+static HELPER_SETTINGS: Mutex<BTreeMap<String, HelperSettings>> = Mutex::new(BTreeMap::new());
+
+/// Response of a credential helper.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+struct HelperSettings {
+    /// Settings merged over the service ones (e.g. `{"token": "..."}`)
+    settings: BTreeMap<String, Value>,
+    /// Expiration of the settings (RFC 3339), the helper being run again afterwards
+    #[serde(default)]
+    expires_at: Option<String>,
+}
+
+impl HelperSettings {
+    fn is_expired(&self) -> bool {
+        self.expires_at
+            .as_deref()
+            .and_then(|expires_at| humantime::parse_rfc3339_weak(expires_at).ok())
+            .is_some_and(|expires_at| expires_at <= SystemTime::now())
+    }
+}
+
+/// Settings of a service obtained from its credential helper, git credential helpers style.
+///
+/// The helper command runs through the shell from the project root, reads a JSON request
+/// (`{"operation": "get", "service": "...", "plugin": "..."}`) on stdin and prints
+/// `{"settings": {...}, "expires_at": "..."}` on stdout. Settings are kept for the run,
+/// or until they expire.
+pub async fn credential_helper_settings(
+    helper: &str,
+    service: &str,
+    plugin: &str,
+) -> Result<BTreeMap<String, Value>> {
+    let key = format!("{}\n{}", service, helper);
+    if let Some(cached) = HELPER_SETTINGS
+        .lock()
+        .map_err(|_| anyhow!("credential helper cache poisoned"))?
+        .get(&key)
+        .filter(|cached| !cached.is_expired())
+    {
+        return Ok(cached.settings.clone());
+    }
+
+    // Helpers may prompt on stderr, e.g. to complete an SSO login
+    let mut child = shell_command(helper)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| anyhow!("unable to run credential helper `{}`: {}", helper, e))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        let request = json!({ "operation": "get", "service": service, "plugin": plugin });
+        stdin.write_all(&serde_json::to_vec(&request)?).await?;
+    }
+
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        bail!("credential helper `{}` failed: {}", helper, output.status)
+    }
+    let response: HelperSettings = serde_json::from_slice(&output.stdout)
+        .map_err(|e| anyhow!("invalid response of credential helper `{}`: {}", helper, e))?;
+    if response.is_expired() {
+        bail!("credential helper `{}` returned expired settings", helper)
+    }
+
+    HELPER_SETTINGS
+        .lock()
+        .map_err(|_| anyhow!("credential helper cache poisoned"))?
+        .insert(key, response.clone());
+    Ok(response.settings)
+}
@@ -0,0 +1,871 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serde_yaml_ng::{Mapping, Value as YamlValue};
+use std::{collections::BTreeMap, fs, path::Path};
+
+use crate::utils::workspace_path;
+
+/// Query language of the rules deployed by a plugin.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SigmaBackend {
+    /// Splunk SPL searches
+    Splunk,
+    /// Microsoft Sentinel KQL queries
+    Kql,
+}
+
+/// Conversion of Sigma rules for a plugin, configured in `sigma.<plugin>`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SigmaMapping {
+    pub backend: SigmaBackend,
+    /// Sigma field names to field names of the service (e.g. `Image: process_path`)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, String>,
+    /// Log sources (`product`, `product/category` or `product/category/service`) to the
+    /// search prefix (SPL, e.g. `index=win sourcetype=sysmon`) or table (KQL) holding them
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub logsources: BTreeMap<String, String>,
+    /// Rule content, `{query}`, `{title}`, `{description}`, `{level}` and `{id}` are replaced in strings
+    pub template: Value,
+}
+
+/// Sigma rule, only the attributes used by the conversion are read.
+#[derive(Deserialize, Debug)]
+pub struct SigmaRule {
+    pub title: String,
+    pub id: Option<String>,
+    pub description: Option<String>,
+    pub level: Option<String>,
+    #[serde(default)]
+    pub logsource: LogSource,
+    pub detection: Mapping,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct LogSource {
+    pub product: Option<String>,
+    pub category: Option<String>,
+    pub service: Option<String>,
+}
+
+impl SigmaRule {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| anyhow!("unable to read `{}`: {}", workspace_path(path), e))?;
+        serde_yaml_ng::from_str(&content)
+            .map_err(|e| anyhow!("invalid sigma rule `{}`: {}", workspace_path(path), e))
+    }
+
+    /// Rule content for a plugin, following its mapping template.
+    pub fn convert(&self, mapping: &SigmaMapping) -> Result<Value> {
+        let query = mapping.query(self)?;
+        let placeholders = [
+            ("{query}", query.as_str()),
+            ("{title}", self.title.as_str()),
+            (
+                "{description}",
+                self.description.as_deref().unwrap_or_default(),
+            ),
+            ("{level}", self.level.as_deref().unwrap_or_default()),
+            ("{id}", self.id.as_deref().unwrap_or_default()),
+        ];
+
+        Ok(render_template(&mapping.template, &placeholders))
+    }
+}
+
+impl SigmaMapping {
+    /// Query of a Sigma rule in the backend language.
+    pub fn query(&self, rule: &SigmaRule) -> Result<String> {
+        let mut selections = BTreeMap::new();
+        let mut conditions = Vec::new();
+        for (name, value) in &rule.detection {
+            let name = name
+                .as_str()
+                .ok_or_else(|| anyhow!("detection keys must be strings"))?;
+            match name {
+                "condition" => match value {
+                    YamlValue::String(condition) => conditions.push(condition.clone()),
+                    YamlValue::Sequence(items) => {
+                        for item in items {
+                            conditions.push(
+                                item.as_str()
+                                    .ok_or_else(|| anyhow!("conditions must be strings"))?
+                                    .to_string(),
+                            )
+                        }
+                    }
+                    _ => bail!("condition must be a string or a list of strings"),
+                },
+                // Not part of the query
+                "timeframe" => (),
+                _ => {
+                    selections.insert(name.to_string(), self.selection(name, value)?);
+                }
+            }
+        }
+
+        if conditions.is_empty() {
+            bail!("detection has no condition")
+        }
+        let query = Query::any(
+            conditions
+                .iter()
+                .map(|condition| Condition::parse(condition, &selections))
+                .collect::<Result<_>>()?,
+        );
+        let query = self.backend.render(&query)?;
+
+        match (self.backend, self.logsource(&rule.logsource)) {
+            (SigmaBackend::Splunk, Some(prefix)) => Ok(format!("{} {}", prefix, query)),
+            (SigmaBackend::Splunk, None) => Ok(query),
+            (SigmaBackend::Kql, Some(table)) => Ok(format!("{}\n| where {}", table, query)),
+            (SigmaBackend::Kql, None) => bail!(
+                "no table mapped for log source `{}`, add it to `logsources`",
+                logsource_key(&rule.logsource)
+            ),
+        }
+    }
+
+    /// Most specific log source mapping of a rule.
+    fn logsource(&self, logsource: &LogSource) -> Option<&String> {
+        [
+            logsource_key(logsource),
+            [&logsource.product, &logsource.category]
+                .into_iter()
+                .flatten()
+                .cloned()
+                .collect::<Vec<_>>()
+                .join("/"),
+            logsource.product.clone().unwrap_or_default(),
+        ]
+        .iter()
+        .find_map(|key| self.logsources.get(key))
+    }
+
+    fn field(&self, name: &str) -> String {
+        self.fields
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| name.to_string())
+    }
+
+    /// Query of a named selection: field maps are and-ed, lists are or-ed.
+    fn selection(&self, name: &str, value: &YamlValue) -> Result<Query> {
+        match value {
+            YamlValue::Mapping(fields) => Ok(Query::all(
+                fields
+                    .iter()
+                    .map(|(key, value)| {
+                        let key = key
+                            .as_str()
+                            .ok_or_else(|| anyhow!("fields of `{}` must be strings", name))?;
+                        self.field_query(key, value)
+                    })
+                    .collect::<Result<_>>()?,
+            )),
+            YamlValue::Sequence(items) => Ok(Query::any(
+                items
+                    .iter()
+                    .map(|item| match item {
+                        YamlValue::Mapping(_) => self.selection(name, item),
+                        _ => Ok(Query::Match {
+                            field: None,
+                            matcher: Matcher::keyword(item)?,
+                        }),
+                    })
+                    .collect::<Result<_>>()?,
+            )),
+            _ => Ok(Query::Match {
+                field: None,
+                matcher: Matcher::keyword(value)?,
+            }),
+        }
+    }
+
+    /// Query of a `field|modifier: value(s)` entry.
+    fn field_query(&self, key: &str, value: &YamlValue) -> Result<Query> {
+        let mut modifiers = key.split('|');
+        // Safe unwrap as split always returns a first item
+        let field = self.field(modifiers.next().unwrap());
+        let mut all = false;
+        let mut modifier = None;
+        for name in modifiers {
+            match name {
+                "all" => all = true,
+                "contains" | "startswith" | "endswith" | "re" => modifier = Some(name),
+                _ => bail!("unsupported sigma modifier `{}` in `{}`", name, key),
+            }
+        }
+
+        let values = match value {
+            YamlValue::Sequence(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        if values.is_empty() {
+            bail!("`{}` has no value", key)
+        }
+
+        let terms = values
+            .into_iter()
+            .map(|value| match value {
+                YamlValue::Null => Ok(Query::Null(field.clone())),
+                value => Ok(Query::Match {
+                    field: Some(field.clone()),
+                    matcher: Matcher::new(value, modifier)?,
+                }),
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(if all {
+            Query::all(terms)
+        } else {
+            Query::any(terms)
+        })
+    }
+}
+
+fn logsource_key(logsource: &LogSource) -> String {
+    [&logsource.product, &logsource.category, &logsource.service]
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Replace placeholders in every string of a template.
+fn render_template(template: &Value, placeholders: &[(&str, &str)]) -> Value {
+    match template {
+        Value::String(value) => Value::String(
+            placeholders
+                .iter()
+                .fold(value.clone(), |value, (placeholder, replacement)| {
+                    value.replace(placeholder, replacement)
+                }),
+        ),
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .map(|item| render_template(item, placeholders))
+                .collect(),
+        ),
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(key, value)| (key.clone(), render_template(value, placeholders)))
+                .collect(),
+        ),
+        value => value.clone(),
+    }
+}
+
+/// Backend agnostic query of a Sigma detection.
+#[derive(Clone, Debug)]
+enum Query {
+    And(Vec<Query>),
+    Or(Vec<Query>),
+    Not(Box<Query>),
+    /// Field is not set
+    Null(String),
+    /// Field value, or any field for keywords
+    Match {
+        field: Option<String>,
+        matcher: Matcher,
+    },
+}
+
+impl Query {
+    fn all(mut terms: Vec<Query>) -> Query {
+        match terms.len() {
+            1 => terms.remove(0),
+            _ => Query::And(terms),
+        }
+    }
+
+    fn any(mut terms: Vec<Query>) -> Query {
+        match terms.len() {
+            1 => terms.remove(0),
+            _ => Query::Or(terms),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Matcher {
+    Number(String),
+    Regex(String),
+    Pattern(Vec<Part>),
+}
+
+/// Part of a Sigma string value, `*` and `?` being wildcards unless escaped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Part {
+    Char(char),
+    Any,
+    One,
+}
+
+impl Matcher {
+    fn new(value: &YamlValue, modifier: Option<&str>) -> Result<Self> {
+        let text = match value {
+            YamlValue::String(text) => text.clone(),
+            YamlValue::Number(number) if modifier.is_none() => {
+                return Ok(Self::Number(number.to_string()))
+            }
+            YamlValue::Number(number) => number.to_string(),
+            YamlValue::Bool(value) => value.to_string(),
+            _ => bail!("unsupported sigma value `{:?}`", value),
+        };
+
+        let parts = parse_pattern(&text);
+        Ok(match modifier {
+            Some("re") => Self::Regex(text),
+            Some("contains") => Self::Pattern(wrap(parts, true, true)),
+            Some("startswith") => Self::Pattern(wrap(parts, false, true)),
+            Some("endswith") => Self::Pattern(wrap(parts, true, false)),
+            _ => Self::Pattern(parts),
+        })
+    }
+
+    /// Keywords match anywhere in the event.
+    fn keyword(value: &YamlValue) -> Result<Self> {
+        match Self::new(value, None)? {
+            Self::Pattern(parts) if !parts.iter().any(|part| *part != Part::Any) => {
+                bail!("keywords cannot be empty")
+            }
+            Self::Pattern(parts) if parts.iter().all(|part| matches!(part, Part::Char(_))) => {
+                Ok(Self::Pattern(wrap(parts, true, true)))
+            }
+            matcher => Ok(matcher),
+        }
+    }
+}
+
+fn parse_pattern(text: &str) -> Vec<Part> {
+    let mut parts = Vec::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.peek() {
+                Some(escaped @ ('*' | '?' | '\\')) => {
+                    parts.push(Part::Char(*escaped));
+                    chars.next();
+                }
+                _ => parts.push(Part::Char(c)),
+            },
+            '*' => parts.push(Part::Any),
+            '?' => parts.push(Part::One),
+            c => parts.push(Part::Char(c)),
+        }
+    }
+    parts
+}
+
+fn wrap(mut parts: Vec<Part>, start: bool, end: bool) -> Vec<Part> {
+    if start && parts.first() != Some(&Part::Any) {
+        parts.insert(0, Part::Any);
+    }
+    if end && parts.last() != Some(&Part::Any) {
+        parts.push(Part::Any);
+    }
+    parts
+}
+
+fn literal(parts: &[Part]) -> Option<String> {
+    parts
+        .iter()
+        .map(|part| match part {
+            Part::Char(c) => Some(*c),
+            _ => None,
+        })
+        .collect()
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Condition expression over named selections (`selection and not 1 of filter_*`).
+struct Condition<'a> {
+    tokens: Vec<String>,
+    position: usize,
+    selections: &'a BTreeMap<String, Query>,
+}
+
+impl<'a> Condition<'a> {
+    fn parse(condition: &str, selections: &'a BTreeMap<String, Query>) -> Result<Query> {
+        if condition.contains('|') {
+            bail!("aggregation conditions are not supported: `{}`", condition)
+        }
+
+        let mut parser = Condition {
+            tokens: condition
+                .replace('(', " ( ")
+                .replace(')', " ) ")
+                .split_whitespace()
+                .map(String::from)
+                .collect(),
+            position: 0,
+            selections,
+        };
+        let query = parser.parse_or()?;
+        if let Some(token) = parser.next() {
+            bail!("unexpected `{}` in condition `{}`", token, condition)
+        }
+
+        Ok(query)
+    }
+
+    fn next(&mut self) -> Option<String> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn eat(&mut self, keyword: &str) -> bool {
+        let found = self.tokens.get(self.position).map(String::as_str) == Some(keyword);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn parse_or(&mut self) -> Result<Query> {
+        let mut terms = vec![self.parse_and()?];
+        while self.eat("or") {
+            terms.push(self.parse_and()?);
+        }
+        Ok(Query::any(terms))
+    }
+
+    fn parse_and(&mut self) -> Result<Query> {
+        let mut terms = vec![self.parse_not()?];
+        while self.eat("and") {
+            terms.push(self.parse_not()?);
+        }
+        Ok(Query::all(terms))
+    }
+
+    fn parse_not(&mut self) -> Result<Query> {
+        if self.eat("not") {
+            Ok(Query::Not(Box::new(self.parse_not()?)))
+        } else {
+            self.parse_primary()
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Query> {
+        let token = self
+            .next()
+            .ok_or_else(|| anyhow!("unexpected end of condition"))?;
+        match token.as_str() {
+            "(" => {
+                let query = self.parse_or()?;
+                if !self.eat(")") {
+                    bail!("missing `)` in condition")
+                }
+                Ok(query)
+            }
+            quantifier @ ("1" | "all") => {
+                if !self.eat("of") {
+                    bail!("expected `of` after `{}` in condition", quantifier)
+                }
+                let target = self
+                    .next()
+                    .ok_or_else(|| anyhow!("unexpected end of condition"))?;
+                let terms: Vec<Query> = self
+                    .selections
+                    .iter()
+                    .filter(|(name, _)| match target.as_str() {
+                        "them" => !name.starts_with('_'),
+                        pattern => matches_name(name, pattern),
+                    })
+                    .map(|(_, query)| query.clone())
+                    .collect();
+                if terms.is_empty() {
+                    bail!("no selection matches `{}` in condition", target)
+                }
+
+                Ok(match quantifier {
+                    "all" => Query::all(terms),
+                    _ => Query::any(terms),
+                })
+            }
+            name => self
+                .selections
+                .get(name)
+                .cloned()
+                .ok_or_else(|| anyhow!("condition references unknown selection `{}`", name)),
+        }
+    }
+}
+
+/// Match selection names against `selection_*` like patterns.
+fn matches_name(name: &str, pattern: &str) -> bool {
+    match pattern.split_once('*') {
+        Some((prefix, suffix)) => {
+            name.len() >= prefix.len() + suffix.len()
+                && name.starts_with(prefix)
+                && name.ends_with(suffix)
+        }
+        None => name == pattern,
+    }
+}
+
+impl SigmaBackend {
+    fn render(self, query: &Query) -> Result<String> {
+        match query {
+            Query::And(terms) => self.join(
+                terms,
+                match self {
+                    Self::Splunk => " AND ",
+                    Self::Kql => " and ",
+                },
+            ),
+            Query::Or(terms) => self.join(
+                terms,
+                match self {
+                    Self::Splunk => " OR ",
+                    Self::Kql => " or ",
+                },
+            ),
+            Query::Not(term) => match self {
+                Self::Splunk => Ok(format!("NOT {}", self.group(term)?)),
+                Self::Kql => Ok(format!("not({})", self.render(term)?)),
+            },
+            Query::Null(field) => match self {
+                Self::Splunk => Ok(format!("NOT {}=*", field)),
+                Self::Kql => Ok(format!("isempty({})", field)),
+            },
+            Query::Match { field, matcher } => match self {
+                Self::Splunk => Self::splunk_match(field.as_deref(), matcher),
+                Self::Kql => Self::kql_match(field.as_deref().unwrap_or("*"), matcher),
+            },
+        }
+    }
+
+    /// Render a term, in parentheses when combining several ones.
+    fn group(self, query: &Query) -> Result<String> {
+        let rendered = self.render(query)?;
+        Ok(match query {
+            Query::And(_) | Query::Or(_) => format!("({})", rendered),
+            _ => rendered,
+        })
+    }
+
+    fn join(self, terms: &[Query], separator: &str) -> Result<String> {
+        Ok(terms
+            .iter()
+            .map(|term| self.group(term))
+            .collect::<Result<Vec<_>>>()?
+            .join(separator))
+    }
+
+    fn splunk_match(field: Option<&str>, matcher: &Matcher) -> Result<String> {
+        let value = match matcher {
+            Matcher::Number(number) => number.clone(),
+            Matcher::Regex(_) => bail!("modifier `re` is not supported by the `splunk` backend"),
+            Matcher::Pattern(parts) => quote(
+                &parts
+                    .iter()
+                    .map(|part| match part {
+                        Part::Char(c) => Ok(*c),
+                        Part::Any => Ok('*'),
+                        Part::One => bail!("wildcard `?` is not supported by the `splunk` backend"),
+                    })
+                    .collect::<Result<String>>()?,
+            ),
+        };
+
+        Ok(match field {
+            Some(field) => format!("{}={}", field, value),
+            None => value,
+        })
+    }
+
+    fn kql_match(field: &str, matcher: &Matcher) -> Result<String> {
+        let parts = match matcher {
+            Matcher::Number(number) => return Ok(format!("{} == {}", field, number)),
+            Matcher::Regex(regex) => {
+                return Ok(format!("{} matches regex {}", field, quote(regex)))
+            }
+            Matcher::Pattern(parts) => parts.as_slice(),
+        };
+
+        let operator = match parts {
+            [Part::Any, inner @ .., Part::Any] => literal(inner).map(|value| ("contains", value)),
+            [inner @ .., Part::Any] => literal(inner).map(|value| ("startswith", value)),
+            [Part::Any, inner @ ..] => literal(inner).map(|value| ("endswith", value)),
+            _ => literal(parts).map(|value| ("=~", value)),
+        };
+
+        Ok(match operator {
+            Some((operator, value)) if !value.is_empty() => {
+                format!("{} {} {}", field, operator, quote(&value))
+            }
+            _ => {
+                let regex: String = parts
+                    .iter()
+                    .map(|part| match part {
+                        Part::Char(c) => regex::escape(&c.to_string()),
+                        Part::Any => String::from(".*"),
+                        Part::One => String::from("."),
+                    })
+                    .collect();
+                format!(
+                    "{} matches regex {}",
+                    field,
+                    quote(&format!("(?i)^{}$", regex))
+                )
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapping(backend: SigmaBackend) -> SigmaMapping {
+        SigmaMapping {
+            backend,
+            fields: BTreeMap::from([("Image".to_string(), "process_path".to_string())]),
+            logsources: BTreeMap::from([(
+                "windows/process_creation".to_string(),
+                match backend {
+                    SigmaBackend::Splunk => "index=win sourcetype=sysmon".to_string(),
+                    SigmaBackend::Kql => "DeviceProcessEvents".to_string(),
+                },
+            )]),
+            template: Value::String("{query}".to_string()),
+        }
+    }
+
+    /// Query of a rule with the given `detection` section, and log source if any.
+    fn query(backend: SigmaBackend, logsource: &str, detection: &str) -> Result<String> {
+        let rule: SigmaRule = serde_yaml_ng::from_str(&format!(
+            "title: test\nlogsource: {}\ndetection:\n{}",
+            logsource,
+            detection
+                .lines()
+                .map(|line| format!("  {}\n", line))
+                .collect::<String>()
+        ))
+        .unwrap();
+        mapping(backend).query(&rule)
+    }
+
+    #[test]
+    fn splunk_queries() {
+        let cases = [
+            (
+                "sel:\n  Image|endswith: '\\cmd.exe'\ncondition: sel",
+                r#"process_path="*\\cmd.exe""#,
+            ),
+            (
+                "sel:\n  CommandLine|contains:\n  - whoami\n  - net user\ncondition: sel",
+                r#"CommandLine="*whoami*" OR CommandLine="*net user*""#,
+            ),
+            (
+                "sel:\n  CommandLine|contains|all:\n  - net\n  - user\ncondition: sel",
+                r#"CommandLine="*net*" AND CommandLine="*user*""#,
+            ),
+            (
+                "sel:\n  EventID: 4688\n  User|startswith: adm\ncondition: sel",
+                r#"EventID=4688 AND User="adm*""#,
+            ),
+            (
+                "sel:\n  EventID: 4688\nfilter:\n  User: SYSTEM\ncondition: sel and not filter",
+                r#"EventID=4688 AND NOT User="SYSTEM""#,
+            ),
+            (
+                "sel_a:\n  A: x\nsel_b:\n  B: y\ncondition: 1 of sel_*",
+                r#"A="x" OR B="y""#,
+            ),
+            (
+                "sel_a:\n  A: x\nsel_b:\n  B: y\ncondition: all of them",
+                r#"A="x" AND B="y""#,
+            ),
+            (
+                "a:\n  A: 1\nb:\n  B: 2\nc:\n  C: 3\ncondition: a or b and c",
+                "A=1 OR (B=2 AND C=3)",
+            ),
+            (
+                "a:\n  A: 1\nb:\n  B: 2\nc:\n  C: 3\ncondition: (a or b) and c",
+                "(A=1 OR B=2) AND C=3",
+            ),
+            ("sel:\n  A: null\ncondition: sel", "NOT A=*"),
+            (
+                "keywords:\n- mimikatz\ncondition: keywords",
+                r#""*mimikatz*""#,
+            ),
+            (
+                "sel:\n  A: 'say \"hi\"'\ncondition: sel",
+                r#"A="say \"hi\"""#,
+            ),
+        ];
+
+        for (detection, expected) in cases {
+            assert_eq!(
+                query(SigmaBackend::Splunk, "{}", detection).unwrap(),
+                expected,
+                "{}",
+                detection
+            );
+        }
+    }
+
+    #[test]
+    fn kql_queries() {
+        let cases = [
+            (
+                "sel:\n  CommandLine|contains: whoami\ncondition: sel",
+                r#"CommandLine contains "whoami""#,
+            ),
+            ("sel:\n  A: x\ncondition: sel", r#"A =~ "x""#),
+            (
+                "sel:\n  A|startswith: x\ncondition: sel",
+                r#"A startswith "x""#,
+            ),
+            ("sel:\n  EventID: 4688\ncondition: sel", "EventID == 4688"),
+            (
+                "sel:\n  A|re: '^a.*$'\ncondition: sel",
+                r#"A matches regex "^a.*$""#,
+            ),
+            (
+                "sel:\n  A: a*b\ncondition: sel",
+                r#"A matches regex "(?i)^a.*b$""#,
+            ),
+            (
+                "sel:\n  A: x\nfilter:\n  B: y\ncondition: sel and not filter",
+                r#"A =~ "x" and not(B =~ "y")"#,
+            ),
+            ("sel:\n  A: null\ncondition: sel", "isempty(A)"),
+        ];
+
+        let logsource = "{product: windows, category: process_creation}";
+        for (detection, expected) in cases {
+            assert_eq!(
+                query(SigmaBackend::Kql, logsource, detection).unwrap(),
+                format!("DeviceProcessEvents\n| where {}", expected),
+                "{}",
+                detection
+            );
+        }
+    }
+
+    #[test]
+    fn logsource_prefixes() {
+        let detection = "sel:\n  A: x\ncondition: sel";
+        let cases = [
+            (
+                "{product: windows, category: process_creation, service: sysmon}",
+                r#"index=win sourcetype=sysmon A="x""#,
+            ),
+            ("{product: linux}", r#"A="x""#),
+        ];
+
+        for (logsource, expected) in cases {
+            assert_eq!(
+                query(SigmaBackend::Splunk, logsource, detection).unwrap(),
+                expected,
+                "{}",
+                logsource
+            );
+        }
+    }
+
+    #[test]
+    fn unsupported_detections() {
+        let cases = [
+            (
+                SigmaBackend::Splunk,
+                "sel:\n  A|base64: x\ncondition: sel",
+                "unsupported sigma modifier `base64`",
+            ),
+            (
+                SigmaBackend::Splunk,
+                "sel:\n  A: x\ncondition: sel | count() > 5",
+                "aggregation conditions are not supported",
+            ),
+            (
+                SigmaBackend::Splunk,
+                "sel:\n  A: x\ncondition: other",
+                "unknown selection `other`",
+            ),
+            (
+                SigmaBackend::Splunk,
+                "sel:\n  A: x\ncondition: 1 of filter_*",
+                "no selection matches `filter_*`",
+            ),
+            (
+                SigmaBackend::Splunk,
+                "sel:\n  A: x\ncondition: (sel",
+                "missing `)`",
+            ),
+            (
+                SigmaBackend::Splunk,
+                "sel:\n  A: x\ncondition: sel sel",
+                "unexpected `sel`",
+            ),
+            (
+                SigmaBackend::Splunk,
+                "sel:\n  A: x",
+                "detection has no condition",
+            ),
+            (
+                SigmaBackend::Splunk,
+                "sel:\n  A|re: x\ncondition: sel",
+                "modifier `re` is not supported",
+            ),
+            (
+                SigmaBackend::Splunk,
+                "sel:\n  A: a?c\ncondition: sel",
+                "wildcard `?` is not supported",
+            ),
+            (
+                SigmaBackend::Kql,
+                "sel:\n  A: x\ncondition: sel",
+                "no table mapped for log source",
+            ),
+        ];
+
+        for (backend, detection, expected) in cases {
+            let error = query(backend, "{product: linux}", detection)
+                .unwrap_err()
+                .to_string();
+            assert!(error.contains(expected), "{}: {}", detection, error);
+        }
+    }
+
+    #[test]
+    fn template_placeholders() {
+        let rule: SigmaRule = serde_yaml_ng::from_str(
+            "title: Whoami\nid: '1234'\nlevel: high\ndetection:\n  sel:\n    A: x\n  condition: sel",
+        )
+        .unwrap();
+        let mut mapping = mapping(SigmaBackend::Splunk);
+        mapping.template = serde_json::json!({
+            "name": "{title} ({level})",
+            "search": "{query}",
+            "tags": ["sigma-{id}"],
+            "enabled": true,
+        });
+
+        assert_eq!(
+            rule.convert(&mapping).unwrap(),
+            serde_json::json!({
+                "name": "Whoami (high)",
+                "search": "A=\"x\"",
+                "tags": ["sigma-1234"],
+                "enabled": true,
+            })
+        );
+    }
+}
@@ -3,11 +3,13 @@
 
 use std::collections::HashMap;
 use std::str::FromStr;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use super::State;
+use super::{LockInfo, State, LGC_DEFAULT_WORKSPACE};
+use crate::configuration::LintFinding;
 use anyhow::{anyhow, bail, Result};
 use async_trait::async_trait;
+use dialoguer::Confirm;
 use regex::Regex;
 use reqwest::{
     header,
@@ -17,9 +19,14 @@ use reqwest::{
 use serde::{Deserialize, Serialize};
 use serde_with::skip_serializing_none;
 use url::Url;
-use uuid::Uuid;
 
-use super::BackendActions;
+use super::{BackendActions, LockMode};
+
+/// Replaced by the workspace name in addresses.
+const WORKSPACE_PLACEHOLDER: &str = "{workspace}";
+
+/// Interval between two state lock attempts.
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_secs(5);
 
 #[skip_serializing_none]
 #[derive(Serialize, Deserialize, Clone)]
@@ -38,9 +45,126 @@ pub struct HttpBackend {
     client_certificate_pem: Option<String>,
     client_private_key_pem: Option<String>,
     headers: Option<HashMap<String, String>>,
+    /// Age after which a lock is considered stale (e.g. `1h`)
+    lock_max_age: Option<String>,
+    /// Address receiving the previous state before each save (`POST ?serial=<n>`), serving it
+    /// back (`GET ?serial=<n>`) and listing the serials it keeps (`GET`, a JSON array).
+    /// Retention is up to the server
+    backup_address: Option<String>,
+    #[serde(skip)]
+    pub(super) lock_timeout: Option<Duration>,
+    #[serde(skip)]
+    pub(super) lock_mode: LockMode,
+    #[serde(skip)]
+    pub(super) lock_operation: String,
 }
 
 impl HttpBackend {
+    /// Replace `{workspace}` in addresses, which must use it for named workspaces.
+    pub(super) fn set_workspace(&mut self, workspace: &str) -> Result<()> {
+        if workspace != LGC_DEFAULT_WORKSPACE && !self.address.contains(WORKSPACE_PLACEHOLDER) {
+            bail!(
+                "http state backend address must contain `{}` to use workspace `{}`",
+                WORKSPACE_PLACEHOLDER,
+                workspace
+            )
+        }
+
+        for address in [
+            Some(&mut self.address),
+            self.lock_address.as_mut(),
+            self.unlock_address.as_mut(),
+            self.backup_address.as_mut(),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *address = address.replace(WORKSPACE_PLACEHOLDER, workspace);
+        }
+
+        Ok(())
+    }
+
+    /// Address of state backups, which are not kept without it.
+    fn backup_address(&self) -> Result<&str> {
+        self.backup_address.as_deref().ok_or_else(|| {
+            anyhow!("state backups are not kept by the http state backend without `backup_address`")
+        })
+    }
+
+    /// Look for options which conflict or are ignored.
+    pub(super) fn lint(&self) -> Vec<LintFinding> {
+        let mut findings = Vec::new();
+
+        if self.lock_address.is_none() {
+            for (option, set) in [
+                ("unlock_address", self.unlock_address.is_some()),
+                ("lock_method", self.lock_method.is_some()),
+                ("unlock_method", self.unlock_method.is_some()),
+                ("lock_max_age", self.lock_max_age.is_some()),
+            ] {
+                if set {
+                    findings.push(LintFinding::new(
+                        format!(
+                            "state option `{}` is ignored as `lock_address` is not set",
+                            option
+                        ),
+                        format!("set `lock_address` or remove `{}`", option),
+                    ));
+                }
+            }
+        } else if self.unlock_address.is_none() {
+            findings.push(LintFinding::new(
+                String::from("state is locked through `lock_address` but never unlocked"),
+                String::from("set `unlock_address`"),
+            ));
+        }
+
+        if self.password.is_some() && self.username.is_none() {
+            findings.push(LintFinding::new(
+                String::from("state option `password` is ignored as `username` is not set"),
+                String::from("set `username` or remove `password`"),
+            ));
+        }
+
+        if self.username.is_some()
+            && self.headers.as_ref().is_some_and(|headers| {
+                headers
+                    .keys()
+                    .any(|key| key.eq_ignore_ascii_case(header::AUTHORIZATION.as_str()))
+            })
+        {
+            findings.push(LintFinding::new(
+                format!(
+                    "state `{}` header conflicts with `username`",
+                    header::AUTHORIZATION
+                ),
+                format!(
+                    "remove either `username` or the `{}` header",
+                    header::AUTHORIZATION
+                ),
+            ));
+        }
+
+        if self.client_certificate_pem.is_some() != self.client_private_key_pem.is_some() {
+            findings.push(LintFinding::new(
+                String::from("state client certificate and private key must be set together"),
+                String::from("set both `client_certificate_pem` and `client_private_key_pem`"),
+            ));
+        }
+
+        if self.skip_cert_verification.unwrap_or_default()
+            && self.client_ca_certificate_pem.is_some()
+        {
+            findings.push(LintFinding::new(
+                String::from("state option `client_ca_certificate_pem` is ignored as `skip_cert_verification` is enabled"),
+                String::from("disable `skip_cert_verification` or remove `client_ca_certificate_pem`"),
+            ));
+        }
+
+        findings
+    }
+
     fn check_headers(&self) -> Result<HeaderMap> {
         let mut headermap = HeaderMap::new();
         if let Some(headers) = &self.headers {
@@ -115,27 +239,84 @@ impl HttpBackend {
         .map_err(|e| anyhow::anyhow!("unable to retrieve state: {}", e))
     }
 
-    async fn lock(&self, client: &Client, lock_address: &str) -> Result<Uuid> {
+    async fn lock(&self, client: &Client, lock_address: &str) -> Result<String> {
         let lock_method = self.lock_method.clone().unwrap_or("LOCK".to_string());
+        let lock_max_age = match &self.lock_max_age {
+            Some(age) => Some(
+                humantime::parse_duration(age)
+                    .map_err(|e| anyhow!("invalid state lock_max_age `{}`: {}", age, e))?,
+            ),
+            None => None,
+        };
 
-        let lock_id = Uuid::new_v4();
+        let lock_info = LockInfo::new(&self.lock_operation);
+        let started = Instant::now();
 
-        let req = client
-            .request(Method::from_str(&lock_method)?, Url::parse(lock_address)?)
-            .query(&[("ID", &lock_id)]);
+        loop {
+            let req = client
+                .request(Method::from_str(&lock_method)?, Url::parse(lock_address)?)
+                .query(&[("ID", &lock_info.id)])
+                .json(&lock_info);
 
-        match self.send_auth(req).await {
-            Ok(resp) => match resp.status() {
-                StatusCode::OK => Ok(lock_id),
-                // StatusCode::CONFLICT => bail!("unable to lock state: already locked"),
+            let resp = self
+                .send_auth(req)
+                .await
+                .map_err(|e| anyhow!("unable to lock state: {}", e))?;
+
+            match resp.status() {
+                StatusCode::OK => return Ok(lock_info.id),
+                StatusCode::CONFLICT | StatusCode::LOCKED => {
+                    let holder: Option<LockInfo> = resp.json().await.ok();
+
+                    // Offer to release stale locks
+                    if let Some(holder) = &holder {
+                        if lock_max_age.is_some_and(|max| holder.age().is_some_and(|age| age > max))
+                            && console::user_attended()
+                            && Confirm::new()
+                                .with_prompt(format!(
+                                    "State {} looks stale, force unlock ?",
+                                    holder
+                                ))
+                                .interact()?
+                        {
+                            self.unlock(client, &holder.id).await?;
+                            continue;
+                        }
+                    }
+
+                    let holder = holder
+                        .map(|holder| holder.to_string())
+                        .unwrap_or(String::from("unknown lock"));
+
+                    if self
+                        .lock_timeout
+                        .map_or(true, |timeout| started.elapsed() >= timeout)
+                    {
+                        bail!(
+                            "unable to lock state: {}, run `lgc state force-unlock <id>` if its run crashed",
+                            holder
+                        )
+                    }
+
+                    tracing::info!("state is locked ({}), retrying...", holder);
+                    tokio::time::sleep(LOCK_RETRY_INTERVAL).await;
+                }
                 _ => bail!(
                     "unable to lock state: {} {}",
                     resp.status(),
                     resp.text().await?
                 ),
-            },
-            Err(e) => bail!("unable to lock state: {}", e),
+            }
+        }
+    }
+
+    /// Release a lock left by a crashed run, the server checks the ID.
+    pub(super) async fn force_unlock(&self, lock_id: &str) -> Result<()> {
+        if self.lock_address.is_none() || self.unlock_address.is_none() {
+            bail!("state is not locked as `lock_address` or `unlock_address` is not set")
         }
+
+        self.unlock(&self.client()?, lock_id).await
     }
 
     async fn unlock(&self, client: &Client, lock_id: &str) -> Result<()> {
@@ -180,52 +361,98 @@ impl BackendActions for HttpBackend {
         }
     }
 
-    async fn save(&self, state: &mut State) -> anyhow::Result<()> {
+    async fn save(&self, state: &mut State, lock_id: Option<&str>) -> anyhow::Result<()> {
         let client = self.client()?;
 
         state.serial += 1;
         state.lgc_version = env!("CARGO_PKG_VERSION").to_string();
 
-        // Lock state - If lock address is not set ignore state locking
-        let (req, lock_id) = if let Some(address) = &self.lock_address {
-            let lock_id = self.lock(&client, address).await?;
-            (
-                client
-                    .request(
-                        Method::from_str(
-                            self.update_method.as_ref().unwrap_or(&"POST".to_string()),
-                        )?,
-                        Url::from_str(&self.address)?,
-                    )
-                    .query(&[("ID", lock_id)])
-                    .json(state),
-                &self.lock_address,
-            )
-        } else {
-            (
-                client
-                    .request(
-                        Method::from_str(
-                            self.update_method.as_ref().unwrap_or(&"POST".to_string()),
-                        )?,
-                        Url::from_str(&self.address)?,
-                    )
-                    .json(state),
-                &None,
-            )
+        // Send the current remote state to the backup address before overwriting it
+        if let Some(address) = &self.backup_address {
+            let previous = self.load().await?;
+            if previous.serial > 0 {
+                let req = client
+                    .request(Method::POST, Url::parse(address)?)
+                    .query(&[("serial", previous.serial)])
+                    .json(&previous);
+                let resp = self
+                    .send_auth(req)
+                    .await
+                    .map_err(|e| anyhow!("unable to backup state: {}", e))?;
+                if !resp.status().is_success() {
+                    bail!("unable to backup state: {}", resp.status())
+                }
+            }
+        }
+
+        // Updates of a locked state carry the ID of its lock
+        let req = client.request(
+            Method::from_str(self.update_method.as_ref().unwrap_or(&"POST".to_string()))?,
+            Url::from_str(&self.address)?,
+        );
+        let req = match lock_id {
+            Some(lock_id) => req.query(&[("ID", lock_id)]),
+            None => req,
         };
+        self.send_auth(req.json(state))
+            .await
+            .map_err(|e| anyhow!("unable to save state: {}", e))?;
+        Ok(())
+    }
 
-        match lock_id {
-            Some(lock_id) => {
-                self.send_auth(req)
+    async fn acquire_lock(&self) -> Result<Option<String>> {
+        match &self.lock_address {
+            Some(address) => self.lock(&self.client()?, address).await.map(Some),
+            // Without lock address, state locking is ignored
+            None => Ok(None),
+        }
+    }
+
+    async fn release_lock(&self, lock_id: &str) -> Result<()> {
+        self.unlock(&self.client()?, lock_id).await
+    }
+
+    async fn backups(&self) -> Result<Vec<usize>> {
+        let req = self
+            .client()?
+            .request(Method::GET, Url::parse(self.backup_address()?)?);
+
+        let resp = self.send_auth(req).await?;
+        match resp.status() {
+            StatusCode::OK => {
+                let mut serials: Vec<usize> = resp
+                    .json()
                     .await
-                    .map_err(|e| anyhow!("unable to save state: {}", e))?;
-                self.unlock(&client, lock_id).await
+                    .map_err(|e| anyhow!("unable to decode state backups: {}", e))?;
+                serials.sort_unstable();
+                Ok(serials)
             }
-            None => {
-                self.send_auth(req).await?;
-                Ok(())
+            StatusCode::NOT_FOUND
+            | StatusCode::METHOD_NOT_ALLOWED
+            | StatusCode::NOT_IMPLEMENTED => {
+                bail!(
+                    "the state backup server does not list backups ({}), restore one by serial",
+                    resp.status()
+                )
             }
+            _ => bail!("unable to list state backups: {}", resp.status()),
+        }
+    }
+
+    async fn load_backup(&self, serial: usize) -> Result<State> {
+        let req = self
+            .client()?
+            .request(Method::GET, Url::parse(self.backup_address()?)?)
+            .query(&[("serial", serial)]);
+
+        let resp = self.send_auth(req).await?;
+        match resp.status() {
+            StatusCode::OK => resp
+                .json()
+                .await
+                .map_err(|e| anyhow!("unable to decode state backup: {}", e)),
+            StatusCode::NOT_FOUND => bail!("state backup `{}` does not exist", serial),
+            _ => bail!("unable to retrieve state backup: {}", resp.status()),
         }
     }
 }
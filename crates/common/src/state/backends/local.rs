@@ -2,28 +2,200 @@
 // SPDX-License-Identifier: MPL-2.0
 
 use crate::state::LGC_DEFAULT_STATE_PATH;
-use anyhow::{anyhow, Ok, Result};
+use anyhow::{anyhow, bail, Ok, Result};
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
-use std::{fs, io, path};
+use std::{
+    fs, io, path,
+    time::{Duration, Instant},
+};
 
-use super::State;
+use super::{LockAttempt, LockInfo, LockMode, State};
 
-use super::BackendActions;
+use super::{workspace_key, BackendActions};
+
+/// Number of state backups kept by default.
+const DEFAULT_BACKUP_RETENTION: usize = 5;
+
+/// Interval between two state lock attempts.
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_secs(1);
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct LocalBackend {
     path: path::PathBuf,
+    /// Number of state backups to keep, `0` disables backups
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    backup_retention: Option<usize>,
+    #[serde(skip)]
+    pub(super) lock_timeout: Option<Duration>,
+    #[serde(skip)]
+    pub(super) lock_mode: LockMode,
+    #[serde(skip)]
+    pub(super) lock_operation: String,
 }
 
 impl Default for LocalBackend {
     fn default() -> Self {
         Self {
             path: path::PathBuf::from(LGC_DEFAULT_STATE_PATH),
+            backup_retention: None,
+            lock_timeout: None,
+            lock_mode: LockMode::default(),
+            lock_operation: String::new(),
         }
     }
 }
 
+impl LocalBackend {
+    pub(super) fn set_workspace(&mut self, workspace: &str) -> Result<()> {
+        self.path = path::PathBuf::from(workspace_key(&self.path.to_string_lossy(), workspace));
+        Ok(())
+    }
+
+    /// Lock file next to the state (`.logcraft/state.json` → `.logcraft/state.lock`).
+    fn lock_path(&self) -> path::PathBuf {
+        self.path.with_extension("lock")
+    }
+
+    /// Create the lock file, only if it does not exist yet.
+    fn try_lock(&self, info: &LockInfo) -> Result<LockAttempt> {
+        let lock_path = self.lock_path();
+        if let Some(parent) = lock_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let file = match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+        {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                return Ok(LockAttempt::Held(self.holder()))
+            }
+            result => result.map_err(|e| anyhow!("unable to lock state: {}", e))?,
+        };
+
+        serde_json::to_writer_pretty(file, info)
+            .map_err(|e| anyhow!("unable to lock state: {}", e))?;
+        Ok(LockAttempt::Acquired)
+    }
+
+    async fn lock(&self) -> Result<String> {
+        let lock_info = LockInfo::new(&self.lock_operation);
+        let started = Instant::now();
+
+        loop {
+            let holder = match self.try_lock(&lock_info)? {
+                LockAttempt::Acquired => return Ok(lock_info.id),
+                LockAttempt::Held(holder) => holder
+                    .map(|holder| holder.to_string())
+                    .unwrap_or(String::from("unknown lock")),
+            };
+
+            if self
+                .lock_timeout
+                .map_or(true, |timeout| started.elapsed() >= timeout)
+            {
+                bail!(
+                    "unable to lock state: {}, run `lgc state force-unlock <id>` if its run crashed",
+                    holder
+                )
+            }
+
+            tracing::info!("state is locked ({}), retrying...", holder);
+            tokio::time::sleep(LOCK_RETRY_INTERVAL).await;
+        }
+    }
+
+    fn unlock(&self) -> Result<()> {
+        fs::remove_file(self.lock_path()).map_err(|e| anyhow!("unable to unlock state: {}", e))
+    }
+
+    /// Information of the current lock holder, if any and readable.
+    pub(super) fn holder(&self) -> Option<LockInfo> {
+        fs::read_to_string(self.lock_path())
+            .ok()
+            .and_then(|holder| serde_json::from_str(&holder).ok())
+    }
+
+    pub(super) fn force_unlock(&self, id: &str) -> Result<()> {
+        let lock_path = self.lock_path();
+        if !lock_path.is_file() {
+            bail!("state is not locked")
+        }
+
+        match self.holder() {
+            Some(holder) if holder.id == id => self.unlock(),
+            Some(holder) => bail!("state {}, not by lock `{}`", holder, id),
+            None => bail!(
+                "state lock file `{}` is unreadable, remove it once no run is in progress",
+                lock_path.display()
+            ),
+        }
+    }
+
+    fn write(&self, state: &mut State) -> Result<()> {
+        self.backup(state.serial)?;
+        let f = fs::File::create(&self.path)?;
+
+        state.serial += 1;
+        state.lgc_version = env!("CARGO_PKG_VERSION").to_string();
+
+        let writer = io::BufWriter::new(f);
+        serde_json::to_writer_pretty(writer, state)
+            .map_err(|e| anyhow!("unable to write state file: {}", e))
+    }
+
+    fn backup_path(&self, serial: usize) -> path::PathBuf {
+        let mut name = self.path.as_os_str().to_owned();
+        name.push(format!(".backup-{}", serial));
+        path::PathBuf::from(name)
+    }
+
+    /// Copy the current state file aside before it is overwritten.
+    fn backup(&self, serial: usize) -> Result<()> {
+        let retention = self.backup_retention.unwrap_or(DEFAULT_BACKUP_RETENTION);
+        if retention == 0 || !self.path.is_file() {
+            return Ok(());
+        }
+
+        fs::copy(&self.path, self.backup_path(serial))
+            .map_err(|e| anyhow!("unable to backup state file: {}", e))?;
+
+        // Remove oldest backups
+        let backups = self.list_backups()?;
+        for serial in backups.iter().take(backups.len().saturating_sub(retention)) {
+            fs::remove_file(self.backup_path(*serial))?;
+        }
+
+        Ok(())
+    }
+
+    fn list_backups(&self) -> Result<Vec<usize>> {
+        let prefix = match self.path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => format!("{}.backup-", name),
+            None => return Ok(Vec::new()),
+        };
+        let directory = match self.path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => path::Path::new("."),
+        };
+        if !directory.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        let mut serials: Vec<usize> = fs::read_dir(directory)?
+            .filter_map(|entry| {
+                let name = entry.ok()?.file_name();
+                name.to_str()?.strip_prefix(&prefix)?.parse().ok()
+            })
+            .collect();
+        serials.sort_unstable();
+
+        Ok(serials)
+    }
+}
+
 #[async_trait]
 impl BackendActions for LocalBackend {
     async fn load(&self) -> Result<State> {
@@ -37,14 +209,29 @@ impl BackendActions for LocalBackend {
         serde_json::from_reader(reader).map_err(|e| anyhow!("unable to load state file: {}", e))
     }
 
-    async fn save(&self, state: &mut State) -> anyhow::Result<()> {
-        let f = fs::File::create(&self.path)?;
+    async fn save(&self, state: &mut State, _lock_id: Option<&str>) -> anyhow::Result<()> {
+        self.write(state)
+    }
 
-        state.serial += 1;
-        state.lgc_version = env!("CARGO_PKG_VERSION").to_string();
+    async fn acquire_lock(&self) -> Result<Option<String>> {
+        self.lock().await.map(Some)
+    }
 
-        let writer = io::BufWriter::new(f);
-        serde_json::to_writer_pretty(writer, state)
-            .map_err(|e| anyhow!("unable to write state file: {}", e))
+    async fn release_lock(&self, _lock_id: &str) -> Result<()> {
+        self.unlock()
+    }
+
+    async fn backups(&self) -> Result<Vec<usize>> {
+        self.list_backups()
+    }
+
+    async fn load_backup(&self, serial: usize) -> Result<State> {
+        let path = self.backup_path(serial);
+        if !path.is_file() {
+            bail!("state backup `{}` does not exist", serial)
+        }
+
+        let reader = io::BufReader::new(fs::File::open(&path)?);
+        serde_json::from_reader(reader).map_err(|e| anyhow!("unable to load state backup: {}", e))
     }
 }
@@ -1,17 +1,26 @@
 // Copyright (c) 2023 LogCraft, SAS.
 // SPDX-License-Identifier: MPL-2.0
 
-use super::State;
-use anyhow::Result;
+use super::{State, LGC_DEFAULT_WORKSPACE};
+use crate::configuration::LintFinding;
+use anyhow::{anyhow, bail, Result};
 use async_trait::async_trait;
 use local::LocalBackend;
 use serde::{Deserialize, Serialize};
+use std::{
+    env,
+    time::{Duration, SystemTime},
+};
+use tokio::runtime::RuntimeFlavor;
+use uuid::Uuid;
 
 // Backends
 mod http;
 mod local;
+mod s3;
 
 use http::HttpBackend;
+use s3::S3Backend;
 
 #[derive(Serialize, Deserialize, Clone)]
 #[serde(tag = "type")]
@@ -20,13 +29,162 @@ pub enum StateBackend {
     Local(LocalBackend),
     /// Http state backend
     Http(Box<HttpBackend>),
+    /// S3 compatible object store state backend
+    S3(Box<S3Backend>),
+}
+
+/// How a command holds the state lock, on backends configured with one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LockMode {
+    /// Commands changing the state hold the lock from its load to their last save, making concurrent changes wait
+    #[default]
+    Exclusive,
+    /// Read-only use, the lock is never taken so applies are not delayed and the state cannot be saved
+    Shared,
+    /// Saves do not lock the state (`--lock=false`), for servers without lock support
+    Disabled,
 }
 
 impl StateBackend {
+    #[tracing::instrument(name = "state.load", skip_all)]
     pub async fn load(&self) -> Result<State> {
+        // Readers do not wait for changes in progress, they only mention them
+        if self.lock_mode() == LockMode::Shared {
+            if let Some(holder) = self.lock_holder().await {
+                tracing::warn!(
+                    "state is being changed ({}), results may be outdated",
+                    holder
+                );
+            }
+        }
+
         match self {
             Self::Local(path) => path.load().await,
             Self::Http(backend) => backend.load().await,
+            Self::S3(backend) => backend.load().await,
+        }
+    }
+
+    pub async fn backups(&self) -> Result<Vec<usize>> {
+        match self {
+            Self::Local(path) => path.backups().await,
+            Self::Http(backend) => backend.backups().await,
+            Self::S3(backend) => backend.backups().await,
+        }
+    }
+
+    pub async fn load_backup(&self, serial: usize) -> Result<State> {
+        match self {
+            Self::Local(path) => path.load_backup(serial).await,
+            Self::Http(backend) => backend.load_backup(serial).await,
+            Self::S3(backend) => backend.load_backup(serial).await,
+        }
+    }
+
+    /// Look for conflicting backend options.
+    pub fn lint(&self) -> Vec<LintFinding> {
+        match self {
+            Self::Local(_) => Vec::new(),
+            Self::Http(backend) => backend.lint(),
+            Self::S3(backend) => backend.lint(),
+        }
+    }
+
+    /// Use the state of a named workspace, the default workspace keeps the configured location.
+    pub fn set_workspace(&mut self, workspace: &str) -> Result<()> {
+        match self {
+            Self::Local(backend) => backend.set_workspace(workspace),
+            Self::Http(backend) => backend.set_workspace(workspace),
+            Self::S3(backend) => backend.set_workspace(workspace),
+        }
+    }
+
+    /// Override the time spent waiting for a locked state to be released.
+    pub fn set_lock_timeout(&mut self, timeout: Duration) {
+        match self {
+            Self::Local(backend) => backend.lock_timeout = Some(timeout),
+            Self::Http(backend) => backend.lock_timeout = Some(timeout),
+            Self::S3(backend) => backend.lock_timeout = Some(timeout),
+        }
+    }
+
+    /// Change how the state lock is held, `Exclusive` unless set.
+    pub fn set_lock_mode(&mut self, mode: LockMode) {
+        match self {
+            Self::Local(backend) => backend.lock_mode = mode,
+            Self::Http(backend) => backend.lock_mode = mode,
+            Self::S3(backend) => backend.lock_mode = mode,
+        }
+    }
+
+    /// Command recorded as the operation of the locks taken (e.g. `deploy`).
+    pub fn set_lock_operation(&mut self, operation: &str) {
+        match self {
+            Self::Local(backend) => backend.lock_operation = operation.to_string(),
+            Self::Http(backend) => backend.lock_operation = operation.to_string(),
+            Self::S3(backend) => backend.lock_operation = operation.to_string(),
+        }
+    }
+
+    pub fn lock_mode(&self) -> LockMode {
+        match self {
+            Self::Local(backend) => backend.lock_mode,
+            Self::Http(backend) => backend.lock_mode,
+            Self::S3(backend) => backend.lock_mode,
+        }
+    }
+
+    /// Take the state lock before loading a state to change, it is held until the guard is dropped
+    /// so that no other run changes the state in between.
+    pub async fn lock(&self) -> Result<StateLock> {
+        let id = match self.lock_mode() {
+            LockMode::Shared => bail!("state cannot be saved by read-only commands"),
+            LockMode::Disabled => None,
+            LockMode::Exclusive => match self {
+                Self::Local(backend) => backend.acquire_lock().await?,
+                Self::Http(backend) => backend.acquire_lock().await?,
+                Self::S3(backend) => backend.acquire_lock().await?,
+            },
+        };
+
+        Ok(StateLock {
+            backend: self.clone(),
+            id,
+        })
+    }
+
+    async fn save(&self, state: &mut State, lock_id: Option<&str>) -> Result<()> {
+        match self {
+            Self::Local(backend) => backend.save(state, lock_id).await,
+            Self::Http(backend) => backend.save(state, lock_id).await,
+            Self::S3(backend) => backend.save(state, lock_id).await,
+        }
+    }
+
+    async fn release_lock(&self, id: &str) -> Result<()> {
+        match self {
+            Self::Local(backend) => backend.release_lock(id).await,
+            Self::Http(backend) => backend.release_lock(id).await,
+            Self::S3(backend) => backend.release_lock(id).await,
+        }
+    }
+
+    /// Release a lock left by a crashed run, given its ID.
+    pub async fn force_unlock(&self, id: &str) -> Result<()> {
+        match self {
+            Self::Local(backend) => backend.force_unlock(id),
+            Self::Http(backend) => backend.force_unlock(id).await,
+            Self::S3(backend) => backend.force_unlock(id).await,
+        }
+    }
+
+    /// Current holder of the state lock, on backends able to tell without taking it.
+    pub async fn lock_holder(&self) -> Option<LockInfo> {
+        match self {
+            Self::Local(backend) => backend.holder(),
+            // The http lock protocol has no way to read a lock
+            Self::Http(_) => None,
+            Self::S3(backend) => backend.lock_holder().await,
         }
     }
 }
@@ -37,8 +195,143 @@ impl Default for StateBackend {
     }
 }
 
+/// State lock held by a command from the load of the state to its last save.
+pub struct StateLock {
+    backend: StateBackend,
+    /// Lock ID, `None` when the backend has no lock or locking is disabled
+    id: Option<String>,
+}
+
+impl StateLock {
+    /// Save the state under this lock.
+    pub(super) async fn save(&self, state: &mut State) -> Result<()> {
+        self.backend.save(state, self.id.as_deref()).await
+    }
+}
+
+impl Drop for StateLock {
+    fn drop(&mut self) {
+        let Some(id) = self.id.take() else {
+            return;
+        };
+
+        // Commands failing while holding the lock release it too
+        let released = match tokio::runtime::Handle::try_current() {
+            Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+                tokio::task::block_in_place(|| handle.block_on(self.backend.release_lock(&id)))
+            }
+            _ => Err(anyhow!("no runtime to release it")),
+        };
+        if let Err(e) = released {
+            tracing::warn!(
+                "state lock `{}` is left, run `lgc state force-unlock {}`: {}",
+                id,
+                id,
+                e
+            );
+        }
+    }
+}
+
+/// Location of a workspace state, suffixing the file name (`lgc/state.json` → `lgc/state-staging.json`).
+fn workspace_key(key: &str, workspace: &str) -> String {
+    if workspace == LGC_DEFAULT_WORKSPACE {
+        return key.to_string();
+    }
+
+    let name_start = key.rfind('/').map_or(0, |position| position + 1);
+    match key[name_start..].rfind('.') {
+        Some(dot) if dot > 0 => {
+            let dot = name_start + dot;
+            format!("{}-{}{}", &key[..dot], workspace, &key[dot..])
+        }
+        _ => format!("{}-{}", key, workspace),
+    }
+}
+
 #[async_trait]
 pub trait BackendActions {
     async fn load(&self) -> Result<State>;
-    async fn save(&self, state: &mut State) -> Result<()>;
+    /// Write the state, holding the lock of `lock_id` if the state is locked.
+    async fn save(&self, state: &mut State, lock_id: Option<&str>) -> Result<()>;
+    /// Take the state lock, returning its ID, or `None` if the state is not locked.
+    async fn acquire_lock(&self) -> Result<Option<String>>;
+    async fn release_lock(&self, lock_id: &str) -> Result<()>;
+    /// Serials of the available state backups, oldest first.
+    async fn backups(&self) -> Result<Vec<usize>>;
+    async fn load_backup(&self, serial: usize) -> Result<State>;
+}
+
+/// Result of a lock attempt.
+enum LockAttempt {
+    Acquired,
+    Held(Option<LockInfo>),
+}
+
+/// State lock holder information, compatible with Terraform lock format.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct LockInfo {
+    #[serde(rename = "ID")]
+    pub id: String,
+    pub operation: String,
+    pub who: String,
+    pub version: String,
+    pub created: String,
+    /// Process holding the lock, set by lgc only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+}
+
+impl LockInfo {
+    /// Lock of the running `operation`, its command path without arguments as they may hold
+    /// secrets.
+    pub fn new(operation: &str) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            operation: operation.to_string(),
+            who: Self::current_user(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            created: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+            pid: Some(std::process::id()),
+        }
+    }
+
+    /// User running lgc, as `user@host`.
+    pub fn current_user() -> String {
+        let user = env::var("USER")
+            .or_else(|_| env::var("USERNAME"))
+            .unwrap_or(String::from("unknown"));
+        let host = hostname::get()
+            .ok()
+            .and_then(|host| host.into_string().ok())
+            .unwrap_or(String::from("localhost"));
+
+        format!("{}@{}", user, host)
+    }
+
+    /// Time elapsed since the lock creation, if known.
+    pub fn age(&self) -> Option<Duration> {
+        let created = humantime::parse_rfc3339_weak(&self.created).ok()?;
+        SystemTime::now()
+            .duration_since(created)
+            .ok()
+            .map(|age| Duration::from_secs(age.as_secs()))
+    }
+}
+
+impl std::fmt::Display for LockInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "lock `{}` held by `{}`", self.id, self.who)?;
+        if let Some(pid) = self.pid {
+            write!(f, " (pid {})", pid)?;
+        }
+        if !self.operation.is_empty() {
+            write!(f, " running `{}`", self.operation)?;
+        }
+        if let Some(age) = self.age() {
+            write!(f, " for {}", humantime::format_duration(age))?;
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,479 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use std::time::{Duration, Instant};
+
+use super::{LockInfo, State};
+use crate::configuration::LintFinding;
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use aws_config::{BehaviorVersion, Region, SdkConfig};
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_s3::{error::DisplayErrorContext, primitives::ByteStream, types::ServerSideEncryption};
+use dialoguer::Confirm;
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+
+use super::{workspace_key, BackendActions, LockAttempt, LockMode};
+
+/// Interval between two state lock attempts.
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Number of state backups kept by default.
+const DEFAULT_BACKUP_RETENTION: usize = 5;
+
+/// State stored in an S3 compatible object store (AWS S3, MinIO, Ceph RGW...).
+///
+/// Credentials are resolved from the standard AWS sources: environment, shared
+/// configuration files, web identity or instance metadata.
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct S3Backend {
+    /// Bucket holding the state
+    bucket: String,
+    /// Key of the state object
+    key: String,
+    region: Option<String>,
+    /// Endpoint of S3 compatible stores
+    endpoint: Option<String>,
+    /// Address objects as `<endpoint>/<bucket>/<key>`, enabled by default with `endpoint`
+    force_path_style: Option<bool>,
+    /// Shared configuration profile used to resolve credentials
+    profile: Option<String>,
+    /// Encrypt the state server side, with AES256 unless `kms_key_id` is set
+    encrypt: Option<bool>,
+    /// KMS key used to encrypt the state server side
+    kms_key_id: Option<String>,
+    /// DynamoDB table used to lock the state, with a `LockID` string partition key
+    dynamodb_table: Option<String>,
+    /// Endpoint of DynamoDB compatible stores
+    dynamodb_endpoint: Option<String>,
+    /// Lock the state with a `<key>.lock` object, requires conditional writes support
+    use_lockfile: Option<bool>,
+    /// Age after which a lock is considered stale (e.g. `1h`)
+    lock_max_age: Option<String>,
+    /// Number of state backups to keep next to the state, `0` disables backups
+    backup_retention: Option<usize>,
+    #[serde(skip)]
+    pub(super) lock_timeout: Option<Duration>,
+    #[serde(skip)]
+    pub(super) lock_mode: LockMode,
+    #[serde(skip)]
+    pub(super) lock_operation: String,
+}
+
+impl S3Backend {
+    pub(super) fn set_workspace(&mut self, workspace: &str) -> Result<()> {
+        self.key = workspace_key(&self.key, workspace);
+        Ok(())
+    }
+
+    /// Look for options which conflict or are ignored.
+    pub(super) fn lint(&self) -> Vec<LintFinding> {
+        let mut findings = Vec::new();
+
+        if self.encrypt == Some(false) && self.kms_key_id.is_some() {
+            findings.push(LintFinding::new(
+                String::from("state option `kms_key_id` is ignored as `encrypt` is disabled"),
+                String::from("enable `encrypt` or remove `kms_key_id`"),
+            ));
+        }
+
+        if self.dynamodb_endpoint.is_some() && self.dynamodb_table.is_none() {
+            findings.push(LintFinding::new(
+                String::from(
+                    "state option `dynamodb_endpoint` is ignored as `dynamodb_table` is not set",
+                ),
+                String::from("set `dynamodb_table` or remove `dynamodb_endpoint`"),
+            ));
+        }
+
+        if self.lock_max_age.is_some() && !self.is_locked() {
+            findings.push(LintFinding::new(
+                String::from("state option `lock_max_age` is ignored as the state is not locked"),
+                String::from("set `dynamodb_table` or `use_lockfile`, or remove `lock_max_age`"),
+            ));
+        }
+
+        if self.dynamodb_table.is_some() && self.use_lockfile.unwrap_or_default() {
+            findings.push(LintFinding::new(
+                String::from("state is locked both through `dynamodb_table` and `use_lockfile`"),
+                String::from("keep only one of `dynamodb_table` and `use_lockfile`"),
+            ));
+        }
+
+        findings
+    }
+
+    fn is_locked(&self) -> bool {
+        self.dynamodb_table.is_some() || self.use_lockfile.unwrap_or_default()
+    }
+
+    fn lock_key(&self) -> String {
+        format!("{}.lock", self.key)
+    }
+
+    fn backup_prefix(&self) -> String {
+        format!("{}.backup-", self.key)
+    }
+
+    fn backup_key(&self, serial: usize) -> String {
+        format!("{}{}", self.backup_prefix(), serial)
+    }
+
+    /// Lock ID, identical to the one used by Terraform.
+    fn lock_id(&self) -> String {
+        format!("{}/{}", self.bucket, self.key)
+    }
+
+    fn server_side_encryption(&self) -> (Option<ServerSideEncryption>, Option<String>) {
+        match (self.encrypt, &self.kms_key_id) {
+            (Some(false), _) => (None, None),
+            (_, Some(key)) => (Some(ServerSideEncryption::AwsKms), Some(key.clone())),
+            (Some(true), None) => (Some(ServerSideEncryption::Aes256), None),
+            (None, None) => (None, None),
+        }
+    }
+
+    async fn sdk_config(&self) -> SdkConfig {
+        let mut loader = aws_config::defaults(BehaviorVersion::latest());
+        if let Some(region) = &self.region {
+            loader = loader.region(Region::new(region.clone()));
+        }
+        if let Some(profile) = &self.profile {
+            loader = loader.profile_name(profile);
+        }
+        loader.load().await
+    }
+
+    async fn s3(&self) -> aws_sdk_s3::Client {
+        let config = self.sdk_config().await;
+        let mut builder = aws_sdk_s3::config::Builder::from(&config)
+            .force_path_style(self.force_path_style.unwrap_or(self.endpoint.is_some()));
+        if let Some(endpoint) = &self.endpoint {
+            builder = builder.endpoint_url(endpoint);
+        }
+        aws_sdk_s3::Client::from_conf(builder.build())
+    }
+
+    async fn dynamodb(&self) -> aws_sdk_dynamodb::Client {
+        let config = self.sdk_config().await;
+        let mut builder = aws_sdk_dynamodb::config::Builder::from(&config);
+        if let Some(endpoint) = &self.dynamodb_endpoint {
+            builder = builder.endpoint_url(endpoint);
+        }
+        aws_sdk_dynamodb::Client::from_conf(builder.build())
+    }
+
+    /// Read a state object, `None` if it does not exist.
+    async fn get(&self, client: &aws_sdk_s3::Client, key: &str) -> Result<Option<Vec<u8>>> {
+        match client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+        {
+            Ok(output) => Ok(Some(
+                output
+                    .body
+                    .collect()
+                    .await
+                    .map_err(|e| anyhow!("unable to read `{}`: {}", key, e))?
+                    .to_vec(),
+            )),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => Ok(None),
+            Err(e) => bail!("unable to retrieve `{}`: {}", key, DisplayErrorContext(&e)),
+        }
+    }
+
+    async fn put(&self, client: &aws_sdk_s3::Client, key: &str, body: Vec<u8>) -> Result<()> {
+        let (encryption, kms_key_id) = self.server_side_encryption();
+        client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .content_type("application/json")
+            .set_server_side_encryption(encryption)
+            .set_ssekms_key_id(kms_key_id)
+            .body(ByteStream::from(body))
+            .send()
+            .await
+            .map_err(|e| anyhow!("unable to write `{}`: {}", key, DisplayErrorContext(&e)))?;
+        Ok(())
+    }
+
+    async fn try_lock(&self, s3: &aws_sdk_s3::Client, info: &LockInfo) -> Result<LockAttempt> {
+        let body = serde_json::to_string(info)?;
+
+        if let Some(table) = &self.dynamodb_table {
+            let client = self.dynamodb().await;
+            let put = client
+                .put_item()
+                .table_name(table)
+                .item("LockID", AttributeValue::S(self.lock_id()))
+                .item("Info", AttributeValue::S(body))
+                .condition_expression("attribute_not_exists(LockID)")
+                .send()
+                .await;
+
+            return match put {
+                Ok(_) => Ok(LockAttempt::Acquired),
+                Err(e)
+                    if e.as_service_error()
+                        .is_some_and(|e| e.is_conditional_check_failed_exception()) =>
+                {
+                    Ok(LockAttempt::Held(self.holder(s3).await))
+                }
+                Err(e) => bail!("unable to lock state: {}", DisplayErrorContext(&e)),
+            };
+        }
+
+        // Lock object written only if it does not exist yet
+        let put = s3
+            .put_object()
+            .bucket(&self.bucket)
+            .key(self.lock_key())
+            .content_type("application/json")
+            .if_none_match("*")
+            .body(ByteStream::from(body.into_bytes()))
+            .send()
+            .await;
+
+        match put {
+            Ok(_) => Ok(LockAttempt::Acquired),
+            Err(e)
+                if e.raw_response()
+                    .is_some_and(|resp| matches!(resp.status().as_u16(), 409 | 412)) =>
+            {
+                Ok(LockAttempt::Held(self.holder(s3).await))
+            }
+            Err(e) => bail!("unable to lock state: {}", DisplayErrorContext(&e)),
+        }
+    }
+
+    /// Information of the current lock holder, if any and readable.
+    async fn holder(&self, s3: &aws_sdk_s3::Client) -> Option<LockInfo> {
+        if let Some(table) = &self.dynamodb_table {
+            return self
+                .dynamodb()
+                .await
+                .get_item()
+                .table_name(table)
+                .key("LockID", AttributeValue::S(self.lock_id()))
+                .send()
+                .await
+                .ok()
+                .and_then(|output| output.item)
+                .and_then(|item| item.get("Info").cloned())
+                .and_then(|info| info.as_s().ok().cloned())
+                .and_then(|info| serde_json::from_str(&info).ok());
+        }
+
+        self.get(s3, &self.lock_key())
+            .await
+            .ok()
+            .flatten()
+            .and_then(|holder| serde_json::from_slice(&holder).ok())
+    }
+
+    /// Holder of the state lock, read without taking it.
+    pub(super) async fn lock_holder(&self) -> Option<LockInfo> {
+        if !self.is_locked() {
+            return None;
+        }
+        self.holder(&self.s3().await).await
+    }
+
+    async fn lock(&self, s3: &aws_sdk_s3::Client) -> Result<String> {
+        let lock_max_age = match &self.lock_max_age {
+            Some(age) => Some(
+                humantime::parse_duration(age)
+                    .map_err(|e| anyhow!("invalid state lock_max_age `{}`: {}", age, e))?,
+            ),
+            None => None,
+        };
+
+        let lock_info = LockInfo::new(&self.lock_operation);
+        let started = Instant::now();
+
+        loop {
+            let holder = match self.try_lock(s3, &lock_info).await? {
+                LockAttempt::Acquired => return Ok(lock_info.id),
+                LockAttempt::Held(holder) => holder,
+            };
+
+            // Offer to release stale locks
+            if let Some(holder) = &holder {
+                if lock_max_age.is_some_and(|max| holder.age().is_some_and(|age| age > max))
+                    && console::user_attended()
+                    && Confirm::new()
+                        .with_prompt(format!("State {} looks stale, force unlock ?", holder))
+                        .interact()?
+                {
+                    self.unlock(s3).await?;
+                    continue;
+                }
+            }
+
+            let holder = holder
+                .map(|holder| holder.to_string())
+                .unwrap_or(String::from("unknown lock"));
+
+            if self
+                .lock_timeout
+                .map_or(true, |timeout| started.elapsed() >= timeout)
+            {
+                bail!(
+                    "unable to lock state: {}, run `lgc state force-unlock <id>` if its run crashed",
+                    holder
+                )
+            }
+
+            tracing::info!("state is locked ({}), retrying...", holder);
+            tokio::time::sleep(LOCK_RETRY_INTERVAL).await;
+        }
+    }
+
+    /// Release a lock left by a crashed run, given its ID.
+    pub(super) async fn force_unlock(&self, lock_id: &str) -> Result<()> {
+        if !self.is_locked() {
+            bail!("state is not locked as neither `dynamodb_table` nor `use_lockfile` is set")
+        }
+
+        let s3 = self.s3().await;
+        match self.holder(&s3).await {
+            Some(holder) if holder.id == lock_id => self.unlock(&s3).await,
+            Some(holder) => bail!("state {}, not by lock `{}`", holder, lock_id),
+            None => bail!("state lock `{}` not found", lock_id),
+        }
+    }
+
+    async fn unlock(&self, s3: &aws_sdk_s3::Client) -> Result<()> {
+        if let Some(table) = &self.dynamodb_table {
+            self.dynamodb()
+                .await
+                .delete_item()
+                .table_name(table)
+                .key("LockID", AttributeValue::S(self.lock_id()))
+                .send()
+                .await
+                .map_err(|e| anyhow!("unable to unlock state: {}", DisplayErrorContext(&e)))?;
+        } else {
+            s3.delete_object()
+                .bucket(&self.bucket)
+                .key(self.lock_key())
+                .send()
+                .await
+                .map_err(|e| anyhow!("unable to unlock state: {}", DisplayErrorContext(&e)))?;
+        }
+
+        Ok(())
+    }
+
+    /// Keep the state being replaced, removing the oldest backups.
+    async fn backup(&self, s3: &aws_sdk_s3::Client) -> Result<()> {
+        let retention = self.backup_retention.unwrap_or(DEFAULT_BACKUP_RETENTION);
+        if retention == 0 {
+            return Ok(());
+        }
+
+        let Some(previous) = self.get(s3, &self.key).await? else {
+            return Ok(());
+        };
+        let serial = serde_json::from_slice::<State>(&previous)
+            .map_err(|e| anyhow!("unable to decode state: {}", e))?
+            .serial;
+        self.put(s3, &self.backup_key(serial), previous).await?;
+
+        let backups = self.backups().await?;
+        for serial in backups.iter().take(backups.len().saturating_sub(retention)) {
+            s3.delete_object()
+                .bucket(&self.bucket)
+                .key(self.backup_key(*serial))
+                .send()
+                .await
+                .map_err(|e| {
+                    anyhow!(
+                        "unable to remove state backup `{}`: {}",
+                        serial,
+                        DisplayErrorContext(&e)
+                    )
+                })?;
+        }
+
+        Ok(())
+    }
+
+    async fn write(&self, s3: &aws_sdk_s3::Client, state: &mut State) -> Result<()> {
+        self.backup(s3).await?;
+
+        state.serial += 1;
+        state.lgc_version = env!("CARGO_PKG_VERSION").to_string();
+        self.put(s3, &self.key, serde_json::to_vec_pretty(state)?)
+            .await
+            .map_err(|e| anyhow!("unable to save state: {}", e))
+    }
+}
+
+#[async_trait]
+impl BackendActions for S3Backend {
+    async fn load(&self) -> Result<State> {
+        match self.get(&self.s3().await, &self.key).await? {
+            Some(state) => {
+                serde_json::from_slice(&state).map_err(|e| anyhow!("unable to decode state: {}", e))
+            }
+            None => Ok(State::default()),
+        }
+    }
+
+    async fn save(&self, state: &mut State, _lock_id: Option<&str>) -> Result<()> {
+        self.write(&self.s3().await, state).await
+    }
+
+    async fn acquire_lock(&self) -> Result<Option<String>> {
+        if !self.is_locked() {
+            return Ok(None);
+        }
+        self.lock(&self.s3().await).await.map(Some)
+    }
+
+    async fn release_lock(&self, _lock_id: &str) -> Result<()> {
+        self.unlock(&self.s3().await).await
+    }
+
+    async fn backups(&self) -> Result<Vec<usize>> {
+        let prefix = self.backup_prefix();
+        let mut pages = self
+            .s3()
+            .await
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(&prefix)
+            .into_paginator()
+            .send();
+
+        let mut serials = Vec::new();
+        while let Some(page) = pages.next().await {
+            let page = page.map_err(|e| {
+                anyhow!("unable to list state backups: {}", DisplayErrorContext(&e))
+            })?;
+            serials.extend(
+                page.contents()
+                    .iter()
+                    .filter_map(|object| object.key()?.strip_prefix(&prefix)?.parse().ok()),
+            );
+        }
+        serials.sort_unstable();
+
+        Ok(serials)
+    }
+
+    async fn load_backup(&self, serial: usize) -> Result<State> {
+        match self.get(&self.s3().await, &self.backup_key(serial)).await? {
+            Some(state) => serde_json::from_slice(&state)
+                .map_err(|e| anyhow!("unable to decode state backup: {}", e)),
+            None => bail!("state backup `{}` does not exist", serial),
+        }
+    }
+}
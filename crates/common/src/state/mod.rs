@@ -1,20 +1,57 @@
 // Copyright (c) 2023 LogCraft, SAS.
 // SPDX-License-Identifier: MPL-2.0
 
-use crate::detections::{DetectionState, ServiceDetections};
-use anyhow::Result;
+use crate::configuration::CoreConfiguration;
+use crate::detections::{sorted_rules, DetectionState, ServiceDetections};
+use crate::utils::{from_workspace_path, workspace_path};
+use anyhow::{anyhow, bail, Result};
 use console::style;
-use dashmap::DashMap;
-use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
-use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use kclvm_api::{gpyrpc::ValidateCodeArgs, service::KclvmServiceImpl};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
 use uuid::Uuid;
 
-const LGC_DEFAULT_STATE_PATH: &str = ".logcraft/state.json";
+pub const LGC_DEFAULT_STATE_PATH: &str = ".logcraft/state.json";
 const LGC_STATE_VERSION: usize = 1;
+/// Local copy of a state the backend failed to save, pushed by `lgc state push --from-recovery`.
+pub const LGC_STATE_RECOVERY_PATH: &str = ".logcraft/state.recovery.json";
+/// Attempts to save a state before writing it to the recovery file.
+const STATE_SAVE_ATTEMPTS: u32 = 4;
+/// Delay before the first retry of a state save, doubled on each retry.
+const STATE_SAVE_BACKOFF: Duration = Duration::from_secs(2);
+
+/// Workspace used when none is selected, its state keeps the configured location.
+pub const LGC_DEFAULT_WORKSPACE: &str = "default";
+/// File holding the selected workspace.
+const LGC_WORKSPACE_PATH: &str = ".logcraft/workspace";
+/// Environment variable overriding the selected workspace.
+pub const LGC_WORKSPACE_ENV: &str = "LGC_WORKSPACE";
 
 pub mod backends;
-use backends::{BackendActions, StateBackend};
+use backends::StateLock;
+
+/// Workspace selected with `lgc workspace select`, `LGC_WORKSPACE` taking precedence.
+pub fn current_workspace() -> String {
+    std::env::var(LGC_WORKSPACE_ENV)
+        .ok()
+        .or_else(|| fs::read_to_string(LGC_WORKSPACE_PATH).ok())
+        .map(|workspace| workspace.trim().to_string())
+        .filter(|workspace| !workspace.is_empty())
+        .unwrap_or_else(|| LGC_DEFAULT_WORKSPACE.to_string())
+}
+
+pub fn select_workspace(workspace: &str) -> Result<()> {
+    if let Some(parent) = Path::new(LGC_WORKSPACE_PATH).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(LGC_WORKSPACE_PATH, workspace)
+        .map_err(|e| anyhow!("unable to select workspace `{}`: {}", workspace, e))
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct State {
@@ -28,7 +65,123 @@ pub struct State {
     /// Version of LogCraft CLI
     lgc_version: String,
     /// List of rules to track service_name => (rule_name, rule_settings)
+    #[serde(serialize_with = "serialize_sorted_services")]
     pub services: ServiceDetections,
+    /// Maintenance windows during which services must not be changed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub maintenance: Vec<MaintenanceWindow>,
+    /// Archived detections, by name
+    #[serde(
+        default,
+        skip_serializing_if = "HashMap::is_empty",
+        serialize_with = "serialize_sorted_map"
+    )]
+    pub archived: HashMap<String, Tombstone>,
+}
+
+/// Write services and their rules sorted by name, so state files only change with their content.
+fn serialize_sorted_services<S: Serializer>(
+    services: &ServiceDetections,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    services
+        .iter()
+        .map(|(id, rules)| (id, sorted_rules(rules)))
+        .collect::<BTreeMap<_, _>>()
+        .serialize(serializer)
+}
+
+fn serialize_sorted_map<S: Serializer, V: Serialize>(
+    map: &HashMap<String, V>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    map.iter().collect::<BTreeMap<_, _>>().serialize(serializer)
+}
+
+/// Paths are stored workspace relative with forward slashes, so that states are portable
+/// between runners. States written with OS specific paths are migrated on next save.
+fn serialize_workspace_path<S: Serializer>(
+    path: &Path,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    workspace_path(path).serialize(serializer)
+}
+
+fn deserialize_workspace_path<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<PathBuf, D::Error> {
+    Ok(from_workspace_path(&String::deserialize(deserializer)?))
+}
+
+/// Trace of an archived detection, used to revive it.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Tombstone {
+    /// Path of the archived detection file
+    #[serde(
+        serialize_with = "serialize_workspace_path",
+        deserialize_with = "deserialize_workspace_path"
+    )]
+    pub path: PathBuf,
+    /// Services the detection was deleted from
+    pub services: Vec<String>,
+    /// Archiving date (RFC 3339)
+    pub archived_at: String,
+}
+
+/// State entry which does not validate against the current plugin schema.
+pub struct SchemaViolation {
+    pub service: String,
+    pub rule: String,
+    pub message: String,
+}
+
+/// Rules changed between two states, serialized as change evidence.
+#[derive(Serialize)]
+pub struct StateDiff {
+    /// Serial of the older state
+    pub from: usize,
+    /// Serial of the newer state
+    pub to: usize,
+    /// Changes by service ID, services without changes are omitted
+    pub services: BTreeMap<String, ServiceChanges>,
+}
+
+/// Names of the rules changed on a service, sorted.
+#[derive(Serialize, Default)]
+pub struct ServiceChanges {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub modified: Vec<String>,
+}
+
+impl ServiceChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+/// Period during which changes to services are refused.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MaintenanceWindow {
+    /// Services affected, all services if empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub services: Vec<String>,
+    /// End of the window (RFC 3339)
+    pub until: String,
+    pub reason: String,
+    /// User having enabled the window
+    pub who: String,
+}
+
+impl MaintenanceWindow {
+    /// Whether the window has not ended yet.
+    pub fn is_active(&self) -> bool {
+        humantime::parse_rfc3339_weak(&self.until).is_ok_and(|until| until > SystemTime::now())
+    }
+
+    pub fn applies_to(&self, service_id: &str) -> bool {
+        self.services.is_empty() || self.services.iter().any(|svc| svc == service_id)
+    }
 }
 
 impl Default for State {
@@ -39,51 +192,318 @@ impl Default for State {
             version: LGC_STATE_VERSION,
             lgc_version: env!("CARGO_PKG_VERSION").to_string(),
             services: HashMap::new(),
+            maintenance: Vec::new(),
+            archived: HashMap::new(),
         }
     }
 }
 
 impl State {
-    pub async fn save(&mut self, backend: &StateBackend) -> Result<()> {
-        match backend {
-            StateBackend::Local(path) => path.save(self).await,
-            StateBackend::Http(backend) => backend.save(self).await,
+    /// Save the state, retrying transient backend failures.
+    ///
+    /// Once retries are exhausted the state is written to the recovery file, so that changes
+    /// already applied to remote services are not forgotten.
+    #[tracing::instrument(name = "state.save", skip_all, fields(serial = self.serial()))]
+    pub async fn save(&mut self, lock: &StateLock) -> Result<()> {
+        // Backends increment the serial before writing
+        let serial = self.serial;
+        let mut attempt = 1;
+        loop {
+            let Err(e) = lock.save(self).await else {
+                return Ok(());
+            };
+            self.serial = serial;
+
+            if attempt < STATE_SAVE_ATTEMPTS {
+                let backoff = STATE_SAVE_BACKOFF * 2u32.pow(attempt - 1);
+                tracing::warn!(
+                    "unable to save state, retrying in {} ({}/{}): {}",
+                    humantime::format_duration(backoff),
+                    attempt,
+                    STATE_SAVE_ATTEMPTS - 1,
+                    e
+                );
+                tokio::time::sleep(backoff).await;
+                attempt += 1;
+                continue;
+            }
+
+            self.write_recovery()?;
+            bail!(
+                "unable to save state: {}. It has been written to `{}`, run `lgc state push --from-recovery` once the backend is available",
+                e,
+                LGC_STATE_RECOVERY_PATH
+            )
+        }
+    }
+
+    fn write_recovery(&self) -> Result<()> {
+        let path = Path::new(LGC_STATE_RECOVERY_PATH);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?).map_err(|e| {
+            anyhow!(
+                "unable to write state recovery file `{}`: {}",
+                LGC_STATE_RECOVERY_PATH,
+                e
+            )
+        })
+    }
+
+    /// Load the state written to the recovery file by a failed save.
+    pub fn load_recovery() -> Result<Self> {
+        let content = fs::read_to_string(LGC_STATE_RECOVERY_PATH).map_err(|_| {
+            anyhow!(
+                "no state recovery file found at `{}`",
+                LGC_STATE_RECOVERY_PATH
+            )
+        })?;
+        serde_json::from_str(&content)
+            .map_err(|e| anyhow!("unable to load state recovery file: {}", e))
+    }
+
+    pub fn remove_recovery() -> Result<()> {
+        Ok(fs::remove_file(LGC_STATE_RECOVERY_PATH)?)
+    }
+
+    /// Fail if `recovered` cannot replace this state, changes saved since would be lost.
+    pub fn check_recovery(&self, recovered: &State) -> Result<()> {
+        if recovered.lineage != self.lineage && self.serial > 0 {
+            bail!(
+                "recovered state lineage `{}` does not match current state lineage `{}`",
+                recovered.lineage,
+                self.lineage
+            )
+        }
+        if recovered.serial != self.serial {
+            bail!(
+                "state changed since the recovery file was written (serial `{}`, now `{}`)",
+                recovered.serial,
+                self.serial
+            )
         }
+
+        Ok(())
+    }
+
+    /// Fail if the service is under an active maintenance window.
+    pub fn check_maintenance(&self, service_id: &str) -> Result<()> {
+        if let Some(window) = self
+            .maintenance
+            .iter()
+            .find(|window| window.is_active() && window.applies_to(service_id))
+        {
+            bail!(
+                "service `{}` is under maintenance until {} ({}), run `lgc maintenance disable` to clear it",
+                service_id,
+                window.until,
+                window.reason
+            )
+        }
+
+        Ok(())
+    }
+
+    /// Validate rules tracked for `service_ids` against a plugin rule `schema`.
+    pub fn schema_violations(
+        &self,
+        schema: &str,
+        service_ids: &[&str],
+    ) -> Result<Vec<SchemaViolation>> {
+        let serv = KclvmServiceImpl::default();
+        let mut args = ValidateCodeArgs {
+            code: schema.to_string(),
+            schema: String::from("Rule"),
+            format: String::from("yaml"),
+            ..Default::default()
+        };
+
+        let mut violations = Vec::new();
+        for service_id in service_ids {
+            for rule in self.services.get(*service_id).into_iter().flatten() {
+                args.data = serde_yaml_ng::to_string(&rule.content)?;
+                let check = serv.validate_code(&args)?;
+                if !check.success {
+                    violations.push(SchemaViolation {
+                        service: service_id.to_string(),
+                        rule: rule.name.clone(),
+                        message: check.err_message,
+                    });
+                }
+            }
+        }
+
+        Ok(violations)
+    }
+
+    pub fn serial(&self) -> usize {
+        self.serial
+    }
+
+    pub fn lineage(&self) -> Uuid {
+        self.lineage
+    }
+
+    /// Rules added, removed or modified from this state to the `to` state.
+    pub fn diff(&self, to: &State) -> StateDiff {
+        let empty = HashSet::new();
+        let mut services = BTreeMap::new();
+
+        for service_id in self.services.keys().chain(to.services.keys()) {
+            if services.contains_key(service_id) {
+                continue;
+            }
+
+            let before = self.services.get(service_id).unwrap_or(&empty);
+            let after = to.services.get(service_id).unwrap_or(&empty);
+
+            let mut changes = ServiceChanges::default();
+            for rule in sorted_rules(after) {
+                match before.get(rule) {
+                    None => changes.added.push(rule.name.clone()),
+                    Some(previous) if previous.content != rule.content => {
+                        changes.modified.push(rule.name.clone())
+                    }
+                    Some(_) => (),
+                }
+            }
+            changes.removed = sorted_rules(before.difference(after))
+                .into_iter()
+                .map(|rule| rule.name.clone())
+                .collect();
+
+            services.insert(service_id.clone(), changes);
+        }
+        services.retain(|_, changes| !changes.is_empty());
+
+        StateDiff {
+            from: self.serial,
+            to: to.serial,
+            services,
+        }
+    }
+
+    /// Replace the state with a backup of the same lineage, only keeping the current serial so
+    /// that it keeps increasing.
+    pub fn restore(&mut self, backup: State) -> Result<()> {
+        if backup.lineage != self.lineage && self.serial > 0 {
+            bail!(
+                "state backup lineage `{}` does not match current state lineage `{}`",
+                backup.lineage,
+                self.lineage
+            )
+        }
+
+        *self = State {
+            serial: self.serial,
+            ..backup
+        };
+        Ok(())
     }
 
+    /// Number of rules a service holds once `created` rules are added and `removed` rules are deleted.
+    pub fn projected_rules(
+        &self,
+        service_id: &str,
+        retrieved: &ServiceDetections,
+        created: usize,
+        removed: usize,
+    ) -> usize {
+        let current = match (self.services.get(service_id), retrieved.get(service_id)) {
+            (Some(tracked), Some(rules)) => tracked.union(rules).count(),
+            (Some(rules), None) | (None, Some(rules)) => rules.len(),
+            (None, None) => 0,
+        };
+
+        (current + created).saturating_sub(removed)
+    }
+
+    /// Rules of the state missing from `detections`, to delete. Protected rules are kept.
     pub fn missing_rules(
         &self,
         detections: &ServiceDetections,
+        core: &CoreConfiguration,
         silent: bool,
         detection_name: Option<String>,
     ) -> ServiceDetections {
-        let to_remove: DashMap<String, HashSet<DetectionState>> = DashMap::new();
-
-        detections.par_iter().for_each(|(service_id, rules)| {
-            if let Some(state_rules) = self.services.get(service_id) {
-                state_rules
-                    .difference(rules)
-                    .filter(|rule| {
-                        detection_name.is_none() || detection_name.as_ref().unwrap() == &rule.name
-                    })
-                    .for_each(|rule| {
-                        to_remove
-                            .entry(service_id.clone())
-                            .and_modify(|s| {
-                                s.insert(rule.clone());
-                            })
-                            .or_insert(HashSet::from([rule.clone()]));
-                        if !silent {
-                            println!(
-                                "[-] rule: `{}` will be deleted from `{}`",
-                                style(&rule.name).red(),
-                                &service_id
-                            );
-                        }
-                    });
+        let mut to_remove: ServiceDetections = HashMap::new();
+
+        let mut service_ids: Vec<&String> = detections.keys().collect();
+        service_ids.sort();
+        for service_id in service_ids {
+            let Some(state_rules) = self.services.get(service_id) else {
+                continue;
+            };
+
+            let removed = state_rules
+                .difference(&detections[service_id])
+                .filter(|rule| {
+                    detection_name.is_none() || detection_name.as_ref().unwrap() == &rule.name
+                });
+            for rule in sorted_rules(removed) {
+                if core.is_protected(&rule.name) {
+                    if !silent {
+                        println!(
+                            "[!] rule: `{}` on `{}` is protected - keeping",
+                            style(&rule.name).dim(),
+                            &service_id
+                        );
+                    }
+                    continue;
+                }
+                to_remove
+                    .entry(service_id.clone())
+                    .or_default()
+                    .insert(rule.clone());
+                if !silent {
+                    println!(
+                        "[-] rule: `{}` will be deleted from `{}`",
+                        style(&rule.name).red(),
+                        &service_id
+                    );
+                }
             }
-        });
+        }
+
+        to_remove
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restore_keeps_only_the_serial() {
+        let mut state = State {
+            serial: 7,
+            ..Default::default()
+        };
+        let backup = State {
+            lineage: state.lineage,
+            serial: 3,
+            maintenance: vec![MaintenanceWindow {
+                services: vec![String::from("splunk-prod")],
+                until: String::from("2030-01-01T00:00:00Z"),
+                reason: String::from("upgrade"),
+                who: String::from("alice@host"),
+            }],
+            archived: HashMap::from([(
+                String::from("brute-force"),
+                Tombstone {
+                    path: PathBuf::from("rules/brute-force.yaml"),
+                    services: vec![String::from("splunk-prod")],
+                    archived_at: String::from("2024-01-01T00:00:00Z"),
+                },
+            )]),
+            ..Default::default()
+        };
+
+        state.restore(backup).unwrap();
 
-        to_remove.into_iter().collect()
+        assert_eq!(state.serial, 7);
+        assert_eq!(state.maintenance.len(), 1);
+        assert!(state.archived.contains_key("brute-force"));
     }
 }
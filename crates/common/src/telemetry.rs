@@ -0,0 +1,161 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    plan::Plan,
+    report::{Outcome, RunReport},
+};
+
+/// Time allowed to the telemetry webhook, commands never wait longer.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Counters of the running command, reported once it ends.
+static COUNTS: Mutex<BTreeMap<String, usize>> = Mutex::new(BTreeMap::new());
+
+/// Usage telemetry, disabled unless a webhook is configured.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct TelemetryConfig {
+    /// Address receiving a JSON event after each command
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub webhook: Option<String>,
+    /// Headers sent with events (e.g. authentication)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+}
+
+impl TelemetryConfig {
+    pub fn is_empty(&self) -> bool {
+        self.webhook.is_none() && self.headers.is_empty()
+    }
+
+    /// Sink receiving events, a no-op one unless a webhook is configured.
+    pub fn sink(&self) -> Box<dyn TelemetrySink> {
+        match &self.webhook {
+            Some(url) => Box::new(WebhookSink {
+                url: url.clone(),
+                headers: self.headers.clone(),
+            }),
+            None => Box::new(NoopSink),
+        }
+    }
+}
+
+/// Usage of one command.
+///
+/// Events only hold the command name, its duration, its success and counters: never
+/// arguments, rule names or rule contents.
+#[derive(Serialize, Clone, Debug)]
+pub struct TelemetryEvent {
+    /// Command path, without arguments (e.g. `services add`)
+    pub command: String,
+    pub duration_ms: u128,
+    pub success: bool,
+    /// Counters recorded by the command (e.g. `create_succeeded`)
+    pub counts: BTreeMap<String, usize>,
+    pub lgc_version: String,
+    pub timestamp: String,
+}
+
+impl TelemetryEvent {
+    /// Event of the command which just ended, with the counters it recorded.
+    pub fn new(command: &str, duration: Duration, success: bool) -> Self {
+        Self {
+            command: command.to_string(),
+            duration_ms: duration.as_millis(),
+            success,
+            counts: COUNTS
+                .lock()
+                .map(|mut counts| std::mem::take(&mut *counts))
+                .unwrap_or_default(),
+            lgc_version: env!("CARGO_PKG_VERSION").to_string(),
+            timestamp: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+        }
+    }
+
+    /// Send the event, failures are only logged as telemetry never fails commands.
+    pub async fn send(&self, sink: &dyn TelemetrySink) {
+        if let Err(e) = sink.record(self).await {
+            tracing::debug!("unable to send telemetry: {}", e);
+        }
+    }
+}
+
+/// Collector of usage events, implemented to plug a custom collector.
+#[async_trait]
+pub trait TelemetrySink: Send + Sync {
+    async fn record(&self, event: &TelemetryEvent) -> Result<()>;
+}
+
+/// Default sink, events are dropped.
+pub struct NoopSink;
+
+#[async_trait]
+impl TelemetrySink for NoopSink {
+    async fn record(&self, _event: &TelemetryEvent) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Sink posting events as JSON to a webhook.
+pub struct WebhookSink {
+    url: String,
+    headers: BTreeMap<String, String>,
+}
+
+#[async_trait]
+impl TelemetrySink for WebhookSink {
+    async fn record(&self, event: &TelemetryEvent) -> Result<()> {
+        let mut request = reqwest::Client::builder()
+            .timeout(WEBHOOK_TIMEOUT)
+            .build()?
+            .post(&self.url)
+            .json(event);
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| anyhow!("telemetry webhook unreachable: {}", e))?;
+        if !response.status().is_success() {
+            bail!("telemetry webhook returned {}", response.status())
+        }
+        Ok(())
+    }
+}
+
+/// Add to a counter of the running command.
+pub fn count(name: &str, value: usize) {
+    if let Ok(mut counts) = COUNTS.lock() {
+        *counts.entry(name.to_string()).or_default() += value;
+    }
+}
+
+/// Count operations of a run by action and outcome (e.g. `delete_failed`).
+pub fn count_report(report: &RunReport) {
+    for operation in &report.operations {
+        let outcome = match operation.outcome {
+            Outcome::Succeeded => "succeeded",
+            Outcome::Failed(_) => "failed",
+            Outcome::Skipped(_) => "skipped",
+        };
+        count(&format!("{}_{}", operation.action, outcome), 1);
+    }
+}
+
+/// Count planned operations by action (e.g. `create_planned`).
+pub fn count_plan(plan: &Plan) {
+    for operation in plan.services.values().flatten() {
+        count(&format!("{}_planned", operation.action), 1);
+    }
+}
@@ -0,0 +1,106 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{anyhow, Result};
+use rhai::{module_resolvers::DummyModuleResolver, Dynamic, Engine, Scope, AST};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{collections::HashSet, path::PathBuf};
+
+use crate::detections::PluginDetections;
+
+/// Limits applied to transform scripts.
+const MAX_OPERATIONS: u64 = 1_000_000;
+const MAX_CALL_LEVELS: usize = 32;
+const MAX_EXPR_DEPTH: usize = 64;
+
+/// Script transforming detections between their loading and the plan.
+///
+/// The script receives the `rule` content along with the `name` of the detection
+/// and the `plugin` it targets, and must evaluate to the new rule content.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Transform {
+    /// Path of the rhai script
+    pub script: PathBuf,
+    /// Plugins whose detections are transformed, all if empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub plugins: Vec<String>,
+}
+
+/// Run the configured transforms, in order, over every detection.
+pub fn apply_transforms(transforms: &[Transform], detections: &mut PluginDetections) -> Result<()> {
+    if transforms.is_empty() {
+        return Ok(());
+    }
+
+    let engine = sandboxed_engine();
+    for transform in transforms {
+        let ast = engine.compile_file(transform.script.clone()).map_err(|e| {
+            anyhow!(
+                "unable to compile transform `{}`: {}",
+                transform.script.display(),
+                e
+            )
+        })?;
+
+        for (plugin, rules) in detections.iter_mut() {
+            if !transform.plugins.is_empty() && !transform.plugins.contains(plugin) {
+                continue;
+            }
+
+            let mut transformed = HashSet::with_capacity(rules.len());
+            for mut rule in rules.drain() {
+                rule.content = transform
+                    .run(&engine, &ast, plugin, &rule.name, &rule.content)
+                    .map_err(|e| {
+                        anyhow!(
+                            "transform `{}` failed on `{}`: {}",
+                            transform.script.display(),
+                            rule.name,
+                            e
+                        )
+                    })?;
+                transformed.insert(rule);
+            }
+            *rules = transformed;
+        }
+    }
+
+    Ok(())
+}
+
+impl Transform {
+    fn run(
+        &self,
+        engine: &Engine,
+        ast: &AST,
+        plugin: &str,
+        name: &str,
+        content: &Value,
+    ) -> Result<Value> {
+        let mut scope = Scope::new();
+        scope.push_constant("plugin", plugin.to_string());
+        scope.push_constant("name", name.to_string());
+        scope.push("rule", rhai::serde::to_dynamic(content)?);
+
+        let result: Dynamic = engine
+            .eval_ast_with_scope(&mut scope, ast)
+            .map_err(|e| anyhow!("{}", e))?;
+
+        rhai::serde::from_dynamic(&result).map_err(|e| anyhow!("invalid result: {}", e))
+    }
+}
+
+/// Engine without access to the host, bounded in time and memory.
+fn sandboxed_engine() -> Engine {
+    let mut engine = Engine::new();
+    engine
+        .set_module_resolver(DummyModuleResolver::new())
+        .set_max_operations(MAX_OPERATIONS)
+        .set_max_call_levels(MAX_CALL_LEVELS)
+        .set_max_expr_depths(MAX_EXPR_DEPTH, MAX_EXPR_DEPTH)
+        .disable_symbol("eval");
+    engine.on_print(|text| tracing::info!("{}", text));
+    engine.on_debug(|text, _, _| tracing::debug!("{}", text));
+    engine
+}
@@ -1,7 +1,12 @@
 // Copyright (c) 2023 LogCraft, SAS.
 // SPDX-License-Identifier: MPL-2.0
 
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Result};
+use std::{
+    env,
+    path::{Component, Path, PathBuf},
+    process::Command,
+};
 
 pub fn ensure_kebab_case(name: &str) -> Result<&str> {
     let mut chars = name.chars();
@@ -53,3 +58,83 @@ pub fn env_forbidden_chars(s: &str) -> bool {
     }
     false
 }
+
+/// Run a git command, returning its trimmed output.
+pub fn git(args: &[&str], dir: Option<&Path>) -> Result<String> {
+    tool("git", args, dir)
+}
+
+/// Run an [oras](https://oras.land) command, used to pull OCI artifacts, returning its
+/// trimmed output.
+pub fn oras(args: &[&str], dir: Option<&Path>) -> Result<String> {
+    tool("oras", args, dir)
+}
+
+fn tool(program: &str, args: &[&str], dir: Option<&Path>) -> Result<String> {
+    let mut cmd = Command::new(program);
+    if let Some(dir) = dir {
+        cmd.current_dir(dir);
+    }
+
+    let output = cmd
+        .args(args)
+        .output()
+        .map_err(|e| anyhow!("unable to run {}: {}", program, e))?;
+
+    if !output.status.success() {
+        bail!(
+            "{} {} failed: {}",
+            program,
+            args.first().unwrap_or(&""),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Command running a command line through the shell, `sh -c` or `cmd /C` on Windows.
+pub fn shell_command(command: &str) -> tokio::process::Command {
+    let (shell, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+
+    let mut cmd = tokio::process::Command::new(shell);
+    cmd.args([flag, command]);
+    cmd
+}
+
+/// Workspace relative, forward slash form of a path, identical on every OS.
+///
+/// Used for every path displayed or stored in state, paths outside of the workspace are kept as is.
+pub fn workspace_path(path: &Path) -> String {
+    let path = match env::current_dir() {
+        Ok(dir) if path.is_absolute() => match path.strip_prefix(&dir) {
+            Ok(path) => path,
+            Err(_) => return path.display().to_string(),
+        },
+        _ => path,
+    };
+
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(component) => Some(component.to_string_lossy()),
+            Component::ParentDir => Some("..".into()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Path from its workspace form, also accepting relative paths written with backslashes.
+pub fn from_workspace_path(path: &str) -> PathBuf {
+    if Path::new(path).is_absolute() {
+        return PathBuf::from(path);
+    }
+
+    path.split(['/', '\\'])
+        .filter(|component| !component.is_empty() && *component != ".")
+        .collect()
+}
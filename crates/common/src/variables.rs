@@ -0,0 +1,283 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{anyhow, bail, Result};
+use std::{
+    collections::{BTreeMap, HashMap},
+    env, fs,
+    path::PathBuf,
+};
+
+use crate::utils::env_forbidden_chars;
+
+/// Prefix of environment variables defining detection variables (e.g. `LGC_VAR_index`).
+pub const LGC_VAR_ENV_PREFIX: &str = "LGC_VAR_";
+
+/// Values substituted to `${name}` placeholders in detection files.
+#[derive(Clone, Default, Debug)]
+pub struct Variables(HashMap<String, String>);
+
+impl Variables {
+    /// Resolve variables, by decreasing precedence: `cli` (`key=value`), `var_files`
+    /// (later files first), `LGC_VAR_<key>` environment variables, then `defaults`.
+    pub fn resolve(
+        defaults: &BTreeMap<String, String>,
+        var_files: &[PathBuf],
+        cli: &[String],
+    ) -> Result<Self> {
+        let mut variables: HashMap<String, String> = defaults.clone().into_iter().collect();
+
+        variables.extend(
+            env::vars()
+                .filter(|(_, value)| !env_forbidden_chars(value))
+                .filter_map(|(key, value)| {
+                    key.strip_prefix(LGC_VAR_ENV_PREFIX)
+                        .map(|key| (key.to_string(), value))
+                }),
+        );
+
+        for path in var_files {
+            let content = fs::read_to_string(path)
+                .map_err(|e| anyhow!("unable to read var file `{}`: {}", path.display(), e))?;
+            let values: BTreeMap<String, serde_yaml_ng::Value> = serde_yaml_ng::from_str(&content)
+                .map_err(|e| anyhow!("invalid var file `{}`: {}", path.display(), e))?;
+
+            for (key, value) in values {
+                let value = match value {
+                    serde_yaml_ng::Value::String(value) => value,
+                    value => serde_yaml_ng::to_string(&value)?.trim_end().to_string(),
+                };
+                variables.insert(key, value);
+            }
+        }
+
+        for var in cli {
+            let (key, value) = var
+                .split_once('=')
+                .ok_or_else(|| anyhow!("invalid variable `{}`, expected `key=value`", var))?;
+            variables.insert(key.to_string(), value.to_string());
+        }
+
+        if let Some((key, _)) = variables
+            .iter()
+            .find(|(key, value)| env_forbidden_chars(key) || env_forbidden_chars(value))
+        {
+            return Err(anyhow!("variable `{}` contains forbidden characters", key));
+        }
+
+        Ok(Self(variables))
+    }
+
+    /// Replace `${name}` placeholders of defined variables, others are left untouched.
+    pub fn substitute(&self, content: String) -> Result<String> {
+        if self.0.is_empty() || !envsubst::is_templated(&content) {
+            return Ok(content);
+        }
+
+        envsubst::substitute(content, &self.0).map_err(|e| anyhow!("{}", e))
+    }
+}
+
+/// Render `${...}` placeholders of the project configuration document, before it is parsed.
+///
+/// `${var.<name>}` is a project variable of the `variables` table, overridden by the
+/// `variables` of `environment`. `${env.<NAME>}` and `${NAME}` are environment variables,
+/// unset ones being left untouched with the short form. `${...:-default}` gives a default
+/// value and `$${` writes a literal `${`.
+pub fn render_configuration(
+    config: &mut serde_yaml_ng::Value,
+    environment: Option<&str>,
+) -> Result<()> {
+    // Variables may only reference environment variables
+    let mut variables = BTreeMap::new();
+    let environment_variables = environment.and_then(|environment| {
+        config
+            .get("environments")?
+            .as_sequence()?
+            .iter()
+            .find(|env| env.get("id").and_then(|id| id.as_str()) == Some(environment))?
+            .get("variables")
+    });
+    for table in [config.get("variables"), environment_variables]
+        .into_iter()
+        .flatten()
+    {
+        let Some(table) = table.as_mapping() else {
+            bail!("`variables` must be a table")
+        };
+        for (name, value) in table {
+            let name = scalar_string(name)?;
+            let value = render_value(&scalar_string(value)?, &BTreeMap::new())
+                .map_err(|e| anyhow!("variable `{}`: {}", name, e))?;
+            variables.insert(name, value);
+        }
+    }
+
+    render_tree(config, &variables)
+}
+
+fn render_tree(
+    value: &mut serde_yaml_ng::Value,
+    variables: &BTreeMap<String, String>,
+) -> Result<()> {
+    match value {
+        serde_yaml_ng::Value::String(content) => *content = render_value(content, variables)?,
+        serde_yaml_ng::Value::Sequence(items) => {
+            for item in items {
+                render_tree(item, variables)?;
+            }
+        }
+        serde_yaml_ng::Value::Mapping(fields) => {
+            for (_, field) in fields.iter_mut() {
+                render_tree(field, variables)?;
+            }
+        }
+        _ => (),
+    }
+
+    Ok(())
+}
+
+fn render_value(value: &str, variables: &BTreeMap<String, String>) -> Result<String> {
+    let mut rendered = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        // `$${` escapes a placeholder
+        if rest[..start].ends_with('$') {
+            rendered.push_str(&rest[..start - 1]);
+            rendered.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+
+        rendered.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .map(|end| start + end)
+            .ok_or_else(|| anyhow!("unterminated placeholder in `{}`", value))?;
+        let placeholder = &rest[start..=end];
+        match resolve_placeholder(&rest[start + 2..end], variables)? {
+            Some(resolved) => rendered.push_str(&resolved),
+            None => rendered.push_str(placeholder),
+        }
+        rest = &rest[end + 1..];
+    }
+    rendered.push_str(rest);
+
+    Ok(rendered)
+}
+
+/// Value of a placeholder expression, `None` for unset environment variables in short form.
+fn resolve_placeholder(
+    expression: &str,
+    variables: &BTreeMap<String, String>,
+) -> Result<Option<String>> {
+    let (name, default) = match expression.split_once(":-") {
+        Some((name, default)) => (name, Some(default)),
+        None => (expression, None),
+    };
+
+    let value = match name.strip_prefix("var.") {
+        Some(variable) => variables.get(variable).cloned(),
+        None => env::var(name.strip_prefix("env.").unwrap_or(name))
+            .ok()
+            .filter(|value| !env_forbidden_chars(value)),
+    };
+
+    match (value, default) {
+        (Some(value), _) => Ok(Some(value)),
+        (None, Some(default)) => Ok(Some(default.to_string())),
+        (None, None) if name.starts_with("var.") => {
+            bail!("variable `{}` is not defined", &name[4..])
+        }
+        (None, None) if name.starts_with("env.") => {
+            bail!("environment variable `{}` is not set", &name[4..])
+        }
+        (None, None) => Ok(None),
+    }
+}
+
+fn scalar_string(value: &serde_yaml_ng::Value) -> Result<String> {
+    Ok(match value {
+        serde_yaml_ng::Value::String(value) => value.clone(),
+        value => serde_yaml_ng::to_string(value)?.trim_end().to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn var_file(content: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+        file
+    }
+
+    fn defaults(values: &[(&str, &str)]) -> BTreeMap<String, String> {
+        values
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    // Each test uses its own variable names, as tests share the process environment
+    #[test]
+    fn cli_overrides_everything() {
+        env::set_var("LGC_VAR_cli_index", "env");
+        let file = var_file("cli_index: file\n");
+        let variables = Variables::resolve(
+            &defaults(&[("cli_index", "default")]),
+            &[file.path().to_path_buf()],
+            &["cli_index=cli".to_string()],
+        )
+        .unwrap();
+        assert_eq!(variables.0["cli_index"], "cli");
+    }
+
+    #[test]
+    fn var_files_override_env_and_defaults() {
+        env::set_var("LGC_VAR_file_index", "env");
+        let first = var_file("file_index: first\nfile_other: first\n");
+        let second = var_file("file_index: second\n");
+        let variables = Variables::resolve(
+            &defaults(&[("file_index", "default")]),
+            &[first.path().to_path_buf(), second.path().to_path_buf()],
+            &[],
+        )
+        .unwrap();
+        assert_eq!(variables.0["file_index"], "second");
+        assert_eq!(variables.0["file_other"], "first");
+    }
+
+    #[test]
+    fn env_overrides_defaults() {
+        env::set_var("LGC_VAR_env_index", "env");
+        let variables = Variables::resolve(
+            &defaults(&[("env_index", "default"), ("env_other", "default")]),
+            &[],
+            &[],
+        )
+        .unwrap();
+        assert_eq!(variables.0["env_index"], "env");
+        assert_eq!(variables.0["env_other"], "default");
+    }
+
+    #[test]
+    fn non_string_values_of_var_files() {
+        let file = var_file("scalar_count: 5\nscalar_enabled: true\n");
+        let variables =
+            Variables::resolve(&BTreeMap::new(), &[file.path().to_path_buf()], &[]).unwrap();
+        assert_eq!(variables.0["scalar_count"], "5");
+        assert_eq!(variables.0["scalar_enabled"], "true");
+    }
+
+    #[test]
+    fn invalid_cli_variable() {
+        let error = Variables::resolve(&BTreeMap::new(), &[], &["index".to_string()])
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("expected `key=value`"), "{}", error);
+    }
+}
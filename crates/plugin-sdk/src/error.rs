@@ -0,0 +1,92 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use serde::de::DeserializeOwned;
+use std::fmt;
+
+use crate::PluginError;
+
+/// Result of plugin functions, errors are reported to lgc as is.
+pub type Result<T> = std::result::Result<T, PluginError>;
+
+impl PluginError {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            status_code: None,
+            message: message.into(),
+            endpoint: None,
+            retryable: false,
+        }
+    }
+
+    /// Error of a remote service which answered `status` on `endpoint`.
+    ///
+    /// Timeouts, rate limits and server errors are retryable, lgc retries them with backoff.
+    pub fn from_status(
+        status: u16,
+        endpoint: impl Into<String>,
+        message: impl Into<String>,
+    ) -> Self {
+        let message = message.into();
+        Self {
+            status_code: Some(status),
+            message: match (message.is_empty(), status_reason(status)) {
+                (true, Some(reason)) => reason.to_string(),
+                (false, Some(reason)) => format!("{}: {}", reason, message),
+                (_, None) => message,
+            },
+            endpoint: Some(endpoint.into()),
+            retryable: matches!(status, 408 | 429 | 500..=599),
+        }
+    }
+
+    /// Error of a remote service which could not be reached, always retryable.
+    pub fn unreachable(endpoint: impl Into<String>, message: impl fmt::Display) -> Self {
+        Self {
+            status_code: None,
+            message: format!("service unreachable: {}", message),
+            endpoint: Some(endpoint.into()),
+            retryable: true,
+        }
+    }
+
+    /// Error of an optional function the plugin does not implement.
+    pub fn unsupported(function: &str) -> Self {
+        Self::new(format!("`{}` is not supported by this plugin", function))
+    }
+}
+
+impl From<serde_json::Error> for PluginError {
+    fn from(error: serde_json::Error) -> Self {
+        Self::new(error.to_string())
+    }
+}
+
+/// Meaning of the status codes services commonly answer with.
+fn status_reason(status: u16) -> Option<&'static str> {
+    Some(match status {
+        400 => "request rejected",
+        401 => "invalid credentials",
+        403 => "permission denied",
+        404 => "not found",
+        408 => "request timed out",
+        409 => "conflicting object",
+        429 => "rate limited",
+        500..=599 => "service error",
+        _ => return None,
+    })
+}
+
+/// Deserialize JSON passed by lgc, errors pointing to the invalid field (e.g. `invalid
+/// rule at `search.query`: expected a string`).
+pub fn from_json<T: DeserializeOwned>(json: &str, what: &str) -> Result<T> {
+    let deserializer = &mut serde_json::Deserializer::from_str(json);
+    serde_path_to_error::deserialize(deserializer).map_err(|e| {
+        let path = e.path().to_string();
+        if path == "." {
+            PluginError::new(format!("invalid {}: {}", what, e.inner()))
+        } else {
+            PluginError::new(format!("invalid {} at `{}`: {}", what, path, e.inner()))
+        }
+    })
+}
@@ -0,0 +1,96 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+//! Adapters between the plugin interface and [`Plugin`], called by [`logcraft_plugin!`].
+
+use crate::{
+    from_json, Bundle, Metadata, Plugin, PluginError, Result, RulePage, ServiceStatus, TailPage,
+    INTERFACE_VERSION,
+};
+
+pub fn load<P: Plugin>() -> Metadata {
+    Metadata {
+        name: P::NAME.to_string(),
+        version: P::VERSION.to_string(),
+        author: P::AUTHOR.to_string(),
+        description: P::DESCRIPTION.to_string(),
+        interface_version: INTERFACE_VERSION.to_string(),
+        capabilities: P::CAPABILITIES
+            .iter()
+            .map(|name| name.to_string())
+            .collect(),
+    }
+}
+
+pub fn from_common<P: Plugin>(rule: &str) -> Result<String> {
+    let rule = P::from_common(from_json(rule, "common rule")?)?;
+    Ok(serde_json::to_string(&rule)?)
+}
+
+/// Call a CRUD function with deserialized settings and rule.
+pub fn rule<P: Plugin>(
+    config: &str,
+    name: &str,
+    params: &str,
+    action: fn(&P::Settings, &str, &P::Rule) -> Result<Option<P::Rule>>,
+) -> Result<Option<String>> {
+    let settings = from_json(config, "settings")?;
+    let rule = from_json(params, "rule")?;
+    action(&settings, name, &rule)?
+        .map(|rule| serde_json::to_string(&rule).map_err(PluginError::from))
+        .transpose()
+}
+
+pub fn read_remote<P: Plugin>(config: &str, name: &str, params: &str) -> Result<Option<String>> {
+    let settings = from_json(config, "settings")?;
+    let rule = from_json(params, "rule")?;
+    P::read_remote(&settings, name, &rule)?
+        .map(|remote| serde_json::to_string(&remote).map_err(PluginError::from))
+        .transpose()
+}
+
+pub fn list_rules<P: Plugin>(config: &str, cursor: Option<&str>, count: u32) -> Result<RulePage> {
+    let page = P::list_rules(&from_json(config, "settings")?, cursor, count)?;
+    Ok(RulePage {
+        rules: page
+            .items
+            .into_iter()
+            .map(|(name, rule)| Ok::<_, PluginError>((name, serde_json::to_string(&rule)?)))
+            .collect::<Result<_>>()?,
+        next: page.next,
+    })
+}
+
+pub fn tail<P: Plugin>(
+    config: &str,
+    name: &str,
+    params: &str,
+    cursor: Option<&str>,
+) -> Result<TailPage> {
+    let settings = from_json(config, "settings")?;
+    let rule = from_json(params, "rule")?;
+    let page = P::tail(&settings, name, &rule, cursor)?;
+    Ok(TailPage {
+        events: page.items,
+        next: page.next,
+    })
+}
+
+pub fn template<P: Plugin>(config: &str, rules: &[(String, String)]) -> Result<Bundle> {
+    let rules = rules
+        .iter()
+        .map(|(name, rule)| Ok::<_, PluginError>((name.clone(), from_json(rule, "rule")?)))
+        .collect::<Result<Vec<_>>>()?;
+    P::template(&from_json(config, "settings")?, &rules)
+}
+
+pub fn check<P: Plugin>(config: Option<&str>, name: &str, params: &str) -> Result<Vec<String>> {
+    let settings = config
+        .map(|config| from_json(config, "settings"))
+        .transpose()?;
+    P::check(settings.as_ref(), name, &from_json(params, "rule")?)
+}
+
+pub fn ping<P: Plugin>(config: &str) -> Result<ServiceStatus> {
+    P::ping(&from_json(config, "settings")?)
+}
@@ -0,0 +1,241 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+//! Build LogCraft CLI plugins.
+//!
+//! Implement [`Plugin`] for a type and export it with [`logcraft_plugin!`]: settings and rules
+//! are deserialized from the JSON passed by lgc, results are serialized back, and optional
+//! functions the plugin does not implement report a clear error.
+//!
+//! ```ignore
+//! use logcraft_plugin_sdk::{logcraft_plugin, Plugin, Result, ServiceStatus};
+//!
+//! struct Sample;
+//!
+//! impl Plugin for Sample {
+//!     const NAME: &'static str = "sample";
+//!     const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+//!     const AUTHOR: &'static str = "LogCraft";
+//!     const DESCRIPTION: &'static str = "Sample plugin";
+//!     const SETTINGS: &'static str = include_str!("../settings.k");
+//!     const RULE: &'static str = include_str!("../rule.k");
+//!
+//!     type Settings = Settings;
+//!     type Rule = Rule;
+//!
+//!     fn create(settings: &Settings, name: &str, rule: &Rule) -> Result<Option<Rule>> { ... }
+//!     // read, update, delete and ping
+//! }
+//!
+//! logcraft_plugin!(Sample);
+//! ```
+
+pub mod bindings {
+    wit_bindgen::generate!({
+        world: "plugins",
+        path: "../../wit",
+        pub_export_macro: true,
+        default_bindings_module: "logcraft_plugin_sdk::bindings",
+    });
+}
+
+mod error;
+#[doc(hidden)]
+pub mod guest;
+
+pub use bindings::exports::logcraft::lgc::plugin::{
+    Bundle, Metadata, PluginError, RulePage, ServiceStatus, TailPage,
+};
+pub use error::{from_json, Result};
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+/// Version of the plugin interface implemented by plugins built with this crate.
+pub const INTERFACE_VERSION: &str = "0.3.0";
+
+/// Optional functions, listed in [`Plugin::CAPABILITIES`] when implemented.
+pub mod capability {
+    /// [`Plugin::from_common`](crate::Plugin::from_common)
+    pub const FROM_COMMON: &str = "from-common";
+    /// [`Plugin::read_remote`](crate::Plugin::read_remote)
+    pub const READ_REMOTE: &str = "read-remote";
+    /// [`Plugin::list_rules`](crate::Plugin::list_rules)
+    pub const LIST_RULES: &str = "list-rules";
+    /// [`Plugin::tail`](crate::Plugin::tail)
+    pub const TAIL: &str = "tail";
+    /// [`Plugin::template`](crate::Plugin::template)
+    pub const TEMPLATE: &str = "template";
+    /// Not a function: rules may carry detection metadata (owner, tags...) in their `metadata`
+    /// field, which [`Plugin::Rule`](crate::Plugin::Rule) must keep and
+    /// [`Plugin::read`](crate::Plugin::read) return. lgc never sends it to other plugins.
+    pub const METADATA: &str = "metadata";
+    /// [`Plugin::check`](crate::Plugin::check)
+    pub const CHECK: &str = "check";
+}
+
+/// A page of items, followed by the cursor of the next page if any.
+#[derive(Debug, Default)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next: Option<String>,
+}
+
+/// A LogCraft CLI plugin, exported with [`logcraft_plugin!`].
+pub trait Plugin {
+    /// Plugin name, in kebab case
+    const NAME: &'static str;
+    const VERSION: &'static str;
+    const AUTHOR: &'static str;
+    const DESCRIPTION: &'static str;
+    /// KCL code defining the `Configuration` schema of service settings
+    const SETTINGS: &'static str;
+    /// KCL code defining the `Rule` schema
+    const RULE: &'static str;
+    /// Optional functions implemented by the plugin, see [`capability`]
+    const CAPABILITIES: &'static [&'static str] = &[];
+
+    /// Service settings
+    type Settings: DeserializeOwned;
+    /// Rule content, as written in detections under the plugin name
+    type Rule: DeserializeOwned + Serialize;
+
+    fn create(
+        settings: &Self::Settings,
+        name: &str,
+        rule: &Self::Rule,
+    ) -> Result<Option<Self::Rule>>;
+    fn read(settings: &Self::Settings, name: &str, rule: &Self::Rule)
+        -> Result<Option<Self::Rule>>;
+    fn update(
+        settings: &Self::Settings,
+        name: &str,
+        rule: &Self::Rule,
+    ) -> Result<Option<Self::Rule>>;
+    fn delete(
+        settings: &Self::Settings,
+        name: &str,
+        rule: &Self::Rule,
+    ) -> Result<Option<Self::Rule>>;
+    fn ping(settings: &Self::Settings) -> Result<ServiceStatus>;
+
+    /// Map a rule written in the common rule format
+    fn from_common(_rule: Value) -> Result<Self::Rule> {
+        Err(PluginError::unsupported(capability::FROM_COMMON))
+    }
+
+    /// Read the full normalized remote object, not only the fields of the rule
+    fn read_remote(
+        _settings: &Self::Settings,
+        _name: &str,
+        _rule: &Self::Rule,
+    ) -> Result<Option<Value>> {
+        Err(PluginError::unsupported(capability::READ_REMOTE))
+    }
+
+    /// List rules deployed on the service, `count` at most per page
+    fn list_rules(
+        _settings: &Self::Settings,
+        _cursor: Option<&str>,
+        _count: u32,
+    ) -> Result<Page<(String, Self::Rule)>> {
+        Err(PluginError::unsupported(capability::LIST_RULES))
+    }
+
+    /// Fetch execution events of a rule after `cursor`, formatted for display
+    fn tail(
+        _settings: &Self::Settings,
+        _name: &str,
+        _rule: &Self::Rule,
+        _cursor: Option<&str>,
+    ) -> Result<Page<String>> {
+        Err(PluginError::unsupported(capability::TAIL))
+    }
+
+    /// Render rules into a single native artifact
+    fn template(_settings: &Self::Settings, _rules: &[(String, Self::Rule)]) -> Result<Bundle> {
+        Err(PluginError::unsupported(capability::TEMPLATE))
+    }
+
+    /// Check a rule beyond its schema, against the service if settings are given
+    fn check(
+        _settings: Option<&Self::Settings>,
+        _name: &str,
+        _rule: &Self::Rule,
+    ) -> Result<Vec<String>> {
+        Err(PluginError::unsupported(capability::CHECK))
+    }
+}
+
+/// Export a [`Plugin`] implementation as the plugin component.
+#[macro_export]
+macro_rules! logcraft_plugin {
+    ($plugin:ty) => {
+        struct LogcraftPlugin;
+
+        impl $crate::bindings::exports::logcraft::lgc::plugin::Guest for LogcraftPlugin {
+            fn load() -> $crate::Metadata {
+                $crate::guest::load::<$plugin>()
+            }
+
+            fn settings() -> String {
+                <$plugin as $crate::Plugin>::SETTINGS.to_string()
+            }
+
+            fn schema() -> String {
+                <$plugin as $crate::Plugin>::RULE.to_string()
+            }
+
+            fn from_common(rule: String) -> $crate::Result<String> {
+                $crate::guest::from_common::<$plugin>(&rule)
+            }
+
+            fn create(config: String, name: String, params: String) -> $crate::Result<Option<String>> {
+                $crate::guest::rule::<$plugin>(&config, &name, &params, <$plugin as $crate::Plugin>::create)
+            }
+
+            fn read(config: String, name: String, params: String) -> $crate::Result<Option<String>> {
+                $crate::guest::rule::<$plugin>(&config, &name, &params, <$plugin as $crate::Plugin>::read)
+            }
+
+            fn read_remote(config: String, name: String, params: String) -> $crate::Result<Option<String>> {
+                $crate::guest::read_remote::<$plugin>(&config, &name, &params)
+            }
+
+            fn update(config: String, name: String, params: String) -> $crate::Result<Option<String>> {
+                $crate::guest::rule::<$plugin>(&config, &name, &params, <$plugin as $crate::Plugin>::update)
+            }
+
+            fn delete(config: String, name: String, params: String) -> $crate::Result<Option<String>> {
+                $crate::guest::rule::<$plugin>(&config, &name, &params, <$plugin as $crate::Plugin>::delete)
+            }
+
+            fn list_rules(config: String, cursor: Option<String>, count: u32) -> $crate::Result<$crate::RulePage> {
+                $crate::guest::list_rules::<$plugin>(&config, cursor.as_deref(), count)
+            }
+
+            fn tail(
+                config: String,
+                name: String,
+                params: String,
+                cursor: Option<String>,
+            ) -> $crate::Result<$crate::TailPage> {
+                $crate::guest::tail::<$plugin>(&config, &name, &params, cursor.as_deref())
+            }
+
+            fn template(config: String, rules: Vec<(String, String)>) -> $crate::Result<$crate::Bundle> {
+                $crate::guest::template::<$plugin>(&config, &rules)
+            }
+
+            fn check(config: Option<String>, name: String, params: String) -> $crate::Result<Vec<String>> {
+                $crate::guest::check::<$plugin>(config.as_deref(), &name, &params)
+            }
+
+            fn ping(config: String) -> $crate::Result<$crate::ServiceStatus> {
+                $crate::guest::ping::<$plugin>(&config)
+            }
+        }
+
+        $crate::bindings::export!(LogcraftPlugin with_types_in $crate::bindings);
+    };
+}
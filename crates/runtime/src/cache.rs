@@ -0,0 +1,226 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{anyhow, Result};
+use fd_lock::RwLock;
+use sha2::{Digest, Sha256};
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    hash::{DefaultHasher, Hash, Hasher},
+    io::Write,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tempfile::NamedTempFile;
+use wasmtime::component::Component;
+
+/// Environment variable overriding the default cache directory.
+pub const LGC_CACHE_DIR_ENV: &str = "LGC_CACHE_DIR";
+
+const CACHE_ENTRY_EXTENSION: &str = "cwasm";
+const CACHE_LOCK_EXTENSION: &str = "lock";
+const SCHEMAS_ENTRY_EXTENSION: &str = "schemas";
+const READ_ENTRY_EXTENSION: &str = "read";
+
+/// Cache of precompiled components, of their schemas and of remote reads, shared by every
+/// project of the user.
+///
+/// Entries are keyed by the component digest and, for compiled ones, the engine compatibility hash.
+/// Each entry is protected by a file lock and written atomically, so concurrent
+/// invocations never compile the same component twice nor read partial files.
+#[derive(Clone)]
+pub struct Cache {
+    directory: PathBuf,
+}
+
+/// Cache usage summary.
+pub struct CacheInfo {
+    pub entries: usize,
+    pub size: u64,
+}
+
+impl Cache {
+    /// Open the cache located in `directory`, or in the user cache directory if unset.
+    pub fn new(directory: Option<PathBuf>) -> Result<Self> {
+        let directory =
+            match directory.or_else(|| env::var_os(LGC_CACHE_DIR_ENV).map(PathBuf::from)) {
+                Some(directory) => directory,
+                None => dirs::cache_dir()
+                    .ok_or_else(|| anyhow!("unable to determine user cache directory"))?
+                    .join("logcraft"),
+            };
+
+        fs::create_dir_all(&directory)
+            .map_err(|e| anyhow!("unable to create cache directory: {}", e))?;
+
+        Ok(Self { directory })
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Load a component, compiling and caching it if needed.
+    pub fn load_component(&self, engine: &wasmtime::Engine, bytes: &[u8]) -> Result<Component> {
+        let entry = self.entry_path(engine, bytes);
+
+        // Concurrent invocations wait for the first one to compile the component
+        let mut lock = self.entry_lock(&entry)?;
+        let _guard = lock.write()?;
+
+        if entry.is_file() {
+            // SAFETY: entries are only written by this cache, atomically, from components
+            // compiled by an engine sharing the same compatibility hash.
+            match unsafe { Component::deserialize_file(engine, &entry) } {
+                Ok(component) => return Ok(component),
+                Err(e) => {
+                    tracing::warn!("ignoring invalid cache entry `{}`: {}", entry.display(), e)
+                }
+            }
+        }
+
+        let compiled = engine.precompile_component(bytes)?;
+
+        // Write to a temporary file first so readers never see partial entries
+        let mut file = NamedTempFile::new_in(&self.directory)?;
+        file.write_all(&compiled)?;
+        file.persist(&entry)
+            .map_err(|e| anyhow!("unable to write cache entry: {}", e))?;
+
+        // SAFETY: `compiled` has just been produced by this engine.
+        unsafe { Component::deserialize(engine, &compiled) }
+    }
+
+    /// Schemas of a plugin, as stored by [`Cache::store_schemas`].
+    pub fn load_schemas(&self, digest: &str) -> Option<String> {
+        fs::read_to_string(self.schemas_path(digest)).ok()
+    }
+
+    /// Store the schemas of a plugin, keyed by its digest.
+    pub fn store_schemas(&self, digest: &str, schemas: &str) -> Result<()> {
+        // Written atomically, readers never see partial entries
+        let mut file = NamedTempFile::new_in(&self.directory)?;
+        file.write_all(schemas.as_bytes())?;
+        file.persist(self.schemas_path(digest))
+            .map_err(|e| anyhow!("unable to write cache entry: {}", e))?;
+        Ok(())
+    }
+
+    /// Remote read stored by [`Cache::store_read`], if more recent than `ttl`.
+    pub fn load_read(&self, key: &str, ttl: Duration) -> Option<String> {
+        let path = self.read_path(key);
+        let age = fs::metadata(&path).ok()?.modified().ok()?.elapsed().ok()?;
+        if age > ttl {
+            return None;
+        }
+        fs::read_to_string(path).ok()
+    }
+
+    /// Store the result of a remote read, keyed by a digest of what was read.
+    pub fn store_read(&self, key: &str, content: &str) -> Result<()> {
+        let mut file = NamedTempFile::new_in(&self.directory)?;
+        file.write_all(content.as_bytes())?;
+        file.persist(self.read_path(key))
+            .map_err(|e| anyhow!("unable to write cache entry: {}", e))?;
+        Ok(())
+    }
+
+    /// Summarize the cache content.
+    pub fn info(&self) -> Result<CacheInfo> {
+        let mut info = CacheInfo {
+            entries: 0,
+            size: 0,
+        };
+
+        for entry in self.entries()? {
+            info.entries += 1;
+            info.size += entry.metadata()?.len();
+        }
+
+        Ok(info)
+    }
+
+    /// Remove every cache entry, returning the number of removed entries.
+    pub fn clean(&self) -> Result<usize> {
+        let mut removed = 0;
+
+        for entry in self.entries()? {
+            if matches!(
+                entry.extension().and_then(|ext| ext.to_str()),
+                Some(SCHEMAS_ENTRY_EXTENSION | READ_ENTRY_EXTENSION)
+            ) {
+                fs::remove_file(&entry)?;
+                removed += 1;
+                continue;
+            }
+
+            let lock_path = entry.with_extension(CACHE_LOCK_EXTENSION);
+            {
+                let mut lock = self.entry_lock(&entry)?;
+                let _guard = lock.write()?;
+                fs::remove_file(&entry)?;
+            }
+            fs::remove_file(lock_path)?;
+            removed += 1;
+        }
+
+        Ok(removed)
+    }
+
+    fn entries(&self) -> Result<Vec<PathBuf>> {
+        Ok(fs::read_dir(&self.directory)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| {
+                path.is_file()
+                    && matches!(
+                        path.extension().and_then(|ext| ext.to_str()),
+                        Some(
+                            CACHE_ENTRY_EXTENSION | SCHEMAS_ENTRY_EXTENSION | READ_ENTRY_EXTENSION
+                        )
+                    )
+            })
+            .collect())
+    }
+
+    fn entry_path(&self, engine: &wasmtime::Engine, bytes: &[u8]) -> PathBuf {
+        let mut hasher = DefaultHasher::new();
+        engine.precompile_compatibility_hash().hash(&mut hasher);
+
+        self.directory.join(format!(
+            "{}-{:016x}.{}",
+            digest(bytes),
+            hasher.finish(),
+            CACHE_ENTRY_EXTENSION
+        ))
+    }
+
+    fn schemas_path(&self, digest: &str) -> PathBuf {
+        self.directory
+            .join(format!("{}.{}", digest, SCHEMAS_ENTRY_EXTENSION))
+    }
+
+    fn read_path(&self, key: &str) -> PathBuf {
+        self.directory
+            .join(format!("{}.{}", key, READ_ENTRY_EXTENSION))
+    }
+
+    fn entry_lock(&self, entry: &Path) -> Result<RwLock<fs::File>> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(entry.with_extension(CACHE_LOCK_EXTENSION))
+            .map_err(|e| anyhow!("unable to lock cache entry: {}", e))?;
+
+        Ok(RwLock::new(file))
+    }
+}
+
+/// Hex encoded SHA-256 digest of a component.
+pub fn digest(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
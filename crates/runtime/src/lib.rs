@@ -1,10 +1,13 @@
 // Copyright (c) 2023 LogCraft, SAS.
 // SPDX-License-Identifier: MPL-2.0
 
+pub mod cache;
 mod engine;
+pub mod network;
 pub mod state;
 use std::time::Duration;
 
+pub use cache::Cache;
 pub use engine::{Config, Engine};
 
 /// The default [`EngineBuilder::epoch_tick_interval`].
@@ -15,6 +18,53 @@ wasmtime::component::bindgen!({
     async: true
 });
 
+/// Bindings of the previous plugin interface versions, plugins built against them lack the
+/// functions added since.
+pub mod compat {
+    pub mod v0_1 {
+        wasmtime::component::bindgen!({
+            path: "../../wit/compat/0.1.0",
+            async: true
+        });
+    }
+
+    pub mod v0_2 {
+        wasmtime::component::bindgen!({
+            path: "../../wit/compat/0.2.0",
+            async: true
+        });
+    }
+}
+
+/// Interfaces imported by a component, such as `wasi:http/outgoing-handler`.
+pub fn component_imports(bytes: &[u8]) -> anyhow::Result<Vec<String>> {
+    let mut imports = Vec::new();
+    for payload in wasmparser::Parser::new(0).parse_all(bytes) {
+        if let wasmparser::Payload::ComponentImportSection(reader) = payload? {
+            for import in reader {
+                imports.push(import?.name.0.to_string());
+            }
+        }
+    }
+    imports.sort();
+    imports.dedup();
+    Ok(imports)
+}
+
+/// Version of the plugin interface exported by a component, e.g. `0.3.0`.
+pub fn exported_interface_version(bytes: &[u8]) -> anyhow::Result<Option<String>> {
+    for payload in wasmparser::Parser::new(0).parse_all(bytes) {
+        if let wasmparser::Payload::ComponentExportSection(reader) = payload? {
+            for export in reader {
+                if let Some(version) = export?.name.0.strip_prefix("logcraft:lgc/plugin@") {
+                    return Ok(Some(version.to_string()));
+                }
+            }
+        }
+    }
+    Ok(None)
+}
+
 pub mod plugin_component {
     pub use crate::exports::logcraft::lgc::plugin;
     pub use crate::Plugins;
@@ -1,10 +1,14 @@
 // Copyright (c) 2023 LogCraft, SAS.
 // SPDX-License-Identifier: MPL-2.0
 
+use anyhow::{anyhow, Result};
 use http_body_util::BodyExt;
+use std::{path::PathBuf, time::Duration};
 use tokio::{net::TcpStream, time::timeout};
-use wasmtime::component::ResourceTable;
-use wasmtime_wasi::{WasiCtx, WasiView};
+use wasmtime::{component::ResourceTable, StoreLimits, StoreLimitsBuilder};
+use wasmtime_wasi::{
+    bindings::filesystem::types::ErrorCode, DirPerms, FilePerms, WasiCtx, WasiView,
+};
 use wasmtime_wasi_http::{
     bindings::http::types::ErrorCode,
     body::HyperOutgoingBody,
@@ -14,19 +18,132 @@ use wasmtime_wasi_http::{
     WasiHttpCtx, WasiHttpView,
 };
 
+use crate::network::{tunnel, Network, Proxy};
+
+/// Host directory exposed to a plugin.
+#[derive(Clone, Debug)]
+pub struct Preopen {
+    /// Directory on the host
+    pub host: PathBuf,
+    /// Path under which the plugin sees the directory
+    pub guest: String,
+    /// Allow the plugin to create and modify files
+    pub writable: bool,
+}
+
+/// Whether a plugin call failed on a WASI filesystem access the plugin was not granted.
+pub fn is_filesystem_denial(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        matches!(
+            cause.downcast_ref::<ErrorCode>(),
+            Some(ErrorCode::Access | ErrorCode::NotPermitted)
+        ) || cause
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|e| e.kind() == std::io::ErrorKind::PermissionDenied)
+    })
+}
+
+/// Resources a plugin may use, unrestricted when not set.
+#[derive(Clone, Debug, Default)]
+pub struct Sandbox {
+    /// Maximum size of each plugin memory, in bytes
+    pub max_memory: Option<usize>,
+    /// Time allowed to wasm execution for each plugin call
+    pub call_timeout: Option<Duration>,
+    /// Hosts the plugin may send requests to, `*.` prefixes matching any subdomain
+    pub allowed_hosts: Option<Vec<String>>,
+}
+
+impl Sandbox {
+    /// Whether requests to `host` are allowed.
+    pub fn allows(&self, host: &str) -> bool {
+        let Some(allowed_hosts) = &self.allowed_hosts else {
+            return true;
+        };
+
+        let host = host.to_lowercase();
+        allowed_hosts.iter().any(|allowed| {
+            let allowed = allowed.to_lowercase();
+            match allowed.strip_prefix("*.") {
+                Some(domain) => host
+                    .strip_suffix(domain)
+                    .is_some_and(|subdomain| subdomain.ends_with('.')),
+                None => host == allowed,
+            }
+        })
+    }
+}
+
 pub struct State {
     pub table: ResourceTable,
     pub ctx: WasiCtx,
     pub http: WasiHttpCtx,
+    pub sandbox: Sandbox,
+    /// Memory limits of the store, from the sandbox
+    pub limits: StoreLimits,
+    pub network: Network,
 }
 
 impl State {
+    /// State without any filesystem, environment or stdio access.
     pub fn new() -> Self {
         Self {
             table: ResourceTable::new(),
             ctx: WasiCtx::builder().build(),
             http: WasiHttpCtx::new(),
+            sandbox: Sandbox::default(),
+            limits: StoreLimits::default(),
+            network: Network::default(),
+        }
+    }
+
+    /// State granting access to the given directories only.
+    pub fn with_preopens(preopens: &[Preopen]) -> Result<Self> {
+        let mut builder = WasiCtx::builder();
+        for preopen in preopens {
+            let (dir_perms, file_perms) = if preopen.writable {
+                (DirPerms::all(), FilePerms::all())
+            } else {
+                (DirPerms::READ, FilePerms::READ)
+            };
+
+            builder
+                .preopened_dir(&preopen.host, &preopen.guest, dir_perms, file_perms)
+                .map_err(|e| {
+                    anyhow!(
+                        "capability error: unable to expose `{}`: {}",
+                        preopen.host.display(),
+                        e
+                    )
+                })?;
+        }
+
+        Ok(Self {
+            table: ResourceTable::new(),
+            ctx: builder.build(),
+            http: WasiHttpCtx::new(),
+            sandbox: Sandbox::default(),
+            limits: StoreLimits::default(),
+            network: Network::default(),
+        })
+    }
+
+    /// Restrict the resources of the plugin, the store must use `limits` as its limiter.
+    pub fn with_sandbox(mut self, sandbox: Sandbox) -> Self {
+        // Failing allocations trap, for an error naming the limit instead of a guest panic
+        let mut limits = StoreLimitsBuilder::new().trap_on_grow_failure(true);
+        if let Some(max_memory) = sandbox.max_memory {
+            limits = limits.memory_size(max_memory);
         }
+        self.limits = limits.build();
+        self.sandbox = sandbox;
+        self
+    }
+
+    /// Send requests of the plugin with the proxy and certificates of the host.
+    pub fn with_network(mut self, network: Network) -> Self {
+        self.network = network;
+        self
     }
 }
 
@@ -60,16 +177,25 @@ impl WasiHttpView for State {
         request: hyper::Request<HyperOutgoingBody>,
         config: OutgoingRequestConfig,
     ) -> wasmtime_wasi_http::HttpResult<HostFutureIncomingResponse> {
-        Ok(default_send_request(request, config))
+        let host = request.uri().host().unwrap_or_default();
+        if !self.sandbox.allows(host) {
+            tracing::warn!("plugin request to `{}` denied, host is not allowed", host);
+            let handle =
+                wasmtime_wasi::runtime::spawn(async { Ok(Err(ErrorCode::HttpRequestDenied)) });
+            return Ok(HostFutureIncomingResponse::pending(handle));
+        }
+
+        Ok(default_send_request(request, config, self.network.clone()))
     }
 }
 
 pub fn default_send_request(
     request: hyper::Request<HyperOutgoingBody>,
     config: OutgoingRequestConfig,
+    network: Network,
 ) -> HostFutureIncomingResponse {
     let handle = wasmtime_wasi::runtime::spawn(async move {
-        Ok(default_send_request_handler(request, config).await)
+        Ok(default_send_request_handler(request, config, &network).await)
     });
     HostFutureIncomingResponse::pending(handle)
 }
@@ -81,7 +207,8 @@ pub(crate) fn dns_error(rcode: String, info_code: u16) -> ErrorCode {
     })
 }
 
-// ! Quick fix to allow invalid certificate (for selfsigned certificates)
+// ! Quick fix to allow invalid certificate (for selfsigned certificates), unless the host
+// ! trusts additional certificates
 pub async fn default_send_request_handler(
     mut request: hyper::Request<HyperOutgoingBody>,
     OutgoingRequestConfig {
@@ -90,6 +217,7 @@ pub async fn default_send_request_handler(
         first_byte_timeout,
         between_bytes_timeout,
     }: OutgoingRequestConfig,
+    network: &Network,
 ) -> Result<IncomingResponse, ErrorCode> {
     let authority = if let Some(authority) = request.uri().authority() {
         if authority.port().is_some() {
@@ -101,24 +229,44 @@ pub async fn default_send_request_handler(
     } else {
         return Err(ErrorCode::HttpRequestUriInvalid);
     };
-    let tcp_stream = timeout(connect_timeout, TcpStream::connect(&authority))
-        .await
-        .map_err(|_| ErrorCode::ConnectionTimeout)?
-        .map_err(|e| match e.kind() {
-            std::io::ErrorKind::AddrNotAvailable => {
-                dns_error("address not available".to_string(), 0)
-            }
+    let proxy = network.proxy_for(request.uri().host().unwrap_or_default(), use_tls);
+    let mut tcp_stream = timeout(
+        connect_timeout,
+        TcpStream::connect(proxy.map_or(authority.as_str(), |proxy| proxy.authority.as_str())),
+    )
+    .await
+    .map_err(|_| ErrorCode::ConnectionTimeout)?
+    .map_err(|e| match e.kind() {
+        std::io::ErrorKind::AddrNotAvailable => dns_error("address not available".to_string(), 0),
 
-            _ => {
-                if e.to_string()
-                    .starts_with("failed to lookup address information")
-                {
-                    dns_error("address not available".to_string(), 0)
-                } else {
-                    ErrorCode::ConnectionRefused
-                }
+        _ => {
+            if e.to_string()
+                .starts_with("failed to lookup address information")
+            {
+                dns_error("address not available".to_string(), 0)
+            } else {
+                ErrorCode::ConnectionRefused
             }
-        })?;
+        }
+    })?;
+    // TLS goes through a tunnel, plain requests are forwarded by the proxy
+    match proxy {
+        Some(proxy) if use_tls => {
+            tunnel(&mut tcp_stream, &authority, proxy, connect_timeout).await?
+        }
+        Some(Proxy {
+            authorization: Some(authorization),
+            ..
+        }) => {
+            let authorization = authorization
+                .parse()
+                .map_err(|_| ErrorCode::InternalError(Some("invalid proxy credentials".into())))?;
+            request
+                .headers_mut()
+                .insert(http::header::PROXY_AUTHORIZATION, authorization);
+        }
+        _ => (),
+    }
 
     let (mut sender, worker) = if use_tls {
         #[cfg(any(target_arch = "riscv64", target_arch = "s390x"))]
@@ -131,7 +279,12 @@ pub async fn default_send_request_handler(
         #[cfg(not(any(target_arch = "riscv64", target_arch = "s390x")))]
         {
             let mut native_tls_builder = tokio_native_tls::native_tls::TlsConnector::builder();
-            native_tls_builder.danger_accept_invalid_certs(true);
+            if network.ca_certificates.is_empty() {
+                native_tls_builder.danger_accept_invalid_certs(true);
+            }
+            for certificate in &network.ca_certificates {
+                native_tls_builder.add_root_certificate(certificate.clone());
+            }
 
             let native_tls_connector: tokio_native_tls::native_tls::TlsConnector =
                 native_tls_builder.build().unwrap();
@@ -192,16 +345,18 @@ pub async fn default_send_request_handler(
     // at this point, the request contains the scheme and the authority, but
     // the http packet should only include those if addressing a proxy, so
     // remove them here, since SendRequest::send_request does not do it for us
-    *request.uri_mut() = http::Uri::builder()
-        .path_and_query(
-            request
-                .uri()
-                .path_and_query()
-                .map(|p| p.as_str())
-                .unwrap_or("/"),
-        )
-        .build()
-        .expect("comes from valid request");
+    if proxy.is_none() || use_tls {
+        *request.uri_mut() = http::Uri::builder()
+            .path_and_query(
+                request
+                    .uri()
+                    .path_and_query()
+                    .map(|p| p.as_str())
+                    .unwrap_or("/"),
+            )
+            .build()
+            .expect("comes from valid request");
+    }
 
     let resp = timeout(first_byte_timeout, sender.send_request(request))
         .await
@@ -4,25 +4,30 @@
 #![forbid(unsafe_code)]
 #![deny(unreachable_pub)]
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use figment::providers::{Env, Format, Yaml};
 use clap::{
     builder::styling,
     CommandFactory, FromArgMatches, Parser, Subcommand
 };
-use std::{env, fs};
+use std::{env, time::Instant};
+use tracing::Instrument;
 
 // Local dependencies
 use lgc::commands;
 use lgc_common::{
-    configuration::{ProjectConfiguration, LGC_CONFIG_PATH},
-    utils::env_forbidden_chars
+    configuration::{resolve_configuration, ProjectConfiguration, ProjectMode, LGC_CONFIG_PATH},
+    report::{PartialFailure, PARTIAL_FAILURE_EXIT_CODE},
+    telemetry::TelemetryEvent
 };
 
 #[tokio::main]
 async fn main() {
     if let Err(err) = LogCraftCli::init().await {
         tracing::error!("{err}");
+        if err.is::<PartialFailure>() {
+            std::process::exit(PARTIAL_FAILURE_EXIT_CODE);
+        }
         std::process::exit(1);
     }
 }
@@ -42,6 +47,13 @@ struct LogCraftCli {
     #[clap(subcommand)]
     commands: LogCraftCommands,
 
+    #[clap(flatten)]
+    logging: commands::LoggingArgs,
+
+    /// Refuse commands changing services or the state, as `core.mode: read-only`
+    #[clap(long, global = true, env = "LGC_READ_ONLY")]
+    read_only: bool,
+
     #[clap(skip)]
     config: ProjectConfiguration,
 }
@@ -49,17 +61,132 @@ struct LogCraftCli {
 /// LogCraft CLI
 #[derive(Subcommand)]
 enum LogCraftCommands {
+    #[clap(subcommand)]
+    Cache(commands::CacheCommands),
+    #[clap(name = "__complete", hide = true)]
+    Complete(commands::CompleteCommand),
+    #[clap(subcommand)]
+    Config(commands::ConfigCommands),
+    #[clap(subcommand)]
+    Convert(commands::ConvertCommands),
     Deploy(commands::DeployCommand),
     Destroy(commands::DestroyCommand),
     Diff(commands::DiffCommand),
+    Doctor(commands::DoctorCommand),
     #[clap(subcommand, name = "envs")]
     Environments(commands::EnvironmentsCommands),
+    Graph(commands::GraphCommand),
+    Import(commands::ImportCommand),
     Init(commands::InitCommand),
     #[clap(subcommand)]
+    Maintenance(commands::MaintenanceCommands),
+    #[clap(subcommand)]
     Plugins(commands::PluginsCommands),
     #[clap(subcommand)]
+    Policies(commands::PoliciesCommands),
+    #[clap(subcommand)]
+    Remote(commands::RemoteCommands),
+    #[clap(subcommand)]
+    Report(commands::ReportCommands),
+    #[clap(subcommand)]
+    Rules(commands::RulesCommands),
+    #[clap(subcommand)]
     Services(commands::ServicesCommands),
+    Show(commands::ShowCommand),
+    #[clap(subcommand)]
+    State(commands::StateCommands),
+    Template(commands::TemplateCommand),
+    Test(commands::TestCommand),
     Validate(commands::ValidateCommand),
+    #[clap(subcommand)]
+    Workspace(commands::WorkspaceCommands),
+}
+
+impl LogCraftCommands {
+    /// Whether the command changes remote services, the state, workspaces or the project.
+    ///
+    /// Every subcommand is listed, so that new ones decide whether they are read-only.
+    fn is_mutating(&self) -> bool {
+        use commands::{
+            CacheCommands as Cache, ConfigCommands as Config, ConvertCommands as Convert,
+            EnvironmentsCommands as Environments, MaintenanceCommands as Maintenance,
+            PluginsCommands as Plugins, PoliciesCommands as Policies, RemoteCommands as Remote,
+            ReportCommands as Report, RulesCommands as Rules, ServicesCommands as Services,
+            StateCommands as State, WorkspaceCommands as Workspace,
+        };
+
+        match self {
+            Self::Deploy(_) | Self::Destroy(_) | Self::Import(_) | Self::Init(_) => true,
+            Self::Complete(_)
+            | Self::Diff(_)
+            | Self::Doctor(_)
+            | Self::Graph(_)
+            | Self::Show(_)
+            | Self::Template(_)
+            | Self::Test(_)
+            | Self::Validate(_) => false,
+            Self::Cache(cmd) => match cmd {
+                // Only the local cache is cleaned
+                Cache::Info(_) | Cache::Clean(_) => false,
+            },
+            Self::Config(cmd) => match cmd {
+                Config::Lint(_) | Config::Resolve(_) | Config::Validate(_) => false,
+            },
+            Self::Convert(cmd) => match cmd {
+                Convert::Sigma(_) => true,
+            },
+            Self::Environments(cmd) => match cmd {
+                Environments::Add(_)
+                | Environments::Remove(_)
+                | Environments::Link(_)
+                | Environments::Unlink(_) => true,
+                Environments::List(_) => false,
+            },
+            Self::Maintenance(cmd) => match cmd {
+                Maintenance::Enable(_) | Maintenance::Disable(_) => true,
+                Maintenance::Status(_) => false,
+            },
+            Self::Plugins(cmd) => match cmd {
+                Plugins::Install(_) | Plugins::Uninstall(_) | Plugins::Update(_) => true,
+                // Plugins are scaffolded outside of projects
+                Plugins::List(_)
+                | Plugins::Schema(_)
+                | Plugins::Info(_)
+                | Plugins::Verify(_)
+                | Plugins::Scaffold(_) => false,
+            },
+            Self::Policies(cmd) => match cmd {
+                Policies::Install(_) | Policies::Uninstall(_) | Policies::Update(_) => true,
+                Policies::List(_) => false,
+            },
+            Self::Remote(cmd) => match cmd {
+                Remote::Ls(_) | Remote::Cat(_) => false,
+            },
+            Self::Report(cmd) => match cmd {
+                Report::Quota(_)
+                | Report::Changes(_)
+                | Report::Workspace(_)
+                | Report::StateDiff(_) => false,
+            },
+            Self::Rules(cmd) => match cmd {
+                Rules::Archive(_) | Rules::Unarchive(_) | Rules::Move(_) => true,
+                Rules::Tail(_) | Rules::Search(_) => false,
+            },
+            Self::Services(cmd) => match cmd {
+                Services::Add(_) | Services::Remove(_) | Services::Configure(_) => true,
+                Services::List(_) | Services::Ping(_) => false,
+            },
+            Self::State(cmd) => match cmd {
+                State::RestoreBackup(_) | State::Push(_) | State::ForceUnlock(_) => true,
+                State::Backups(_) | State::Validate(_) => false,
+            },
+            Self::Workspace(cmd) => match cmd {
+                Workspace::Sync(_) | Workspace::New(_) | Workspace::Delete(_) => true,
+                // Only the local selection changes
+                Workspace::Select(_) | Workspace::List(_) | Workspace::Show(_) => false,
+            },
+        }
+    }
 }
 
 impl LogCraftCli {
@@ -80,38 +207,55 @@ impl LogCraftCli {
         let matches = LogCraftCli::command().styles(styles).get_matches();
         let mut cli = LogCraftCli::from_arg_matches(&matches)?;
 
-        tracing_subscriber::fmt()
-            .with_writer(std::io::stdout)
-            .with_target(false)
-            .without_time()
-            .with_env_filter(tracing_subscriber::EnvFilter::from_env("LGC_LOG"))
-            .with_max_level(tracing::Level::INFO)
-            .init();
+        // JSON output keeps stdout for the results document
+        let json_output = match &cli.commands {
+            LogCraftCommands::Deploy(cmd) => cmd.output.is_json(),
+            LogCraftCommands::Destroy(cmd) => cmd.output.is_json(),
+            LogCraftCommands::Diff(cmd) => cmd.output.is_json(),
+            LogCraftCommands::Test(cmd) => cmd.output.is_json(),
+            LogCraftCommands::Validate(cmd) => cmd.output.is_json(),
+            _ => false,
+        };
+        // Errors cannot be logged without a logger
+        if let Err(e) = cli.logging.init(json_output) {
+            eprintln!("error: {}", e);
+            std::process::exit(1)
+        }
 
         // Load configuration
         match cli.commands {
-            LogCraftCommands::Init(cmd) => return cmd.run(),
+            LogCraftCommands::Init(cmd) => return cmd.run().await,
+            LogCraftCommands::Cache(cmd) => return cmd.run(),
+            LogCraftCommands::Complete(cmd) => return cmd.run(),
+            // Plugins are scaffolded outside of projects
+            LogCraftCommands::Plugins(commands::PluginsCommands::Scaffold(cmd)) => {
+                return cmd.run()
+            }
+            // Validation reports problems that would stop configuration loading
+            LogCraftCommands::Config(commands::ConfigCommands::Validate(cmd)) => {
+                return cmd.run().await
+            }
             _ => {
                 let configuration_path = std::path::PathBuf::from(LGC_CONFIG_PATH);
 
                 if configuration_path.is_file() {
-                    let mut configuration_file = fs::read_to_string(configuration_path)?;
-
-                    // Environment variables substitution
-                    if envsubst::is_templated(&configuration_file) {
-                        configuration_file = envsubst::substitute(
-                            configuration_file,
-                            &env::vars()
-                                .filter_map(|(key, value)| {
-                                    if !env_forbidden_chars(&key) && !env_forbidden_chars(&value) {
-                                        Some((key, value))
-                                    } else {
-                                        None
-                                    }
-                                })
-                                .collect::<std::collections::HashMap<String, String>>(),
-                        )?;
-                    }
+                    // Overlay and variables of the targeted environment apply
+                    let environment = match &cli.commands {
+                        LogCraftCommands::Deploy(cmd) => cmd.env_id.as_deref(),
+                        LogCraftCommands::Destroy(cmd) => cmd.env_id.as_deref(),
+                        LogCraftCommands::Diff(cmd) => cmd.env_id.as_deref(),
+                        LogCraftCommands::Config(commands::ConfigCommands::Resolve(cmd)) => {
+                            cmd.env.as_deref()
+                        }
+                        _ => None,
+                    };
+                    let configuration_file = match resolve_configuration(environment) {
+                        Ok(configuration_file) => configuration_file,
+                        Err(e) => {
+                            tracing::error!("unable to load configuration: {}", e);
+                            std::process::exit(1)
+                        }
+                    };
 
                     cli.config = match figment::Figment::new()
                         .merge(Yaml::string(&configuration_file))
@@ -131,24 +275,179 @@ impl LogCraftCli {
             }
         };
 
-        cli.run().await
+        // Workspace commands handle missing workspaces themselves
+        if !matches!(cli.commands, LogCraftCommands::Workspace(_)) {
+            let workspace = cli.config.workspace()?;
+            cli.config.state.set_workspace(&workspace)?;
+        }
+
+        // Telemetry only records the command path, never its arguments
+        let mut command = Vec::new();
+        let mut subcommand = matches.subcommand();
+        while let Some((name, matches)) = subcommand {
+            command.push(name);
+            subcommand = matches.subcommand();
+        }
+        let command = command.join(" ");
+        cli.config.state.set_lock_operation(&command);
+
+        // Auditors can inspect drift with shared credentials without risking changes
+        if (cli.read_only || cli.config.core.mode == ProjectMode::ReadOnly)
+            && cli.commands.is_mutating()
+        {
+            bail!("`lgc {}` is not allowed in read-only mode", command)
+        }
+
+        let sink = cli.config.core.telemetry.sink();
+        let started = Instant::now();
+        let result = cli
+            .run()
+            .instrument(tracing::info_span!("command", command = %command))
+            .await;
+        TelemetryEvent::new(&command, started.elapsed(), result.is_ok())
+            .send(sink.as_ref())
+            .await;
+        commands::shutdown_tracing();
+        result
     }
 
     /// LogCraft CLI entrypoint.
     async fn run(mut self) -> Result<()> {
         match self.commands {
             // General commands
-            LogCraftCommands::Init(cmd) => cmd.run(),
+            LogCraftCommands::Init(cmd) => cmd.run().await,
+            LogCraftCommands::Cache(cmd) => cmd.run(),
+            LogCraftCommands::Complete(cmd) => cmd.run(),
             LogCraftCommands::Diff(cmd) => cmd.run(&self.config).await,
             LogCraftCommands::Deploy(cmd) => cmd.run(&self.config).await,
             LogCraftCommands::Destroy(cmd) => cmd.run(&self.config).await,
             LogCraftCommands::Validate(cmd) => cmd.run(&self.config).await,
+            LogCraftCommands::Test(cmd) => cmd.run(&self.config).await,
+            LogCraftCommands::Doctor(cmd) => cmd.run(&self.config).await,
+            LogCraftCommands::Graph(cmd) => cmd.run(&self.config).await,
+            LogCraftCommands::Import(cmd) => cmd.run(&self.config).await,
+            LogCraftCommands::Show(cmd) => cmd.run(&self.config).await,
+            LogCraftCommands::Template(cmd) => cmd.run(&self.config).await,
+            // Configuration commands
+            LogCraftCommands::Config(cmd) => cmd.run(&self.config).await,
+            // Conversion commands
+            LogCraftCommands::Convert(cmd) => cmd.run(&self.config).await,
             // Plugins commands
             LogCraftCommands::Plugins(cmd) => cmd.run(&mut self.config).await,
+            // Policies commands
+            LogCraftCommands::Policies(cmd) => cmd.run(&mut self.config).await,
+            // Maintenance commands
+            LogCraftCommands::Maintenance(cmd) => cmd.run(&self.config).await,
+            // Remote commands
+            LogCraftCommands::Remote(cmd) => cmd.run(&self.config).await,
+            // Report commands
+            LogCraftCommands::Report(cmd) => cmd.run(&self.config).await,
+            // Rules commands
+            LogCraftCommands::Rules(cmd) => cmd.run(&self.config).await,
             // Environments commands
             LogCraftCommands::Environments(cmd) => cmd.run(&mut self.config).await,
             // Services commands
             LogCraftCommands::Services(cmd) => cmd.run(&mut self.config).await,
+            // State commands
+            LogCraftCommands::State(cmd) => cmd.run(&self.config).await,
+            // Workspace commands
+            LogCraftCommands::Workspace(cmd) => cmd.run(&mut self.config).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Commands refused in read-only mode, every other one is allowed.
+    const MUTATING: [&str; 29] = [
+        "convert sigma",
+        "deploy",
+        "destroy",
+        "envs add",
+        "envs link",
+        "envs remove",
+        "envs unlink",
+        "import",
+        "init",
+        "maintenance disable",
+        "maintenance enable",
+        "plugins install",
+        "plugins uninstall",
+        "plugins update",
+        "policies install",
+        "policies uninstall",
+        "policies update",
+        "rules archive",
+        "rules move",
+        "rules unarchive",
+        "services add",
+        "services configure",
+        "services remove",
+        "state force-unlock",
+        "state push",
+        "state restore-backup",
+        "workspace delete",
+        "workspace new",
+        "workspace sync",
+    ];
+
+    /// Paths of the commands without subcommands, with the arguments they require.
+    fn leaves(
+        command: &clap::Command,
+        path: &mut Vec<String>,
+        leaves: &mut Vec<(Vec<String>, Vec<String>)>,
+    ) {
+        if !command.has_subcommands() {
+            leaves.push((path.clone(), required_args(command)));
+            return;
+        }
+        for subcommand in command
+            .get_subcommands()
+            .filter(|cmd| cmd.get_name() != "help")
+        {
+            path.push(subcommand.get_name().to_string());
+            self::leaves(subcommand, path, leaves);
+            path.pop();
+        }
+    }
+
+    fn required_args(command: &clap::Command) -> Vec<String> {
+        let mut args = Vec::new();
+        for arg in command.get_arguments().filter(|arg| arg.is_required_set()) {
+            let value = arg
+                .get_possible_values()
+                .first()
+                .map_or(String::from("1"), |value| value.get_name().to_string());
+            match arg.get_long() {
+                Some(long) if arg.get_action().takes_values() => {
+                    args.extend([format!("--{}", long), value])
+                }
+                Some(long) => args.push(format!("--{}", long)),
+                None => args.push(value),
+            }
+        }
+        args
+    }
+
+    #[test]
+    fn read_only_mode_covers_every_subcommand() {
+        let mut commands = Vec::new();
+        leaves(&LogCraftCli::command(), &mut Vec::new(), &mut commands);
+
+        for (path, args) in commands {
+            let name = path.join(" ");
+            let cli = LogCraftCli::try_parse_from(
+                std::iter::once(String::from("lgc")).chain(path).chain(args),
+            )
+            .unwrap_or_else(|e| panic!("`lgc {}` does not parse: {}", name, e));
+            assert_eq!(
+                cli.commands.is_mutating(),
+                MUTATING.contains(&name.as_str()),
+                "`lgc {}` read-only status",
+                name
+            );
         }
     }
 }
@@ -2,26 +2,69 @@
 // SPDX-License-Identifier: MPL-2.0
 
 // Commands
+mod complete;
 mod deploy;
 mod destroy;
 mod diff;
+mod doctor;
+mod graph;
+mod import;
 mod init;
+mod limits;
+mod lock;
+mod logging;
+mod output;
+mod progress;
+mod scaffold;
+mod show;
+mod target;
+mod template;
+mod test;
 mod validate;
+mod variables;
 // Subcommands
+mod cache;
+mod config;
+mod convert;
 mod environments;
+mod maintenance;
 pub mod plugins;
+mod policies;
+mod remote;
+mod report;
+mod rules;
 pub mod services;
+mod state;
+mod workspace;
 
 // Re-exporting the commands
 pub use {
     // Commands
+    complete::CompleteCommand,
     deploy::DeployCommand,
     destroy::DestroyCommand,
     diff::DiffCommand,
+    doctor::DoctorCommand,
+    graph::GraphCommand,
+    import::ImportCommand,
     init::InitCommand,
+    logging::{shutdown as shutdown_tracing, LoggingArgs},
+    show::ShowCommand,
+    template::TemplateCommand,
+    test::TestCommand,
     validate::ValidateCommand,
     // Subcommands
+    cache::CacheCommands,
+    config::ConfigCommands,
+    convert::ConvertCommands,
     environments::EnvironmentsCommands,
+    maintenance::MaintenanceCommands,
     plugins::PluginsCommands,
+    policies::PoliciesCommands,
+    remote::RemoteCommands,
+    report::ReportCommands,
+    rules::RulesCommands,
     services::ServicesCommands,
+    state::StateCommands,
+    workspace::WorkspaceCommands,
 };
\ No newline at end of file
@@ -0,0 +1,54 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+use console::style;
+use lgc_runtime::Cache;
+
+/// Manage compiled plugins cache
+#[derive(Subcommand)]
+pub enum CacheCommands {
+    /// Show cache location and usage
+    Info(CacheInfo),
+
+    /// Remove all compiled plugins, schemas and remote reads from cache
+    Clean(CleanCache),
+}
+
+impl CacheCommands {
+    pub fn run(self) -> Result<()> {
+        match self {
+            Self::Info(cmd) => cmd.run(),
+            Self::Clean(cmd) => cmd.run(),
+        }
+    }
+}
+
+#[derive(Parser)]
+pub struct CacheInfo;
+
+impl CacheInfo {
+    pub fn run(self) -> Result<()> {
+        let cache = Cache::new(None)?;
+        let info = cache.info()?;
+
+        println!("directory: `{}`", style(cache.directory().display()).bold());
+        println!("entries: {}", info.entries);
+        println!("size: {:.2} MB", info.size as f64 / (1 << 20) as f64);
+
+        Ok(())
+    }
+}
+
+#[derive(Parser)]
+pub struct CleanCache;
+
+impl CleanCache {
+    pub fn run(self) -> Result<()> {
+        let removed = Cache::new(None)?.clean()?;
+        tracing::info!("{} entries removed from cache", removed);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::Result;
+use clap::{Parser, ValueEnum};
+use lgc_common::{
+    configuration::{ProjectConfiguration, LGC_CONFIG_PATH, LGC_RULES_DIR},
+    detections::{DetectionFormat, DEFAULT_DETECTION_EXTENSIONS},
+};
+use std::{fs, path::Path};
+
+/// Values which can be completed by shell completion scripts
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum CompletionKind {
+    Services,
+    Environments,
+    Plugins,
+    Rules,
+}
+
+/// Print completion candidates, one per line
+///
+/// Intended to be called by shell completion scripts, errors are never reported.
+#[derive(Parser, Debug)]
+pub struct CompleteCommand {
+    /// Kind of value to complete
+    pub kind: CompletionKind,
+
+    /// Only print candidates starting with this prefix
+    #[clap(default_value = "")]
+    pub prefix: String,
+}
+
+impl CompleteCommand {
+    pub fn run(self) -> Result<()> {
+        // Configuration is loaded silently, completions must never print errors
+        let config: ProjectConfiguration = fs::read_to_string(LGC_CONFIG_PATH)
+            .ok()
+            .and_then(|content| serde_yaml_ng::from_str(&content).ok())
+            .unwrap_or_default();
+
+        let candidates: Vec<String> = match self.kind {
+            CompletionKind::Services => config.services.iter().map(|svc| svc.id.clone()).collect(),
+            CompletionKind::Environments => config
+                .environments
+                .iter()
+                .map(|env| env.id.clone())
+                .collect(),
+            CompletionKind::Plugins => config.plugins.keys().cloned().collect(),
+            // Rule files are matched as the detection loader does, with its default extensions
+            // when the configured ones are invalid
+            CompletionKind::Rules => rule_ids(
+                Path::new(LGC_RULES_DIR),
+                &config
+                    .core
+                    .detection_extensions()
+                    .unwrap_or_else(|_| DEFAULT_DETECTION_EXTENSIONS.map(String::from).to_vec()),
+            ),
+        };
+
+        candidates
+            .iter()
+            .filter(|candidate| candidate.starts_with(&self.prefix))
+            .for_each(|candidate| println!("{candidate}"));
+
+        Ok(())
+    }
+}
+
+/// Detection ids are rule file paths relative to the rules directory, without extension.
+fn rule_ids(dir: &Path, extensions: &[String]) -> Vec<String> {
+    let mut ids: Vec<String> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| DetectionFormat::from_path(path, extensions).is_some())
+        .filter_map(|path| path.file_stem()?.to_str().map(String::from))
+        .collect();
+
+    ids.sort();
+    ids.dedup();
+    ids
+}
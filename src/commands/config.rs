@@ -0,0 +1,125 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{anyhow, bail, Result};
+use clap::{Parser, Subcommand};
+use console::style;
+use lgc_common::{
+    configuration::{ProjectConfiguration, LGC_CONFIG_PATH},
+    detections::lint_detections,
+    diagnostics::ConfigValidation,
+    plugins::manager::PluginManager,
+    secrets::mask_sensitive,
+};
+
+/// Manage project configuration
+#[derive(Subcommand)]
+pub enum ConfigCommands {
+    /// Detect unused or undefined references in configuration
+    Lint(LintConfig),
+
+    /// Print the configuration as resolved for an environment
+    Resolve(ResolveConfig),
+
+    /// Check the configuration files and plugin settings, reporting every problem
+    Validate(ValidateConfig),
+}
+
+impl ConfigCommands {
+    pub async fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        match self {
+            Self::Lint(cmd) => cmd.run(config),
+            Self::Resolve(cmd) => cmd.run(config),
+            Self::Validate(cmd) => cmd.run().await,
+        }
+    }
+}
+
+#[derive(Parser)]
+pub struct LintConfig;
+
+impl LintConfig {
+    pub fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        let mut findings = config.lint();
+        findings.extend(lint_detections(config)?);
+
+        if findings.is_empty() {
+            tracing::info!("all good, no problems identified");
+            return Ok(());
+        }
+
+        for finding in &findings {
+            println!("[!] {}", style(&finding.message).yellow());
+            println!("    {} {}", style("fix:").dim(), finding.suggestion);
+        }
+
+        bail!("{} problem(s) identified", findings.len())
+    }
+}
+
+#[derive(Parser)]
+pub struct ResolveConfig {
+    /// Environment whose overlay and variables apply, as with `lgc deploy <env>`
+    #[clap(long)]
+    pub env: Option<String>,
+
+    /// Print values of sensitive settings (tokens, passwords, keys...) instead of masking them
+    #[clap(long)]
+    pub show_secrets: bool,
+}
+
+impl ResolveConfig {
+    /// Print the configuration loaded by commands, values from `LGC_` environment variables included.
+    pub fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        if let Some(env) = &self.env {
+            if !config.environments.iter().any(|known| &known.id == env) {
+                bail!("environment `{}` not found", env)
+            }
+        }
+
+        let mut resolved = serde_yaml_ng::to_value(config)?;
+        if !self.show_secrets {
+            mask_sensitive(&mut resolved)?;
+        }
+
+        print!("{}", serde_yaml_ng::to_string(&resolved)?);
+        Ok(())
+    }
+}
+
+#[derive(Parser)]
+pub struct ValidateConfig {
+    /// Environment whose overlay and variables apply, as with `lgc deploy <env>`
+    #[clap(long)]
+    pub env: Option<String>,
+}
+
+impl ValidateConfig {
+    /// Run before the configuration is loaded, as loading stops at the first problem.
+    pub async fn run(self) -> Result<()> {
+        let mut validation = ConfigValidation::new(self.env.as_deref())
+            .map_err(|e| anyhow!("unable to read `{}`: {}", LGC_CONFIG_PATH, e))?;
+
+        if let Some(config) = validation.check_structure(self.env.as_deref()) {
+            if let Some(env) = &self.env {
+                if !config.environments.iter().any(|known| &known.id == env) {
+                    bail!("environment `{}` not found", env)
+                }
+            }
+
+            let plugin_manager = PluginManager::new()?.with_capabilities(&config.plugins);
+            validation.check_settings(&config, &plugin_manager).await?;
+        }
+
+        if validation.diagnostics.is_empty() {
+            tracing::info!("all good, no problems identified");
+            return Ok(());
+        }
+
+        for diagnostic in &validation.diagnostics {
+            println!("[!] {}", style(diagnostic).yellow());
+        }
+
+        bail!("{} problem(s) identified", validation.diagnostics.len())
+    }
+}
@@ -0,0 +1,141 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{anyhow, bail, Result};
+use clap::{Parser, Subcommand};
+use lgc_common::{
+    configuration::{ProjectConfiguration, LGC_RULES_DIR},
+    sigma::SigmaRule,
+    utils::workspace_path,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use super::import::{file_name, write_rule};
+
+/// Convert rules from other formats into detections
+#[derive(Subcommand)]
+pub enum ConvertCommands {
+    /// Convert Sigma rules into detections of a plugin
+    Sigma(ConvertSigma),
+}
+
+impl ConvertCommands {
+    pub async fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        match self {
+            Self::Sigma(cmd) => cmd.run(config),
+        }
+    }
+}
+
+#[derive(Parser)]
+pub struct ConvertSigma {
+    /// Sigma rule files, or directories holding them
+    #[clap(required = true)]
+    pub paths: Vec<PathBuf>,
+
+    /// Plugin the rules are converted for, following its `sigma` mapping
+    #[clap(short, long)]
+    pub plugin: String,
+
+    /// Replace the plugin content of existing detections
+    #[clap(long)]
+    pub force: bool,
+
+    /// Continue with the other rules when one cannot be converted
+    #[clap(long)]
+    pub skip_invalid: bool,
+}
+
+impl ConvertSigma {
+    pub fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        let mapping = config.sigma.get(&self.plugin).ok_or_else(|| {
+            anyhow!(
+                "no sigma mapping for plugin `{}`, add it to `sigma` in the configuration",
+                self.plugin
+            )
+        })?;
+        if !config.plugins.contains_key(&self.plugin) {
+            bail!("plugin `{}` not found", self.plugin)
+        }
+
+        let mut files = Vec::new();
+        for path in &self.paths {
+            sigma_files(path, &mut files)?;
+        }
+        if files.is_empty() {
+            bail!("no sigma rules found")
+        }
+        fs::create_dir_all(LGC_RULES_DIR)?;
+
+        let (mut converted, mut skipped) = (0, 0);
+        for path in files {
+            let (rule, content) = match SigmaRule::load(&path).and_then(|rule| {
+                let content = rule
+                    .convert(mapping)
+                    .and_then(|content| file_name(&rule.title).map(|_| content))
+                    .map_err(|e| anyhow!("unable to convert `{}`: {}", workspace_path(&path), e))?;
+                Ok((rule, content))
+            }) {
+                Ok(converted) => converted,
+                Err(e) if self.skip_invalid => {
+                    tracing::warn!("{}", e);
+                    skipped += 1;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            if write_rule(&self.plugin, &rule.title, &content, self.force)? {
+                converted += 1;
+            } else {
+                tracing::warn!(
+                    "skipping `{}`: detection `{}` already defines `{}`, use `--force` to replace it",
+                    workspace_path(&path),
+                    rule.title,
+                    self.plugin
+                );
+                skipped += 1;
+            }
+        }
+
+        tracing::info!(
+            "{} rule(s) converted for `{}`, {} skipped",
+            converted,
+            self.plugin,
+            skipped
+        );
+        Ok(())
+    }
+}
+
+/// Collect Sigma rule files, directories being walked recursively in name order.
+fn sigma_files(path: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    if path.is_file() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+    if !path.is_dir() {
+        bail!("`{}` does not exist", workspace_path(path))
+    }
+
+    let mut entries: Vec<PathBuf> = fs::read_dir(path)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect();
+    entries.sort();
+
+    for entry in entries {
+        if entry.is_dir() {
+            sigma_files(&entry, files)?;
+        } else if entry
+            .extension()
+            .is_some_and(|ext| ext == "yml" || ext == "yaml")
+        {
+            files.push(entry);
+        }
+    }
+
+    Ok(())
+}
@@ -1,7 +1,11 @@
 // Copyright (c) 2023 LogCraft, SAS.
 // SPDX-License-Identifier: MPL-2.0
 
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use anyhow::{anyhow, bail, Result};
 use clap::Parser;
@@ -9,11 +13,38 @@ use console::style;
 use dialoguer::{theme::ColorfulTheme, Confirm, Select};
 use lgc_common::{
     configuration::{Environment, ProjectConfiguration, Service},
-    detections::{compare_detections, map_plugin_detections, DetectionState, ServiceDetections},
-    plugins::manager::{PluginActions, PluginManager},
+    detections::{
+        compare_detections, map_plugin_detections, resolve_common_rules, sorted_rules,
+        DetectionState, ServiceDetections,
+    },
+    hooks::HookEvent,
+    limits::Limits,
+    notifications::notify_run,
+    plan::Plan,
+    plugins::{
+        manager::{read_rules, InstanceData, PluginActions, PluginManager},
+        Capability,
+    },
+    report::{Action, ChangeReport, CircuitBreaker, Outcome, RunReport},
+    telemetry,
+    transforms::apply_transforms,
+};
+use lgc_runtime::state::State;
+use serde_json::{json, Value};
+use tokio::{
+    sync::{watch, Semaphore},
+    task::JoinSet,
+};
+use wasmtime::Store;
+
+use super::{
+    limits::LimitsArgs,
+    lock::LockArgs,
+    output::OutputArgs,
+    progress::{Progress, ProgressArgs},
+    target::{TagArgs, TargetArgs},
+    variables::VariablesArgs,
 };
-use serde_json::Value;
-use tokio::task::JoinSet;
 
 /// Prepare working directory for other lgcli commands
 #[derive(Parser, Debug, Default)]
@@ -30,25 +61,99 @@ pub struct DeployCommand {
     pub service_id: Option<String>,
 
     /// Show differences for this detection path
-    #[clap(short, long)]
+    #[clap(short, long, conflicts_with = "targets")]
     pub detection_id: Option<String>,
 
+    #[clap(flatten)]
+    pub target: TargetArgs,
+
+    #[clap(flatten)]
+    pub tags: TagArgs,
+
+    #[clap(flatten)]
+    pub variables: VariablesArgs,
+
+    #[clap(flatten)]
+    pub limits: LimitsArgs,
+
+    #[clap(flatten)]
+    pub output: OutputArgs,
+
+    #[clap(flatten)]
+    pub progress: ProgressArgs,
+
+    /// Continue without invalid detection files, rules they define are not deleted
+    #[clap(long)]
+    pub skip_invalid: bool,
+
+    /// Fetch remote rules with list calls instead of one read per rule
+    #[clap(long)]
+    pub bulk: bool,
+
+    /// Deploy other rules and services when reading some of them fails, unread rules are left unchanged
+    #[clap(long)]
+    pub keep_going: bool,
+
     /// Skip interactive approval of changes deployment
     #[clap(long)]
     pub auto_approve: bool,
+
+    #[clap(flatten)]
+    pub lock: LockArgs,
+
+    /// Write a JSON report of performed operations to this file
+    #[clap(long)]
+    pub report: Option<PathBuf>,
+
+    /// Apply exactly the changes saved by `lgc diff --out`, without reading remote rules
+    #[clap(long, conflicts_with_all = ["env_id", "service_id", "detection_id", "targets", "tags", "bulk"])]
+    pub plan: Option<PathBuf>,
 }
 
 impl DeployCommand {
     pub async fn run(self, config: &ProjectConfiguration) -> Result<()> {
-        // Load all detections
-        let detections = map_plugin_detections(self.detection_id.clone())?;
+        // Services depending on each other cannot be ordered
+        config.deployment_order()?;
+
+        if let Some(path) = &self.plan {
+            return self.run_plan(config, path).await;
+        }
+
+        let limits = self.limits.resolve(config);
+
+        // Load all detections, or only targeted ones
+        let variables = self.variables.resolve(config)?;
+        let extensions = config.core.detection_extensions()?;
+        let (mut detections, skipped, targets) = if self.target.is_empty() {
+            let (detections, skipped) = map_plugin_detections(
+                self.detection_id.clone(),
+                &variables,
+                &extensions,
+                self.skip_invalid,
+            )?;
+            (detections, skipped, None)
+        } else {
+            let (detections, targets) = self.target.load(config, &variables, &extensions)?;
+            (detections, Vec::new(), Some(targets))
+        };
+        self.tags.retain(&mut detections);
+        if !skipped.is_empty() {
+            tracing::warn!(
+                "rules missing locally will not be deleted while detection files are skipped"
+            );
+        }
+
+        // Prepare state backend
+        let backend = self.lock.backend(config);
 
         // Prompt theme
         let prompt_theme = ColorfulTheme::default();
 
         // Retrieve services depending on targeted environment or service
         let mut services: HashMap<String, Vec<&Service>> = HashMap::new();
-        if let Some(svc_id) = self.service_id {
+        if !self.target.is_empty() {
+            services = self.target.services(config)?;
+        } else if let Some(svc_id) = &self.service_id {
             let svc = config
                 .services
                 .get(&Service {
@@ -59,8 +164,8 @@ impl DeployCommand {
 
             services.insert(svc.plugin.clone(), vec![svc]);
         } else {
-            let env_id = match self.env_id {
-                Some(id) => id,
+            let env_id = match &self.env_id {
+                Some(id) => id.clone(),
                 None => {
                     let environment = config.environment_ids()?;
                     let selection = Select::with_theme(&prompt_theme)
@@ -89,19 +194,51 @@ impl DeployCommand {
                 })
         };
 
+        let auto_approve = self.auto_approve(config, services.values().flatten().copied())?;
+
         // Load plugins
-        let plugin_manager = PluginManager::new()?;
+        let plugin_manager = PluginManager::new()?.with_capabilities(&config.plugins);
+        resolve_common_rules(&plugin_manager, &mut detections).await?;
+        apply_transforms(&config.transforms, &mut detections)?;
+        let parallelism = limits.parallelism()?;
+        let permits = Arc::new(Semaphore::new(parallelism));
         let mut set = JoinSet::new();
 
         for plugin_id in detections.keys() {
             let plugin_id = plugin_id.to_string();
             let plugin_manager = plugin_manager.clone();
-            set.spawn(async move { plugin_manager.load_plugin(plugin_id).await });
+            let permits = permits.clone();
+            set.spawn(async move {
+                let _permit = permits.acquire_owned().await?;
+                plugin_manager
+                    .load_plugin_pool(plugin_id, parallelism)
+                    .await
+            });
         }
 
-        // Call get schema and retrieve all detections
+        let mut report = RunReport::default();
+        let mut changes = ChangeReport::default();
+        let overrides = self.limits.overrides();
+        let progress = self.progress.start(self.output.is_json());
+
+        // Plugins load concurrently but are processed by name for a stable output
+        let mut instances = BTreeMap::new();
         while let Some(plugin) = set.join_next().await {
-            let (instance, mut store) = plugin??;
+            // Additional instances only serve concurrent reads
+            let mut readers = plugin??;
+            let (instance, store) = readers.remove(0);
+            instances.insert(instance.metadata.name.clone(), (instance, store, readers));
+        }
+
+        let state_lock = backend.lock().await?;
+        let mut state = backend.load().await?;
+        // Unchanged remote rules missing from the state are recorded even without changes
+        let mut refreshed = false;
+        // Changes are planned for every plugin before any of them is deployed
+        let mut jobs: Vec<PluginJob> = Vec::new();
+
+        // Call get schema and retrieve all detections
+        for (instance, mut store, mut readers) in instances.into_values() {
             let meta = &instance.metadata;
 
             // Safe unwrap as we load plugins with detection HashMap.
@@ -111,27 +248,155 @@ impl DeployCommand {
             if let Some(plugin_services) = services.get(plugin) {
                 let mut returned_rules: ServiceDetections = HashMap::new();
                 let mut missing_rules: HashMap<String, HashSet<&DetectionState>> = HashMap::new();
+                // Rules which could not be read, by service, with `--keep-going`
+                let mut unread: HashMap<String, HashSet<String>> = HashMap::new();
 
                 for svc in plugin_services {
-                    let service_config = serde_json::to_string(&svc.settings)?;
-                    for rule in rules {
-                        let requested_rule = serde_json::to_string(&rule.content)?;
-                        if let Some(resp) = instance
-                            .read(&mut store, &service_config, &rule.name, &requested_rule)
+                    let naming = config.naming(svc)?;
+                    let sorted: Vec<&DetectionState> = sorted_rules(rules)
+                        .into_iter()
+                        .filter(|rule| {
+                            targets
+                                .as_ref()
+                                .map_or(true, |targets| targets.includes(&svc.id, &rule.name))
+                        })
+                        .collect();
+                    let bulk = self.bulk && instance.supports(Capability::ListRules);
+                    if self.bulk && !bulk {
+                        tracing::warn!(
+                            "plugin `{}` cannot list rules, reading rules of `{}` one by one",
+                            plugin,
+                            svc.id
+                        );
+                    }
+                    // Rules are read one by one unless listed, each read may fail on its own
+                    let read = async {
+                        let service_config = svc.config().await?;
+                        let contents: Vec<Result<Option<Value>>> = if bulk {
+                            let wanted =
+                                sorted.iter().map(|rule| naming.apply(&rule.name)).collect();
+                            let listed = instance
+                                .list_all_rules(
+                                    &mut store,
+                                    &service_config,
+                                    &wanted,
+                                    limits.list_page_size()?,
+                                )
+                                .await?;
+                            sorted
+                                .iter()
+                                .map(|rule| {
+                                    listed
+                                        .contents
+                                        .get(&naming.apply(&rule.name))
+                                        .map(|listed| rule.from_listing(listed))
+                                        .transpose()
+                                })
+                                .collect()
+                        } else {
+                            let limits = svc.limits(&config.core.limits, &overrides);
+                            read_rules(
+                                &instance,
+                                &mut store,
+                                &mut readers,
+                                &service_config,
+                                &naming,
+                                &sorted,
+                                &limits,
+                                self.limits.timeout(svc, &limits)?,
+                                &permits,
+                            )
                             .await?
-                        {
-                            let content: Value = serde_json::from_str(&resp)?;
+                            .into_iter()
+                            .map(|resp| {
+                                Ok(resp?.map(|resp| serde_json::from_str(&resp)).transpose()?)
+                            })
+                            .collect()
+                        };
+                        Ok::<_, anyhow::Error>((service_config, contents))
+                    };
+                    let reading = progress.reading(&svc.id, sorted.len());
+                    let read = read.await;
+                    reading.finish_and_clear();
+                    let (service_config, contents) = match read {
+                        Ok(read) => read,
+                        // Rules of an unreadable service are neither changed nor deleted
+                        Err(e) if self.keep_going => {
+                            tracing::warn!(
+                                "unable to read rules of `{}`, skipping it: {}",
+                                svc.id,
+                                e
+                            );
+                            for rule in &sorted {
+                                report.record(
+                                    &svc.id,
+                                    &rule.name,
+                                    Action::Read,
+                                    Outcome::Failed(e.to_string()),
+                                );
+                            }
+                            continue;
+                        }
+                        Err(e) => return Err(e),
+                    };
+
+                    for (rule, content) in sorted.into_iter().zip(contents) {
+                        let requested_rule = serde_json::to_string(&rule.content)?;
+                        let read = match content {
+                            Ok(Some(content))
+                                if svc.remote_snapshots
+                                    && instance.supports(Capability::ReadRemote) =>
+                            {
+                                instance
+                                    .read_remote(
+                                        &mut store,
+                                        &service_config,
+                                        &naming.apply(&rule.name),
+                                        &requested_rule,
+                                    )
+                                    .await
+                                    .and_then(|remote| {
+                                        Ok(remote
+                                            .map(|remote| serde_json::from_str(&remote))
+                                            .transpose()?)
+                                    })
+                                    .map(|remote| Some((content, remote)))
+                            }
+                            content => {
+                                content.map(|content| content.map(|content| (content, None)))
+                            }
+                        };
+                        let read = match read {
+                            Ok(read) => read,
+                            Err(e) => {
+                                self.skip_unread(e, svc, &rule.name, &mut report)?;
+                                unread
+                                    .entry(svc.id.clone())
+                                    .or_default()
+                                    .insert(rule.name.clone());
+                                continue;
+                            }
+                        };
+                        if let Some((content, remote)) = read {
                             returned_rules
                                 .entry(svc.id.clone())
                                 .and_modify(|rules| {
                                     rules.insert(DetectionState {
                                         name: rule.name.clone(),
                                         content: content.clone(),
+                                        common: None,
+                                        metadata: None,
+                                        remote: remote.clone(),
+                                        manage_fields: Vec::new(),
                                     });
                                 })
                                 .or_insert(HashSet::from([DetectionState {
                                     name: rule.name.clone(),
                                     content,
+                                    common: None,
+                                    metadata: None,
+                                    remote,
+                                    manage_fields: Vec::new(),
                                 }]));
                         } else {
                             has_diff = true;
@@ -141,7 +406,7 @@ impl DeployCommand {
                                     rules.insert(rule);
                                 })
                                 .or_insert(HashSet::from([rule]));
-                            if !self.auto_approve {
+                            if !auto_approve {
                                 println!(
                                     "[+] rule: `{}` will be created on `{}`",
                                     style(&rule.name).green(),
@@ -152,131 +417,119 @@ impl DeployCommand {
                     }
                 }
 
-                let mut state = config.state.load().await?;
-                let to_remove = state.missing_rules(
+                // Plugin upgrades may leave state entries incompatible with the new schema
+                let service_ids: Vec<&str> =
+                    plugin_services.iter().map(|svc| svc.id.as_str()).collect();
+                let schema = instance.schema(&mut store).await?;
+                for violation in state.schema_violations(&schema, &service_ids)? {
+                    tracing::warn!(
+                        "state entry `{}` on `{}` does not match the current plugin schema, run `lgc state validate` for details",
+                        violation.rule,
+                        violation.service
+                    );
+                }
+
+                // Rules of skipped, untargeted or untagged files would look deleted, deletions wait for a full run
+                let to_remove = if skipped.is_empty() && targets.is_none() && self.tags.is_empty() {
+                    // Rules which could not be read are left as recorded in the state
+                    let mut known_rules = returned_rules.clone();
+                    for (service_id, names) in &unread {
+                        if let (Some(known), Some(recorded)) = (
+                            known_rules.get_mut(service_id),
+                            state.services.get(service_id),
+                        ) {
+                            known.extend(
+                                recorded
+                                    .iter()
+                                    .filter(|rule| names.contains(&rule.name))
+                                    .cloned(),
+                            );
+                        }
+                    }
+                    state.missing_rules(
+                        &known_rules,
+                        &config.core,
+                        auto_approve,
+                        self.detection_id.clone(),
+                    )
+                } else {
+                    ServiceDetections::new()
+                };
+                let changed = compare_detections(
+                    &detections,
                     &returned_rules,
-                    self.auto_approve,
-                    self.detection_id.clone(),
+                    &services,
+                    &config.plugins,
+                    !auto_approve,
                 );
-                let changed =
-                    compare_detections(&detections, &returned_rules, &services, !self.auto_approve);
 
-                if !changed.is_empty() || has_diff || !to_remove.is_empty() {
-                    if self.auto_approve
-                        || Confirm::with_theme(&prompt_theme)
-                            .with_prompt("Do you want to deploy these changes?")
-                            .interact()?
-                    {
-                        for svc in plugin_services {
-                            let service_config = serde_json::to_string(&svc.settings)?;
-                            let state_service = state.services.entry(svc.id.clone()).or_default();
-
-                            // Create
-                            if let Some(missing_rules) = missing_rules.get(&svc.id) {
-                                for &rule in missing_rules {
-                                    let rule_content = serde_json::to_string(&rule.content)?;
-                                    match instance
-                                        .create(
-                                            &mut store,
-                                            &service_config,
-                                            &rule.name,
-                                            &rule_content,
-                                        )
-                                        .await
-                                    {
-                                        Ok(_) => {
-                                            state_service.insert(rule.clone());
-                                            println!(
-                                                "[+] rule: `{}` created on `{}`",
-                                                style(&rule.name).green(),
-                                                svc.id
-                                            )
-                                        }
-                                        Err(e) => {
-                                            state.save(&config.state).await?;
-                                            bail!(
-                                                "on update for `{}` in `{}`: {}",
-                                                style(&rule.name).red(),
-                                                svc.id,
-                                                e
-                                            );
-                                        }
-                                    }
-                                }
-                            }
+                // Check services quotas before deploying anything
+                for svc in plugin_services {
+                    svc.check_quota(state.projected_rules(
+                        &svc.id,
+                        &returned_rules,
+                        missing_rules.get(&svc.id).map_or(0, |rules| rules.len()),
+                        to_remove.get(&svc.id).map_or(0, |rules| rules.len()),
+                    ))?;
+                }
 
-                            // Update
-                            if let Some(changed_rules) = changed.get(&svc.id) {
-                                for rule in rules.intersection(changed_rules) {
-                                    let rule_content = serde_json::to_string(&rule.content)?;
-                                    match instance
-                                        .update(
-                                            &mut store,
-                                            &service_config,
-                                            &rule.name,
-                                            &rule_content,
-                                        )
-                                        .await
-                                    {
-                                        Ok(_) => {
-                                            state_service.replace(rule.clone());
-                                            println!(
-                                                "[~] rule: `{}` updated on `{}`",
-                                                style(&rule.name).yellow(),
-                                                svc.id
-                                            )
-                                        }
-                                        Err(e) => {
-                                            state.save(&config.state).await?;
-                                            bail!(
-                                                "on update for `{}` in `{}`: {}",
-                                                style(&rule.name).red(),
-                                                svc.id,
-                                                e
-                                            );
-                                        }
-                                    }
-                                }
-                            }
+                if !changed.is_empty() || has_diff || !to_remove.is_empty() {
+                    // Refuse changes to services under maintenance or with read-only credentials
+                    for svc in plugin_services {
+                        if changed.contains_key(&svc.id)
+                            || missing_rules.contains_key(&svc.id)
+                            || to_remove.contains_key(&svc.id)
+                        {
+                            state.check_maintenance(&svc.id)?;
+                            instance
+                                .check_writable(&mut store, &svc.id, &svc.config().await?)
+                                .await?;
+                        }
+                    }
 
-                            // Delete
-                            if let Some(rules) = to_remove.get(&svc.id) {
-                                for rule in rules {
-                                    let rule_content = serde_json::to_string(&rule.content)?;
-                                    match instance
-                                        .delete(
-                                            &mut store,
-                                            &service_config,
-                                            &rule.name,
-                                            &rule_content,
-                                        )
-                                        .await
-                                    {
-                                        Ok(_) => {
-                                            state_service.remove(rule);
-                                            println!(
-                                                "[-] rule: `{}` deleted from `{}`",
-                                                style(&rule.name).red(),
-                                                svc.id
-                                            );
-                                        }
-                                        Err(e) => {
-                                            state.save(&config.state).await?;
-                                            bail!(
-                                                "on deletion for `{}` in `{}`: {}",
-                                                style(&rule.name).red(),
-                                                svc.id,
-                                                e
-                                            );
-                                        }
-                                    }
-                                }
-                            }
+                    let mut job = PluginJob {
+                        instance,
+                        store,
+                        services: Vec::new(),
+                    };
+                    for svc in plugin_services {
+                        // Collect operations to run on this service
+                        let mut operations: Vec<(Action, DetectionState)> = Vec::new();
+                        if let Some(missing_rules) = missing_rules.get(&svc.id) {
+                            operations.extend(
+                                sorted_rules(missing_rules.iter().copied())
+                                    .into_iter()
+                                    .map(|rule| (Action::Create, rule.clone())),
+                            );
+                        }
+                        if let Some(changed_rules) = changed.get(&svc.id) {
+                            operations.extend(
+                                sorted_rules(rules.intersection(changed_rules))
+                                    .into_iter()
+                                    .map(|rule| (Action::Update, rule.clone())),
+                            );
+                        }
+                        if let Some(removed_rules) = to_remove.get(&svc.id) {
+                            operations.extend(
+                                sorted_rules(removed_rules)
+                                    .into_iter()
+                                    .map(|rule| (Action::Delete, rule.clone())),
+                            );
+                        }
+                        for (action, rule) in &operations {
+                            let remote = returned_rules
+                                .get(&svc.id)
+                                .and_then(|rules| rules.get(rule));
+                            changes.plan(
+                                &svc.id,
+                                *action,
+                                rule,
+                                remote.map(|remote| &remote.content),
+                            )?;
                         }
-                        state.save(&config.state).await?;
-                    } else {
-                        bail!("action aborted")
+                        job.services.push(((*svc).clone(), operations));
                     }
+                    jobs.push(job);
                 } else {
                     // Update state to include any missing rules detected
                     if returned_rules
@@ -284,14 +537,690 @@ impl DeployCommand {
                         .any(|(k, v)| state.services.get(k) != Some(v))
                     {
                         tracing::info!("including unchanged remote detection rules that are not currently referenced in state");
-                        state.services.extend(returned_rules);
-                        state.save(&config.state).await?;
+                        // Entries of rules which were not read are kept
+                        for (service_id, rules) in returned_rules {
+                            let state_service = state.services.entry(service_id).or_default();
+                            for rule in rules {
+                                state_service.replace(rule);
+                            }
+                        }
+                        refreshed = true;
                     }
 
                     tracing::info!("no differences found");
                 }
             }
         }
+
+        if jobs.is_empty() {
+            if refreshed {
+                state.save(&state_lock).await?;
+            }
+            return self.finish(report, changes);
+        }
+
+        if !auto_approve
+            && !Confirm::with_theme(&prompt_theme)
+                .with_prompt("Do you want to deploy these changes?")
+                .interact()?
+        {
+            if refreshed {
+                state.save(&state_lock).await?;
+            }
+            bail!("action aborted")
+        }
+        if let Err(e) = config
+            .hooks
+            .run(HookEvent::PreApply, self.hook_context(&changes, None, None))
+            .await
+        {
+            if refreshed {
+                state.save(&state_lock).await?;
+            }
+            return Err(e);
+        }
+
+        let deployment = Deployment {
+            config: Arc::new(config.clone()),
+            plugin_manager,
+            overrides,
+            timeout_override: self.limits.timeout.is_some(),
+            progress,
+            permits: Arc::new(Semaphore::new(parallelism)),
+        };
+        // The state records operations which ran, even when a plugin failed
+        let deployed = deployment
+            .deploy(jobs, &mut state.services, &mut report)
+            .await;
+        state.save(&state_lock).await?;
+        self.after_apply(config, &changes, &report, deployed.as_ref().err())
+            .await;
+        deployed?;
+
+        self.finish(report, changes)
+    }
+
+    /// Deploy operations of a saved plan, as long as the state did not change since.
+    async fn run_plan(&self, config: &ProjectConfiguration, path: &Path) -> Result<()> {
+        let limits = self.limits.resolve(config);
+        let plan = Plan::load(path)?;
+
+        // Prepare state backend
+        let backend = self.lock.backend(config);
+        let state_lock = backend.lock().await?;
+        let mut state = backend.load().await?;
+        plan.check_state(&state)?;
+
+        if plan.is_empty() {
+            tracing::info!("no differences found");
+            return self.finish(RunReport::default(), ChangeReport::default());
+        }
+
+        // Services of the plan, by plugin
+        let mut services: BTreeMap<String, Vec<&Service>> = BTreeMap::new();
+        for service_id in plan.services.keys() {
+            let svc = config
+                .services
+                .get(&Service {
+                    id: service_id.clone(),
+                    ..Default::default()
+                })
+                .ok_or_else(|| anyhow!("service `{}` not found", service_id))?;
+            services.entry(svc.plugin.clone()).or_default().push(svc);
+        }
+        let auto_approve = self.auto_approve(config, services.values().flatten().copied())?;
+
+        // Refuse changes to services under maintenance or with read-only credentials
+        let plugin_manager = PluginManager::new()?.with_capabilities(&config.plugins);
+        let mut jobs = Vec::new();
+        let mut changes = ChangeReport::default();
+        for (plugin, plugin_services) in &services {
+            let (instance, mut store) = plugin_manager.load_plugin(plugin).await?;
+            let mut job_services = Vec::new();
+            for svc in plugin_services {
+                state.check_maintenance(&svc.id)?;
+                instance
+                    .check_writable(&mut store, &svc.id, &svc.config().await?)
+                    .await?;
+
+                let operations: Vec<_> = plan.services[&svc.id]
+                    .iter()
+                    .map(|operation| (operation.action, operation.rule.clone()))
+                    .collect();
+                // Remote rules are not read again, updates diff is in the saved plan
+                for (action, rule) in &operations {
+                    changes.add(&svc.id, &rule.name, *action, None);
+                }
+                job_services.push(((*svc).clone(), operations));
+            }
+            jobs.push(PluginJob {
+                instance,
+                store,
+                services: job_services,
+            });
+        }
+
+        if !auto_approve {
+            for (service_id, operations) in &plan.services {
+                for operation in operations {
+                    let name = &operation.rule.name;
+                    match operation.action {
+                        Action::Create => println!(
+                            "[+] rule: `{}` will be created on `{}`",
+                            style(name).green(),
+                            service_id
+                        ),
+                        Action::Update => println!(
+                            "[~] rule: `{}` will be updated on `{}`",
+                            style(name).yellow(),
+                            service_id
+                        ),
+                        _ => println!(
+                            "[-] rule: `{}` will be deleted from `{}`",
+                            style(name).red(),
+                            service_id
+                        ),
+                    }
+                }
+            }
+
+            if !Confirm::with_theme(&ColorfulTheme::default())
+                .with_prompt("Do you want to deploy these changes?")
+                .interact()?
+            {
+                bail!("action aborted")
+            }
+        }
+        config
+            .hooks
+            .run(HookEvent::PreApply, self.hook_context(&changes, None, None))
+            .await?;
+
+        let mut report = RunReport::default();
+        let deployment = Deployment {
+            config: Arc::new(config.clone()),
+            plugin_manager,
+            overrides: self.limits.overrides(),
+            timeout_override: self.limits.timeout.is_some(),
+            progress: self.progress.start(self.output.is_json()),
+            permits: Arc::new(Semaphore::new(limits.parallelism()?)),
+        };
+        // The state records operations which ran, even when a plugin failed
+        let deployed = deployment
+            .deploy(jobs, &mut state.services, &mut report)
+            .await;
+        state.save(&state_lock).await?;
+        self.after_apply(config, &changes, &report, deployed.as_ref().err())
+            .await;
+        deployed?;
+
+        self.finish(report, changes)
+    }
+
+    /// Central guard against unattended changes.
+    fn auto_approve<'a>(
+        &self,
+        config: &ProjectConfiguration,
+        services: impl Iterator<Item = &'a Service>,
+    ) -> Result<bool> {
+        let mut auto_approve = self.auto_approve;
+        if auto_approve {
+            let service_ids: Vec<&str> = services.map(|svc| svc.id.as_str()).collect();
+            if let Some(reason) = config.approval_required(&service_ids) {
+                tracing::warn!("`--auto-approve` ignored: {} requires approval", reason);
+                auto_approve = false;
+            }
+        }
+
+        // Changes are only printed once deployed
+        if self.output.is_json() && !auto_approve {
+            bail!("`--output json` requires changes approved with `--auto-approve`, review them with `lgc diff` first")
+        }
+
+        Ok(auto_approve)
+    }
+
+    /// With `--keep-going`, record a rule which could not be read so that the run goes on
+    /// without it, fail otherwise.
+    fn skip_unread(
+        &self,
+        e: anyhow::Error,
+        svc: &Service,
+        rule: &str,
+        report: &mut RunReport,
+    ) -> Result<()> {
+        if !self.keep_going {
+            return Err(e);
+        }
+
+        tracing::warn!(
+            "unable to read `{}` on `{}`, skipping it: {}",
+            rule,
+            svc.id,
+            e
+        );
+        report.record(&svc.id, rule, Action::Read, Outcome::Failed(e.to_string()));
+        Ok(())
+    }
+
+    /// Context written to hooks: targeted environment, planned changes and, once deployed,
+    /// the operations which ran.
+    fn hook_context(
+        &self,
+        changes: &ChangeReport,
+        report: Option<&RunReport>,
+        error: Option<&anyhow::Error>,
+    ) -> Value {
+        json!({
+            "environment": self.env_id,
+            "changes": changes.changes,
+            "operations": report.map(|report| &report.operations),
+            "error": error.map(|e| e.to_string()),
+        })
+    }
+
+    /// Run the `post_apply` hook, or `on_failure` if some changes failed, and send
+    /// notifications. Changes are deployed by then, failures are only reported.
+    async fn after_apply(
+        &self,
+        config: &ProjectConfiguration,
+        changes: &ChangeReport,
+        report: &RunReport,
+        error: Option<&anyhow::Error>,
+    ) {
+        let event = if error.is_some() || report.has_failures() {
+            HookEvent::OnFailure
+        } else {
+            HookEvent::PostApply
+        };
+        let context = self.hook_context(changes, Some(report), error);
+        if let Err(e) = config.hooks.run(event, context).await {
+            tracing::warn!("{}", e);
+        }
+
+        notify_run(config, "deploy", self.env_id.as_deref(), report).await;
+    }
+
+    fn finish(&self, report: RunReport, changes: ChangeReport) -> Result<()> {
+        telemetry::count_report(&report);
+        if let Some(path) = &self.report {
+            report.save(path)?;
+        }
+
+        if self.output.is_json() {
+            self.output.emit(&changes.with_outcomes(&report))?;
+        } else if report.has_failures() {
+            report.print_table();
+            report.print_summary("lgc deploy");
+        }
+
+        report.check()
+    }
+}
+
+/// Operations to run on the services of a plugin, with its own plugin instance.
+struct PluginJob {
+    instance: InstanceData,
+    store: Store<State>,
+    services: Vec<(Service, Vec<(Action, DetectionState)>)>,
+}
+
+/// Runs operations on remote services, with the limits of a deployment.
+#[derive(Clone)]
+struct Deployment {
+    config: Arc<ProjectConfiguration>,
+    plugin_manager: PluginManager,
+    /// Limits set by flags, overriding project and services limits
+    overrides: Limits,
+    /// `--timeout` overrides services timeout
+    timeout_override: bool,
+    progress: Progress,
+    /// Bounds the service calls running at once, all plugins included
+    permits: Arc<Semaphore>,
+}
+
+impl Deployment {
+    /// Run plugins concurrently, services of a plugin one after the other, with no more rule
+    /// operations at once than there are `permits`. Services are taken out of the state while
+    /// their plugin runs, and put back with the operations which ran, even if the plugin
+    /// failed. Should its task panic, they are put back as they were before the run.
+    ///
+    /// Services wait for the ones they depend on, and are skipped if any of them failed.
+    async fn deploy(
+        &self,
+        jobs: Vec<PluginJob>,
+        state_services: &mut ServiceDetections,
+        report: &mut RunReport,
+    ) -> Result<()> {
+        let order = self.config.deployment_order()?;
+        // Services signal whether they succeeded, those without operations are not waited for
+        let (mut senders, receivers): (HashMap<_, _>, HashMap<_, _>) = jobs
+            .iter()
+            .flat_map(|job| job.services.iter())
+            .map(|(svc, _)| {
+                let (sender, receiver) = watch::channel(false);
+                ((svc.id.clone(), sender), (svc.id.clone(), receiver))
+            })
+            .unzip();
+
+        let mut set = JoinSet::new();
+        // State of the services taken out, until their task hands them back
+        let mut taken = HashMap::new();
+        for (index, job) in jobs.into_iter().enumerate() {
+            let PluginJob {
+                mut instance,
+                mut store,
+                mut services,
+            } = job;
+            // Dependencies of a service come first in its plugin too, so plugins cannot wait
+            // on each other
+            services.sort_by_key(|(svc, _)| order.iter().position(|id| *id == svc.id));
+            let mut services: Vec<_> = services
+                .into_iter()
+                .map(|(svc, operations)| {
+                    let state_service = state_services.remove(&svc.id).unwrap_or_default();
+                    taken.insert(svc.id.clone(), state_service.clone());
+                    let dependencies: Vec<_> = svc
+                        .depends_on
+                        .iter()
+                        .filter_map(|id| {
+                            receivers
+                                .get(id)
+                                .map(|receiver| (id.clone(), receiver.clone()))
+                        })
+                        .collect();
+                    // Dropped once the service is done, unless it succeeded
+                    let sender = senders.remove(&svc.id);
+                    (svc, operations, state_service, dependencies, sender)
+                })
+                .collect();
+            let deployment = self.clone();
+            set.spawn(async move {
+                let mut report = RunReport::default();
+                let result = async {
+                    for (svc, operations, state_service, dependencies, sender) in
+                        services.iter_mut()
+                    {
+                        if let Some(id) = failed_dependency(dependencies).await {
+                            // Dependents of a skipped service are skipped too
+                            sender.take();
+                            for (action, rule) in operations.iter() {
+                                report.record(
+                                    &svc.id,
+                                    &rule.name,
+                                    *action,
+                                    Outcome::Skipped(format!("dependency `{}` failed", id)),
+                                );
+                            }
+                            continue;
+                        }
+
+                        let operations = operations
+                            .iter()
+                            .map(|(action, rule)| (*action, rule))
+                            .collect();
+                        let failures = report.failures().count();
+                        deployment
+                            .apply(
+                                &mut instance,
+                                &mut store,
+                                svc,
+                                operations,
+                                state_service,
+                                &mut report,
+                            )
+                            .await?;
+                        signal(sender, report.failures().count() == failures);
+                    }
+                    Ok::<_, anyhow::Error>(())
+                }
+                .await;
+                let services = services
+                    .into_iter()
+                    .map(|(svc, operations, state_service, _, _)| (svc, operations, state_service))
+                    .collect::<Vec<_>>();
+                (index, services, report, result)
+            });
+        }
+
+        let mut outcomes = BTreeMap::new();
+        let mut failure = None;
+        while let Some(outcome) = set.join_next().await {
+            match outcome {
+                Ok((index, services, plugin_report, result)) => {
+                    for (svc, _, state_service) in services {
+                        taken.remove(&svc.id);
+                        state_services.insert(svc.id, state_service);
+                    }
+                    outcomes.insert(index, (plugin_report, result));
+                }
+                Err(e) => {
+                    failure.get_or_insert(anyhow!("plugin deployment task failed: {}", e));
+                }
+            }
+        }
+        state_services.extend(taken);
+
+        // Operations are reported by plugin, whatever the order plugins finished in
+        for (plugin_report, result) in outcomes.into_values() {
+            report.operations.extend(plugin_report.operations);
+            if let Err(e) = result {
+                failure.get_or_insert(e);
+            }
+        }
+
+        match failure {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    async fn apply(
+        &self,
+        instance: &mut InstanceData,
+        store: &mut Store<State>,
+        svc: &Service,
+        operations: Vec<(Action, &DetectionState)>,
+        state_service: &mut HashSet<DetectionState>,
+        report: &mut RunReport,
+    ) -> Result<()> {
+        let limits = &svc.limits(&self.config.core.limits, &self.overrides);
+        let service_config = svc.config().await?;
+        let naming = self.config.naming(svc)?;
+        let timeout = if self.timeout_override {
+            limits.timeout()?
+        } else {
+            svc.timeout(limits.timeout()?)?
+        };
+        let mut rate_limiter = limits.rate_limiter();
+        let mut breaker = CircuitBreaker::new(svc.max_failures());
+        let mut progress = self.progress.service(&svc.id, operations.len());
+
+        for (action, rule) in operations {
+            if breaker.is_open() {
+                report.record(
+                    &svc.id,
+                    &rule.name,
+                    action,
+                    Outcome::Skipped(String::from("too many consecutive failures on service")),
+                );
+                progress.skip();
+                continue;
+            }
+
+            let rule_content = serde_json::to_string(&rule.content)?;
+            let remote_name = naming.apply(&rule.name);
+            let mut attempt = 0;
+            let (result, timed_out) = loop {
+                rate_limiter.wait().await;
+                // Backoffs do not hold a permit
+                let permit = self.permits.acquire().await?;
+                let outcome = match action {
+                    Action::Create => {
+                        tokio::time::timeout(
+                            timeout,
+                            instance.create(store, &service_config, &remote_name, &rule_content),
+                        )
+                        .await
+                    }
+                    // Only managed fields are sent, the plugin keeps other remote fields
+                    Action::Update => {
+                        tokio::time::timeout(
+                            timeout,
+                            instance.update(
+                                store,
+                                &service_config,
+                                &remote_name,
+                                &serde_json::to_string(&rule.managed_content())?,
+                            ),
+                        )
+                        .await
+                    }
+                    _ => {
+                        tokio::time::timeout(
+                            timeout,
+                            instance.delete(store, &service_config, &remote_name, &rule_content),
+                        )
+                        .await
+                    }
+                };
+
+                let (result, timed_out) = match outcome {
+                    Ok(result) => (result.map(|_| ()), false),
+                    Err(_) => (
+                        Err(anyhow!(
+                            "timed out after {}",
+                            humantime::format_duration(timeout)
+                        )),
+                        true,
+                    ),
+                };
+                drop(permit);
+
+                // Only transient plugin errors are retried, timed out
+                // operations may still be running remotely
+                if attempt < limits.retries()
+                    && result.as_ref().is_err_and(|e| limits.is_retryable(e))
+                {
+                    attempt += 1;
+                    let backoff = limits.retry_backoff(attempt)?;
+                    tracing::warn!(
+                        "retrying {} of `{}` on `{}` in {} ({}/{})",
+                        action,
+                        rule.name,
+                        svc.id,
+                        humantime::format_duration(backoff),
+                        attempt,
+                        limits.retries()
+                    );
+                    tokio::time::sleep(backoff).await;
+                    continue;
+                }
+                break (result, timed_out);
+            };
+
+            // Snapshot the remote object once it has been written
+            let mut deployed = rule.clone();
+            if result.is_ok()
+                && svc.remote_snapshots
+                && instance.supports(Capability::ReadRemote)
+                && action != Action::Delete
+            {
+                let _permit = self.permits.acquire().await?;
+                match instance
+                    .read_remote(store, &service_config, &remote_name, &rule_content)
+                    .await
+                    .and_then(|remote| {
+                        Ok(remote
+                            .map(|remote| serde_json::from_str(&remote))
+                            .transpose()?)
+                    }) {
+                    Ok(remote) => deployed.remote = remote,
+                    Err(e) => {
+                        tracing::warn!("unable to snapshot `{}` on `{}`: {}", rule.name, svc.id, e)
+                    }
+                }
+            }
+
+            breaker.record(result.is_ok());
+            match result {
+                Ok(()) => {
+                    let (sign, verb, name) = match action {
+                        Action::Create => {
+                            state_service.insert(deployed);
+                            ("+", "created on", style(&rule.name).green())
+                        }
+                        Action::Update => {
+                            state_service.replace(deployed);
+                            ("~", "updated on", style(&rule.name).yellow())
+                        }
+                        _ => {
+                            state_service.remove(rule);
+                            ("-", "deleted from", style(&rule.name).red())
+                        }
+                    };
+                    progress.record(
+                        action,
+                        true,
+                        &format!("[{}] rule: `{}` {} `{}`", sign, name, verb, svc.id),
+                    );
+                    report.record(&svc.id, &rule.name, action, Outcome::Succeeded);
+                }
+                Err(e) => {
+                    progress.record(
+                        action,
+                        false,
+                        &format!(
+                            "[!] rule: `{}` failed to {} on `{}`: {}",
+                            style(&rule.name).red(),
+                            action,
+                            svc.id,
+                            e
+                        ),
+                    );
+                    report.record(&svc.id, &rule.name, action, Outcome::Failed(e.to_string()));
+                }
+            }
+
+            // A timed out plugin instance cannot be trusted anymore
+            if timed_out {
+                (*instance, *store) = self.plugin_manager.load_plugin(&svc.plugin).await?;
+            }
+        }
+
+        progress.finish();
         Ok(())
     }
 }
+
+/// Wait for the services a service depends on, returning the first one which did not succeed.
+/// A dropped sender means the dependency failed or was skipped.
+async fn failed_dependency(dependencies: &mut [(String, watch::Receiver<bool>)]) -> Option<String> {
+    let mut failed = None;
+    for (id, receiver) in dependencies.iter_mut() {
+        if receiver.wait_for(|succeeded| *succeeded).await.is_err() {
+            failed.get_or_insert(id.clone());
+        }
+    }
+    failed
+}
+
+/// Signal dependents of a service whether it succeeded, releasing them either way.
+fn signal(sender: &mut Option<watch::Sender<bool>>, succeeded: bool) {
+    if let Some(sender) = sender.take() {
+        if succeeded {
+            sender.send_replace(true);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn dependents_of_failed_services_are_released() {
+        // `second` depends on `first`, both deployed by the same plugin task
+        let (first, receiver) = watch::channel(false);
+        let mut first = Some(first);
+        let mut dependencies = vec![(String::from("first"), receiver)];
+
+        signal(&mut first, false);
+        let failed =
+            tokio::time::timeout(Duration::from_secs(1), failed_dependency(&mut dependencies))
+                .await
+                .expect("dependent of a failed service must not wait");
+        assert_eq!(failed.as_deref(), Some("first"));
+    }
+
+    #[tokio::test]
+    async fn dependents_of_skipped_services_are_released() {
+        let (first, first_receiver) = watch::channel(false);
+        let (second, second_receiver) = watch::channel(false);
+        let (mut first, mut second) = (Some(first), Some(second));
+
+        // `first` fails, `second` depending on it is skipped, `third` depends on `second`
+        signal(&mut first, false);
+        let mut dependencies = vec![(String::from("first"), first_receiver)];
+        assert!(failed_dependency(&mut dependencies).await.is_some());
+        second.take();
+
+        let mut dependencies = vec![(String::from("second"), second_receiver)];
+        let failed =
+            tokio::time::timeout(Duration::from_secs(1), failed_dependency(&mut dependencies))
+                .await
+                .expect("dependent of a skipped service must not wait");
+        assert_eq!(failed.as_deref(), Some("second"));
+    }
+
+    #[tokio::test]
+    async fn dependents_of_succeeded_services_run() {
+        let (first, receiver) = watch::channel(false);
+        let mut first = Some(first);
+        let mut dependencies = vec![(String::from("first"), receiver)];
+
+        signal(&mut first, true);
+        assert_eq!(failed_dependency(&mut dependencies).await, None);
+    }
+}
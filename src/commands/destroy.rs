@@ -4,13 +4,26 @@
 use anyhow::{anyhow, bail, Result};
 use clap::Parser;
 use console::style;
-use dialoguer::{theme::ColorfulTheme, Confirm, Select};
+use dialoguer::{theme::ColorfulTheme, Confirm, Input, Select};
 use lgc_common::{
     configuration::{Environment, ProjectConfiguration, Service},
+    detections::sorted_rules,
+    notifications::notify_run,
     plugins::manager::{PluginActions, PluginManager},
+    report::{Action, ChangeReport, Outcome, RunReport},
+    telemetry,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+    sync::Arc,
+};
+use tokio::{sync::Semaphore, task::JoinSet};
+
+use super::{
+    limits::LimitsArgs, lock::LockArgs, output::OutputArgs, target::TargetArgs,
+    variables::VariablesArgs,
 };
-use std::collections::HashMap;
-use tokio::task::JoinSet;
 
 #[derive(Parser, Debug, Default)]
 #[clap(
@@ -25,22 +38,62 @@ pub struct DestroyCommand {
     #[clap(short, long)]
     pub service_id: Option<String>,
 
+    #[clap(flatten)]
+    pub target: TargetArgs,
+
+    #[clap(flatten)]
+    pub variables: VariablesArgs,
+
+    /// Confirm destroying rules from production environments, without typing their ID
+    #[clap(long)]
+    pub yes_i_am_sure: bool,
+
     /// Skip interactive approval of rules destruction
     #[clap(long)]
     pub auto_approve: bool,
+
+    #[clap(flatten)]
+    pub lock: LockArgs,
+
+    #[clap(flatten)]
+    pub limits: LimitsArgs,
+
+    #[clap(flatten)]
+    pub output: OutputArgs,
+
+    /// Write a JSON report of performed operations to this file
+    #[clap(long)]
+    pub report: Option<PathBuf>,
 }
 
 impl DestroyCommand {
     pub async fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        let limits = self.limits.resolve(config);
+        let environment = self.env_id.clone();
+
+        // Prepare state backend
+        let backend = self.lock.backend(config);
+
         // Load all detections
-        let mut state = config.state.load().await?;
+        let state_lock = backend.lock().await?;
+        let mut state = backend.load().await?;
 
         // Prompt theme
         let prompt_theme = ColorfulTheme::default();
 
         // Retrieve services
         let mut services: HashMap<String, Vec<&Service>> = HashMap::new();
-        if let Some(svc_id) = self.service_id {
+        // Rules targeted on each service, all of them if none
+        let mut targets = None;
+        if !self.target.is_empty() {
+            let variables = self.variables.resolve(config)?;
+            let extensions = config.core.detection_extensions()?;
+            targets = Some(
+                self.target
+                    .deployed(config, &state, &variables, &extensions)?,
+            );
+            services = self.target.services(config)?;
+        } else if let Some(svc_id) = self.service_id {
             let svc = config
                 .services
                 .get(&Service {
@@ -82,19 +135,87 @@ impl DestroyCommand {
                 })
         };
 
+        // Central guard against unattended changes
+        let mut auto_approve = self.auto_approve;
+        if auto_approve {
+            let service_ids: Vec<&str> = services
+                .values()
+                .flatten()
+                .map(|svc| svc.id.as_str())
+                .collect();
+            if let Some(reason) = config.approval_required(&service_ids) {
+                tracing::warn!("`--auto-approve` ignored: {} requires approval", reason);
+                auto_approve = false;
+            }
+        }
+
+        // Changes are only printed once destroyed
+        if self.output.is_json() && !auto_approve {
+            bail!("`--output json` requires changes approved with `--auto-approve`")
+        }
+
+        // Production environments are only destroyed deliberately
+        if !self.yes_i_am_sure {
+            for env in config.environments.iter().filter(|env| {
+                env.production
+                    && services
+                        .values()
+                        .flatten()
+                        .any(|svc| env.services.contains(&svc.id))
+            }) {
+                if auto_approve {
+                    bail!(
+                        "destroying rules from production environment `{}` requires `--yes-i-am-sure`",
+                        env.id
+                    )
+                }
+                let confirmation: String = Input::with_theme(&prompt_theme)
+                    .with_prompt(format!(
+                        "`{}` is a production environment, type its ID to destroy its rules",
+                        env.id
+                    ))
+                    .interact_text()?;
+                if confirmation != env.id {
+                    bail!("action aborted")
+                }
+            }
+        }
+
+        // Rules kept on services, protected or not targeted
+        let selected = |svc: &Service, name: &str| {
+            !config.core.is_protected(name)
+                && targets
+                    .as_ref()
+                    .map_or(true, |targets| targets.includes(&svc.id, name))
+        };
+
         // Load plugins
-        let plugin_manager = PluginManager::new()?;
+        let plugin_manager = PluginManager::new()?.with_capabilities(&config.plugins);
+        let permits = Arc::new(Semaphore::new(limits.parallelism()?));
         let mut set = JoinSet::new();
 
         for plugin_id in services.keys() {
             let plugin_id = plugin_id.to_string();
             let plugin_manager = plugin_manager.clone();
-            set.spawn(async move { plugin_manager.load_plugin(plugin_id).await });
+            let permits = permits.clone();
+            set.spawn(async move {
+                let _permit = permits.acquire_owned().await?;
+                plugin_manager.load_plugin(plugin_id).await
+            });
         }
 
-        // Call get schema and retrieve all detections
+        let mut report = RunReport::default();
+        let mut changes = ChangeReport::default();
+
+        // Plugins load concurrently but are processed by name for a stable output
+        let mut instances = BTreeMap::new();
         while let Some(plugin) = set.join_next().await {
-            let (instance, mut store) = plugin??;
+            let (instance, store) = plugin??;
+            instances.insert(instance.metadata.name.clone(), (instance, store));
+        }
+
+        // Call get schema and retrieve all detections
+        for (instance, mut store) in instances.into_values() {
             let meta = &instance.metadata;
 
             // Safe unwrap as we load plugins with detection HashMap.
@@ -102,22 +223,35 @@ impl DestroyCommand {
             let mut has_diff = false;
 
             for svc in services {
-                let service_config = serde_json::to_string(&svc.settings)?;
+                let service_config = svc.config().await?;
+                let naming = config.naming(svc)?;
                 if let Some(rules) = state.services.get(&svc.id) {
-                    for rule_state in rules {
+                    for rule_state in sorted_rules(rules) {
+                        if !selected(svc, &rule_state.name) {
+                            if config.core.is_protected(&rule_state.name) && !self.output.is_json()
+                            {
+                                println!(
+                                    "[!] rule: `{}` on `{}` is protected - keeping",
+                                    style(&rule_state.name).dim(),
+                                    svc.id
+                                );
+                            }
+                            continue;
+                        }
                         let requested_rule = serde_json::to_string(&rule_state.content)?;
                         if instance
                             .read(
                                 &mut store,
                                 &service_config,
-                                &rule_state.name,
+                                &naming.apply(&rule_state.name),
                                 &requested_rule,
                             )
                             .await?
                             .is_some()
                         {
                             has_diff = true;
-                            if !self.auto_approve {
+                            changes.plan(&svc.id, Action::Delete, rule_state, None)?;
+                            if !auto_approve {
                                 println!(
                                     "[-] rule: `{}` will be deleted from `{}`",
                                     style(&rule_state.name).red(),
@@ -131,54 +265,130 @@ impl DestroyCommand {
 
             // Destroy rules
             if has_diff {
-                if self.auto_approve
+                // Refuse changes to services under maintenance or with read-only credentials
+                for svc in services {
+                    if state.services.contains_key(&svc.id) {
+                        state.check_maintenance(&svc.id)?;
+                        instance
+                            .check_writable(&mut store, &svc.id, &svc.config().await?)
+                            .await?;
+                    }
+                }
+
+                if auto_approve
                     || Confirm::with_theme(&prompt_theme)
                         .with_prompt("Do you want to deploy these changes?")
                         .interact()?
                 {
                     for svc in services {
-                        let service_config = serde_json::to_string(&svc.settings)?;
+                        let service_config = svc.config().await?;
+                        let naming = config.naming(svc)?;
+                        let limits = svc.limits(&config.core.limits, &self.limits.overrides());
+                        let timeout = self.limits.timeout(svc, &limits)?;
+                        let mut rate_limiter = limits.rate_limiter();
                         if let Some(service) = state.services.get_mut(&svc.id) {
                             // Collect rules to avoid borrowing issues during iteration
-                            let rules: Vec<_> = service.iter().cloned().collect();
+                            let rules: Vec<_> = sorted_rules(service.iter())
+                                .into_iter()
+                                .filter(|rule| selected(svc, &rule.name))
+                                .cloned()
+                                .collect();
 
                             for rule_state in rules {
                                 let rule_content = serde_json::to_string(&rule_state.content)?;
-                                match instance
-                                    .delete(
-                                        &mut store,
-                                        &service_config,
-                                        &rule_state.name,
-                                        &rule_content,
+                                let mut attempt = 0;
+                                let result = loop {
+                                    rate_limiter.wait().await;
+                                    let result = tokio::time::timeout(
+                                        timeout,
+                                        instance.delete(
+                                            &mut store,
+                                            &service_config,
+                                            &naming.apply(&rule_state.name),
+                                            &rule_content,
+                                        ),
                                     )
-                                    .await
-                                {
+                                    .await;
+
+                                    // Only transient plugin errors are retried, timed out
+                                    // deletions may still be running remotely
+                                    match result {
+                                        Ok(Err(e))
+                                            if attempt < limits.retries()
+                                                && limits.is_retryable(&e) =>
+                                        {
+                                            attempt += 1;
+                                            let backoff = limits.retry_backoff(attempt)?;
+                                            tracing::warn!(
+                                                "retrying delete of `{}` on `{}` in {} ({}/{})",
+                                                rule_state.name,
+                                                svc.id,
+                                                humantime::format_duration(backoff),
+                                                attempt,
+                                                limits.retries()
+                                            );
+                                            tokio::time::sleep(backoff).await;
+                                        }
+                                        Ok(result) => break result,
+                                        Err(_) => {
+                                            break Err(anyhow!(
+                                                "timed out after {}",
+                                                humantime::format_duration(timeout)
+                                            ))
+                                        }
+                                    }
+                                };
+
+                                match result {
                                     Ok(Some(_)) => {
-                                        println!(
-                                            "[-] rule: `{}` deleted from `{}`",
-                                            style(&rule_state.name).red(),
-                                            svc.id
-                                        );
+                                        if !self.output.is_json() {
+                                            println!(
+                                                "[-] rule: `{}` deleted from `{}`",
+                                                style(&rule_state.name).red(),
+                                                svc.id
+                                            );
+                                        }
                                         service.remove(&rule_state);
                                     }
                                     Ok(None) => {
-                                        println!(
-                                            "[!] rule: `{}` not found on `{}` - ignoring",
-                                            style(&rule_state.name).dim(),
-                                            svc.id
-                                        );
+                                        if !self.output.is_json() {
+                                            println!(
+                                                "[!] rule: `{}` not found on `{}` - ignoring",
+                                                style(&rule_state.name).dim(),
+                                                svc.id
+                                            );
+                                        }
                                         service.remove(&rule_state);
                                     }
                                     Err(e) => {
-                                        state.save(&config.state).await?;
-                                        bail!(
-                                            "on deletion for `{}` in `{}`: {}",
-                                            style(&rule_state.name).red(),
-                                            svc.id,
-                                            e
+                                        if !self.output.is_json() {
+                                            println!(
+                                                "[!] rule: `{}` failed to delete from `{}`: {}",
+                                                style(&rule_state.name).red(),
+                                                svc.id,
+                                                e
+                                            );
+                                        }
+                                        report.record(
+                                            &svc.id,
+                                            &rule_state.name,
+                                            Action::Delete,
+                                            Outcome::Failed(e.to_string()),
                                         );
+                                        continue;
                                     }
                                 }
+                                report.record(
+                                    &svc.id,
+                                    &rule_state.name,
+                                    Action::Delete,
+                                    Outcome::Succeeded,
+                                );
+                            }
+
+                            // Keep rules which could not be deleted in state
+                            if !service.is_empty() {
+                                continue;
                             }
                             state.services.remove(&svc.id);
                         }
@@ -188,10 +398,24 @@ impl DestroyCommand {
                 }
             } else {
                 tracing::info!("no differences found");
-                return Ok(());
+                return self.output.emit(&changes);
             }
         }
 
-        state.save(&config.state).await
+        state.save(&state_lock).await?;
+        notify_run(config, "destroy", environment.as_deref(), &report).await;
+
+        telemetry::count_report(&report);
+        if let Some(path) = &self.report {
+            report.save(path)?;
+        }
+
+        if self.output.is_json() {
+            self.output.emit(&changes.with_outcomes(&report))?;
+        } else if report.has_failures() {
+            report.print_summary("lgc destroy");
+        }
+
+        report.check()
     }
 }
@@ -1,21 +1,46 @@
 // Copyright (c) 2023 LogCraft, SAS.
 // SPDX-License-Identifier: MPL-2.0
 
-use anyhow::{anyhow, Result};
-use clap::Parser;
+use anyhow::{anyhow, bail, Result};
+use clap::{Parser, ValueEnum};
 use console::style;
 use dialoguer::{theme::ColorfulTheme, Select};
 use lgc_common::{
     configuration::{Environment, ProjectConfiguration, Service},
     detections::{
-        compare_detections, map_plugin_detections, DetectionState, PluginDetections,
-        ServiceDetections,
+        compare_detections, map_plugin_detections, resolve_common_rules, sorted_rules,
+        sorted_services, DetectionState, PluginDetections, ServiceDetections,
     },
-    plugins::manager::{PluginActions, PluginManager},
+    plan::Plan,
+    plugins::{
+        manager::{read_rules, InstanceData, PluginActions, PluginManager},
+        Capability,
+    },
+    report::{Action, ChangeReport},
+    state::State,
+    telemetry,
+    transforms::apply_transforms,
+    utils::workspace_path,
 };
+use lgc_runtime::{cache::digest, state::State as PluginState, Cache};
 use serde_json::Value;
-use std::collections::{HashMap, HashSet};
-use tokio::task::JoinSet;
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{sync::Semaphore, task::JoinSet};
+use wasmtime::Store;
+
+use super::{
+    limits::LimitsArgs,
+    lock::LockArgs,
+    output::OutputArgs,
+    progress::ProgressArgs,
+    target::{TagArgs, TargetArgs},
+    variables::VariablesArgs,
+};
 
 /// Prepare working directory for other lgcli commands
 #[derive(Parser, Debug, Default)]
@@ -32,21 +57,122 @@ pub struct DiffCommand {
     pub service_id: Option<String>,
 
     /// Show differences for this detection path
-    #[clap(short, long)]
+    #[clap(short, long, conflicts_with = "targets")]
     pub detection_id: Option<String>,
+
+    #[clap(flatten)]
+    pub target: TargetArgs,
+
+    #[clap(flatten)]
+    pub tags: TagArgs,
+
+    #[clap(flatten)]
+    pub variables: VariablesArgs,
+
+    #[clap(flatten)]
+    pub lock: LockArgs,
+
+    #[clap(flatten)]
+    pub output: OutputArgs,
+
+    #[clap(flatten)]
+    pub progress: ProgressArgs,
+
+    /// Continue without invalid detection files, rules they define are not deleted
+    #[clap(long)]
+    pub skip_invalid: bool,
+
+    /// Fetch remote rules with list calls instead of one read per rule, also reporting rules unmanaged by lgc
+    #[clap(long)]
+    pub bulk: bool,
+
+    #[clap(flatten)]
+    pub limits: LimitsArgs,
+
+    /// Save the changes to this file, to deploy them exactly with `lgc deploy --plan`
+    #[clap(long)]
+    pub out: Option<PathBuf>,
+
+    /// Simulate remote rules instead of reading them from services, no credentials are needed
+    #[clap(long, value_enum, conflicts_with_all = ["bulk", "out"])]
+    pub mock_remote: Option<MockRemote>,
+
+    /// Have plugins check rules against each service before planning, e.g. the semantics of queries
+    #[clap(long, conflicts_with = "mock_remote")]
+    pub validate_remote: bool,
+
+    /// Reuse remote rules read by previous runs for this long (e.g. `5m`), to iterate quickly on slow services
+    #[clap(long, value_name = "TTL", conflicts_with_all = ["bulk", "mock_remote", "out"])]
+    pub read_cache: Option<String>,
+}
+
+/// Simulated remote rules, for demos and pipelines without access to services.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum MockRemote {
+    /// Services hold no rule
+    Empty,
+    /// Services hold the rules recorded in the state, as last deployed
+    State,
+}
+
+impl MockRemote {
+    fn remote(self, state: &State, service_id: &str, rule: &DetectionState) -> Option<Value> {
+        match self {
+            Self::Empty => None,
+            Self::State => state
+                .services
+                .get(service_id)
+                .and_then(|rules| rules.get(rule))
+                .map(|rule| rule.content.clone()),
+        }
+    }
 }
 
 impl DiffCommand {
     pub async fn run(self, config: &ProjectConfiguration) -> Result<()> {
-        // Load all detections
-        let detections: PluginDetections = map_plugin_detections(self.detection_id.clone())?;
+        let json = self.output.is_json();
+
+        let progress = self.progress.start(self.output.is_json());
+
+        // Load all detections, or only targeted ones
+        let variables = self.variables.resolve(config)?;
+        let extensions = config.core.detection_extensions()?;
+        let (mut detections, skipped, targets) = if self.target.is_empty() {
+            let (detections, skipped): (PluginDetections, _) = map_plugin_detections(
+                self.detection_id.clone(),
+                &variables,
+                &extensions,
+                self.skip_invalid,
+            )?;
+            (detections, skipped, None)
+        } else {
+            let (detections, targets) = self.target.load(config, &variables, &extensions)?;
+            (detections, Vec::new(), Some(targets))
+        };
+        self.tags.retain(&mut detections);
+        if !skipped.is_empty() {
+            tracing::warn!(
+                "rules missing locally will not be deleted while detection files are skipped"
+            );
+        }
+
+        if self.mock_remote.is_some() {
+            tracing::warn!("remote rules are simulated, services are not contacted");
+        }
+
+        let read_cache = self.read_cache.as_deref().map(ReadCache::new).transpose()?;
+        if read_cache.is_some() {
+            tracing::warn!("remote rules may be read from cache, changes made meanwhile on services can be missed");
+        }
 
         // Prompt theme
         let prompt_theme = ColorfulTheme::default();
 
         // Retrieve services
         let mut services: HashMap<String, Vec<&Service>> = HashMap::new();
-        if let Some(svc_id) = self.service_id {
+        if !self.target.is_empty() {
+            services = self.target.services(config)?;
+        } else if let Some(svc_id) = self.service_id {
             let svc = config
                 .services
                 .get(&Service {
@@ -89,58 +215,238 @@ impl DiffCommand {
         };
 
         // Load plugins
-        let plugin_manager = PluginManager::new()?;
+        let plugin_manager = PluginManager::new()?.with_capabilities(&config.plugins);
+        resolve_common_rules(&plugin_manager, &mut detections).await?;
+        apply_transforms(&config.transforms, &mut detections)?;
+        let overrides = self.limits.overrides();
+        let parallelism = self.limits.resolve(config).parallelism()?;
+        let permits = Arc::new(Semaphore::new(parallelism));
         let mut set = JoinSet::new();
 
-        for plugin_id in detections.keys() {
+        // Remote rules are not read when mocked, plugins are not loaded
+        let plugin_ids = detections.keys().filter(|_| self.mock_remote.is_none());
+        for plugin_id in plugin_ids {
             let plugin_id = plugin_id.to_string();
             let plugin_manager = plugin_manager.clone();
-            set.spawn(async move { plugin_manager.load_plugin(plugin_id).await });
+            let permits = permits.clone();
+            set.spawn(async move {
+                let _permit = permits.acquire_owned().await?;
+                plugin_manager
+                    .load_plugin_pool(plugin_id, parallelism)
+                    .await
+            });
         }
 
+        // Planning never waits for, nor delays, changes in progress
+        let state = self.lock.shared(config).load().await?;
+        let mut plan = Plan::new(&state);
         let mut returned_rules: ServiceDetections = HashMap::new();
+        let mut created: HashMap<String, usize> = HashMap::new();
         let mut has_diff = false;
+        let mut rejected = 0;
 
-        // Call get schema and retrieve all detections
+        let mut instances = HashMap::new();
         while let Some(plugin) = set.join_next().await {
-            let (instance, mut store) = plugin??;
-            let meta = &instance.metadata;
+            // Additional instances only serve concurrent reads
+            let mut readers = plugin??;
+            let (instance, store) = readers.remove(0);
+            instances.insert(instance.metadata.name.clone(), (instance, store, readers));
+        }
 
-            // Safe unwrap as we load plugins with detection HashMap.
-            let (plugin, rules) = detections.get_key_value(&meta.name).unwrap();
+        // Plugins are processed by name for a stable output
+        let mut plugin_ids: Vec<&String> = detections.keys().collect();
+        plugin_ids.sort();
+        for plugin in plugin_ids {
+            let rules = &detections[plugin];
+            let mut loaded = instances.remove(plugin);
 
             if let Some(services) = services.get(plugin) {
-                for svc in services {
-                    let service_config = serde_json::to_string(&svc.settings)?;
-                    for rule_state in rules {
-                        let requested_rule = serde_json::to_string(&rule_state.content)?;
-                        if let Some(rule) = instance
-                            .read(
-                                &mut store,
-                                &service_config,
-                                &rule_state.name,
-                                &requested_rule,
-                            )
-                            .await?
-                        {
-                            let content: Value = serde_json::from_str(&rule)?;
+                for svc in sorted_services(services) {
+                    let naming = config.naming(svc)?;
+                    let sorted: Vec<&DetectionState> = sorted_rules(rules)
+                        .into_iter()
+                        .filter(|rule| {
+                            targets
+                                .as_ref()
+                                .map_or(true, |targets| targets.includes(&svc.id, &rule.name))
+                        })
+                        .collect();
+                    let mut listed = None;
+
+                    let contents: Vec<Option<Value>> = if let Some(mock) = self.mock_remote {
+                        sorted
+                            .iter()
+                            .map(|rule| mock.remote(&state, &svc.id, rule))
+                            .collect()
+                    } else {
+                        let (instance, store, readers) = loaded
+                            .as_mut()
+                            .ok_or_else(|| anyhow!("plugin `{}` instance not found", plugin))?;
+                        let service_config = svc.config().await?;
+                        if self.validate_remote {
+                            rejected +=
+                                check_rules(instance, store, svc, &service_config, &sorted).await?;
+                        }
+                        let reading = progress.reading(&svc.id, sorted.len());
+                        if self.bulk && !instance.supports(Capability::ListRules) {
+                            tracing::warn!(
+                                "plugin `{}` cannot list rules, reading rules of `{}` one by one",
+                                plugin,
+                                svc.id
+                            );
+                        } else if self.bulk {
+                            let wanted =
+                                sorted.iter().map(|rule| naming.apply(&rule.name)).collect();
+                            listed = Some(
+                                instance
+                                    .list_all_rules(
+                                        store,
+                                        &service_config,
+                                        &wanted,
+                                        svc.limits(&config.core.limits, &overrides)
+                                            .list_page_size()?,
+                                    )
+                                    .await?,
+                            );
+                        }
+
+                        let contents = match &listed {
+                            Some(listed) => sorted
+                                .iter()
+                                .map(|rule| {
+                                    listed
+                                        .contents
+                                        .get(&naming.apply(&rule.name))
+                                        .map(|listed| rule.from_listing(listed))
+                                        .transpose()
+                                })
+                                .collect::<Result<_>>()?,
+                            None => {
+                                // Rules read by previous runs within the TTL are not read again
+                                let keys = match &read_cache {
+                                    Some(_) => sorted
+                                        .iter()
+                                        .map(|rule| {
+                                            ReadCache::key(
+                                                instance,
+                                                &svc.id,
+                                                &service_config,
+                                                &naming.apply(&rule.name),
+                                                rule,
+                                            )
+                                        })
+                                        .collect::<Result<Vec<_>>>()?,
+                                    None => Vec::new(),
+                                };
+                                let mut contents: Vec<Option<Option<String>>> = keys
+                                    .iter()
+                                    .map(|key| {
+                                        read_cache.as_ref().and_then(|cache| cache.load(key))
+                                    })
+                                    .collect();
+                                contents.resize(sorted.len(), None);
+
+                                let missing: Vec<&DetectionState> = sorted
+                                    .iter()
+                                    .zip(&contents)
+                                    .filter(|(_, content)| content.is_none())
+                                    .map(|(rule, _)| *rule)
+                                    .collect();
+                                let limits = svc.limits(&config.core.limits, &overrides);
+                                let mut read = read_rules(
+                                    instance,
+                                    store,
+                                    readers,
+                                    &service_config,
+                                    &naming,
+                                    &missing,
+                                    &limits,
+                                    self.limits.timeout(svc, &limits)?,
+                                    &permits,
+                                )
+                                .await?
+                                .into_iter();
+                                for (index, content) in contents.iter_mut().enumerate() {
+                                    if content.is_none() {
+                                        // Safe unwrap, a rule is read for each missing content
+                                        let rule = read.next().unwrap()?;
+                                        if let (Some(cache), Some(key)) =
+                                            (&read_cache, keys.get(index))
+                                        {
+                                            cache.store(key, &rule);
+                                        }
+                                        *content = Some(rule);
+                                    }
+                                }
+
+                                contents
+                                    .into_iter()
+                                    .flatten()
+                                    .map(|rule| {
+                                        Ok(rule
+                                            .map(|rule| serde_json::from_str(&rule))
+                                            .transpose()?)
+                                    })
+                                    .collect::<Result<_>>()?
+                            }
+                        };
+                        reading.finish_and_clear();
+                        contents
+                    };
+
+                    for (rule_state, content) in sorted.into_iter().zip(contents) {
+                        if let Some(content) = content {
                             returned_rules
                                 .entry(svc.id.clone())
                                 .and_modify(|rules| {
                                     rules.insert(DetectionState {
                                         name: rule_state.name.clone(),
                                         content: content.clone(),
+                                        common: None,
+                                        metadata: None,
+                                        remote: None,
+                                        manage_fields: Vec::new(),
                                     });
                                 })
                                 .or_insert(HashSet::from([DetectionState {
                                     name: rule_state.name.clone(),
                                     content,
+                                    common: None,
+                                    metadata: None,
+                                    remote: None,
+                                    manage_fields: Vec::new(),
                                 }]));
                         } else {
                             has_diff = true;
+                            *created.entry(svc.id.clone()).or_default() += 1;
+                            plan.add(&svc.id, Action::Create, rule_state);
+                            if !json {
+                                println!(
+                                    "[+] rule: `{}` will be created on `{}`",
+                                    style(&rule_state.name).green(),
+                                    &svc.id
+                                )
+                            }
+                        }
+                    }
+
+                    // Rules created outside of lgc, only known when every detection is loaded
+                    let unmanaged = listed.iter().filter(|_| {
+                        self.detection_id.is_none() && targets.is_none() && self.tags.is_empty()
+                    });
+                    for name in unmanaged.flat_map(|listed| &listed.names) {
+                        // Rules not following the naming convention are never managed
+                        let managed = naming.reverse(name).is_some_and(|name| {
+                            rules.iter().any(|rule| rule.name == name)
+                                || state
+                                    .services
+                                    .get(&svc.id)
+                                    .is_some_and(|rules| rules.iter().any(|rule| rule.name == name))
+                        });
+                        if !managed && !json {
                             println!(
-                                "[+] rule: `{}` will be created on `{}`",
-                                style(&rule_state.name).green(),
+                                "[?] rule: `{}` exists on `{}` but is not managed by lgc",
+                                style(name).yellow(),
                                 &svc.id
                             )
                         }
@@ -149,20 +455,173 @@ impl DiffCommand {
             }
         }
 
-        let changes = compare_detections(&detections, &returned_rules, &services, true).is_empty();
+        if rejected > 0 {
+            bail!(
+                "{} rule(s) rejected by services, nothing is planned",
+                rejected
+            )
+        }
+
+        let changed = compare_detections(
+            &detections,
+            &returned_rules,
+            &services,
+            &config.plugins,
+            !json,
+        );
+
+        // Rules of skipped, untargeted or untagged files would look deleted, deletions wait for a full run
+        let to_remove = if skipped.is_empty() && targets.is_none() && self.tags.is_empty() {
+            state.missing_rules(&returned_rules, &config.core, json, self.detection_id)
+        } else {
+            ServiceDetections::new()
+        };
+
+        // Check services quotas against planned changes
+        for svc in services.values().flatten() {
+            svc.check_quota(state.projected_rules(
+                &svc.id,
+                &returned_rules,
+                created.get(&svc.id).copied().unwrap_or_default(),
+                to_remove.get(&svc.id).map_or(0, |rules| rules.len()),
+            ))?;
+        }
 
-        if config
-            .state
-            .load()
-            .await?
-            .missing_rules(&returned_rules, false, self.detection_id)
-            .is_empty()
-            && changes
-            && !has_diff
-        {
+        if to_remove.is_empty() && changed.is_empty() && !has_diff {
             tracing::info!("no differences found");
         }
 
+        let mut service_ids: Vec<&String> = changed.keys().chain(to_remove.keys()).collect();
+        service_ids.sort();
+        service_ids.dedup();
+        for service_id in service_ids {
+            for rule in sorted_rules(changed.get(service_id).into_iter().flatten()) {
+                plan.add(service_id, Action::Update, rule);
+            }
+            for rule in sorted_rules(to_remove.get(service_id).into_iter().flatten()) {
+                plan.add(service_id, Action::Delete, rule);
+            }
+        }
+
+        telemetry::count_plan(&plan);
+
+        if json {
+            let mut changes = ChangeReport::default();
+            for (service_id, operations) in &plan.services {
+                for operation in operations {
+                    let remote = returned_rules
+                        .get(service_id)
+                        .and_then(|rules| rules.get(&operation.rule));
+                    changes.plan(
+                        service_id,
+                        operation.action,
+                        &operation.rule,
+                        remote.map(|remote| &remote.content),
+                    )?;
+                }
+            }
+            self.output.emit(&changes)?;
+        }
+
+        if let Some(path) = &self.out {
+            plan.save(path)?;
+            tracing::info!(
+                "plan saved to `{}`, deploy it with `lgc deploy --plan {}`",
+                workspace_path(path),
+                workspace_path(path)
+            );
+        }
+
         Ok(())
     }
 }
+
+/// Check rules against a service with the plugin, returning the number of rejected rules.
+async fn check_rules(
+    instance: &InstanceData,
+    store: &mut Store<PluginState>,
+    svc: &Service,
+    service_config: &str,
+    rules: &[&DetectionState],
+) -> Result<usize> {
+    if !instance.supports(Capability::Check) {
+        tracing::warn!(
+            "plugin `{}` cannot check rules, rules of `{}` are not validated remotely",
+            instance.metadata.name,
+            svc.id
+        );
+        return Ok(0);
+    }
+
+    let mut rejected = 0;
+    for rule in rules {
+        let problems = instance
+            .check(
+                store,
+                Some(service_config),
+                &rule.name,
+                &serde_json::to_string(&rule.content)?,
+            )
+            .await?;
+        for problem in &problems {
+            tracing::error!("rule `{}` rejected by `{}`: {}", rule.name, svc.id, problem);
+        }
+        if !problems.is_empty() {
+            rejected += 1;
+        }
+    }
+
+    Ok(rejected)
+}
+
+/// Remote rules read by previous runs, reused by `--read-cache`.
+struct ReadCache {
+    cache: Cache,
+    ttl: Duration,
+}
+
+impl ReadCache {
+    fn new(ttl: &str) -> Result<Self> {
+        Ok(Self {
+            cache: Cache::new(None)?,
+            ttl: humantime::parse_duration(ttl)
+                .map_err(|e| anyhow!("invalid read cache TTL `{}`: {}", ttl, e))?,
+        })
+    }
+
+    /// Digest of a read, settings included so that reads with other credentials are never reused.
+    fn key(
+        instance: &InstanceData,
+        service_id: &str,
+        config: &str,
+        name: &str,
+        rule: &DetectionState,
+    ) -> Result<String> {
+        let params = serde_json::to_string(&rule.managed_content())?;
+        let read = [
+            instance.metadata.name.as_str(),
+            &instance.metadata.version,
+            service_id,
+            config,
+            name,
+            &params,
+        ];
+        Ok(digest(read.join("\0").as_bytes()))
+    }
+
+    fn load(&self, key: &str) -> Option<Option<String>> {
+        self.cache
+            .load_read(key, self.ttl)
+            .and_then(|content| serde_json::from_str(&content).ok())
+    }
+
+    /// Store a read, failures only cost a later read.
+    fn store(&self, key: &str, content: &Option<String>) {
+        let stored = serde_json::to_string(content)
+            .map_err(anyhow::Error::from)
+            .and_then(|content| self.cache.store_read(key, &content));
+        if let Err(e) = stored {
+            tracing::warn!("unable to cache remote read: {}", e);
+        }
+    }
+}
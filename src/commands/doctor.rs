@@ -0,0 +1,75 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{bail, Result};
+use clap::Parser;
+use console::style;
+use lgc_common::{
+    configuration::ProjectConfiguration, detections::lint_detections,
+    state::LGC_STATE_RECOVERY_PATH,
+};
+use std::path::Path;
+
+use super::{
+    lock::shared_backend,
+    services::{check_services, print_health},
+};
+
+/// Check the project configuration, state backend and services health
+#[derive(Parser, Debug, Default)]
+#[clap(about = "Check the project configuration, state backend and services health")]
+pub struct DoctorCommand;
+
+impl DoctorCommand {
+    pub async fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        let mut problems = 0;
+
+        println!("{}", style("Configuration").bold().underlined());
+        let mut findings = config.lint();
+        findings.extend(lint_detections(config)?);
+        if findings.is_empty() {
+            println!("no problems identified");
+        }
+        for finding in &findings {
+            println!("[!] {}", style(&finding.message).yellow());
+            println!("    {} {}", style("fix:").dim(), finding.suggestion);
+        }
+        problems += findings.len();
+
+        println!("\n{}", style("State").bold().underlined());
+        match shared_backend(config).load().await {
+            Ok(state) => println!("serial `{}`", state.serial()),
+            Err(e) => {
+                problems += 1;
+                println!("[!] {}", style(e).red());
+            }
+        }
+        if Path::new(LGC_STATE_RECOVERY_PATH).exists() {
+            problems += 1;
+            println!(
+                "[!] {}",
+                style(format!("a failed save left `{}`", LGC_STATE_RECOVERY_PATH)).yellow()
+            );
+            println!(
+                "    {} review it and run `lgc state push --from-recovery`",
+                style("fix:").dim()
+            );
+        }
+
+        println!("\n{}", style("Services").bold().underlined());
+        if config.services.is_empty() {
+            println!("no services defined");
+        } else {
+            let health = check_services(config).await?;
+            print_health(&health);
+            problems += health.iter().filter(|svc| !svc.is_healthy()).count();
+        }
+
+        if problems > 0 {
+            bail!("{} problem(s) identified", problems)
+        }
+
+        tracing::info!("all good, no problems identified");
+        Ok(())
+    }
+}
@@ -0,0 +1,274 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{bail, Result};
+use clap::{Parser, ValueEnum};
+use lgc_common::{
+    configuration::{ProjectConfiguration, Service},
+    detections::{map_plugin_detections, sorted_rules},
+    variables::Variables,
+};
+use std::collections::BTreeSet;
+
+use super::lock::shared_backend;
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GraphFormat {
+    /// Mermaid flowchart, rendered by GitHub and GitLab
+    #[default]
+    Mermaid,
+    /// Graphviz DOT
+    Dot,
+}
+
+/// Print the project graph
+#[derive(Parser, Debug, Default)]
+#[clap(
+    about = "Print the graph of environments, services, plugins and rules",
+    allow_hyphen_values = true
+)]
+pub struct GraphCommand {
+    /// Only show the services of this environment
+    #[clap(short, long)]
+    pub env: Option<String>,
+
+    /// Graph format
+    #[clap(short, long, value_enum, default_value_t)]
+    pub format: GraphFormat,
+
+    /// Show a node per rule, rules are only counted otherwise
+    #[clap(long)]
+    pub rules: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum NodeKind {
+    Environment,
+    Service,
+    Plugin,
+    Rule,
+}
+
+struct Node {
+    id: String,
+    label: Vec<String>,
+    kind: NodeKind,
+    /// Whether the node has changes which are not deployed
+    pending: bool,
+}
+
+#[derive(Default)]
+struct Graph {
+    nodes: Vec<Node>,
+    edges: Vec<(String, String)>,
+}
+
+impl GraphCommand {
+    pub async fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        let environments: Vec<_> = config
+            .environments
+            .iter()
+            .filter(|env| self.env.as_ref().map_or(true, |id| &env.id == id))
+            .collect();
+        if let Some(id) = &self.env {
+            if environments.is_empty() {
+                bail!("environment `{}` not found", id)
+            }
+        }
+        let services: Vec<&Service> = config
+            .services
+            .iter()
+            .filter(|svc| {
+                environments
+                    .iter()
+                    .any(|env| env.services.contains(&svc.id))
+            })
+            .chain(
+                // Services outside of environments are deployed on their own
+                config.services.iter().filter(|svc| {
+                    self.env.is_none()
+                        && !config
+                            .environments
+                            .iter()
+                            .any(|env| env.services.contains(&svc.id))
+                }),
+            )
+            .collect();
+
+        // Pending changes are computed against the state, without contacting services
+        let (detections, _) = map_plugin_detections(
+            None,
+            &Variables::resolve(&config.variables, &[], &[])?,
+            &config.core.detection_extensions()?,
+            false,
+        )?;
+        let state = shared_backend(config).load().await?;
+
+        let mut graph = Graph::default();
+        for (index, env) in environments.iter().enumerate() {
+            let mut label = vec![env.id.clone()];
+            if env.require_approval {
+                label.push(String::from("approval required"));
+            }
+            graph.node(format!("env{}", index), label, NodeKind::Environment, false);
+        }
+
+        let plugins: BTreeSet<&str> = services.iter().map(|svc| svc.plugin.as_str()).collect();
+        let plugin_id = |name: &str| {
+            format!(
+                "plugin{}",
+                plugins
+                    .iter()
+                    .position(|plugin| *plugin == name)
+                    .unwrap_or_default()
+            )
+        };
+        // Rules pending on a service of their plugin
+        let mut pending_rules: BTreeSet<(&str, &str)> = BTreeSet::new();
+
+        for (index, svc) in services.iter().enumerate() {
+            let id = format!("service{}", index);
+            let rules = detections.get(&svc.plugin);
+            let deployed = state.services.get(&svc.id);
+
+            let mut pending = 0;
+            for rule in rules.into_iter().flatten() {
+                if deployed
+                    .and_then(|deployed| deployed.get(rule))
+                    .map_or(true, |deployed| deployed.content != rule.content)
+                {
+                    pending += 1;
+                    pending_rules.insert((svc.plugin.as_str(), rule.name.as_str()));
+                }
+            }
+            pending += deployed
+                .into_iter()
+                .flatten()
+                .filter(|rule| !rules.is_some_and(|rules| rules.contains(*rule)))
+                .count();
+
+            let status = match pending {
+                0 => String::from("in sync"),
+                pending => format!("{} pending", pending),
+            };
+            graph.node(
+                id.clone(),
+                vec![
+                    svc.id.clone(),
+                    format!(
+                        "{} deployed, {}",
+                        deployed.map_or(0, |rules| rules.len()),
+                        status
+                    ),
+                ],
+                NodeKind::Service,
+                pending > 0,
+            );
+
+            for (env_index, env) in environments.iter().enumerate() {
+                if env.services.contains(&svc.id) {
+                    graph.edge(format!("env{}", env_index), id.clone());
+                }
+            }
+            graph.edge(id, plugin_id(&svc.plugin));
+        }
+
+        for name in &plugins {
+            let rules = detections.get(*name);
+            let title = match config.plugins.get(*name) {
+                Some(plugin) => format!("{} {}", name, plugin.version),
+                None => name.to_string(),
+            };
+            let count = format!("{} rules", rules.map_or(0, |rules| rules.len()));
+            graph.node(plugin_id(name), vec![title, count], NodeKind::Plugin, false);
+
+            if !self.rules {
+                continue;
+            }
+            for rule in sorted_rules(rules.into_iter().flatten()) {
+                let id = format!("rule{}", graph.nodes.len());
+                let pending = pending_rules.contains(&(*name, rule.name.as_str()));
+                graph.node(id.clone(), vec![rule.name.clone()], NodeKind::Rule, pending);
+                graph.edge(plugin_id(name), id);
+            }
+        }
+
+        let output = match self.format {
+            GraphFormat::Mermaid => graph.mermaid(),
+            GraphFormat::Dot => graph.dot(),
+        };
+        print!("{}", output);
+
+        Ok(())
+    }
+}
+
+impl Graph {
+    fn node(&mut self, id: String, label: Vec<String>, kind: NodeKind, pending: bool) {
+        self.nodes.push(Node {
+            id,
+            label,
+            kind,
+            pending,
+        });
+    }
+
+    fn edge(&mut self, from: String, to: String) {
+        self.edges.push((from, to));
+    }
+
+    fn mermaid(&self) -> String {
+        let mut output = String::from("flowchart LR\n");
+        for node in &self.nodes {
+            let label = node
+                .label
+                .iter()
+                .map(|line| line.replace('"', "#quot;"))
+                .collect::<Vec<_>>()
+                .join("<br/>");
+            let (open, close) = match node.kind {
+                NodeKind::Environment => ("([", "])"),
+                NodeKind::Service => ("[", "]"),
+                NodeKind::Plugin => ("{{", "}}"),
+                NodeKind::Rule => ("(", ")"),
+            };
+            output.push_str(&format!("    {}{}\"{}\"{}\n", node.id, open, label, close));
+            if node.pending {
+                output.push_str(&format!("    class {} pending\n", node.id));
+            }
+        }
+        for (from, to) in &self.edges {
+            output.push_str(&format!("    {} --> {}\n", from, to));
+        }
+        output.push_str("    classDef pending stroke:#d97706,stroke-width:2px\n");
+        output
+    }
+
+    fn dot(&self) -> String {
+        let mut output = String::from("digraph lgc {\n    rankdir=LR;\n");
+        for node in &self.nodes {
+            let label = node
+                .label
+                .iter()
+                .map(|line| line.replace('\\', "\\\\").replace('"', "\\\""))
+                .collect::<Vec<_>>()
+                .join("\\n");
+            let shape = match node.kind {
+                NodeKind::Environment => "ellipse",
+                NodeKind::Service => "box",
+                NodeKind::Plugin => "hexagon",
+                NodeKind::Rule => "note",
+            };
+            let color = if node.pending { ", color=orange" } else { "" };
+            output.push_str(&format!(
+                "    {} [label=\"{}\", shape={}{}];\n",
+                node.id, label, shape, color
+            ));
+        }
+        for (from, to) in &self.edges {
+            output.push_str(&format!("    {} -> {};\n", from, to));
+        }
+        output.push_str("}\n");
+        output
+    }
+}
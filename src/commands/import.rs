@@ -0,0 +1,292 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{anyhow, bail, Result};
+use clap::Parser;
+use console::style;
+use indicatif::{ProgressBar, ProgressStyle};
+use lgc_common::{
+    configuration::{ProjectConfiguration, Service, LGC_RULES_DIR},
+    detections::DetectionState,
+    plugins::manager::{PluginActions, PluginManager},
+    utils::workspace_path,
+};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{fs, path::PathBuf, time::Duration};
+
+use super::{lock::LockArgs, services::SPINNER};
+
+/// Directory holding import checkpoints.
+const LGC_IMPORT_CHECKPOINT_DIR: &str = ".logcraft/imports";
+
+#[derive(Parser, Debug, Default)]
+#[clap(
+    about = "Import detection rules deployed on a service",
+    allow_hyphen_values = true
+)]
+pub struct ImportCommand {
+    /// Import from this service
+    #[clap(short, long)]
+    pub service_id: String,
+
+    /// Import every rule of the service matching filters
+    #[clap(long)]
+    pub all: bool,
+
+    /// Only import rules whose name matches this regex
+    #[clap(long)]
+    pub title: Option<String>,
+
+    /// Only import rules whose field matches a regex (e.g. `/acl/app=^search$`)
+    #[clap(long = "filter", value_name = "FIELD=REGEX")]
+    pub filters: Vec<String>,
+
+    /// Number of rules requested per page
+    #[clap(long, default_value_t = 100)]
+    pub batch_size: u32,
+
+    /// Restart from the beginning instead of resuming an interrupted import
+    #[clap(long)]
+    pub restart: bool,
+
+    /// Overwrite existing rule files
+    #[clap(long)]
+    pub force: bool,
+
+    #[clap(flatten)]
+    pub lock: LockArgs,
+}
+
+/// Progress of an import, saved after each page.
+#[derive(Serialize, Deserialize, Default)]
+struct Checkpoint {
+    cursor: Option<String>,
+    imported: usize,
+    skipped: usize,
+}
+
+/// Rule filter on a content field, addressed by a JSON pointer or a top level key.
+pub(super) struct FieldFilter {
+    field: String,
+    regex: Regex,
+}
+
+impl FieldFilter {
+    pub(super) fn parse(filter: &str) -> Result<Self> {
+        let (field, regex) = filter
+            .split_once('=')
+            .ok_or_else(|| anyhow!("invalid filter `{}`, expected `FIELD=REGEX`", filter))?;
+
+        Ok(Self {
+            field: field.to_string(),
+            regex: Regex::new(regex)?,
+        })
+    }
+
+    pub(super) fn matches(&self, content: &Value) -> bool {
+        let value = if self.field.starts_with('/') {
+            content.pointer(&self.field)
+        } else {
+            content.get(&self.field)
+        };
+
+        match value {
+            Some(Value::String(value)) => self.regex.is_match(value),
+            Some(value) => self.regex.is_match(&value.to_string()),
+            None => false,
+        }
+    }
+}
+
+impl ImportCommand {
+    pub async fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        let title = self.title.as_deref().map(Regex::new).transpose()?;
+        let filters = self
+            .filters
+            .iter()
+            .map(|filter| FieldFilter::parse(filter))
+            .collect::<Result<Vec<_>>>()?;
+
+        if !self.all && title.is_none() && filters.is_empty() {
+            bail!("use `--all` to import every rule of the service, or restrict it with `--title` or `--filter`")
+        }
+
+        if self.batch_size == 0 {
+            bail!("batch size must be greater than 0")
+        }
+
+        let svc = config
+            .services
+            .get(&Service {
+                id: self.service_id.clone(),
+                ..Default::default()
+            })
+            .ok_or_else(|| anyhow!("service `{}` not found", &self.service_id))?;
+
+        // Resume interrupted imports
+        let checkpoint_path =
+            PathBuf::from(LGC_IMPORT_CHECKPOINT_DIR).join(format!("{}.json", svc.id));
+        let mut checkpoint = if !self.restart && checkpoint_path.is_file() {
+            let checkpoint: Checkpoint =
+                serde_json::from_str(&fs::read_to_string(&checkpoint_path)?)?;
+            tracing::info!(
+                "resuming import of `{}` after {} rule(s)",
+                svc.id,
+                checkpoint.imported + checkpoint.skipped
+            );
+            checkpoint
+        } else {
+            Checkpoint::default()
+        };
+        fs::create_dir_all(LGC_IMPORT_CHECKPOINT_DIR)?;
+        fs::create_dir_all(LGC_RULES_DIR)?;
+
+        let (instance, mut store) = PluginManager::new()?
+            .with_capabilities(&config.plugins)
+            .load_plugin(&svc.plugin)
+            .await?;
+        let service_config = svc.config().await?;
+        let naming = config.naming(svc)?;
+        let backend = self.lock.backend(config);
+        let state_lock = backend.lock().await?;
+        let mut state = backend.load().await?;
+
+        let spinner = ProgressBar::new_spinner();
+        spinner.enable_steady_tick(Duration::from_millis(130));
+        spinner.set_style(
+            ProgressStyle::with_template("{spinner:.bold.dim} {msg}")
+                .unwrap()
+                .tick_strings(SPINNER),
+        );
+
+        loop {
+            spinner.set_message(format!(
+                "`{}`: {} imported, {} skipped",
+                svc.id, checkpoint.imported, checkpoint.skipped
+            ));
+
+            let page = instance
+                .list_rules(
+                    &mut store,
+                    &service_config,
+                    checkpoint.cursor.as_deref(),
+                    self.batch_size,
+                )
+                .await?;
+
+            let state_service = state.services.entry(svc.id.clone()).or_default();
+            for (name, content) in page.rules {
+                let content: Value = serde_json::from_str(&content)?;
+                // Rules deployed by lgc keep their local name
+                let name = naming.reverse(&name).unwrap_or(name);
+                if title.as_ref().is_some_and(|title| !title.is_match(&name))
+                    || !filters.iter().all(|filter| filter.matches(&content))
+                {
+                    checkpoint.skipped += 1;
+                    continue;
+                }
+                // A single unusable remote name does not stop the import
+                if let Err(e) = file_name(&name) {
+                    tracing::warn!("skipping rule on `{}`: {}", svc.id, e);
+                    checkpoint.skipped += 1;
+                    continue;
+                }
+
+                if write_rule(&svc.plugin, &name, &content, self.force)? {
+                    state_service.replace(DetectionState {
+                        name,
+                        content,
+                        common: None,
+                        metadata: None,
+                        remote: None,
+                        manage_fields: Vec::new(),
+                    });
+                    checkpoint.imported += 1;
+                } else {
+                    checkpoint.skipped += 1;
+                }
+            }
+
+            // Checkpoint once the page is fully written
+            state.save(&state_lock).await?;
+            checkpoint.cursor = page.next;
+            fs::write(&checkpoint_path, serde_json::to_string(&checkpoint)?)?;
+
+            if checkpoint.cursor.is_none() {
+                break;
+            }
+        }
+
+        spinner.finish_with_message(format!(
+            "`{}`: {} imported, {} skipped",
+            style(&svc.id).bold().green(),
+            checkpoint.imported,
+            checkpoint.skipped
+        ));
+        fs::remove_file(&checkpoint_path)?;
+
+        Ok(())
+    }
+}
+
+/// Write the rule in its own detection file, adding the plugin content to existing detections.
+/// Returns false if the detection already holds content for this plugin, unless forced.
+pub(super) fn write_rule(plugin: &str, name: &str, content: &Value, force: bool) -> Result<bool> {
+    let path = PathBuf::from(LGC_RULES_DIR).join(format!("{}.yaml", file_name(name)?));
+
+    let mut detection: serde_yaml_ng::Mapping = if path.is_file() {
+        serde_yaml_ng::from_str(&fs::read_to_string(&path)?)?
+    } else {
+        serde_yaml_ng::Mapping::new()
+    };
+
+    match detection.get("name").and_then(|name| name.as_str()) {
+        Some(existing) if existing != name => {
+            tracing::warn!(
+                "skipping `{}`: `{}` holds detection `{}`",
+                name,
+                workspace_path(&path),
+                existing
+            );
+            return Ok(false);
+        }
+        Some(_) => (),
+        None => {
+            detection.insert("name".into(), name.into());
+        }
+    }
+
+    let rules = detection
+        .entry("rules".into())
+        .or_insert_with(|| serde_yaml_ng::Mapping::new().into());
+    let rules = rules
+        .as_mapping_mut()
+        .ok_or_else(|| anyhow!("invalid rules in `{}`", workspace_path(&path)))?;
+
+    if rules.contains_key(plugin) && !force {
+        return Ok(false);
+    }
+    rules.insert(plugin.into(), serde_yaml_ng::to_value(content)?);
+
+    fs::write(&path, serde_yaml_ng::to_string(&detection)?)?;
+    Ok(true)
+}
+
+/// Kebab case file name derived from a rule name, which must hold alphanumeric characters.
+pub(super) fn file_name(name: &str) -> Result<String> {
+    let file_name = name
+        .to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    if file_name.is_empty() {
+        bail!(
+            "invalid rule name `{}`: must contain alphanumeric characters",
+            name
+        )
+    }
+    Ok(file_name)
+}
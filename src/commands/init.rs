@@ -1,17 +1,28 @@
 // Copyright (c) 2023 LogCraft, SAS.
 // SPDX-License-Identifier: MPL-2.0
 
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Result};
 use clap::Parser;
-use lgc_common::configuration::{ProjectConfiguration, LGC_CONFIG_PATH, LGC_RULES_DIR};
+use console::Term;
+use dialoguer::{theme::ColorfulTheme, Confirm, Input, Select};
+use lgc_common::{
+    configuration::{ProjectConfiguration, LGC_CONFIG_PATH, LGC_RULES_DIR},
+    state::{backends::StateBackend, LGC_DEFAULT_STATE_PATH},
+};
+use serde_json::{Map, Value};
 use std::{
-    env::current_dir,
+    env::{self, current_dir},
     fmt::Debug,
     fs::{self, File},
     path::{Path, PathBuf},
     str::FromStr,
 };
 
+use super::plugins::InstallPlugin;
+
+/// State backends offered by the wizard.
+const STATE_BACKENDS: [&str; 3] = ["local", "http", "s3"];
+
 /// Prepare working directory for other lgcli commands
 #[derive(Parser, Debug, Default)]
 #[clap(
@@ -22,15 +33,31 @@ pub struct InitCommand {
     /// Optional path for the project root
     #[clap(short, long, default_value = ".")]
     pub path: Option<String>,
+
+    /// Write the default configuration without prompting, as when not run from a terminal
+    #[clap(long)]
+    pub no_input: bool,
 }
 
 impl InitCommand {
-    /// Run the init command.
-    pub fn run(self) -> Result<()> {
-        let project_path = match self.path {
+    /// Run the init command, as a wizard on terminals.
+    pub async fn run(self) -> Result<()> {
+        // Prompt theme
+        let prompt_theme = ColorfulTheme::default();
+        let interactive = !self.no_input && Term::stdout().is_term();
+
+        let mut project_path = match self.path {
             Some(path) => PathBuf::from_str(&path)?,
             None => current_dir()?,
         };
+        if interactive {
+            project_path = PathBuf::from(
+                Input::<String>::with_theme(&prompt_theme)
+                    .with_prompt("Project directory:")
+                    .default(project_path.display().to_string())
+                    .interact_text()?,
+            );
+        }
 
         let rules_dir = &project_path.join(LGC_RULES_DIR);
         if Path::new(rules_dir).exists() {
@@ -54,12 +81,128 @@ impl InitCommand {
             )
         }
 
-        ProjectConfiguration::default().save_config(Some(full_path))?;
+        let mut config = ProjectConfiguration::default();
+        if interactive {
+            config.state = prompt_state_backend(&prompt_theme)?;
+        }
+        config.save_config(Some(full_path))?;
 
         tracing::info!(
             "LogCraft configuration initialized in `{}`",
             &project_path.canonicalize()?.display()
         );
+
+        if interactive {
+            let source = Input::<String>::with_theme(&prompt_theme)
+                .with_prompt("Plugin to install (leave empty to skip):")
+                .allow_empty(true)
+                .interact_text()?;
+            if !source.trim().is_empty() {
+                // Plugins are installed relative to the project root
+                env::set_current_dir(&project_path)?;
+                InstallPlugin {
+                    source: Some(source.trim().to_string()),
+                    ..Default::default()
+                }
+                .run(&mut config)
+                .await?;
+            }
+        }
+
         Ok(())
     }
 }
+
+/// Ask for the state backend and its parameters.
+fn prompt_state_backend(theme: &ColorfulTheme) -> Result<StateBackend> {
+    let selection = Select::with_theme(theme)
+        .with_prompt("State backend:")
+        .items(&STATE_BACKENDS)
+        .default(0)
+        .interact()?;
+
+    let mut backend = Map::new();
+    match STATE_BACKENDS[selection] {
+        "http" => {
+            backend.insert("type".into(), "Http".into());
+            backend.insert(
+                "address".into(),
+                prompt(theme, "State address:", None)?.into(),
+            );
+            for (field, question) in [
+                (
+                    "lock_address",
+                    "Lock address (leave empty to disable locking):",
+                ),
+                ("unlock_address", "Unlock address:"),
+                ("username", "Username (leave empty for none):"),
+            ] {
+                // Unlocking is only asked for when locking
+                if field == "unlock_address" && !backend.contains_key("lock_address") {
+                    continue;
+                }
+                if let Some(value) = prompt_optional(theme, question)? {
+                    backend.insert(field.into(), value.into());
+                }
+            }
+            if backend.contains_key("username") {
+                tracing::info!(
+                    "set the password with the `LGC_STATE_PASSWORD` environment variable rather than in `{}`",
+                    LGC_CONFIG_PATH
+                );
+            }
+        }
+        "s3" => {
+            backend.insert("type".into(), "S3".into());
+            backend.insert("bucket".into(), prompt(theme, "Bucket:", None)?.into());
+            backend.insert(
+                "key".into(),
+                prompt(theme, "State object key:", Some("lgc/state.json"))?.into(),
+            );
+            for (field, question) in [
+                ("region", "Region (leave empty for the default one):"),
+                (
+                    "endpoint",
+                    "Endpoint of S3 compatible stores (leave empty for AWS):",
+                ),
+            ] {
+                if let Some(value) = prompt_optional(theme, question)? {
+                    backend.insert(field.into(), value.into());
+                }
+            }
+            if Confirm::with_theme(theme)
+                .with_prompt("Lock the state with a lock object?")
+                .default(true)
+                .interact()?
+            {
+                backend.insert("use_lockfile".into(), true.into());
+            }
+        }
+        _ => {
+            backend.insert("type".into(), "Local".into());
+            backend.insert(
+                "path".into(),
+                prompt(theme, "State path:", Some(LGC_DEFAULT_STATE_PATH))?.into(),
+            );
+        }
+    }
+
+    serde_json::from_value(Value::Object(backend))
+        .map_err(|e| anyhow!("invalid state backend: {}", e))
+}
+
+fn prompt(theme: &ColorfulTheme, question: &str, default: Option<&str>) -> Result<String> {
+    let mut input = Input::<String>::with_theme(theme).with_prompt(question);
+    if let Some(default) = default {
+        input = input.default(default.to_string());
+    }
+    Ok(input.interact_text()?.trim().to_string())
+}
+
+fn prompt_optional(theme: &ColorfulTheme, question: &str) -> Result<Option<String>> {
+    let value: String = Input::with_theme(theme)
+        .with_prompt(question)
+        .allow_empty(true)
+        .interact_text()?;
+    Ok(Some(value.trim().to_string()).filter(|value| !value.is_empty()))
+}
@@ -0,0 +1,72 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::Result;
+use clap::Args;
+use lgc_common::{
+    configuration::{ProjectConfiguration, Service},
+    limits::Limits,
+};
+use std::time::Duration;
+
+/// Operational limits overrides.
+///
+/// Precedence: CLI flags > `core.limits` of lgc.yaml > built-in defaults.
+#[derive(Args, Debug, Default)]
+pub struct LimitsArgs {
+    /// Number of plugins loaded and deployed, and remote reads run per plugin, concurrently
+    #[clap(long)]
+    pub parallelism: Option<usize>,
+
+    /// Time allowed to each remote operation, overriding services timeout (e.g. `30s`)
+    #[clap(long)]
+    pub timeout: Option<String>,
+
+    /// Number of retries of operations failing with retryable errors
+    #[clap(long)]
+    pub retries: Option<usize>,
+
+    /// Delay before the first retry, doubled on each retry (e.g. `2s`)
+    #[clap(long)]
+    pub retry_backoff: Option<String>,
+
+    /// HTTP status codes to retry, `429` and `5xx` by default (e.g. `429,503`)
+    #[clap(long, value_delimiter = ',')]
+    pub retry_on: Option<Vec<u16>>,
+
+    /// Maximum number of remote operations per second on a service
+    #[clap(long)]
+    pub rate_limit: Option<u32>,
+
+    /// Number of rules requested per page by bulk listings
+    #[clap(long)]
+    pub list_page_size: Option<u32>,
+}
+
+impl LimitsArgs {
+    pub fn resolve(&self, config: &ProjectConfiguration) -> Limits {
+        config.core.limits.merge(&self.overrides())
+    }
+
+    /// Limits set by flags only, overriding project and services values.
+    pub fn overrides(&self) -> Limits {
+        Limits {
+            parallelism: self.parallelism,
+            timeout: self.timeout.clone(),
+            retries: self.retries,
+            retry_backoff: self.retry_backoff.clone(),
+            retry_on: self.retry_on.clone(),
+            rate_limit: self.rate_limit,
+            list_page_size: self.list_page_size,
+        }
+    }
+
+    /// Time allowed to each operation on `svc` within its `limits`, `--timeout` overriding the
+    /// service timeout.
+    pub fn timeout(&self, svc: &Service, limits: &Limits) -> Result<Duration> {
+        match &self.timeout {
+            Some(_) => limits.timeout(),
+            None => svc.timeout(limits.timeout()?),
+        }
+    }
+}
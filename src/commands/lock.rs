@@ -0,0 +1,69 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use clap::{ArgAction, Args};
+use console::style;
+use lgc_common::{
+    configuration::ProjectConfiguration,
+    state::backends::{LockMode, StateBackend},
+};
+use std::time::Duration;
+
+/// State locking overrides of commands using the state.
+#[derive(Args, Debug)]
+pub struct LockArgs {
+    /// Lock the state while changing it, `--lock=false` is only meant for servers without lock support
+    #[clap(long, action = ArgAction::Set, default_value_t = true)]
+    pub lock: bool,
+
+    /// Duration to retry acquiring a locked state (e.g. `10m`)
+    #[clap(long, value_parser = humantime::parse_duration)]
+    pub lock_timeout: Option<Duration>,
+}
+
+impl Default for LockArgs {
+    fn default() -> Self {
+        Self {
+            lock: true,
+            lock_timeout: None,
+        }
+    }
+}
+
+impl LockArgs {
+    /// State backend with the locking overrides applied.
+    pub fn backend(&self, config: &ProjectConfiguration) -> StateBackend {
+        let mut backend = config.state.clone();
+        if let Some(timeout) = self.lock_timeout {
+            backend.set_lock_timeout(timeout);
+        }
+        if !self.lock {
+            tracing::warn!(
+                "{}",
+                style(
+                    "state locking is disabled, concurrent runs may overwrite each other's changes"
+                )
+                .yellow()
+                .bold()
+            );
+            backend.set_lock_mode(LockMode::Disabled);
+        }
+        backend
+    }
+
+    /// State backend of read-only commands, `--lock=false` also skips looking up the lock holder.
+    pub fn shared(&self, config: &ProjectConfiguration) -> StateBackend {
+        let mut backend = shared_backend(config);
+        if !self.lock {
+            backend.set_lock_mode(LockMode::Disabled);
+        }
+        backend
+    }
+}
+
+/// State backend of read-only commands, which neither wait for nor take the state lock.
+pub fn shared_backend(config: &ProjectConfiguration) -> StateBackend {
+    let mut backend = config.state.clone();
+    backend.set_lock_mode(LockMode::Shared);
+    backend
+}
@@ -0,0 +1,169 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{anyhow, Result};
+use clap::{Args, ValueEnum};
+use opentelemetry::{trace::TracerProvider as _, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{runtime, trace::TracerProvider, Resource};
+use std::{env, fs::OpenOptions, path::PathBuf, sync::Mutex};
+use tracing::{level_filters::LevelFilter, Subscriber};
+use tracing_subscriber::{
+    filter::{filter_fn, FilterExt},
+    fmt::writer::BoxMakeWriter,
+    layer::{Filter, SubscriberExt},
+    util::SubscriberInitExt,
+    EnvFilter, Layer,
+};
+
+/// Environment variable holding per-module log filters (e.g. `lgc_common::plugins=debug`).
+pub const LGC_LOG_ENV: &str = "LGC_LOG";
+/// Environment variable holding the OTLP (gRPC) endpoint spans are exported to.
+pub const LGC_OTEL_ENDPOINT_ENV: &str = "LGC_OTEL_ENDPOINT";
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => LevelFilter::ERROR,
+            LogLevel::Warn => LevelFilter::WARN,
+            LogLevel::Info => LevelFilter::INFO,
+            LogLevel::Debug => LevelFilter::DEBUG,
+            LogLevel::Trace => LevelFilter::TRACE,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Messages for humans
+    #[default]
+    Pretty,
+    /// One JSON object per event, with its timestamp, level, module and fields
+    Json,
+}
+
+/// Logging options, shared by every command.
+#[derive(Args, Debug, Default)]
+pub struct LoggingArgs {
+    /// Level of logged events, modules filtered in `LGC_LOG` excepted
+    #[clap(long, global = true, value_enum, default_value_t)]
+    pub log_level: LogLevel,
+
+    /// Format of logged events
+    #[clap(long, global = true, value_enum, default_value_t)]
+    pub log_format: LogFormat,
+
+    /// Also append logged events to this file, as JSON
+    #[clap(long, global = true)]
+    pub log_file: Option<PathBuf>,
+}
+
+impl LoggingArgs {
+    /// Install the global logger, writing to stderr when stdout is kept for a JSON document.
+    ///
+    /// Spans of commands, plugin calls and state operations are exported when
+    /// `LGC_OTEL_ENDPOINT` is set, [`shutdown`] flushes them.
+    pub fn init(&self, json_output: bool) -> Result<()> {
+        let writer = || {
+            if json_output {
+                BoxMakeWriter::new(std::io::stderr)
+            } else {
+                BoxMakeWriter::new(std::io::stdout)
+            }
+        };
+        let console = match self.log_format {
+            LogFormat::Pretty => tracing_subscriber::fmt::layer()
+                .with_writer(writer())
+                .with_target(false)
+                .without_time()
+                .boxed(),
+            LogFormat::Json => tracing_subscriber::fmt::layer()
+                .json()
+                .with_writer(writer())
+                .boxed(),
+        };
+
+        let file = match &self.log_file {
+            Some(path) => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|e| anyhow!("unable to open log file `{}`: {}", path.display(), e))?;
+                Some(
+                    tracing_subscriber::fmt::layer()
+                        .json()
+                        .with_ansi(false)
+                        .with_writer(Mutex::new(file))
+                        .with_filter(self.events_filter()?),
+                )
+            }
+            None => None,
+        };
+
+        let otel = match env::var(LGC_OTEL_ENDPOINT_ENV)
+            .ok()
+            .filter(|endpoint| !endpoint.is_empty())
+        {
+            Some(endpoint) => {
+                let exporter = opentelemetry_otlp::SpanExporter::builder()
+                    .with_tonic()
+                    .with_endpoint(&endpoint)
+                    .build()
+                    .map_err(|e| anyhow!("invalid OTLP endpoint `{}`: {}", endpoint, e))?;
+                let provider = TracerProvider::builder()
+                    .with_batch_exporter(exporter, runtime::Tokio)
+                    .with_resource(Resource::new([
+                        KeyValue::new("service.name", "lgc"),
+                        KeyValue::new("service.version", env!("CARGO_PKG_VERSION")),
+                    ]))
+                    .build();
+                let tracer = provider.tracer("lgc");
+                opentelemetry::global::set_tracer_provider(provider);
+                Some(
+                    tracing_opentelemetry::layer()
+                        .with_tracer(tracer)
+                        .with_filter(self.filter()?),
+                )
+            }
+            None => None,
+        };
+
+        tracing_subscriber::registry()
+            .with(console.with_filter(self.events_filter()?))
+            .with(file)
+            .with(otel)
+            .init();
+
+        Ok(())
+    }
+
+    /// Modules filtered in `LGC_LOG` get their own level, others the `--log-level` one.
+    fn filter(&self) -> Result<EnvFilter> {
+        EnvFilter::builder()
+            .with_default_directive(LevelFilter::from(self.log_level).into())
+            .with_env_var(LGC_LOG_ENV)
+            .from_env()
+            .map_err(|e| anyhow!("invalid `{}` filters: {}", LGC_LOG_ENV, e))
+    }
+
+    /// Filter of logs, spans are only exported so that messages stay the same.
+    fn events_filter<S: Subscriber>(&self) -> Result<impl Filter<S> + Send + Sync + 'static> {
+        Ok(self.filter()?.and(filter_fn(|meta| meta.is_event())))
+    }
+}
+
+/// Export pending spans, before exiting.
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
@@ -0,0 +1,183 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{anyhow, bail, Result};
+use clap::{Parser, Subcommand};
+use console::style;
+use lgc_common::{
+    configuration::ProjectConfiguration,
+    state::{backends::LockInfo, MaintenanceWindow},
+};
+use std::time::SystemTime;
+
+use super::lock::shared_backend;
+
+/// Pause changes to services
+#[derive(Subcommand)]
+pub enum MaintenanceCommands {
+    /// Refuse changes to services until the given time
+    Enable(EnableMaintenance),
+
+    /// Clear maintenance windows
+    Disable(DisableMaintenance),
+
+    /// Show active maintenance windows
+    Status(MaintenanceStatus),
+}
+
+impl MaintenanceCommands {
+    pub async fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        match self {
+            Self::Enable(cmd) => cmd.run(config).await,
+            Self::Disable(cmd) => cmd.run(config).await,
+            Self::Status(cmd) => cmd.run(config).await,
+        }
+    }
+}
+
+#[derive(Parser)]
+pub struct EnableMaintenance {
+    /// End of the window, as a date (e.g. `2024-06-01T18:00:00Z`) or a duration (e.g. `2h`)
+    #[clap(long)]
+    pub until: String,
+
+    /// Reason of the maintenance
+    #[clap(long)]
+    pub reason: String,
+
+    /// Affected services, all services if not set
+    #[clap(short, long = "service-id")]
+    pub service_ids: Vec<String>,
+}
+
+impl EnableMaintenance {
+    pub async fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        for id in &self.service_ids {
+            if !config.services.iter().any(|svc| &svc.id == id) {
+                bail!("service `{}` not found", id)
+            }
+        }
+
+        let until = match humantime::parse_rfc3339_weak(&self.until) {
+            Ok(until) => until,
+            Err(_) => {
+                SystemTime::now()
+                    + humantime::parse_duration(&self.until).map_err(|_| {
+                        anyhow!(
+                            "invalid time `{}`, expected a date or a duration",
+                            self.until
+                        )
+                    })?
+            }
+        };
+        if until <= SystemTime::now() {
+            bail!("maintenance end `{}` is in the past", self.until)
+        }
+
+        let window = MaintenanceWindow {
+            services: self.service_ids,
+            until: humantime::format_rfc3339_seconds(until).to_string(),
+            reason: self.reason,
+            who: LockInfo::current_user(),
+        };
+
+        let state_lock = config.state.lock().await?;
+        let mut state = config.state.load().await?;
+        state.maintenance.retain(|window| window.is_active());
+        tracing::info!("maintenance enabled until {}", window.until);
+        state.maintenance.push(window);
+        state.save(&state_lock).await
+    }
+}
+
+#[derive(Parser)]
+pub struct DisableMaintenance {
+    /// Only release this service from the windows listing it, windows of all services are kept
+    #[clap(short, long)]
+    pub service_id: Option<String>,
+}
+
+impl DisableMaintenance {
+    pub async fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        let state_lock = config.state.lock().await?;
+        let mut state = config.state.load().await?;
+
+        let Some(id) = &self.service_id else {
+            let count = state.maintenance.len();
+            if count == 0 {
+                bail!("no maintenance window to clear")
+            }
+            state.maintenance.clear();
+            tracing::info!("{} maintenance window(s) cleared", count);
+            return state.save(&state_lock).await;
+        };
+
+        let mut cleared = 0;
+        state.maintenance.retain_mut(|window| {
+            if !window.services.contains(id) {
+                return window.is_active();
+            }
+            cleared += 1;
+            window.services.retain(|svc| svc != id);
+            // A window left without services is over, rather than affecting all services
+            !window.services.is_empty() && window.is_active()
+        });
+
+        if cleared == 0 {
+            if state
+                .maintenance
+                .iter()
+                .any(|window| window.is_active() && window.applies_to(id))
+            {
+                bail!(
+                    "service `{}` is only under maintenance windows of all services, run `lgc maintenance disable` without `--service-id` to clear them",
+                    id
+                )
+            }
+            bail!("no maintenance window to clear for service `{}`", id)
+        }
+
+        tracing::info!(
+            "service `{}` released from {} maintenance window(s)",
+            id,
+            cleared
+        );
+        state.save(&state_lock).await
+    }
+}
+
+#[derive(Parser)]
+pub struct MaintenanceStatus;
+
+impl MaintenanceStatus {
+    pub async fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        let state = shared_backend(config).load().await?;
+        let windows: Vec<_> = state
+            .maintenance
+            .iter()
+            .filter(|window| window.is_active())
+            .collect();
+
+        if windows.is_empty() {
+            tracing::info!("no active maintenance window");
+            return Ok(());
+        }
+
+        for window in windows {
+            let services = if window.services.is_empty() {
+                String::from("all services")
+            } else {
+                window.services.join(", ")
+            };
+            println!(
+                "- until `{}` on {}: {} (by `{}`)",
+                style(&window.until).bold(),
+                services,
+                window.reason,
+                window.who
+            );
+        }
+
+        Ok(())
+    }
+}
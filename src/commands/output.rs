@@ -0,0 +1,39 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::Result;
+use clap::{Args, ValueEnum};
+use serde::Serialize;
+
+/// Format of command results.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Output format selection.
+///
+/// In JSON mode, stdout only receives one JSON document and logs go to stderr.
+#[derive(Args, Debug, Default)]
+pub struct OutputArgs {
+    /// Output format of the results
+    #[clap(short, long, value_enum, default_value_t)]
+    pub output: OutputFormat,
+}
+
+impl OutputArgs {
+    pub fn is_json(&self) -> bool {
+        self.output == OutputFormat::Json
+    }
+
+    /// Print `value` on stdout in JSON mode, text output is printed along the way.
+    pub fn emit<T: Serialize>(&self, value: &T) -> Result<()> {
+        if self.is_json() {
+            println!("{}", serde_json::to_string_pretty(value)?);
+        }
+
+        Ok(())
+    }
+}
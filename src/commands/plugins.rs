@@ -6,14 +6,20 @@ use clap::{Parser, Subcommand};
 use console::style;
 use dialoguer::{theme::ColorfulTheme, Confirm, Input, Select};
 use lgc_common::{
-    configuration::ProjectConfiguration,
+    configuration::{ensure_workspace_dirs, schema_fields, ProjectConfiguration, SchemaField},
     plugins::{
         cleanup_plugin, determine_plugin_location,
         manager::{PluginActions, PluginManager},
-        Plugin, PluginLocation, LGC_PLUGINS_PATH,
+        trust::PluginTrust,
+        InterfaceCompatibility, Plugin, PluginLocation, PluginTransport, LGC_PLUGINS_PATH,
+        PLUGIN_INTERFACE_VERSION,
     },
+    report::print_table,
 };
-use std::path::PathBuf;
+use lgc_runtime::{cache::digest, component_imports};
+use std::{fs, path::PathBuf, time::Instant};
+
+use super::scaffold::ScaffoldPlugin;
 
 /// Manage plugins
 #[derive(Subcommand)]
@@ -33,6 +39,15 @@ pub enum PluginsCommands {
 
     /// Get plugin configuration schema
     Schema(PluginSchema),
+
+    /// Show everything known about an installed plugin
+    Info(PluginInfo),
+
+    /// Check that installed plugins load and are compatible with this lgc
+    Verify(VerifyPlugins),
+
+    /// Generate a new plugin crate
+    Scaffold(ScaffoldPlugin),
 }
 
 impl PluginsCommands {
@@ -40,17 +55,32 @@ impl PluginsCommands {
         match self {
             Self::Install(cmd) => cmd.run(config).await,
             Self::Schema(cmd) => cmd.run(config).await,
+            Self::Info(cmd) => cmd.run(config).await,
+            Self::Verify(cmd) => cmd.run(config).await,
             Self::List(cmd) => cmd.run(config),
             Self::Uninstall(cmd) => cmd.run(config).await,
             Self::Update(cmd) => cmd.run(config).await,
+            Self::Scaffold(cmd) => cmd.run(),
         }
     }
 }
 
-#[derive(Parser)]
+#[derive(Parser, Default)]
 pub struct InstallPlugin {
     /// Location of the plugin
     pub source: Option<String>,
+
+    /// Minisign signature of the plugin, `<source>.minisig` by default
+    #[clap(long)]
+    pub signature: Option<PathBuf>,
+
+    /// Install the plugin without verifying its signature against `core.plugin_trust`
+    #[clap(long)]
+    pub insecure_skip_verify: bool,
+
+    /// Run the plugin as a native executable over gRPC, outside of the wasm sandbox
+    #[clap(long)]
+    pub grpc: bool,
     // /// Version of plugin to fetch
     // #[clap(default_value = "latest")]
     // pub version: String,
@@ -72,8 +102,24 @@ impl InstallPlugin {
         // Determine the plugin location
         let location = determine_plugin_location(&source)?;
 
+        let trust = if self.insecure_skip_verify {
+            if !config.core.plugin_trust.is_empty() {
+                tracing::warn!("plugin signature is not verified, install only trusted plugins");
+            }
+            PluginTrust::default()
+        } else {
+            config.core.plugin_trust.clone()
+        };
+
         // Retrieve plugin informations
-        let meta = PluginManager::new()?.install_plugin(&location).await?;
+        let transport = if self.grpc {
+            PluginTransport::Grpc
+        } else {
+            PluginTransport::Wasm
+        };
+        let meta = PluginManager::new()?
+            .install_plugin(&location, &trust, self.signature.as_deref(), transport)
+            .await?;
 
         let source = match location {
             PluginLocation::Local(_) => {
@@ -82,6 +128,8 @@ impl InstallPlugin {
               // PluginLocation::Oci(image) => image,
         };
 
+        // Keep capabilities, sandbox, naming and diff settings of a previously installed version
+        let previous = config.plugins.remove(&meta.name).unwrap_or_default();
         config.plugins.insert(
             meta.name,
             Plugin {
@@ -89,9 +137,19 @@ impl InstallPlugin {
                 version: meta.version,
                 description: meta.description,
                 author: meta.author,
+                transport,
+                filesystem: previous.filesystem,
+                naming: previous.naming,
+                diff: previous.diff,
+                sandbox: previous.sandbox,
             },
         );
 
+        // Detections can be written right after install
+        for dir in ensure_workspace_dirs()? {
+            tracing::info!("created `{}` directory", dir);
+        }
+
         config.save_config(None)
     }
 }
@@ -214,17 +272,281 @@ impl PluginSchema {
             }
         };
 
-        // Load plugin
-        let (instance, mut store) = PluginManager::new()?.load_plugin(&name).await?;
-
         // Retrieve schema
-        let schema = instance.schema(&mut store).await?;
+        let schema = PluginManager::new()?
+            .with_capabilities(&config.plugins)
+            .plugin_schemas(&name)
+            .await?
+            .rule;
 
         println!("{schema}");
         Ok(())
     }
 }
 
+#[derive(Parser)]
+pub struct VerifyPlugins {
+    /// Only verify this plugin
+    pub name: Option<String>,
+}
+
+impl VerifyPlugins {
+    pub async fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        if let Some(name) = &self.name {
+            if !config.plugins.contains_key(name) {
+                bail!("plugin `{}` does not exists", name)
+            }
+        }
+
+        let plugin_manager = PluginManager::new()?.with_capabilities(&config.plugins);
+        let mut rows = Vec::new();
+        let mut errors = Vec::new();
+        for name in config.plugins.keys() {
+            if self.name.as_ref().is_some_and(|only| only != name) {
+                continue;
+            }
+
+            let (row, plugin_errors) = verify_plugin(&plugin_manager, name).await;
+            if !plugin_errors.is_empty() {
+                errors.push((name, plugin_errors));
+            }
+            let [version, interface, settings, schema] = row;
+            rows.push([name.clone(), version, interface, settings, schema]);
+        }
+
+        print_table(
+            ["PLUGIN", "VERSION", "INTERFACE", "SETTINGS", "SCHEMA"],
+            &rows,
+        );
+
+        for (name, plugin_errors) in &errors {
+            for error in plugin_errors {
+                println!("[!] plugin: `{}` {}", style(name).red(), error);
+            }
+        }
+
+        if !errors.is_empty() {
+            bail!(
+                "{} plugins cannot be used with lgc {} (plugin interface `{}`)",
+                errors.len(),
+                env!("CARGO_PKG_VERSION"),
+                PLUGIN_INTERFACE_VERSION
+            )
+        }
+
+        tracing::info!("all good, installed plugins are compatible");
+        Ok(())
+    }
+}
+
+/// Version, interface compatibility, settings and rule schema status of an installed plugin,
+/// with the errors of failed checks.
+async fn verify_plugin(plugin_manager: &PluginManager, name: &str) -> ([String; 4], Vec<String>) {
+    let skipped = || String::from("-");
+    // The interface version is checked before calling anything but `load`
+    let (instance, mut store) = match plugin_manager.load_unchecked(name).await {
+        Ok(plugin) => plugin,
+        Err(e) => {
+            return (
+                [String::from("error"), skipped(), skipped(), skipped()],
+                vec![format!("load: {}", e)],
+            );
+        }
+    };
+
+    let mut errors = Vec::new();
+    let metadata = &instance.metadata;
+    let interface = match InterfaceCompatibility::of(&metadata.interface_version) {
+        InterfaceCompatibility::Incompatible => {
+            return (
+                [
+                    metadata.version.clone(),
+                    format!("{} (incompatible)", metadata.interface_version),
+                    skipped(),
+                    skipped(),
+                ],
+                vec![format!(
+                    "interface: `{}` is not implemented by lgc, which implements `{}`",
+                    metadata.interface_version, PLUGIN_INTERFACE_VERSION
+                )],
+            );
+        }
+        compatibility => format!("{} ({})", metadata.interface_version, compatibility),
+    };
+
+    // Schemas are KCL code, checked for the schema lgc reads in them
+    let settings = match instance
+        .settings(&mut store)
+        .await
+        .and_then(|code| schema_fields(&code, "Configuration"))
+    {
+        Ok(fields) => format!("ok ({} fields)", fields.len()),
+        Err(e) => {
+            errors.push(format!("settings: {}", e));
+            String::from("error")
+        }
+    };
+    let schema = match instance
+        .schema(&mut store)
+        .await
+        .and_then(|code| schema_fields(&code, "Rule"))
+    {
+        Ok(fields) if !fields.is_empty() => format!("ok ({} fields)", fields.len()),
+        Ok(_) => {
+            errors.push(String::from("schema: no `Rule` schema"));
+            String::from("error")
+        }
+        Err(e) => {
+            errors.push(format!("schema: {}", e));
+            String::from("error")
+        }
+    };
+
+    (
+        [metadata.version.clone(), interface, settings, schema],
+        errors,
+    )
+}
+
+#[derive(Parser)]
+pub struct PluginInfo {
+    /// Name of the plugin.
+    pub name: String,
+}
+
+impl PluginInfo {
+    pub async fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        let plugin = config
+            .plugins
+            .get(&self.name)
+            .ok_or_else(|| anyhow!("plugin `{}` does not exists", &self.name))?;
+
+        let path = PathBuf::from(LGC_PLUGINS_PATH).join(&self.name);
+        let bytes = fs::read(&path)
+            .map_err(|e| anyhow!("unable to read plugin `{}`: {}", path.display(), e))?;
+
+        // Loading compiles the component if it is not cached yet
+        let started = Instant::now();
+        let (instance, mut store) = PluginManager::new()?
+            .with_capabilities(&config.plugins)
+            .load_plugin(&self.name)
+            .await?;
+        let load_time = started.elapsed();
+
+        let metadata = &instance.metadata;
+        println!("{} `{}`", style(&metadata.name).bold(), metadata.version);
+        if !metadata.description.is_empty() {
+            println!("{}", metadata.description);
+        }
+        println!("author: {}", metadata.author);
+        println!("interface: {}", metadata.interface_version);
+        println!(
+            "capabilities: {}",
+            if metadata.capabilities.is_empty() {
+                "none".to_string()
+            } else {
+                metadata.capabilities.join(", ")
+            }
+        );
+        match &plugin.source {
+            PluginLocation::Local(source) => println!("source: {}", source.display()),
+        }
+        println!("digest: sha256:{}", digest(&bytes));
+        println!("size: {} KiB", bytes.len() / 1024);
+        println!("load time: {:.2?}", load_time);
+        if metadata.version != plugin.version {
+            tracing::warn!(
+                "installed binary reports version `{}`, configuration records `{}`",
+                metadata.version,
+                plugin.version
+            );
+        }
+
+        println!("\n{}", style("Settings:").bold());
+        print_fields(&schema_fields(
+            &instance.settings(&mut store).await?,
+            "Configuration",
+        )?);
+
+        println!("\n{}", style("Detection fields:").bold());
+        print_fields(&schema_fields(&instance.schema(&mut store).await?, "Rule")?);
+
+        println!("\n{}", style("Capabilities:").bold());
+        match plugin.transport {
+            PluginTransport::Wasm => {
+                println!("- transport: wasm");
+                for import in component_imports(&bytes)? {
+                    println!("- imports `{}`", import);
+                }
+            }
+            PluginTransport::Grpc => println!("- transport: grpc (native process, not sandboxed)"),
+        }
+        for grant in &plugin.filesystem {
+            println!(
+                "- filesystem `{}` ({})",
+                grant.path.display(),
+                if grant.writable {
+                    "read-write"
+                } else {
+                    "read-only"
+                }
+            );
+        }
+        let sandbox = &plugin.sandbox;
+        if let Some(max_memory) = sandbox.max_memory_mb {
+            println!("- memory up to {} MiB", max_memory);
+        }
+        if let Some(timeout) = &sandbox.call_timeout {
+            println!("- calls up to {}", timeout);
+        }
+        match &sandbox.allowed_hosts {
+            Some(hosts) => println!("- requests to {}", hosts.join(", ")),
+            None => println!("- requests to any host"),
+        }
+
+        let services: Vec<_> = config
+            .services
+            .iter()
+            .filter(|svc| svc.plugin == self.name)
+            .map(|svc| svc.id.as_str())
+            .collect();
+        println!("\n{}", style("Services:").bold());
+        if services.is_empty() {
+            println!("- none");
+        }
+        for svc in services {
+            println!("- `{}`", svc);
+        }
+
+        Ok(())
+    }
+}
+
+fn print_fields(fields: &[SchemaField]) {
+    if fields.is_empty() {
+        println!("- none");
+    }
+
+    for field in fields {
+        let mut flags = Vec::new();
+        if field.required {
+            flags.push("required");
+        }
+        if field.sensitive {
+            flags.push("sensitive");
+        }
+
+        print!("- `{}`: {}", style(&field.name).bold(), field.ty);
+        if !flags.is_empty() {
+            print!(" ({})", flags.join(", "));
+        }
+        match &field.doc {
+            Some(doc) => println!(" - {}", style(doc).dim()),
+            None => println!(),
+        }
+    }
+}
+
 #[derive(Parser)]
 pub struct UpdatePlugin {
     /// Name of the plugin.
@@ -0,0 +1,195 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{bail, Result};
+use clap::{Parser, Subcommand};
+use console::style;
+use dialoguer::{theme::ColorfulTheme, Input, Select};
+use lgc_common::{
+    configuration::ProjectConfiguration,
+    policies::{cleanup_policy_pack, policy_pack_name, PolicyPack},
+    utils::ensure_kebab_case,
+};
+
+/// Manage policy packs
+#[derive(Subcommand)]
+pub enum PoliciesCommands {
+    /// Install a policy pack from a git or OCI repository
+    #[clap(alias = "i")]
+    Install(InstallPolicies),
+
+    /// List installed policy packs
+    List(ListPolicies),
+
+    /// Remove a policy pack
+    Uninstall(UninstallPolicies),
+
+    /// Refresh installed policy packs from their source
+    Update(UpdatePolicies),
+}
+
+impl PoliciesCommands {
+    pub async fn run(self, config: &mut ProjectConfiguration) -> Result<()> {
+        match self {
+            Self::Install(cmd) => cmd.run(config),
+            Self::List(cmd) => cmd.run(config),
+            Self::Uninstall(cmd) => cmd.run(config),
+            Self::Update(cmd) => cmd.run(config),
+        }
+    }
+}
+
+#[derive(Parser)]
+pub struct InstallPolicies {
+    /// Git repository of the policy pack, or OCI repository prefixed with `oci://`
+    pub source: Option<String>,
+
+    /// Branch or tag to install, tag or digest of OCI policy packs
+    #[clap(short, long)]
+    pub reference: Option<String>,
+
+    /// Name of the policy pack, derived from source if not set
+    #[clap(short, long)]
+    pub name: Option<String>,
+}
+
+impl InstallPolicies {
+    pub fn run(self, config: &mut ProjectConfiguration) -> Result<()> {
+        // Prompt theme
+        let prompt_theme = ColorfulTheme::default();
+
+        // Prompt source if not set
+        let source = match self.source {
+            Some(source) => source,
+            None => Input::<String>::with_theme(&prompt_theme)
+                .with_prompt("Policy pack source:")
+                .interact_text()?,
+        };
+
+        let name = self.name.unwrap_or_else(|| policy_pack_name(&source));
+        let name = ensure_kebab_case(&name)?;
+
+        if config.policies.contains_key(name) {
+            bail!(
+                "policy pack `{}` already installed, use `lgc policies update {}` instead",
+                name,
+                name
+            )
+        }
+
+        let mut pack = PolicyPack {
+            source,
+            reference: self.reference,
+            ..Default::default()
+        };
+        pack.install(name)?;
+
+        tracing::info!(
+            "policy pack `{}` installed at revision `{}`",
+            name,
+            pack.short_revision()
+        );
+        config.policies.insert(name.to_string(), pack);
+        config.save_config(None)
+    }
+}
+
+#[derive(Parser)]
+pub struct ListPolicies;
+
+impl ListPolicies {
+    pub fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        if config.policies.is_empty() {
+            bail!("no policy pack installed");
+        }
+
+        for (name, pack) in &config.policies {
+            println!(
+                "- `{}` (`{}`) from {}",
+                style(name).bold(),
+                style(pack.short_revision()).bold(),
+                pack.source
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Parser)]
+pub struct UninstallPolicies {
+    /// Name of the policy pack
+    pub name: Option<String>,
+}
+
+impl UninstallPolicies {
+    pub fn run(self, config: &mut ProjectConfiguration) -> Result<()> {
+        if config.policies.is_empty() {
+            bail!("no policy pack installed")
+        }
+
+        // Prompt theme
+        let prompt_theme = ColorfulTheme::default();
+
+        let name = match self.name {
+            Some(name) => name,
+            None => {
+                let packs = config.policies.keys().cloned().collect::<Vec<_>>();
+                let selection = Select::with_theme(&prompt_theme)
+                    .with_prompt("Select the policy pack to uninstall:")
+                    .items(&packs)
+                    .default(0)
+                    .interact()?;
+                packs[selection].clone()
+            }
+        };
+
+        if config.policies.remove(&name).is_none() {
+            bail!("policy pack `{}` does not exists", &name)
+        };
+
+        cleanup_policy_pack(&name)?;
+        config.save_config(None)
+    }
+}
+
+#[derive(Parser)]
+pub struct UpdatePolicies {
+    /// Name of the policy pack, all packs are updated if not set
+    pub name: Option<String>,
+}
+
+impl UpdatePolicies {
+    pub fn run(self, config: &mut ProjectConfiguration) -> Result<()> {
+        if config.policies.is_empty() {
+            bail!("no policy pack installed")
+        }
+
+        if let Some(name) = &self.name {
+            if !config.policies.contains_key(name) {
+                bail!("policy pack `{}` does not exists", name)
+            }
+        }
+
+        for (name, pack) in config
+            .policies
+            .iter_mut()
+            .filter(|(name, _)| self.name.is_none() || self.name.as_ref() == Some(*name))
+        {
+            let previous = pack.revision.clone();
+            pack.install(name)?;
+
+            if previous == pack.revision {
+                tracing::info!("policy pack `{}` is up to date", name);
+            } else {
+                tracing::info!(
+                    "policy pack `{}` updated to revision `{}`",
+                    name,
+                    pack.short_revision()
+                );
+            }
+        }
+
+        config.save_config(None)
+    }
+}
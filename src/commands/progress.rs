@@ -0,0 +1,153 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use clap::Args;
+use console::Term;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use lgc_common::report::Action;
+use std::time::Duration;
+
+use super::services::SPINNER;
+
+/// Progress display selection.
+#[derive(Args, Debug, Default)]
+pub struct ProgressArgs {
+    /// Show progress bars per service instead of one line per rule, on terminals only
+    #[clap(long)]
+    pub progress: bool,
+}
+
+impl ProgressArgs {
+    /// Progress display of a run, `quiet` runs printing nothing along the way.
+    pub fn start(&self, quiet: bool) -> Progress {
+        Progress {
+            bars: (self.progress && !quiet && Term::stdout().is_term()).then(MultiProgress::new),
+            quiet,
+        }
+    }
+}
+
+/// Progress of a run: bars per service on terminals, plain lines otherwise.
+#[derive(Clone)]
+pub struct Progress {
+    bars: Option<MultiProgress>,
+    quiet: bool,
+}
+
+impl Progress {
+    /// Spinner shown while rules of a service are read, hidden with plain output.
+    pub fn reading(&self, service_id: &str, count: usize) -> ProgressBar {
+        let Some(bars) = &self.bars else {
+            return ProgressBar::hidden();
+        };
+
+        let spinner = bars.add(ProgressBar::new_spinner());
+        spinner.enable_steady_tick(Duration::from_millis(130));
+        spinner.set_style(
+            ProgressStyle::with_template("{spinner:.bold.dim} {msg} ({elapsed})")
+                .unwrap()
+                .tick_strings(SPINNER),
+        );
+        spinner.set_message(format!("reading {} rule(s) of `{}`", count, service_id));
+        spinner
+    }
+
+    /// Progress of the `total` operations run on a service.
+    pub fn service(&self, service_id: &str, total: usize) -> ServiceProgress {
+        let bar = self.bars.as_ref().map(|bars| {
+            let bar = bars.add(ProgressBar::new(total as u64));
+            bar.set_style(
+                ProgressStyle::with_template(
+                    "{prefix:.bold} [{bar:30}] {pos}/{len} {msg} ({elapsed})",
+                )
+                .unwrap()
+                .progress_chars("=> "),
+            );
+            bar.set_prefix(service_id.to_string());
+            bar
+        });
+
+        let progress = ServiceProgress {
+            bar,
+            quiet: self.quiet,
+            total,
+            counts: Default::default(),
+        };
+        progress.refresh();
+        progress
+    }
+}
+
+/// Progress of the operations run on a service.
+pub struct ServiceProgress {
+    bar: Option<ProgressBar>,
+    quiet: bool,
+    total: usize,
+    /// Created, updated, deleted, failed and skipped operations
+    counts: [usize; 5],
+}
+
+impl ServiceProgress {
+    /// Count a performed operation. `line` describes it with plain output, and is printed above
+    /// the bars for failures.
+    pub fn record(&mut self, action: Action, success: bool, line: &str) {
+        let index = match (success, action) {
+            (false, _) => 3,
+            (true, Action::Create) => 0,
+            (true, Action::Update) => 1,
+            (true, _) => 2,
+        };
+        self.counts[index] += 1;
+
+        match &self.bar {
+            Some(bar) => {
+                if !success {
+                    bar.println(line);
+                }
+                bar.inc(1);
+                self.refresh();
+            }
+            None if !self.quiet => println!("{}", line),
+            None => (),
+        }
+    }
+
+    /// Count an operation which was not attempted.
+    pub fn skip(&mut self) {
+        self.counts[4] += 1;
+        if let Some(bar) = &self.bar {
+            bar.inc(1);
+            self.refresh();
+        }
+    }
+
+    /// Leave the bar of the service with its final counts.
+    pub fn finish(&self) {
+        if let Some(bar) = &self.bar {
+            bar.finish_with_message(self.summary());
+        }
+    }
+
+    fn refresh(&self) {
+        if let Some(bar) = &self.bar {
+            bar.set_message(self.summary());
+        }
+    }
+
+    fn summary(&self) -> String {
+        let [created, updated, deleted, failed, skipped] = self.counts;
+        // Operations counted beyond the planned ones (e.g. retried) leave none pending
+        let pending = self.total.saturating_sub(self.counts.iter().sum::<usize>());
+        let mut summary = format!(
+            "pending {}, created {}, updated {}, deleted {}",
+            pending, created, updated, deleted
+        );
+        if failed > 0 {
+            summary.push_str(&format!(", failed {}", failed));
+        }
+        if skipped > 0 {
+            summary.push_str(&format!(", skipped {}", skipped));
+        }
+        summary
+    }
+}
@@ -0,0 +1,105 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{anyhow, bail, Result};
+use clap::{Parser, Subcommand};
+use lgc_common::{
+    configuration::{ProjectConfiguration, Service},
+    plugins::manager::{PluginActions, PluginManager},
+};
+use serde_json::Value;
+use std::collections::HashSet;
+
+/// Browse rules deployed on remote services, read-only
+#[derive(Subcommand)]
+pub enum RemoteCommands {
+    /// List rules deployed on a service
+    Ls(ListRemote),
+
+    /// Show a rule deployed on a service
+    Cat(ShowRemote),
+}
+
+impl RemoteCommands {
+    pub async fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        match self {
+            Self::Ls(cmd) => cmd.run(config).await,
+            Self::Cat(cmd) => cmd.run(config).await,
+        }
+    }
+}
+
+#[derive(Parser)]
+pub struct ListRemote {
+    /// List rules of this service
+    pub service_id: String,
+}
+
+impl ListRemote {
+    pub async fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        let svc = find_service(config, &self.service_id)?;
+        let (instance, mut store) = PluginManager::new()?
+            .with_capabilities(&config.plugins)
+            .load_plugin(&svc.plugin)
+            .await?;
+
+        let rules = instance
+            .list_all_rules(
+                &mut store,
+                &svc.config().await?,
+                &HashSet::new(),
+                config.core.limits.list_page_size()?,
+            )
+            .await?
+            .names;
+        if rules.is_empty() {
+            tracing::info!("no rules found on `{}`", svc.id);
+        }
+        for name in &rules {
+            println!("{}", name);
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Parser)]
+pub struct ShowRemote {
+    /// Read the rule from this service
+    pub service_id: String,
+
+    /// Name of the rule on the service, as listed by `lgc remote ls`
+    pub rule: String,
+}
+
+impl ShowRemote {
+    pub async fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        let svc = find_service(config, &self.service_id)?;
+        let (instance, mut store) = PluginManager::new()?
+            .with_capabilities(&config.plugins)
+            .load_plugin(&svc.plugin)
+            .await?;
+
+        let Some(content) = instance
+            .read_remote(&mut store, &svc.config().await?, &self.rule, "{}")
+            .await?
+        else {
+            bail!("rule `{}` not found on `{}`", self.rule, svc.id)
+        };
+
+        let content: Value = serde_json::from_str(&content)?;
+        println!("{}", serde_json::to_string_pretty(&content)?);
+
+        Ok(())
+    }
+}
+
+fn find_service<'a>(config: &'a ProjectConfiguration, id: &str) -> Result<&'a Service> {
+    config
+        .services
+        .get(&Service {
+            id: id.to_string(),
+            ..Default::default()
+        })
+        .ok_or_else(|| anyhow!("service `{}` not found", id))
+}
@@ -0,0 +1,608 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{anyhow, bail, Result};
+use clap::{Parser, Subcommand};
+use console::style;
+use lgc_common::{
+    configuration::{ProjectConfiguration, LGC_RULES_DIR},
+    detections::{map_plugin_detections, DetectionFormat, ServiceDetections},
+    state::State,
+    utils::{git, workspace_path},
+    variables::Variables,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use super::lock::shared_backend;
+
+/// Generate reports
+#[derive(Subcommand)]
+pub enum ReportCommands {
+    /// Show deployed rules count against services quotas
+    Quota(QuotaReport),
+
+    /// Show which commits introduced each pending change
+    Changes(ChangesReport),
+
+    /// Summarize detection content of the workspace
+    Workspace(WorkspaceReport),
+
+    /// Show rules changed in state between two points, as change evidence
+    StateDiff(StateDiffReport),
+}
+
+impl ReportCommands {
+    pub async fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        match self {
+            Self::Quota(cmd) => cmd.run(config).await,
+            Self::Changes(cmd) => cmd.run(config).await,
+            Self::Workspace(cmd) => cmd.run(config),
+            Self::StateDiff(cmd) => cmd.run(config).await,
+        }
+    }
+}
+
+#[derive(Parser)]
+pub struct QuotaReport {
+    /// ID of the service
+    #[clap(short, long)]
+    pub service_id: Option<String>,
+}
+
+impl QuotaReport {
+    pub async fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        if config.services.is_empty() {
+            bail!("no services defined")
+        }
+
+        if let Some(id) = &self.service_id {
+            if !config.services.iter().any(|svc| &svc.id == id) {
+                bail!("service `{}` not found", id)
+            }
+        }
+
+        let state = shared_backend(config).load().await?;
+        let retrieved = ServiceDetections::new();
+
+        for svc in config
+            .services
+            .iter()
+            .filter(|svc| self.service_id.is_none() || self.service_id.as_ref() == Some(&svc.id))
+        {
+            let count = state.projected_rules(&svc.id, &retrieved, 0, 0);
+            match &svc.quota {
+                Some(quota) => {
+                    let usage = format!("{}/{}", count, quota.max_rules);
+                    let usage = if count > quota.max_rules {
+                        style(usage).red()
+                    } else if count * 10 >= quota.max_rules * 9 {
+                        style(usage).yellow()
+                    } else {
+                        style(usage).green()
+                    };
+                    println!(
+                        "- `{}` (`{}`) {} rules",
+                        style(&svc.id).bold(),
+                        style(&svc.plugin).bold(),
+                        usage
+                    );
+                }
+                None => println!(
+                    "- `{}` (`{}`) {} rules (no quota)",
+                    style(&svc.id).bold(),
+                    style(&svc.plugin).bold(),
+                    count
+                ),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Parser)]
+pub struct StateDiffReport {
+    /// Backup serial or state file to compare from
+    #[clap(long)]
+    pub from: String,
+
+    /// Backup serial, state file or `current`
+    #[clap(long, default_value = "current")]
+    pub to: String,
+
+    /// Write the diff as JSON to this file
+    #[clap(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+impl StateDiffReport {
+    pub async fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        let from = load_state(config, &self.from).await?;
+        let to = load_state(config, &self.to).await?;
+        let diff = from.diff(&to);
+
+        println!(
+            "state changes from serial `{}` to `{}`:",
+            style(diff.from).bold(),
+            style(diff.to).bold()
+        );
+        for (service_id, changes) in &diff.services {
+            for name in &changes.added {
+                println!(
+                    "[+] rule: `{}` added to `{}`",
+                    style(name).green(),
+                    service_id
+                );
+            }
+            for name in &changes.modified {
+                println!(
+                    "[~] rule: `{}` modified on `{}`",
+                    style(name).yellow(),
+                    service_id
+                );
+            }
+            for name in &changes.removed {
+                println!(
+                    "[-] rule: `{}` removed from `{}`",
+                    style(name).red(),
+                    service_id
+                );
+            }
+        }
+        if diff.services.is_empty() {
+            tracing::info!("no differences found");
+        }
+
+        if let Some(output) = &self.output {
+            fs::write(output, serde_json::to_string_pretty(&diff)?)
+                .map_err(|e| anyhow!("unable to write `{}`: {}", workspace_path(output), e))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Load the current state, one of its backups by serial, or a state file.
+async fn load_state(config: &ProjectConfiguration, point: &str) -> Result<State> {
+    if point == "current" {
+        return shared_backend(config).load().await;
+    }
+
+    if let Ok(serial) = point.parse() {
+        return config.state.load_backup(serial).await;
+    }
+
+    let content = fs::read_to_string(point).map_err(|e| {
+        anyhow!(
+            "`{}` is neither a backup serial nor a state file: {}",
+            point,
+            e
+        )
+    })?;
+    serde_json::from_str(&content).map_err(|e| anyhow!("invalid state file `{}`: {}", point, e))
+}
+
+#[derive(Parser)]
+pub struct ChangesReport {
+    /// Git revision from which commits are looked up
+    #[clap(long)]
+    pub since: String,
+
+    /// ID of the service
+    #[clap(short, long)]
+    pub service_id: Option<String>,
+}
+
+/// Commit having modified a rule file.
+struct Commit {
+    hash: String,
+    author: String,
+    subject: String,
+}
+
+impl ChangesReport {
+    pub async fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        if let Some(id) = &self.service_id {
+            if !config.services.iter().any(|svc| &svc.id == id) {
+                bail!("service `{}` not found", id)
+            }
+        }
+
+        // Pending changes are computed against the state, without contacting services
+        let (detections, _) = map_plugin_detections(
+            None,
+            &Variables::resolve(&config.variables, &[], &[])?,
+            &config.core.detection_extensions()?,
+            false,
+        )?;
+        let state = shared_backend(config).load().await?;
+
+        let mut changes: BTreeMap<String, Vec<(char, &str)>> = BTreeMap::new();
+        for svc in config
+            .services
+            .iter()
+            .filter(|svc| self.service_id.is_none() || self.service_id.as_ref() == Some(&svc.id))
+        {
+            let rules = detections.get(&svc.plugin);
+            let deployed = state.services.get(&svc.id);
+
+            for rule in rules.into_iter().flatten() {
+                let sign = match deployed.and_then(|deployed| deployed.get(rule)) {
+                    None => '+',
+                    Some(deployed) if deployed.content != rule.content => '~',
+                    Some(_) => continue,
+                };
+                changes
+                    .entry(rule.name.clone())
+                    .or_default()
+                    .push((sign, &svc.id));
+            }
+
+            for rule in deployed.into_iter().flatten() {
+                if !rules.is_some_and(|rules| rules.contains(rule)) {
+                    changes
+                        .entry(rule.name.clone())
+                        .or_default()
+                        .push(('-', &svc.id));
+                }
+            }
+        }
+
+        if changes.is_empty() {
+            tracing::info!("no pending changes");
+            return Ok(());
+        }
+
+        let files = rule_files(&config.core.detection_extensions()?)?;
+        let history = rules_history(&self.since)?;
+        let uncommitted = [
+            git(
+                &[
+                    "diff",
+                    "--name-only",
+                    "--relative",
+                    "HEAD",
+                    "--",
+                    LGC_RULES_DIR,
+                ],
+                None,
+            )?,
+            git(
+                &[
+                    "ls-files",
+                    "--others",
+                    "--exclude-standard",
+                    "--",
+                    LGC_RULES_DIR,
+                ],
+                None,
+            )?,
+        ]
+        .join("\n");
+
+        for (name, services) in changes {
+            for (sign, service) in services {
+                let name = match sign {
+                    '+' => style(&name).green(),
+                    '~' => style(&name).yellow(),
+                    _ => style(&name).red(),
+                };
+                println!("[{}] rule: `{}` on `{}`", sign, name, service);
+            }
+
+            // Deleted rule files are matched on their file name
+            let file = files
+                .get(&name)
+                .cloned()
+                .unwrap_or_else(|| PathBuf::from(LGC_RULES_DIR).join(format!("{}.yaml", name)));
+            let file = file.to_string_lossy();
+
+            if uncommitted.lines().any(|line| line == file.as_ref()) {
+                println!("    {} uncommitted changes", style("working tree").dim());
+            }
+            match history.get(file.as_ref()) {
+                Some(commits) => {
+                    for commit in commits {
+                        println!(
+                            "    {} {}: {}",
+                            style(&commit.hash).dim(),
+                            style(&commit.author).bold(),
+                            commit.subject
+                        );
+                    }
+                }
+                None => println!("    no commit since `{}`", self.since),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Map detection names to their rule file.
+fn rule_files(extensions: &[String]) -> Result<HashMap<String, PathBuf>> {
+    let mut files = HashMap::new();
+    for path in fs::read_dir(LGC_RULES_DIR)?.filter_map(|entry| entry.ok().map(|e| e.path())) {
+        let Some(format) = DetectionFormat::from_path(&path, extensions) else {
+            continue;
+        };
+
+        let content = format.parse(&fs::read_to_string(&path)?)?;
+        if let Some(name) = content.get("name").and_then(|name| name.as_str()) {
+            files.insert(name.to_string(), path);
+        }
+    }
+
+    Ok(files)
+}
+
+/// Commits having modified each rule file since `since`, most recent first.
+fn rules_history(since: &str) -> Result<HashMap<String, Vec<Commit>>> {
+    let range = format!("{}..HEAD", since);
+    let log = git(
+        &[
+            "log",
+            "--format=%x1e%h%x09%an%x09%s",
+            "--name-only",
+            "--relative",
+            &range,
+            "--",
+            LGC_RULES_DIR,
+        ],
+        None,
+    )?;
+
+    let mut history: HashMap<String, Vec<Commit>> = HashMap::new();
+    for entry in log.split('\x1e').filter(|entry| !entry.trim().is_empty()) {
+        let mut lines = entry.lines();
+        let mut header = lines.next().unwrap_or_default().splitn(3, '\t');
+        let (hash, author, subject) = (
+            header.next().unwrap_or_default(),
+            header.next().unwrap_or_default(),
+            header.next().unwrap_or_default(),
+        );
+
+        for file in lines.map(str::trim).filter(|file| !file.is_empty()) {
+            history
+                .entry(Path::new(file).to_string_lossy().to_string())
+                .or_default()
+                .push(Commit {
+                    hash: hash.to_string(),
+                    author: author.to_string(),
+                    subject: subject.to_string(),
+                });
+        }
+    }
+
+    Ok(history)
+}
+
+/// Fields holding the severity of a rule.
+const SEVERITY_FIELDS: &[&str] = &["severity", "level"];
+/// Fields holding the tags of a rule.
+const TAG_FIELDS: &[&str] = &["tags", "labels"];
+/// Fields holding the ATT&CK mapping of a rule.
+const ATTACK_FIELDS: &[&str] = &["mitre", "attack", "techniques", "tactics"];
+/// Files larger than this multiple of the median size are reported as outliers.
+const SIZE_OUTLIER_FACTOR: u64 = 3;
+
+#[derive(Parser)]
+pub struct WorkspaceReport {
+    /// Number of largest and oldest rules listed
+    #[clap(long, default_value_t = 5)]
+    pub top: usize,
+}
+
+/// Metadata of a detection file, read without validation.
+struct RuleFile {
+    path: PathBuf,
+    name: String,
+    plugins: Vec<String>,
+    severity: Option<String>,
+    tags: Vec<String>,
+    described: bool,
+    attack: bool,
+    size: u64,
+    age: Duration,
+}
+
+impl RuleFile {
+    fn read(path: PathBuf, format: DetectionFormat) -> Result<Self> {
+        let metadata = fs::metadata(&path)?;
+        let content = format.parse(&fs::read_to_string(&path)?)?;
+
+        // Metadata is looked up in the common rule first, then in plugin rules
+        let mut sources: Vec<&serde_yaml_ng::Value> = content.get("common").into_iter().collect();
+        let plugins = match content.get("rules").and_then(|rules| rules.as_mapping()) {
+            Some(rules) => {
+                sources.extend(rules.values());
+                rules
+                    .keys()
+                    .filter_map(|plugin| plugin.as_str().map(str::to_string))
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+        let find = |fields: &[&str]| {
+            sources
+                .iter()
+                .find_map(|source| fields.iter().find_map(|field| source.get(*field)))
+        };
+
+        Ok(Self {
+            name: content
+                .get("name")
+                .and_then(|name| name.as_str())
+                .unwrap_or_default()
+                .to_string(),
+            plugins,
+            severity: find(SEVERITY_FIELDS)
+                .and_then(|severity| severity.as_str())
+                .map(str::to_lowercase),
+            tags: match find(TAG_FIELDS) {
+                Some(serde_yaml_ng::Value::Sequence(tags)) => tags
+                    .iter()
+                    .filter_map(|tag| tag.as_str().map(str::to_string))
+                    .collect(),
+                Some(serde_yaml_ng::Value::String(tags)) => tags
+                    .split(',')
+                    .map(|tag| tag.trim().to_string())
+                    .filter(|tag| !tag.is_empty())
+                    .collect(),
+                _ => Vec::new(),
+            },
+            described: find(&["description"])
+                .and_then(|description| description.as_str())
+                .is_some_and(|description| !description.trim().is_empty()),
+            attack: find(ATTACK_FIELDS).is_some_and(|attack| !is_empty(attack)),
+            size: metadata.len(),
+            age: metadata.modified()?.elapsed().unwrap_or_default(),
+            path,
+        })
+    }
+}
+
+fn is_empty(value: &serde_yaml_ng::Value) -> bool {
+    match value {
+        serde_yaml_ng::Value::Null => true,
+        serde_yaml_ng::Value::String(value) => value.is_empty(),
+        serde_yaml_ng::Value::Sequence(value) => value.is_empty(),
+        serde_yaml_ng::Value::Mapping(value) => value.is_empty(),
+        _ => false,
+    }
+}
+
+impl WorkspaceReport {
+    pub fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        let extensions = config.core.detection_extensions()?;
+        let mut rules = Vec::new();
+        for entry in fs::read_dir(LGC_RULES_DIR)? {
+            let path = entry?.path();
+            let Some(format) = DetectionFormat::from_path(&path, &extensions) else {
+                continue;
+            };
+
+            match RuleFile::read(path.clone(), format) {
+                Ok(rule) => rules.push(rule),
+                Err(e) => tracing::warn!("skipping `{}`: {}", workspace_path(&path), e),
+            }
+        }
+
+        if rules.is_empty() {
+            bail!("no detection found in `{}`", LGC_RULES_DIR)
+        }
+
+        println!(
+            "{} detection(s) in `{}`",
+            style(rules.len()).bold(),
+            LGC_RULES_DIR
+        );
+
+        let mut plugins: BTreeMap<&str, usize> = BTreeMap::new();
+        let mut severities: BTreeMap<&str, usize> = BTreeMap::new();
+        let mut tags: BTreeMap<&str, usize> = BTreeMap::new();
+        for rule in &rules {
+            for plugin in &rule.plugins {
+                *plugins.entry(plugin).or_default() += 1;
+            }
+            *severities
+                .entry(rule.severity.as_deref().unwrap_or("unset"))
+                .or_default() += 1;
+            for tag in &rule.tags {
+                *tags.entry(tag).or_default() += 1;
+            }
+        }
+
+        print_counts("Rules per plugin", &plugins);
+        print_counts("Rules per severity", &severities);
+        print_counts("Rules per tag", &tags);
+
+        print_names(
+            "Missing description",
+            rules.iter().filter(|rule| !rule.described),
+        );
+        print_names(
+            "Missing ATT&CK mapping",
+            rules.iter().filter(|rule| !rule.attack),
+        );
+
+        // Largest files, flagging the ones far above the median
+        rules.sort_by_key(|rule| rule.size);
+        let median = rules[rules.len() / 2].size;
+        println!("\n{} (median {} B)", style("Largest rules:").bold(), median);
+        for rule in rules.iter().rev().take(self.top) {
+            let size = format!("{} B", rule.size);
+            let size = if rule.size > median * SIZE_OUTLIER_FACTOR {
+                style(size).red()
+            } else {
+                style(size)
+            };
+            println!("- `{}` {}", workspace_path(&rule.path), size);
+        }
+
+        // Age of the last modification
+        let mut ages: BTreeMap<u8, usize> = BTreeMap::new();
+        for rule in &rules {
+            let days = rule.age.as_secs() / 86400;
+            let bucket = match days {
+                0..=29 => 0,
+                30..=89 => 1,
+                90..=364 => 2,
+                _ => 3,
+            };
+            *ages.entry(bucket).or_default() += 1;
+        }
+        println!("\n{}", style("Last modified:").bold());
+        for (bucket, count) in ages {
+            let label = match bucket {
+                0 => "less than 30 days",
+                1 => "30 to 90 days",
+                2 => "90 days to a year",
+                _ => "more than a year",
+            };
+            println!("- {}: {}", label, count);
+        }
+
+        rules.sort_by_key(|rule| std::cmp::Reverse(rule.age));
+        println!("\n{}", style("Oldest rules:").bold());
+        for rule in rules.iter().take(self.top) {
+            println!(
+                "- `{}` {} days",
+                workspace_path(&rule.path),
+                rule.age.as_secs() / 86400
+            );
+        }
+
+        Ok(())
+    }
+}
+
+fn print_counts(title: &str, counts: &BTreeMap<&str, usize>) {
+    println!("\n{}", style(format!("{}:", title)).bold());
+    if counts.is_empty() {
+        println!("- none");
+    }
+
+    let mut counts: Vec<_> = counts.iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+    for (key, count) in counts {
+        println!("- `{}`: {}", key, count);
+    }
+}
+
+fn print_names<'a>(title: &str, rules: impl Iterator<Item = &'a RuleFile>) {
+    let names: Vec<_> = rules.map(|rule| rule.name.as_str()).collect();
+    println!(
+        "\n{} ({})",
+        style(format!("{}:", title)).bold(),
+        names.len()
+    );
+    for name in names {
+        println!("- `{}`", name);
+    }
+}
@@ -0,0 +1,597 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{anyhow, bail, Result};
+use clap::{Parser, Subcommand};
+use console::style;
+use dialoguer::{theme::ColorfulTheme, Confirm};
+use lgc_common::{
+    configuration::{ProjectConfiguration, Service, LGC_ARCHIVE_DIR, LGC_RULES_DIR},
+    detections::{Detection, DetectionFormat, DetectionState},
+    plugins::manager::{PluginActions, PluginManager},
+    state::{State, Tombstone},
+    utils::{from_workspace_path, workspace_path},
+    variables::Variables,
+};
+use rayon::prelude::*;
+use regex::Regex;
+use serde_json::Value;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use super::{import::FieldFilter, lock::shared_backend};
+
+/// Manage detection rules
+#[derive(Subcommand)]
+pub enum RulesCommands {
+    /// Delete a detection remotely and move it to the archive
+    Archive(ArchiveRule),
+
+    /// Restore an archived detection, redeployed on next deploy
+    Unarchive(UnarchiveRule),
+
+    /// Show recent execution results or errors of a deployed rule
+    Tail(TailRule),
+
+    /// Rename a deployed rule or move it to another service, keeping its state
+    Move(MoveRule),
+
+    /// Find detections by name, query, content fields or tags
+    #[clap(alias = "grep")]
+    Search(SearchRules),
+}
+
+impl RulesCommands {
+    pub async fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        match self {
+            Self::Archive(cmd) => cmd.run(config).await,
+            Self::Unarchive(cmd) => cmd.run(config).await,
+            Self::Tail(cmd) => cmd.run(config).await,
+            Self::Move(cmd) => cmd.run(config).await,
+            Self::Search(cmd) => cmd.run(config),
+        }
+    }
+}
+
+#[derive(Parser)]
+pub struct ArchiveRule {
+    /// Path or ID of the detection
+    pub detection: String,
+
+    /// Skip interactive approval of the remote deletion
+    #[clap(long)]
+    pub auto_approve: bool,
+}
+
+impl ArchiveRule {
+    pub async fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        let extensions = config.core.detection_extensions()?;
+        let path = detection_path(&self.detection, &extensions);
+        if !path.is_file() || !path.starts_with(LGC_RULES_DIR) {
+            bail!("detection `{}` does not exist", self.detection)
+        }
+        let name = detection_name(&path, &extensions)?;
+
+        let state_lock = config.state.lock().await?;
+        let mut state = config.state.load().await?;
+        if state.archived.contains_key(&name) {
+            bail!("detection `{}` is already archived", name)
+        }
+
+        // Services on which the detection is deployed
+        let services: Vec<_> = config
+            .services
+            .iter()
+            .filter(|svc| {
+                state
+                    .services
+                    .get(&svc.id)
+                    .is_some_and(|rules| rules.iter().any(|rule| rule.name == name))
+            })
+            .collect();
+
+        for svc in &services {
+            println!(
+                "[-] rule: `{}` will be deleted from `{}`",
+                style(&name).red(),
+                svc.id
+            );
+        }
+
+        if !services.is_empty()
+            && !self.auto_approve
+            && !Confirm::with_theme(&ColorfulTheme::default())
+                .with_prompt("Do you want to archive this detection?")
+                .interact()?
+        {
+            bail!("action aborted")
+        }
+
+        let plugin_manager = PluginManager::new()?.with_capabilities(&config.plugins);
+        for svc in &services {
+            let (instance, mut store) = plugin_manager.load_plugin(&svc.plugin).await?;
+            // Safe unwrap as services are filtered on state content
+            let rules = state.services.get_mut(&svc.id).unwrap();
+            let rule = rules.iter().find(|rule| rule.name == name).unwrap().clone();
+
+            if let Err(e) = instance
+                .delete(
+                    &mut store,
+                    &svc.config().await?,
+                    &config.naming(svc)?.apply(&rule.name),
+                    &serde_json::to_string(&rule.content)?,
+                )
+                .await
+            {
+                state.save(&state_lock).await?;
+                bail!("on deletion for `{}` in `{}`: {}", name, svc.id, e)
+            }
+
+            rules.remove(&rule);
+            println!(
+                "[-] rule: `{}` deleted from `{}`",
+                style(&name).red(),
+                svc.id
+            );
+        }
+
+        // Keep the detection out of the rules directory
+        let archived = PathBuf::from(LGC_ARCHIVE_DIR).join(path.strip_prefix(LGC_RULES_DIR)?);
+        if let Some(parent) = archived.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(&path, &archived)?;
+
+        state.archived.insert(
+            name.clone(),
+            Tombstone {
+                path: archived.clone(),
+                services: services.iter().map(|svc| svc.id.clone()).collect(),
+                archived_at: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+            },
+        );
+        state.save(&state_lock).await?;
+
+        tracing::info!(
+            "detection `{}` archived to `{}`",
+            name,
+            workspace_path(&archived)
+        );
+        Ok(())
+    }
+}
+
+#[derive(Parser)]
+pub struct UnarchiveRule {
+    /// Name of the archived detection
+    pub name: String,
+}
+
+impl UnarchiveRule {
+    pub async fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        let state_lock = config.state.lock().await?;
+        let mut state = config.state.load().await?;
+        let tombstone = state
+            .archived
+            .remove(&self.name)
+            .ok_or_else(|| anyhow!("detection `{}` is not archived", self.name))?;
+
+        let restored =
+            PathBuf::from(LGC_RULES_DIR).join(tombstone.path.strip_prefix(LGC_ARCHIVE_DIR)?);
+        if restored.exists() {
+            bail!("`{}` already exists", workspace_path(&restored))
+        }
+        if let Some(parent) = restored.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(&tombstone.path, &restored).map_err(|e| {
+            anyhow!(
+                "unable to restore `{}`: {}",
+                workspace_path(&tombstone.path),
+                e
+            )
+        })?;
+
+        state.save(&state_lock).await?;
+
+        tracing::info!(
+            "detection `{}` restored to `{}`, run `lgc deploy` to redeploy it",
+            self.name,
+            workspace_path(&restored)
+        );
+        Ok(())
+    }
+}
+
+#[derive(Parser)]
+pub struct TailRule {
+    /// Deployed rule, as `<service>/<rule>`
+    pub target: String,
+
+    /// Keep polling the service for new events
+    #[clap(short, long)]
+    pub follow: bool,
+
+    /// Delay between polls when following (e.g. `10s`)
+    #[clap(long, default_value = "10s")]
+    pub interval: humantime::Duration,
+}
+
+impl TailRule {
+    pub async fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        let (svc, name) = rule_target(config, &self.target)?;
+
+        // Plugins need the deployed content to locate the rule
+        let state = shared_backend(config).load().await?;
+        let rule = state
+            .services
+            .get(&svc.id)
+            .and_then(|rules| rules.iter().find(|rule| rule.name == name))
+            .ok_or_else(|| anyhow!("rule `{}` is not deployed on `{}`", name, svc.id))?;
+
+        let (instance, mut store) = PluginManager::new()?
+            .with_capabilities(&config.plugins)
+            .load_plugin(&svc.plugin)
+            .await?;
+        let service_config = svc.config().await?;
+        let params = serde_json::to_string(&rule.content)?;
+
+        let mut cursor = None;
+        loop {
+            let page = instance
+                .tail(
+                    &mut store,
+                    &service_config,
+                    &rule.name,
+                    &params,
+                    cursor.as_deref(),
+                )
+                .await
+                .map_err(|e| anyhow!("unable to tail `{}` on `{}`: {}", name, svc.id, e))?;
+
+            for event in page.events {
+                println!("{}", event);
+            }
+
+            if page.next.is_some() {
+                cursor = page.next;
+            }
+
+            if !self.follow {
+                break;
+            }
+            tokio::time::sleep(*self.interval).await;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Parser)]
+pub struct MoveRule {
+    /// Deployed rule, as `<service>/<rule>`
+    pub from: String,
+
+    /// New service and name of the rule, as `<service>/<rule>`
+    pub to: String,
+
+    /// Also rename the remote rule, creating it under its new name before deleting the old one
+    #[clap(long)]
+    pub remote: bool,
+
+    /// Skip interactive approval of the remote changes
+    #[clap(long)]
+    pub auto_approve: bool,
+}
+
+impl MoveRule {
+    pub async fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        let (from_svc, from_name) = rule_target(config, &self.from)?;
+        let (to_svc, to_name) = rule_target(config, &self.to)?;
+        if from_svc.plugin != to_svc.plugin {
+            bail!(
+                "rules can only move between services of the same plugin, `{}` uses `{}` and `{}` uses `{}`",
+                from_svc.id,
+                from_svc.plugin,
+                to_svc.id,
+                to_svc.plugin
+            )
+        }
+        if from_svc.id == to_svc.id && from_name == to_name {
+            bail!("`{}` and `{}` are the same rule", self.from, self.to)
+        }
+
+        let state_lock = config.state.lock().await?;
+        let mut state = config.state.load().await?;
+        let mut rule = state
+            .services
+            .get(&from_svc.id)
+            .and_then(|rules| rules.iter().find(|rule| rule.name == from_name))
+            .cloned()
+            .ok_or_else(|| anyhow!("rule `{}` is not deployed on `{}`", from_name, from_svc.id))?;
+        if state
+            .services
+            .get(&to_svc.id)
+            .is_some_and(|rules| rules.iter().any(|rule| rule.name == to_name))
+        {
+            bail!("rule `{}` is already deployed on `{}`", to_name, to_svc.id)
+        }
+
+        let from_remote = config.naming(from_svc)?.apply(from_name);
+        let to_remote = config.naming(to_svc)?.apply(to_name);
+        let remote_changes = from_svc.id != to_svc.id || from_remote != to_remote;
+
+        if self.remote && remote_changes {
+            println!(
+                "[~] rule: `{}` on `{}` will be moved to `{}` on `{}`",
+                style(&from_remote).yellow(),
+                from_svc.id,
+                style(&to_remote).yellow(),
+                to_svc.id
+            );
+            if !self.auto_approve
+                && !Confirm::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Do you want to move this rule?")
+                    .interact()?
+            {
+                bail!("action aborted")
+            }
+
+            let (instance, mut store) = PluginManager::new()?
+                .with_capabilities(&config.plugins)
+                .load_plugin(&to_svc.plugin)
+                .await?;
+            let params = serde_json::to_string(&rule.content)?;
+
+            // The rule is created first, so that a failure leaves the old one in place
+            instance
+                .create(&mut store, &to_svc.config().await?, &to_remote, &params)
+                .await
+                .map_err(|e| anyhow!("on creation for `{}` in `{}`: {}", to_name, to_svc.id, e))?;
+            // Snapshots of the old remote rule no longer apply, even if its deletion fails
+            rule.remote = None;
+            if let Err(e) = instance
+                .delete(&mut store, &from_svc.config().await?, &from_remote, &params)
+                .await
+            {
+                // Both rules exist remotely, only the new one is tracked from now on
+                move_rule(&mut state, &from_svc.id, &to_svc.id, rule, to_name);
+                state.save(&state_lock).await?;
+                bail!(
+                    "on deletion for `{}` in `{}`: {}, delete it manually",
+                    from_name,
+                    from_svc.id,
+                    e
+                )
+            }
+        } else if remote_changes {
+            tracing::warn!(
+                "remote rule `{}` is not renamed, only the state is updated (use `--remote` to rename it)",
+                from_remote
+            );
+        }
+
+        move_rule(&mut state, &from_svc.id, &to_svc.id, rule, to_name);
+        state.save(&state_lock).await?;
+
+        tracing::info!("rule `{}` moved to `{}`", self.from, self.to);
+        Ok(())
+    }
+}
+
+#[derive(Parser)]
+pub struct SearchRules {
+    /// Only list detections whose name or title matches this regex
+    pub pattern: Option<String>,
+
+    /// Only list detections with a query, or any other text of their rules, matching this regex
+    #[clap(short, long)]
+    pub query: Option<String>,
+
+    /// Only list detections with a rule field matching a regex (e.g. `/acl/app=^search$`), repeatable
+    #[clap(long = "field", value_name = "FIELD=REGEX")]
+    pub fields: Vec<String>,
+
+    /// Only list detections with this metadata tag, repeatable
+    #[clap(long = "tag", value_name = "TAG")]
+    pub tags: Vec<String>,
+
+    /// Only list detections with a rule for this plugin
+    #[clap(short, long)]
+    pub plugin: Option<String>,
+}
+
+impl SearchRules {
+    pub fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        let pattern = self.pattern.as_deref().map(Regex::new).transpose()?;
+        let query = self.query.as_deref().map(Regex::new).transpose()?;
+        let fields = self
+            .fields
+            .iter()
+            .map(|filter| FieldFilter::parse(filter))
+            .collect::<Result<Vec<_>>>()?;
+
+        let extensions = config.core.detection_extensions()?;
+        let variables = Variables::resolve(&config.variables, &[], &[])?;
+        let mut paths: Vec<PathBuf> = fs::read_dir(LGC_RULES_DIR)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file())
+            .collect();
+        paths.sort();
+
+        // Files are parsed in parallel, matches keep the order of paths
+        let matches: Vec<(PathBuf, Detection)> = paths
+            .into_par_iter()
+            .filter_map(|path| {
+                let format = DetectionFormat::from_path(&path, &extensions)?;
+                match Detection::pre_validate(path.display().to_string(), format, &variables) {
+                    Ok(detections) => Some(
+                        detections
+                            .into_iter()
+                            .map(|detection| (path.clone(), detection))
+                            .collect::<Vec<_>>(),
+                    ),
+                    Err(e) => {
+                        tracing::warn!("skipping `{}`: {}", workspace_path(&path), e);
+                        None
+                    }
+                }
+            })
+            .flatten()
+            .filter(|(_, detection)| {
+                let rules: Vec<&Value> = detection
+                    .rules
+                    .iter()
+                    .filter(|(plugin, _)| self.plugin.as_ref().map_or(true, |only| only == *plugin))
+                    .map(|(_, content)| content)
+                    .collect();
+                let title = detection
+                    .common
+                    .as_ref()
+                    .map(|common| common.title.as_str());
+
+                !rules.is_empty()
+                    && pattern.as_ref().map_or(true, |pattern| {
+                        pattern.is_match(&detection.name)
+                            || title.is_some_and(|title| pattern.is_match(title))
+                    })
+                    && query.as_ref().map_or(true, |query| {
+                        detection
+                            .common
+                            .as_ref()
+                            .is_some_and(|common| query.is_match(&common.query))
+                            || rules.iter().any(|content| matches_text(content, query))
+                    })
+                    && (fields.is_empty()
+                        || rules
+                            .iter()
+                            .any(|content| fields.iter().all(|field| field.matches(content))))
+                    && (self.tags.is_empty()
+                        || detection
+                            .metadata
+                            .as_ref()
+                            .is_some_and(|metadata| metadata.has_any_tag(&self.tags)))
+            })
+            .collect();
+
+        if matches.is_empty() {
+            bail!("no detection found")
+        }
+
+        for (path, detection) in &matches {
+            let services: Vec<&str> = config
+                .services
+                .iter()
+                .filter(|svc| detection.rules.contains_key(&svc.plugin))
+                .filter(|svc| {
+                    self.plugin
+                        .as_ref()
+                        .map_or(true, |only| only == &svc.plugin)
+                })
+                .map(|svc| svc.id.as_str())
+                .collect();
+            println!(
+                "{}: `{}` {}",
+                workspace_path(path),
+                style(&detection.name).bold(),
+                style(if services.is_empty() {
+                    String::from("(no service)")
+                } else {
+                    format!("({})", services.join(", "))
+                })
+                .dim()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether any text of a rule content matches `regex`.
+fn matches_text(content: &Value, regex: &Regex) -> bool {
+    match content {
+        Value::String(text) => regex.is_match(text),
+        Value::Array(items) => items.iter().any(|item| matches_text(item, regex)),
+        Value::Object(fields) => fields.values().any(|field| matches_text(field, regex)),
+        _ => false,
+    }
+}
+
+/// Move the state entry of `rule` from one service to another, under its new name.
+fn move_rule(
+    state: &mut State,
+    from_service: &str,
+    to_service: &str,
+    mut rule: DetectionState,
+    to_name: &str,
+) {
+    if let Some(rules) = state.services.get_mut(from_service) {
+        rules.remove(&rule);
+    }
+    rule.name = to_name.to_string();
+    state
+        .services
+        .entry(to_service.to_string())
+        .or_default()
+        .insert(rule);
+}
+
+/// Resolve a `<service>/<rule>` target.
+fn rule_target<'a>(
+    config: &'a ProjectConfiguration,
+    target: &'a str,
+) -> Result<(&'a Service, &'a str)> {
+    let (service_id, name) = target
+        .split_once('/')
+        .ok_or_else(|| anyhow!("invalid target `{}`, expected `<service>/<rule>`", target))?;
+
+    let svc = config
+        .services
+        .get(&Service {
+            id: service_id.to_string(),
+            ..Default::default()
+        })
+        .ok_or_else(|| anyhow!("service `{}` not found", service_id))?;
+
+    Ok((svc, name))
+}
+
+/// Resolve a detection path from either a path or an ID.
+fn detection_path(detection: &str, extensions: &[String]) -> PathBuf {
+    let path = PathBuf::from(detection);
+    if path.is_file() {
+        // Workspace relative so the path can be matched against the rules directory
+        return from_workspace_path(&workspace_path(&path));
+    }
+
+    let candidates: Vec<PathBuf> = extensions
+        .iter()
+        .map(|ext| PathBuf::from(LGC_RULES_DIR).join(format!("{}.{}", detection, ext)))
+        .collect();
+    candidates
+        .iter()
+        .find(|path| path.is_file())
+        .or(candidates.first())
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// Read the detection name without validating the whole file.
+fn detection_name(path: &Path, extensions: &[String]) -> Result<String> {
+    let format = DetectionFormat::from_path(path, extensions).ok_or_else(|| {
+        anyhow!(
+            "`{}` is not a detection file ({})",
+            workspace_path(path),
+            extensions.join(", ")
+        )
+    })?;
+    let content = format.parse(&fs::read_to_string(path)?)?;
+    content
+        .get("name")
+        .and_then(|name| name.as_str())
+        .map(str::to_string)
+        .ok_or_else(|| anyhow!("detection `{}` has no name", workspace_path(path)))
+}
@@ -0,0 +1,216 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{bail, Result};
+use clap::Parser;
+use console::style;
+use lgc_common::utils::ensure_kebab_case;
+use std::{fs, path::PathBuf};
+
+const CARGO_TOML: &str = r#"[package]
+name = "__NAME__"
+version = "0.1.0"
+edition = "2021"
+
+[lib]
+crate-type = ["cdylib"]
+
+[dependencies]
+logcraft-plugin-sdk = "__SDK_VERSION__"
+serde = { version = "1.0", features = ["derive"] }
+serde_json = "1.0"
+
+[profile.release]
+opt-level = "s"
+lto = true
+strip = true
+"#;
+
+const LIB_RS: &str = r#"use logcraft_plugin_sdk::{logcraft_plugin, Plugin, PluginError, Result, ServiceStatus};
+use serde::{Deserialize, Serialize};
+
+/// Service settings, defined by the `Configuration` schema of `settings.k`
+#[derive(Deserialize)]
+pub struct Settings {
+    pub url: String,
+    pub token: String,
+}
+
+/// Rule content, defined by the `Rule` schema of `rule.k`
+#[derive(Deserialize, Serialize)]
+pub struct Rule {
+    pub query: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+}
+
+struct __STRUCT__;
+
+impl Plugin for __STRUCT__ {
+    const NAME: &'static str = "__NAME__";
+    const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+    const AUTHOR: &'static str = "";
+    const DESCRIPTION: &'static str = "__NAME__ plugin";
+    const SETTINGS: &'static str = include_str!("../settings.k");
+    const RULE: &'static str = include_str!("../rule.k");
+
+    type Settings = Settings;
+    type Rule = Rule;
+
+    fn create(_settings: &Settings, _name: &str, _rule: &Rule) -> Result<Option<Rule>> {
+        Err(PluginError::new("create is not implemented yet"))
+    }
+
+    /// Rule as deployed on the service, `None` when it does not exist
+    fn read(_settings: &Settings, _name: &str, _rule: &Rule) -> Result<Option<Rule>> {
+        Ok(None)
+    }
+
+    fn update(_settings: &Settings, _name: &str, _rule: &Rule) -> Result<Option<Rule>> {
+        Err(PluginError::new("update is not implemented yet"))
+    }
+
+    fn delete(_settings: &Settings, _name: &str, _rule: &Rule) -> Result<Option<Rule>> {
+        Err(PluginError::new("delete is not implemented yet"))
+    }
+
+    fn ping(_settings: &Settings) -> Result<ServiceStatus> {
+        Ok(ServiceStatus {
+            alive: true,
+            writable: None,
+            api_version: None,
+            license: None,
+            remaining_quota: None,
+        })
+    }
+}
+
+logcraft_plugin!(__STRUCT__);
+"#;
+
+const SETTINGS_K: &str = r#"schema Configuration:
+    # Address of the service API
+    url: str
+    # API token, e.g. `env:__ENV___TOKEN`
+    token: str
+"#;
+
+const RULE_K: &str = r#"schema Rule:
+    # Search query of the detection
+    query: str
+    # Whether the rule runs on the service
+    enabled?: bool
+"#;
+
+const BUILD_SH: &str = r#"#!/bin/sh
+# Build the plugin component, then install it with `lgc plugins install target/__NAME__.wasm`
+set -e
+
+rustup target add wasm32-wasip2
+cargo build --release --target wasm32-wasip2
+cp target/wasm32-wasip2/release/__LIB__.wasm target/__NAME__.wasm
+"#;
+
+const WORKFLOW: &str = r#"name: build
+
+on:
+  push:
+  pull_request:
+
+jobs:
+  build:
+    runs-on: ubuntu-latest
+    steps:
+      - uses: actions/checkout@v4
+      - uses: dtolnay/rust-toolchain@stable
+        with:
+          targets: wasm32-wasip2
+      - run: ./build.sh
+      - uses: actions/upload-artifact@v4
+        with:
+          name: __NAME__
+          path: target/__NAME__.wasm
+"#;
+
+const README: &str = r#"# __NAME__
+
+LogCraft CLI plugin.
+
+Define the service settings in `settings.k` and the rule fields in `rule.k`, then implement the calls to the service in `src/lib.rs`. Build the component with `./build.sh` and install it in a LogCraft project:
+
+```bash
+lgc plugins install target/__NAME__.wasm
+```
+"#;
+
+/// Generate the skeleton of a new plugin crate.
+#[derive(Parser)]
+pub struct ScaffoldPlugin {
+    /// Plugin name, in kebab case
+    pub name: String,
+
+    /// Directory to create, the plugin name by default
+    #[clap(short, long)]
+    pub path: Option<PathBuf>,
+}
+
+impl ScaffoldPlugin {
+    pub fn run(self) -> Result<()> {
+        ensure_kebab_case(&self.name)?;
+        let root = self.path.unwrap_or_else(|| PathBuf::from(&self.name));
+        if root.exists() && fs::read_dir(&root)?.next().is_some() {
+            bail!("directory `{}` is not empty", root.display())
+        }
+
+        let type_name: String = self
+            .name
+            .split('-')
+            .map(|word| {
+                let mut chars = word.chars();
+                chars
+                    .next()
+                    .map(|first| first.to_ascii_uppercase().to_string() + chars.as_str())
+                    .unwrap_or_default()
+            })
+            .collect();
+        let render = |template: &str| {
+            template
+                .replace("__NAME__", &self.name)
+                .replace("__LIB__", &self.name.replace('-', "_"))
+                .replace("__ENV__", &self.name.replace('-', "_").to_uppercase())
+                .replace("__STRUCT__", &type_name)
+                .replace("__SDK_VERSION__", env!("CARGO_PKG_VERSION"))
+        };
+
+        for (path, template) in [
+            ("Cargo.toml", CARGO_TOML),
+            ("src/lib.rs", LIB_RS),
+            ("settings.k", SETTINGS_K),
+            ("rule.k", RULE_K),
+            ("build.sh", BUILD_SH),
+            (".github/workflows/build.yml", WORKFLOW),
+            ("README.md", README),
+            (".gitignore", "/target\n"),
+        ] {
+            let path = root.join(path);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&path, render(template))?;
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(root.join("build.sh"), fs::Permissions::from_mode(0o755))?;
+        }
+
+        tracing::info!(
+            "plugin `{}` created in `{}`, build it with `{}`",
+            self.name,
+            root.display(),
+            style("./build.sh").bold()
+        );
+        Ok(())
+    }
+}
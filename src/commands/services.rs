@@ -11,7 +11,11 @@ use lgc_common::{
     plugins::manager::{PluginActions, PluginManager},
     utils,
 };
-use std::{collections::HashMap, time::Duration};
+use lgc_runtime::plugin_component::plugin::ServiceStatus;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
 use tokio::task::JoinSet;
 
 /// Manage backend services
@@ -111,11 +115,14 @@ impl AddService {
             bail!("action aborted")
         }
 
-        // Load plugin
-        let (instance, mut store) = PluginManager::new()?.load_plugin(plugin_name).await?;
+        // Cached schemas spare instantiating the plugin
+        let schemas = PluginManager::new()?
+            .with_capabilities(&config.plugins)
+            .plugin_schemas(plugin_name)
+            .await?;
 
         // Start plugin configuration
-        service.configure(instance.settings(&mut store).await?, !self.configure)?;
+        service.configure(schemas.settings, !self.configure)?;
 
         config.services.insert(service);
         tracing::info!("service `{}` created", &id);
@@ -239,11 +246,14 @@ impl ConfigureService {
             })
             .ok_or_else(|| anyhow!("service `{}` does not exist", &id))?;
 
-        // Load plugin
-        let (instance, mut store) = PluginManager::new()?.load_plugin(&service.plugin).await?;
+        // Cached schemas spare instantiating the plugin
+        let schemas = PluginManager::new()?
+            .with_capabilities(&config.plugins)
+            .plugin_schemas(&service.plugin)
+            .await?;
 
         // Start plugin configuration
-        service.configure(instance.settings(&mut store).await?, false)?;
+        service.configure(schemas.settings, false)?;
 
         config.services.insert(service);
         tracing::info!("service `{}` configured", &id);
@@ -262,57 +272,159 @@ impl PingService {
             bail!("no services defined")
         }
 
-        let mut plugins: HashMap<&str, Vec<&Service>> =
-            HashMap::with_capacity(config.services.len());
+        let health = check_services(config).await?;
+        print_health(&health);
 
-        // Map service to plugin
-        for svc in &config.services {
-            plugins.entry(&svc.plugin).or_default().push(svc);
+        let unhealthy = health.iter().filter(|svc| !svc.is_healthy()).count();
+        if unhealthy > 0 {
+            bail!("{} service(s) unreachable", unhealthy)
         }
 
-        // Load plugins
-        let plugin_manager = PluginManager::new()?;
-        let mut set = JoinSet::new();
+        Ok(())
+    }
+}
+
+/// Health of a service, as reported by its plugin.
+pub struct ServiceHealth {
+    pub service_id: String,
+    /// Time taken by the ping call
+    pub latency: Duration,
+    pub status: Result<ServiceStatus>,
+}
+
+impl ServiceHealth {
+    pub fn is_healthy(&self) -> bool {
+        matches!(&self.status, Ok(status) if status.alive)
+    }
+}
+
+/// Ping every service of the project, sorted by service ID.
+pub async fn check_services(config: &ProjectConfiguration) -> Result<Vec<ServiceHealth>> {
+    let mut plugins: HashMap<&str, Vec<&Service>> = HashMap::with_capacity(config.services.len());
+
+    // Map service to plugin
+    for svc in &config.services {
+        plugins.entry(&svc.plugin).or_default().push(svc);
+    }
+
+    // Load plugins
+    let plugin_manager = PluginManager::new()?.with_capabilities(&config.plugins);
+    let mut set = JoinSet::new();
 
-        for plugin_name in plugins.keys() {
-            let plugin_name = plugin_name.to_string();
-            let plugin_manager = plugin_manager.clone();
-            set.spawn(async move { plugin_manager.load_plugin(plugin_name).await });
+    for plugin_name in plugins.keys() {
+        let plugin_name = plugin_name.to_string();
+        let plugin_manager = plugin_manager.clone();
+        set.spawn(async move { plugin_manager.load_plugin(plugin_name).await });
+    }
+
+    let spinner = ProgressBar::new_spinner();
+    spinner.enable_steady_tick(Duration::from_millis(130));
+    spinner.set_style(
+        ProgressStyle::with_template("{spinner:.bold.dim} {msg}")
+            .unwrap()
+            .tick_strings(SPINNER),
+    );
+
+    // Call ping function for each plugin's service
+    let mut health = Vec::with_capacity(config.services.len());
+    while let Some(plugin) = set.join_next().await {
+        let (instance, mut store) = plugin??;
+        let meta = &instance.metadata;
+
+        for svc in plugins
+            .get(meta.name.as_str())
+            .ok_or_else(|| anyhow!("plugin `{}` instance not found", &meta.name))?
+            .iter()
+        {
+            spinner.set_message(svc.id.clone());
+
+            let config = &svc.config().await?;
+            let start = Instant::now();
+            let status = instance.ping(&mut store, config).await;
+            health.push(ServiceHealth {
+                service_id: svc.id.clone(),
+                latency: start.elapsed(),
+                status,
+            });
         }
+    }
+    spinner.finish_and_clear();
 
-        // Call ping function for each plugin's service
-        while let Some(plugin) = set.join_next().await {
-            let (instance, mut store) = plugin??;
-            let meta = &instance.metadata;
-
-            for svc in plugins
-                .get(meta.name.as_str())
-                .ok_or_else(|| anyhow!("plugin `{}` instance not found", &meta.name))?
-                .iter()
-            {
-                let spinner = ProgressBar::new_spinner();
-                spinner.enable_steady_tick(Duration::from_millis(130));
-                spinner.set_style(
-                    ProgressStyle::with_template("{spinner:.bold.dim} {msg}")
-                        .unwrap()
-                        .tick_strings(SPINNER),
-                );
-                spinner.set_message(svc.id.clone());
-
-                let config = &serde_json::to_string(&svc.settings)?;
-                if let Err(e) = instance.ping(&mut store, config).await {
-                    spinner.finish_with_message(format!(
-                        "{} ... {}",
-                        style(&svc.id).bold().red(),
-                        e
-                    ));
-                } else {
-                    spinner
-                        .finish_with_message(format!("{} ... OK", style(&svc.id).bold().green()));
-                }
-            }
+    health.sort_by(|a, b| a.service_id.cmp(&b.service_id));
+    Ok(health)
+}
+
+/// Print services health as a table, followed by errors of unreachable services.
+pub fn print_health(health: &[ServiceHealth]) {
+    let rows: Vec<[String; 6]> = health
+        .iter()
+        .map(|svc| {
+            let (status, api_version, license, quota) = match &svc.status {
+                Err(_) => ("error".to_string(), None, None, None),
+                Ok(status) => (
+                    match (status.alive, status.writable) {
+                        (false, _) => "down",
+                        (true, Some(false)) => "read-only",
+                        (true, _) => "ok",
+                    }
+                    .to_string(),
+                    status.api_version.clone(),
+                    status.license.clone(),
+                    status.remaining_quota.map(|quota| quota.to_string()),
+                ),
+            };
+            let missing = || String::from("-");
+            [
+                svc.service_id.clone(),
+                status,
+                format!("{}ms", svc.latency.as_millis()),
+                api_version.unwrap_or_else(missing),
+                license.unwrap_or_else(missing),
+                quota.unwrap_or_else(missing),
+            ]
+        })
+        .collect();
+
+    let header = [
+        "SERVICE",
+        "STATUS",
+        "LATENCY",
+        "API VERSION",
+        "LICENSE",
+        "QUOTA",
+    ];
+    let mut widths = header.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
         }
+    }
 
-        Ok(())
+    let line = |cells: [String; 6]| {
+        cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+
+    println!("{}", style(line(header.map(String::from))).bold());
+    for (row, svc) in rows.into_iter().zip(health) {
+        let row = line(row);
+        let row = match &svc.status {
+            Ok(status) if status.alive && status.writable != Some(false) => style(row).green(),
+            Ok(status) if status.alive => style(row).yellow(),
+            _ => style(row).red(),
+        };
+        println!("{}", row);
+    }
+
+    for svc in health {
+        if let Err(e) = &svc.status {
+            println!("[!] {}: {}", style(&svc.service_id).bold().red(), e);
+        }
     }
 }
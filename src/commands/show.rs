@@ -0,0 +1,180 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{anyhow, bail, Result};
+use clap::Parser;
+use console::{pad_str, style, truncate_str, Alignment, Term};
+use lgc_common::{
+    configuration::{ProjectConfiguration, Service},
+    detections::{labeled_diff, resolve_common_rules},
+    plugins::manager::{PluginActions, PluginManager},
+    transforms::apply_transforms,
+};
+use serde_json::Value;
+
+use super::{
+    lock::shared_backend,
+    target::{Target, TargetArgs},
+    variables::VariablesArgs,
+};
+
+/// Width used when stdout is not a terminal.
+const DEFAULT_WIDTH: usize = 160;
+
+#[derive(Parser, Debug, Default)]
+#[clap(
+    about = "Show the local, remote and state versions of a detection on a service",
+    allow_hyphen_values = true
+)]
+pub struct ShowCommand {
+    /// Service the detection is deployed on
+    pub service_id: String,
+
+    /// Path of the detection (e.g. `rules/brute-force.yaml`)
+    pub rule_path: String,
+
+    /// Print unified diffs instead of the versions side by side
+    #[clap(long)]
+    pub diff: bool,
+
+    #[clap(flatten)]
+    pub variables: VariablesArgs,
+}
+
+impl ShowCommand {
+    pub async fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        let svc = config
+            .services
+            .get(&Service {
+                id: self.service_id.clone(),
+                ..Default::default()
+            })
+            .ok_or_else(|| anyhow!("service `{}` not found", &self.service_id))?;
+
+        // Render the detection as it would be deployed
+        let target: Target = format!("{}/{}", svc.id, self.rule_path).parse()?;
+        let (mut detections, _) = TargetArgs {
+            targets: vec![target],
+        }
+        .load(
+            config,
+            &self.variables.resolve(config)?,
+            &config.core.detection_extensions()?,
+        )?;
+        let plugin_manager = PluginManager::new()?.with_capabilities(&config.plugins);
+        resolve_common_rules(&plugin_manager, &mut detections).await?;
+        apply_transforms(&config.transforms, &mut detections)?;
+
+        let Some(rule) = detections
+            .get(&svc.plugin)
+            .and_then(|rules| rules.iter().next())
+        else {
+            bail!(
+                "detection `{}` has no rule for service `{}`",
+                self.rule_path,
+                svc.id
+            )
+        };
+
+        let (instance, mut store) = plugin_manager.load_plugin(&svc.plugin).await?;
+        let remote: Option<Value> = instance
+            .read(
+                &mut store,
+                &svc.config().await?,
+                &config.naming(svc)?.apply(&rule.name),
+                &serde_json::to_string(&rule.content)?,
+            )
+            .await?
+            .map(|remote| serde_json::from_str(&remote))
+            .transpose()?;
+
+        let state = shared_backend(config).load().await?;
+        let recorded = state
+            .services
+            .get(&svc.id)
+            .and_then(|rules| rules.get(rule))
+            .map(|rule| &rule.content);
+
+        if self.diff {
+            println!(
+                "{}",
+                style("Remote to local (changes planned by `lgc diff`):").bold()
+            );
+            print_diff(&labeled_diff(
+                "remote",
+                remote.as_ref(),
+                "local",
+                Some(&rule.content),
+            )?);
+            println!(
+                "\n{}",
+                style("State to remote (changes made outside of lgc):").bold()
+            );
+            print_diff(&labeled_diff("state", recorded, "remote", remote.as_ref())?);
+        } else {
+            print_columns(&[
+                ("LOCAL", Some(&rule.content)),
+                ("REMOTE", remote.as_ref()),
+                ("STATE", recorded),
+            ])?;
+        }
+
+        Ok(())
+    }
+}
+
+fn print_diff(diff: &str) {
+    if diff.is_empty() {
+        println!("{}", style("no differences").dim());
+    } else {
+        print!("{}", diff);
+    }
+}
+
+/// Print versions of a rule as YAML columns, fitting the terminal width.
+fn print_columns(columns: &[(&str, Option<&Value>)]) -> Result<()> {
+    let contents = columns
+        .iter()
+        .map(|(_, content)| {
+            Ok(match content {
+                Some(content) => serde_yaml_ng::to_string(content)?,
+                None => String::from("(none)\n"),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let lines: Vec<Vec<&str>> = contents.iter().map(|c| c.lines().collect()).collect();
+
+    let separator = " │ ";
+    let width = Term::stdout()
+        .size_checked()
+        .map_or(DEFAULT_WIDTH, |(_, width)| width as usize);
+    let column_width = (width.saturating_sub(separator.chars().count() * (columns.len() - 1))
+        / columns.len())
+    .max(10);
+    let cell = |text: &str| {
+        pad_str(
+            &truncate_str(text, column_width, "…"),
+            column_width,
+            Alignment::Left,
+            None,
+        )
+        .to_string()
+    };
+
+    let header: Vec<String> = columns
+        .iter()
+        .map(|(title, _)| style(cell(title)).bold().to_string())
+        .collect();
+    println!("{}", header.join(separator).trim_end());
+
+    let rows = lines.iter().map(Vec::len).max().unwrap_or_default();
+    for row in 0..rows {
+        let cells: Vec<String> = lines
+            .iter()
+            .map(|lines| cell(lines.get(row).copied().unwrap_or_default()))
+            .collect();
+        println!("{}", cells.join(separator).trim_end());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,221 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{bail, Result};
+use clap::{Parser, Subcommand};
+use console::style;
+use dialoguer::{theme::ColorfulTheme, Confirm};
+use lgc_common::{
+    configuration::ProjectConfiguration,
+    plugins::manager::{PluginActions, PluginManager},
+    state::State,
+};
+
+use super::lock::shared_backend;
+
+/// Manage the state
+#[derive(Subcommand)]
+pub enum StateCommands {
+    /// List available state backups
+    Backups(ListBackups),
+
+    /// Replace the state with one of its backups
+    RestoreBackup(RestoreBackup),
+
+    /// Save a state which could not be saved to the backend
+    Push(PushState),
+
+    /// Check state entries against current plugin schemas
+    Validate(ValidateState),
+
+    /// Release a state lock left by a crashed run
+    ForceUnlock(ForceUnlock),
+}
+
+impl StateCommands {
+    pub async fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        match self {
+            Self::Backups(cmd) => cmd.run(config).await,
+            Self::RestoreBackup(cmd) => cmd.run(config).await,
+            Self::Push(cmd) => cmd.run(config).await,
+            Self::Validate(cmd) => cmd.run(config).await,
+            Self::ForceUnlock(cmd) => cmd.run(config).await,
+        }
+    }
+}
+
+#[derive(Parser)]
+pub struct ListBackups;
+
+impl ListBackups {
+    pub async fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        let backups = config.state.backups().await?;
+        if backups.is_empty() {
+            bail!("no state backup found")
+        }
+
+        for serial in backups {
+            println!("- `{}`", style(serial).bold());
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Parser)]
+pub struct RestoreBackup {
+    /// Serial of the backup to restore
+    pub serial: usize,
+
+    /// Skip interactive approval of the restoration
+    #[clap(long)]
+    pub auto_approve: bool,
+}
+
+impl RestoreBackup {
+    pub async fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        let backup = config.state.load_backup(self.serial).await?;
+        let state_lock = config.state.lock().await?;
+        let mut state = config.state.load().await?;
+
+        if !self.auto_approve
+            && !Confirm::with_theme(&ColorfulTheme::default())
+                .with_prompt(format!(
+                    "Replace current state (serial `{}`) with backup `{}`?",
+                    state.serial(),
+                    self.serial
+                ))
+                .interact()?
+        {
+            bail!("action aborted")
+        }
+
+        // Saving keeps the serial increasing, the replaced state is backed up in turn
+        state.restore(backup)?;
+        state.save(&state_lock).await?;
+
+        tracing::info!("state restored from backup `{}`", self.serial);
+        Ok(())
+    }
+}
+
+#[derive(Parser)]
+pub struct ForceUnlock {
+    /// ID of the lock, as reported by the run waiting for it
+    pub id: String,
+
+    /// Release the lock without confirmation
+    #[clap(long)]
+    pub force: bool,
+}
+
+impl ForceUnlock {
+    pub async fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        if !self.force
+            && !Confirm::with_theme(&ColorfulTheme::default())
+                .with_prompt(format!(
+                    "Release state lock `{}`? Only do so if the run holding it is not in progress",
+                    self.id
+                ))
+                .interact()?
+        {
+            bail!("action aborted")
+        }
+
+        config.state.force_unlock(&self.id).await?;
+
+        tracing::info!("state lock `{}` released", self.id);
+        Ok(())
+    }
+}
+
+#[derive(Parser)]
+pub struct PushState {
+    /// Push the state written to the recovery file by a failed save
+    #[clap(long, required = true)]
+    pub from_recovery: bool,
+
+    /// Skip interactive approval of the push
+    #[clap(long)]
+    pub auto_approve: bool,
+}
+
+impl PushState {
+    pub async fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        let mut recovered = State::load_recovery()?;
+        let state_lock = config.state.lock().await?;
+        let state = config.state.load().await?;
+        state.check_recovery(&recovered)?;
+
+        if !self.auto_approve
+            && !Confirm::with_theme(&ColorfulTheme::default())
+                .with_prompt(format!(
+                    "Replace current state (serial `{}`) with the recovered state?",
+                    state.serial()
+                ))
+                .interact()?
+        {
+            bail!("action aborted")
+        }
+
+        recovered.save(&state_lock).await?;
+        State::remove_recovery()?;
+
+        tracing::info!("recovered state saved (serial `{}`)", recovered.serial());
+        Ok(())
+    }
+}
+
+#[derive(Parser)]
+pub struct ValidateState {
+    /// ID of the service
+    #[clap(short, long)]
+    pub service_id: Option<String>,
+}
+
+impl ValidateState {
+    pub async fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        let state = shared_backend(config).load().await?;
+        let plugin_manager = PluginManager::new()?.with_capabilities(&config.plugins);
+
+        let mut count = 0;
+        for plugin in config.plugins.keys() {
+            let service_ids: Vec<&str> = config
+                .services
+                .iter()
+                .filter(|svc| &svc.plugin == plugin)
+                .filter(|svc| {
+                    self.service_id.is_none() || self.service_id.as_ref() == Some(&svc.id)
+                })
+                .map(|svc| svc.id.as_str())
+                .collect();
+            if service_ids.is_empty() {
+                continue;
+            }
+
+            let (instance, mut store) = plugin_manager.load_plugin(plugin).await?;
+            let schema = instance.schema(&mut store).await?;
+            for violation in state.schema_violations(&schema, &service_ids)? {
+                count += 1;
+                println!(
+                    "[!] rule: `{}` on `{}` does not match `{}` schema {}: {}",
+                    style(&violation.rule).red(),
+                    violation.service,
+                    plugin,
+                    instance.metadata.version,
+                    violation.message
+                );
+            }
+        }
+
+        if count > 0 {
+            bail!(
+                "{} state entries need migration, redeploy them with `lgc deploy`",
+                count
+            )
+        }
+
+        tracing::info!("all good, state matches plugin schemas");
+        Ok(())
+    }
+}
@@ -0,0 +1,241 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{anyhow, bail, Result};
+use clap::Args;
+use lgc_common::{
+    configuration::{ProjectConfiguration, Service, LGC_RULES_DIR},
+    detections::{map_plugin_detections, retain_tagged, PluginDetections},
+    state::State,
+    variables::Variables,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    str::FromStr,
+};
+
+/// Detection of a service, addressed as `<service>/<rule-path>` (e.g. `splunk-prod/rules/brute-force.yaml`).
+#[derive(Clone, Debug)]
+pub struct Target {
+    pub service_id: String,
+    /// Detection file name, without the rules directory and extension
+    pub detection_id: String,
+}
+
+impl FromStr for Target {
+    type Err = anyhow::Error;
+
+    fn from_str(target: &str) -> Result<Self> {
+        let Some((service_id, path)) = target.split_once('/') else {
+            bail!(
+                "invalid target `{}`, expected `<service>/<rule-path>`",
+                target
+            )
+        };
+
+        let path = Path::new(path);
+        let path = path.strip_prefix(LGC_RULES_DIR).unwrap_or(path);
+        let detection_id = match path.extension() {
+            Some(_) => path.with_extension(""),
+            None => path.to_path_buf(),
+        };
+
+        if service_id.is_empty() || detection_id.as_os_str().is_empty() {
+            bail!(
+                "invalid target `{}`, expected `<service>/<rule-path>`",
+                target
+            )
+        }
+
+        Ok(Self {
+            service_id: service_id.to_string(),
+            detection_id: detection_id.to_string_lossy().to_string(),
+        })
+    }
+}
+
+/// Restriction of a plan to some detections of some services.
+#[derive(Args, Debug, Default)]
+pub struct TargetArgs {
+    /// Only change this detection on this service (`<service>/<rule-path>`), repeatable
+    #[clap(
+        long = "target",
+        value_name = "SERVICE/RULE",
+        conflicts_with_all = ["env_id", "service_id"]
+    )]
+    pub targets: Vec<Target>,
+}
+
+/// Restriction of a plan to detections with some tags in their metadata.
+#[derive(Args, Debug, Default)]
+pub struct TagArgs {
+    /// Only change detections with this metadata tag, repeatable
+    #[clap(long = "tag", value_name = "TAG")]
+    pub tags: Vec<String>,
+}
+
+impl TagArgs {
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty()
+    }
+
+    /// Keep tagged detections only, if tags are given.
+    pub fn retain(&self, detections: &mut PluginDetections) {
+        if !self.is_empty() {
+            retain_tagged(detections, &self.tags);
+            if detections.is_empty() {
+                tracing::warn!("no detection is tagged `{}`", self.tags.join("`, `"));
+            }
+        }
+    }
+}
+
+/// Rule names targeted on each service.
+#[derive(Debug, Default)]
+pub struct Targets(HashMap<String, HashSet<String>>);
+
+impl Targets {
+    pub fn includes(&self, service_id: &str, rule: &str) -> bool {
+        self.0
+            .get(service_id)
+            .is_some_and(|rules| rules.contains(rule))
+    }
+}
+
+impl TargetArgs {
+    pub fn is_empty(&self) -> bool {
+        self.targets.is_empty()
+    }
+
+    /// Targeted services, by plugin.
+    pub fn services<'a>(
+        &self,
+        config: &'a ProjectConfiguration,
+    ) -> Result<HashMap<String, Vec<&'a Service>>> {
+        let mut services: HashMap<String, Vec<&Service>> = HashMap::new();
+        for target in &self.targets {
+            let svc = target.service(config)?;
+
+            let plugin_services = services.entry(svc.plugin.clone()).or_default();
+            if !plugin_services.iter().any(|known| known.id == svc.id) {
+                plugin_services.push(svc);
+            }
+        }
+
+        Ok(services)
+    }
+
+    /// Load targeted detections only, with the rules they define for each targeted service.
+    pub fn load(
+        &self,
+        config: &ProjectConfiguration,
+        variables: &Variables,
+        extensions: &[String],
+    ) -> Result<(PluginDetections, Targets)> {
+        let mut detections = PluginDetections::new();
+        let mut targets = Targets::default();
+
+        for target in &self.targets {
+            let svc = target.service(config)?;
+
+            let (loaded, _) = map_plugin_detections(
+                Some(target.detection_id.clone()),
+                variables,
+                extensions,
+                false,
+            )?;
+            let Some(rules) = loaded.get(&svc.plugin) else {
+                bail!(
+                    "detection `{}` has no rule for plugin `{}` of service `{}`",
+                    target.detection_id,
+                    svc.plugin,
+                    svc.id
+                )
+            };
+
+            targets
+                .0
+                .entry(svc.id.clone())
+                .or_default()
+                .extend(rules.iter().map(|rule| rule.name.clone()));
+            for (plugin, rules) in loaded {
+                detections.entry(plugin).or_default().extend(rules);
+            }
+        }
+
+        Ok((detections, targets))
+    }
+    /// Deployed rules targeted on each service.
+    ///
+    /// Detections removed from the project address their rules by name.
+    pub fn deployed(
+        &self,
+        config: &ProjectConfiguration,
+        state: &State,
+        variables: &Variables,
+        extensions: &[String],
+    ) -> Result<Targets> {
+        let mut targets = Targets::default();
+
+        for target in &self.targets {
+            let svc = target.service(config)?;
+
+            let names: Vec<String> = if extensions.iter().any(|ext| {
+                Path::new(LGC_RULES_DIR)
+                    .join(format!("{}.{}", target.detection_id, ext))
+                    .is_file()
+            }) {
+                let (loaded, _) = map_plugin_detections(
+                    Some(target.detection_id.clone()),
+                    variables,
+                    extensions,
+                    false,
+                )?;
+                loaded
+                    .get(&svc.plugin)
+                    .map(|rules| rules.iter().map(|rule| rule.name.clone()).collect())
+                    .unwrap_or_default()
+            } else {
+                vec![target.detection_id.clone()]
+            };
+
+            let deployed: Vec<String> = names
+                .into_iter()
+                .filter(|name| {
+                    state
+                        .services
+                        .get(&svc.id)
+                        .is_some_and(|rules| rules.iter().any(|rule| &rule.name == name))
+                })
+                .collect();
+            if deployed.is_empty() {
+                bail!(
+                    "detection `{}` is not deployed on `{}`",
+                    target.detection_id,
+                    svc.id
+                )
+            }
+
+            targets
+                .0
+                .entry(svc.id.clone())
+                .or_default()
+                .extend(deployed);
+        }
+
+        Ok(targets)
+    }
+}
+
+impl Target {
+    fn service<'a>(&self, config: &'a ProjectConfiguration) -> Result<&'a Service> {
+        config
+            .services
+            .get(&Service {
+                id: self.service_id.clone(),
+                ..Default::default()
+            })
+            .ok_or_else(|| anyhow!("service `{}` not found", self.service_id))
+    }
+}
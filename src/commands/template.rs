@@ -0,0 +1,99 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use lgc_common::{
+    configuration::{ProjectConfiguration, Service},
+    detections::{map_plugin_detections, resolve_common_rules, sorted_rules},
+    plugins::manager::{PluginActions, PluginManager},
+    transforms::apply_transforms,
+    utils::workspace_path,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use super::variables::VariablesArgs;
+
+#[derive(Parser, Debug, Default)]
+#[clap(
+    about = "Render every detection of a service into a native artifact",
+    allow_hyphen_values = true
+)]
+pub struct TemplateCommand {
+    /// Render detections for this service
+    pub service_id: String,
+
+    /// Write the artifact to this file, `-` for stdout (defaults to the plugin file name)
+    #[clap(short, long)]
+    pub output: Option<PathBuf>,
+
+    #[clap(flatten)]
+    pub variables: VariablesArgs,
+}
+
+impl TemplateCommand {
+    pub async fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        let svc = config
+            .services
+            .get(&Service {
+                id: self.service_id.clone(),
+                ..Default::default()
+            })
+            .ok_or_else(|| anyhow!("service `{}` not found", &self.service_id))?;
+
+        // Render detections as they would be deployed
+        let (mut detections, _) = map_plugin_detections(
+            None,
+            &self.variables.resolve(config)?,
+            &config.core.detection_extensions()?,
+            false,
+        )?;
+        let plugin_manager = PluginManager::new()?.with_capabilities(&config.plugins);
+        resolve_common_rules(&plugin_manager, &mut detections).await?;
+        apply_transforms(&config.transforms, &mut detections)?;
+
+        let naming = config.naming(svc)?;
+        let rules = match detections.get(&svc.plugin) {
+            Some(rules) => sorted_rules(rules)
+                .into_iter()
+                .map(|rule| {
+                    Ok((
+                        naming.apply(&rule.name),
+                        serde_json::to_string(&rule.content)?,
+                    ))
+                })
+                .collect::<Result<Vec<_>>>()?,
+            None => Vec::new(),
+        };
+
+        let (instance, mut store) = plugin_manager.load_plugin(&svc.plugin).await?;
+        let bundle = instance
+            .template(&mut store, &svc.config().await?, &rules)
+            .await?;
+
+        // Plugins only suggest a name, never a location
+        let output = match self.output {
+            Some(output) => output,
+            None => Path::new(&bundle.file_name)
+                .file_name()
+                .map(PathBuf::from)
+                .ok_or_else(|| anyhow!("invalid artifact file name `{}`", bundle.file_name))?,
+        };
+        if output.as_os_str() == "-" {
+            print!("{}", bundle.content);
+        } else {
+            fs::write(&output, bundle.content)?;
+            tracing::info!(
+                "{} rule(s) of `{}` rendered to `{}`",
+                rules.len(),
+                svc.id,
+                workspace_path(&output)
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,238 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{anyhow, bail, Result};
+use clap::Parser;
+use console::style;
+use kclvm_api::{gpyrpc::ValidateCodeArgs, service::KclvmServiceImpl};
+use lgc_common::{
+    configuration::{ProjectConfiguration, LGC_TESTS_DIR},
+    plugins::{
+        manager::{InstanceData, PluginActions, PluginManager, PluginSchemas},
+        Capability,
+    },
+    utils::workspace_path,
+};
+use lgc_runtime::state::State;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{collections::HashMap, fs, path::Path};
+use wasmtime::Store;
+
+use super::output::OutputArgs;
+
+/// Run detection test cases
+#[derive(Parser, Debug, Default)]
+#[clap(
+    about = "Check rules of test cases against plugins",
+    allow_hyphen_values = true
+)]
+pub struct TestCommand {
+    /// Only run test cases whose name contains this text
+    pub filter: Option<String>,
+
+    #[clap(flatten)]
+    pub output: OutputArgs,
+}
+
+/// Rule of a plugin with its expected validation outcome, listed in `tests/*.yaml`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TestCase {
+    name: String,
+    plugin: String,
+    rule: Value,
+    #[serde(default)]
+    expect: Expectation,
+    /// Patterns each matching one of the reported problems
+    #[serde(default)]
+    messages: Vec<String>,
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Expectation {
+    #[default]
+    Valid,
+    Invalid,
+}
+
+/// Test results, as printed by `--output json`.
+#[derive(Serialize, Default)]
+struct TestReport {
+    passed: usize,
+    failed: usize,
+    tests: Vec<TestResult>,
+}
+
+#[derive(Serialize)]
+struct TestResult {
+    file: String,
+    name: String,
+    passed: bool,
+    /// Problems reported for the rule
+    problems: Vec<String>,
+    /// Why the test failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+
+/// Loaded plugin, with its instance only when it can check rules.
+struct TestedPlugin {
+    schemas: PluginSchemas,
+    checker: Option<(InstanceData, Store<State>)>,
+}
+
+impl TestCommand {
+    pub async fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        let cases = load_cases(Path::new(LGC_TESTS_DIR))?;
+        let cases: Vec<_> = cases
+            .into_iter()
+            .filter(|(_, case)| {
+                self.filter
+                    .as_ref()
+                    .map_or(true, |filter| case.name.contains(filter))
+            })
+            .collect();
+        if cases.is_empty() {
+            tracing::warn!("no test case found in `{}`", LGC_TESTS_DIR);
+            return Ok(());
+        }
+
+        let plugin_manager = PluginManager::new()?.with_capabilities(&config.plugins);
+        let serv = KclvmServiceImpl::default();
+        let mut plugins: HashMap<String, TestedPlugin> = HashMap::new();
+        let mut report = TestReport::default();
+
+        for (file, case) in cases {
+            if !plugins.contains_key(&case.plugin) {
+                let schemas = plugin_manager
+                    .plugin_schemas(&case.plugin)
+                    .await
+                    .map_err(|e| anyhow!("test `{}`: {}", case.name, e))?;
+                let (instance, store) = plugin_manager.load_plugin(&case.plugin).await?;
+                let checker = Some((instance, store))
+                    .filter(|(instance, _)| instance.supports(Capability::Check));
+                plugins.insert(case.plugin.clone(), TestedPlugin { schemas, checker });
+            }
+            // Safe unwrap, the plugin has just been loaded
+            let plugin = plugins.get_mut(&case.plugin).unwrap();
+
+            // Problems reported by `lgc validate`, offline
+            let mut problems = Vec::new();
+            let check = serv.validate_code(&ValidateCodeArgs {
+                code: plugin.schemas.rule.clone(),
+                schema: String::from("Rule"),
+                format: String::from("yaml"),
+                data: serde_yaml_ng::to_string(&case.rule)?,
+                ..Default::default()
+            })?;
+            if !check.success {
+                problems.push(check.err_message);
+            }
+            if let Some((instance, store)) = &mut plugin.checker {
+                problems.extend(
+                    instance
+                        .check(store, None, &case.name, &serde_json::to_string(&case.rule)?)
+                        .await?,
+                );
+            }
+
+            let reason = case.failure(&problems)?;
+            if !self.output.is_json() {
+                match &reason {
+                    None => println!("[+] {} ... {}", case.name, style("ok").green()),
+                    Some(reason) => {
+                        println!("[-] {} ... {}", case.name, style("FAILED").red());
+                        println!("    {} {}", style("in:").dim(), file);
+                        println!("    {}", reason);
+                        for problem in &problems {
+                            println!("    {} {}", style("problem:").dim(), problem);
+                        }
+                    }
+                }
+            }
+
+            match reason {
+                None => report.passed += 1,
+                Some(_) => report.failed += 1,
+            }
+            report.tests.push(TestResult {
+                file,
+                name: case.name,
+                passed: reason.is_none(),
+                problems,
+                reason,
+            });
+        }
+
+        self.output.emit(&report)?;
+        if report.failed > 0 {
+            bail!(
+                "{} of {} test(s) failed",
+                report.failed,
+                report.passed + report.failed
+            )
+        }
+        tracing::info!("{} test(s) passed", report.passed);
+
+        Ok(())
+    }
+}
+
+impl TestCase {
+    /// Why the reported problems do not match the expectation, if they do not.
+    fn failure(&self, problems: &[String]) -> Result<Option<String>> {
+        match self.expect {
+            Expectation::Valid if !problems.is_empty() => {
+                return Ok(Some(String::from("expected a valid rule")))
+            }
+            Expectation::Invalid if problems.is_empty() => {
+                return Ok(Some(String::from("expected an invalid rule")))
+            }
+            _ => (),
+        }
+
+        for pattern in &self.messages {
+            let regex = Regex::new(pattern).map_err(|e| {
+                anyhow!("test `{}`: invalid pattern `{}`: {}", self.name, pattern, e)
+            })?;
+            if !problems.iter().any(|problem| regex.is_match(problem)) {
+                return Ok(Some(format!("no problem matches `{}`", pattern)));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// Test cases of every file of the tests directory, by file name.
+fn load_cases(dir: &Path) -> Result<Vec<(String, TestCase)>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut paths: Vec<_> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.is_file()
+                && matches!(
+                    path.extension().and_then(|ext| ext.to_str()),
+                    Some("yaml" | "yml")
+                )
+        })
+        .collect();
+    paths.sort();
+
+    let mut cases = Vec::new();
+    for path in paths {
+        let file = workspace_path(&path);
+        let content = fs::read_to_string(&path)?;
+        let file_cases: Vec<TestCase> = serde_yaml_ng::from_str(&content)
+            .map_err(|e| anyhow!("invalid test file `{}`: {}", file, e))?;
+        cases.extend(file_cases.into_iter().map(|case| (file.clone(), case)));
+    }
+
+    Ok(cases)
+}
@@ -1,35 +1,95 @@
 // Copyright (c) 2023 LogCraft, SAS.
 // SPDX-License-Identifier: MPL-2.0
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use clap::Parser;
 use kclvm_api::{gpyrpc::ValidateCodeArgs, service::KclvmServiceImpl};
-use tokio::task::JoinSet;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
 
 use lgc_common::{
     configuration::ProjectConfiguration,
-    detections::map_plugin_detections,
-    plugins::manager::{PluginActions, PluginManager},
+    detections::{map_plugin_detections, resolve_common_rules, sorted_rules},
+    plugins::{
+        manager::{PluginActions, PluginManager},
+        Capability,
+    },
+    policies::{load_policies, Severity},
+    transforms::apply_transforms,
 };
+
+use super::{output::OutputArgs, variables::VariablesArgs};
+
 /// Validate configuration
 #[derive(Parser, Debug, Default)]
 #[clap(about = "Validate local detection rules", allow_hyphen_values = true)]
-pub struct ValidateCommand;
+pub struct ValidateCommand {
+    #[clap(flatten)]
+    pub variables: VariablesArgs,
+
+    #[clap(flatten)]
+    pub output: OutputArgs,
+
+    /// Never use services when plugins check rules, e.g. the syntax of queries
+    #[clap(long)]
+    pub offline: bool,
+}
+
+/// Validation results, as printed by `--output json`.
+#[derive(Serialize, Default)]
+struct ValidationReport {
+    valid: bool,
+    problems: Vec<Problem>,
+}
+
+/// Service settings or rule not matching its plugin schema, or rule violating a policy.
+#[derive(Serialize)]
+struct Problem {
+    plugin: String,
+    service: Option<String>,
+    rule: Option<String>,
+    /// Violated policy, schema problems have none
+    #[serde(skip_serializing_if = "Option::is_none")]
+    policy: Option<String>,
+    severity: Severity,
+    message: String,
+}
 
 impl ValidateCommand {
     pub async fn run(self, config: &ProjectConfiguration) -> Result<()> {
         // Load all detections
-        let detections = map_plugin_detections(None)?;
+        let (mut detections, _) = map_plugin_detections(
+            None,
+            &self.variables.resolve(config)?,
+            &config.core.detection_extensions()?,
+            false,
+        )?;
 
         // Load plugins
-        let plugin_manager = PluginManager::new()?;
-        let mut set = JoinSet::new();
+        let plugin_manager = PluginManager::new()?.with_capabilities(&config.plugins);
+        resolve_common_rules(&plugin_manager, &mut detections).await?;
+        apply_transforms(&config.transforms, &mut detections)?;
+        let permits = Arc::new(Semaphore::new(config.core.limits.parallelism()?));
 
-        for plugin_name in detections.keys() {
-            let plugin_name = plugin_name.to_string();
-            let plugin_manager = plugin_manager.clone();
-            set.spawn(async move { plugin_manager.load_plugin(plugin_name).await });
-        }
+        // Schemas load concurrently, plugins are validated by name as soon as theirs are loaded,
+        // so that problems are streamed in a stable order
+        let mut plugin_names: Vec<&String> = detections.keys().collect();
+        plugin_names.sort();
+        let loading: Vec<_> = plugin_names
+            .into_iter()
+            .map(|plugin_name| {
+                let plugin_name = plugin_name.to_string();
+                let plugin_manager = plugin_manager.clone();
+                let permits = permits.clone();
+                tokio::spawn(async move {
+                    let _permit = permits.acquire_owned().await?;
+                    let schemas = plugin_manager.plugin_schemas(&plugin_name).await?;
+                    Ok::<_, anyhow::Error>((plugin_name, schemas))
+                })
+            })
+            .collect();
 
         // Start kclvm service
         let serv = KclvmServiceImpl::default();
@@ -38,44 +98,162 @@ impl ValidateCommand {
             ..Default::default()
         };
 
-        let mut has_err: bool = false;
+        let mut report = ValidationReport::default();
         // Call get schema and retrieve all detections
-        while let Some(plugin) = set.join_next().await {
-            let (instance, mut store) = plugin??;
-            let meta = &instance.metadata;
+        for plugin in loading {
+            let (plugin_name, schemas) = plugin.await??;
 
-            // Safe unwrap as we load plugins with detection HashMap.
-            let (plugin, rules) = detections.get_key_value(&meta.name).unwrap();
+            // Safe unwrap as we load schemas with detection HashMap.
+            let (plugin, rules) = detections.get_key_value(&plugin_name).unwrap();
+            let rules = sorted_rules(rules);
 
             // Check services
-            args.code = instance.settings(&mut store).await?;
+            args.code = schemas.settings;
             args.schema = String::from("Configuration");
             for svc in config.services.iter().filter(|svc| &svc.plugin == plugin) {
-                args.data = serde_yaml_ng::to_string(&svc.settings)?;
+                args.data = serde_yaml_ng::to_string(&svc.checked_settings().await?)?;
                 let check = serv.validate_code(&args)?;
                 if !check.success {
-                    has_err = true;
                     tracing::error!("{}", check.err_message);
+                    report.problems.push(Problem {
+                        plugin: plugin.clone(),
+                        service: Some(svc.id.clone()),
+                        rule: None,
+                        policy: None,
+                        severity: Severity::Error,
+                        message: check.err_message,
+                    });
                 }
             }
 
-            // Check rules
-            args.code = instance.schema(&mut store).await?;
-            args.schema = String::from("Rule");
-            for detection in rules {
-                args.data = serde_yaml_ng::to_string(&detection.content)?;
-                let check = serv.validate_code(&args)?;
+            // Check rules, each thread validating with its own kclvm service
+            let checks = rules
+                .par_iter()
+                .map_init(
+                    || {
+                        let args = ValidateCodeArgs {
+                            code: schemas.rule.clone(),
+                            schema: String::from("Rule"),
+                            format: String::from("yaml"),
+                            ..Default::default()
+                        };
+                        (KclvmServiceImpl::default(), args)
+                    },
+                    |(serv, args), detection| {
+                        args.data = serde_yaml_ng::to_string(&detection.content)?;
+                        Ok::<_, anyhow::Error>((detection, serv.validate_code(args)?))
+                    },
+                )
+                .collect::<Result<Vec<_>>>()?;
+            for (detection, check) in checks {
                 if !check.success {
-                    has_err = true;
                     tracing::error!("{}", check.err_message);
+                    report.problems.push(Problem {
+                        plugin: plugin.clone(),
+                        service: None,
+                        rule: Some(detection.name.clone()),
+                        policy: None,
+                        severity: Severity::Error,
+                        message: check.err_message,
+                    });
+                }
+            }
+
+            // Check rules with the plugin, e.g. the syntax of queries
+            let (instance, mut store) = plugin_manager.load_plugin(plugin).await?;
+            if instance.supports(Capability::Check) {
+                // Rules are the same on every service, checking them against one is enough
+                let svc = config
+                    .services
+                    .iter()
+                    .find(|svc| &svc.plugin == plugin)
+                    .filter(|_| !self.offline);
+                let service_config = match svc {
+                    Some(svc) => Some(svc.config().await?),
+                    None => None,
+                };
+
+                for detection in &rules {
+                    let params = serde_json::to_string(&detection.content)?;
+                    match instance
+                        .check(
+                            &mut store,
+                            service_config.as_deref(),
+                            &detection.name,
+                            &params,
+                        )
+                        .await
+                    {
+                        Ok(problems) => {
+                            for message in problems {
+                                let message = format!("rule `{}`: {}", detection.name, message);
+                                tracing::error!("{}", message);
+                                report.problems.push(Problem {
+                                    plugin: plugin.clone(),
+                                    service: svc.map(|svc| svc.id.clone()),
+                                    rule: Some(detection.name.clone()),
+                                    policy: None,
+                                    severity: Severity::Error,
+                                    message,
+                                });
+                            }
+                        }
+                        Err(e) => {
+                            tracing::warn!("unable to check rule `{}`: {}", detection.name, e)
+                        }
+                    }
+                }
+            }
+
+            // Check rules against policies of the plugin
+            let policies = load_policies(plugin, config.policies.keys())?;
+            for detection in &rules {
+                for policy in &policies {
+                    for violation in policy.check(&detection.content)? {
+                        let message = format!(
+                            "rule `{}` violates policy `{}`: {}",
+                            detection.name, policy.name, violation
+                        );
+                        match policy.severity {
+                            Severity::Error => tracing::error!("{}", message),
+                            Severity::Warning => tracing::warn!("{}", message),
+                            Severity::Info => tracing::info!("{}", message),
+                        }
+                        report.problems.push(Problem {
+                            plugin: plugin.clone(),
+                            service: None,
+                            rule: Some(detection.name.clone()),
+                            policy: Some(policy.name.clone()),
+                            severity: policy.severity,
+                            message,
+                        });
+                    }
                 }
             }
         }
 
-        if !has_err {
+        // Warnings and notices do not invalidate detections
+        report.valid = !report
+            .problems
+            .iter()
+            .any(|problem| problem.severity == Severity::Error);
+        if report.problems.is_empty() {
             tracing::info!("all good, no problems identified");
         }
 
+        // Plugins are validated concurrently
+        report.problems.sort_by(|a, b| {
+            (&a.plugin, &a.service, &a.rule).cmp(&(&b.plugin, &b.service, &b.rule))
+        });
+        self.output.emit(&report)?;
+
+        if report
+            .problems
+            .iter()
+            .any(|problem| problem.policy.is_some() && problem.severity == Severity::Error)
+        {
+            bail!("detections violate error-level policies")
+        }
         Ok(())
     }
 }
@@ -0,0 +1,27 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::Result;
+use clap::Args;
+use lgc_common::{configuration::ProjectConfiguration, variables::Variables};
+use std::path::PathBuf;
+
+/// Detection variables overrides.
+///
+/// Precedence: `--var` > `--var-file` > `LGC_VAR_<key>` environment variables > `variables` of lgc.yaml.
+#[derive(Args, Debug, Default)]
+pub struct VariablesArgs {
+    /// Set a detection variable, overriding every other source
+    #[clap(long = "var", value_name = "KEY=VALUE")]
+    pub vars: Vec<String>,
+
+    /// Load detection variables from a yaml file, later files take precedence
+    #[clap(long = "var-file", value_name = "PATH")]
+    pub var_files: Vec<PathBuf>,
+}
+
+impl VariablesArgs {
+    pub fn resolve(&self, config: &ProjectConfiguration) -> Result<Variables> {
+        Variables::resolve(&config.variables, &self.var_files, &self.vars)
+    }
+}
@@ -0,0 +1,179 @@
+// Copyright (c) 2023 LogCraft, SAS.
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{bail, Result};
+use clap::{Parser, Subcommand};
+use console::style;
+use lgc_common::{
+    configuration::{ensure_workspace_dirs, ProjectConfiguration},
+    detections::lint_detections,
+    state::{current_workspace, select_workspace, LGC_DEFAULT_WORKSPACE, LGC_WORKSPACE_ENV},
+    utils::ensure_kebab_case,
+};
+use std::env;
+
+/// Manage the project workspace
+#[derive(Subcommand)]
+pub enum WorkspaceCommands {
+    /// Create missing project directories and report mismatches with installed plugins
+    Sync(SyncWorkspace),
+
+    /// Create a named workspace, with its own state, and select it
+    New(NewWorkspace),
+
+    /// Select the workspace used by other commands
+    Select(SelectWorkspace),
+
+    /// List workspaces
+    List(ListWorkspaces),
+
+    /// Show the selected workspace
+    Show(ShowWorkspace),
+
+    /// Remove a workspace, its state is left in the backend
+    Delete(DeleteWorkspace),
+}
+
+impl WorkspaceCommands {
+    pub async fn run(self, config: &mut ProjectConfiguration) -> Result<()> {
+        match self {
+            Self::Sync(cmd) => cmd.run(config),
+            Self::New(cmd) => cmd.run(config),
+            Self::Select(cmd) => cmd.run(config),
+            Self::List(cmd) => cmd.run(config),
+            Self::Show(cmd) => cmd.run(),
+            Self::Delete(cmd) => cmd.run(config),
+        }
+    }
+}
+
+#[derive(Parser)]
+pub struct SyncWorkspace;
+
+impl SyncWorkspace {
+    pub fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        for dir in ensure_workspace_dirs()? {
+            tracing::info!("created `{}` directory", dir);
+        }
+
+        let mut findings = config.lint_plugins_dir();
+        findings.extend(lint_detections(config)?);
+        for finding in &findings {
+            println!("[!] {}", style(&finding.message).yellow());
+            println!("    {} {}", style("fix:").dim(), finding.suggestion);
+        }
+
+        if findings.is_empty() {
+            tracing::info!("workspace in sync with installed plugins");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Parser)]
+pub struct NewWorkspace {
+    /// Name of the workspace
+    pub name: String,
+}
+
+impl NewWorkspace {
+    pub fn run(self, config: &mut ProjectConfiguration) -> Result<()> {
+        ensure_kebab_case(&self.name)?;
+        if self.name == LGC_DEFAULT_WORKSPACE || config.workspaces.contains(&self.name) {
+            bail!("workspace `{}` already exists", self.name)
+        }
+
+        config.workspaces.insert(self.name.clone());
+        config.save_config(None)?;
+        select_workspace(&self.name)?;
+
+        tracing::info!("workspace `{}` created and selected", self.name);
+        Ok(())
+    }
+}
+
+#[derive(Parser)]
+pub struct SelectWorkspace {
+    /// Name of the workspace
+    pub name: String,
+}
+
+impl SelectWorkspace {
+    pub fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        if self.name != LGC_DEFAULT_WORKSPACE && !config.workspaces.contains(&self.name) {
+            bail!(
+                "workspace `{}` does not exist, create it with `lgc workspace new {}`",
+                self.name,
+                self.name
+            )
+        }
+
+        select_workspace(&self.name)?;
+        if env::var(LGC_WORKSPACE_ENV).is_ok() {
+            tracing::warn!("`{}` overrides the selected workspace", LGC_WORKSPACE_ENV);
+        }
+
+        tracing::info!("workspace `{}` selected", self.name);
+        Ok(())
+    }
+}
+
+#[derive(Parser)]
+pub struct ListWorkspaces;
+
+impl ListWorkspaces {
+    pub fn run(self, config: &ProjectConfiguration) -> Result<()> {
+        let current = current_workspace();
+        for workspace in std::iter::once(LGC_DEFAULT_WORKSPACE)
+            .chain(config.workspaces.iter().map(String::as_str))
+        {
+            if workspace == current {
+                println!("* {}", style(workspace).bold().green());
+            } else {
+                println!("  {}", workspace);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Parser)]
+pub struct ShowWorkspace;
+
+impl ShowWorkspace {
+    pub fn run(self) -> Result<()> {
+        println!("{}", current_workspace());
+        Ok(())
+    }
+}
+
+#[derive(Parser)]
+pub struct DeleteWorkspace {
+    /// Name of the workspace
+    pub name: String,
+}
+
+impl DeleteWorkspace {
+    pub fn run(self, config: &mut ProjectConfiguration) -> Result<()> {
+        if self.name == LGC_DEFAULT_WORKSPACE {
+            bail!("the default workspace cannot be deleted")
+        }
+        if self.name == current_workspace() {
+            bail!(
+                "workspace `{}` is selected, select another one before deleting it",
+                self.name
+            )
+        }
+        if !config.workspaces.remove(&self.name) {
+            bail!("workspace `{}` does not exist", self.name)
+        }
+
+        config.save_config(None)?;
+        tracing::info!(
+            "workspace `{}` deleted, its state is left in the backend",
+            self.name
+        );
+        Ok(())
+    }
+}